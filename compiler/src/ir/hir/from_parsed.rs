@@ -26,13 +26,17 @@ impl Module {
                         arity: arity,
                         lambda: None,
                     };
+                    let mut hir_fun = ::ir::hir::Function::from_parsed(&f.fun.0, &fun_ident);
+                    if hir_fun.body.line.is_none() {
+                        hir_fun.body.line = f.fun.line();
+                    }
                     FunctionDefinition {
                         visibility: if is_visible {
                             FunctionVisibility::Public
                         } else {
                             FunctionVisibility::Private
                         },
-                        hir_fun: ::ir::hir::Function::from_parsed(&f.fun.0, &fun_ident),
+                        hir_fun: hir_fun,
                         ident: fun_ident,
                         lambda_env_idx: None,
                         eir_fun: None,
@@ -315,15 +319,21 @@ impl SingleExpression {
         SingleExpression {
             ssa: INVALID_SSA,
             kind,
+            line: None,
         }
     }
     fn from_parsed(fun: &::parser::Expression, fun_ident: &FunctionIdent) -> SingleExpression {
         let values: Vec<_> = fun.0.iter()
-            .map(|val| SingleExpression::from_parsed_single(&val.0, fun_ident))
+            .map(|val| {
+                let mut expr = SingleExpression::from_parsed_single(&val.0, fun_ident);
+                expr.line = val.line();
+                expr
+            })
             .collect();
         SingleExpression {
             ssa: INVALID_SSA,
             kind: SingleExpressionKind::ValueList(values),
+            line: fun.line(),
         }
     }
 }
@@ -56,6 +56,8 @@ impl EachSingleExpression for Expression {
 pub struct SingleExpression {
     pub ssa: SSAVariable,
     pub kind: SingleExpressionKind,
+    /// Source line, from the Core Erlang annotations.
+    pub line: Option<u32>,
 }
 
 impl EachSingleExpression for SingleExpression {
@@ -277,6 +277,12 @@ impl hir::SingleExpression {
     fn lower(&self, b: &mut FunctionBuilder, st: &mut LirLowerState)
              -> SSAVariable {
 
+        // Ops get the line of the innermost expression that has one
+        let parent_line = b.source_line();
+        if self.line.is_some() {
+            b.set_source_line(self.line);
+        }
+
         //println!("-> lower");
         let a = match self.kind {
             HSEK::InterModuleCall { ref module, ref name, ref args } => {
@@ -872,8 +878,12 @@ impl hir::SingleExpression {
                                     st.bindings.insert(ssa, val);
                                     ssa
                                 }),
-                                body: Box::new(move |b, st, _case, _matches|
-                                               body.lower(b, st)),
+                                // The message is only consumed once a
+                                // clause matched
+                                body: Box::new(move |b, st, _case, _matches| {
+                                    b.op_receive_finish(receive_loop_var);
+                                    body.lower(b, st)
+                                }),
                             }
                         }).collect();
                     let def = CaseStructureDef {
@@ -969,7 +979,73 @@ impl hir::SingleExpression {
             }
             ref s => panic!("Unhandled: {:?}", s),
         };
+        b.set_source_line(parent_line);
         //println!("<- lower");
         a
     }
 }
+
+#[cfg(test)]
+mod test {
+    use ::eir::op::OpKind;
+
+    #[test]
+    fn receive_clauses_finish_receive() {
+        let text = "module 'test' ['recv'/0] attributes []
+'recv'/0 =
+    fun () ->
+\treceive
+\t  <{'ok',_0}> when 'true' ->
+\t      _0
+\tafter 'infinity' ->
+\t  'true'
+end
+";
+        let parsed = ::parser::parse(text).unwrap();
+        let module = ::ir::from_parsed(&parsed.0);
+        let fun = module.functions.values().next().unwrap();
+        let kinds: Vec<_> = fun.iter_ebb()
+            .flat_map(|ebb| fun.iter_op(ebb))
+            .map(|op| fun.op_kind(op).clone())
+            .collect();
+
+        let waits = kinds.iter()
+            .filter(|kind| match kind { OpKind::ReceiveWait => true, _ => false })
+            .count();
+        let finishes = kinds.iter()
+            .filter(|kind| match kind { OpKind::ReceiveFinish => true, _ => false })
+            .count();
+        assert!(waits == 1);
+        assert!(finishes == 1);
+    }
+
+    #[test]
+    fn line_annotations_reach_ops() {
+        let text = "module 'test' ['f'/1] attributes []
+'f'/1 =
+    %% Line 3
+    fun (_0) ->
+\t%% Line 4
+\tcase _0 of
+\t  <'a'> when 'true' ->
+\t      ( call 'foo':'bar'
+\t\t    ()
+\t\t-| [5] )
+\t  <_1> when 'true' ->
+\t      _1
+\tend
+end
+";
+        let parsed = ::parser::parse(text).unwrap();
+        let module = ::ir::from_parsed(&parsed.0);
+        let fun = module.functions.values().next().unwrap();
+        let mut lines: Vec<_> = fun.iter_ebb()
+            .flat_map(|ebb| fun.iter_op(ebb))
+            .filter_map(|op| fun.op_line(op))
+            .collect();
+        lines.sort();
+        lines.dedup();
+        assert!(lines == vec![4, 5]);
+    }
+
+}
@@ -368,7 +368,8 @@ fn decision_tree_to_cfg(dec: &PatternCfg<ErlangPatternProvider>,
 
 
 
-pub fn compile_pattern(b: &mut FunctionBuilder) {
+/// Returns true if any pattern matching construct was compiled.
+pub fn compile_pattern(b: &mut FunctionBuilder) -> bool {
 
     // Find all pattern matching constructs
     let case_starts = {
@@ -470,4 +471,5 @@ pub fn compile_pattern(b: &mut FunctionBuilder) {
 
     }
 
+    case_starts.len() > 0
 }
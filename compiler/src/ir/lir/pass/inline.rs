@@ -0,0 +1,539 @@
+use std::collections::{ HashMap, HashSet };
+
+use ::petgraph::Graph;
+use ::petgraph::algo::tarjan_scc;
+use ::petgraph::visit::DfsPostOrder;
+
+use ::eir::{ Module, Function, FunctionBuilder, FunctionIdent, CfgNode };
+use ::eir::{ Ebb, Op, Value };
+use ::eir::op::OpKind;
+use ::eir::fun::{ DefUse, ValueDefinition, ValueUse, FunctionCloner };
+
+#[derive(Debug, Clone)]
+pub struct InlineOptions {
+    /// Functions with more ops than this are never inlined.
+    pub max_callee_size: usize,
+    /// No more functions are inlined into a caller once it would grow
+    /// past this many ops.
+    pub max_caller_size: usize,
+}
+
+impl Default for InlineOptions {
+    fn default() -> Self {
+        InlineOptions {
+            max_callee_size: 24,
+            max_caller_size: 2000,
+        }
+    }
+}
+
+/// An `Apply` of a function known at compile time.
+struct CallSite {
+    op: Op,
+    callee: FunctionIdent,
+    /// Arguments for the entry Ebb of the callee. For a closure, the
+    /// first one is the env.
+    args: Vec<Value>,
+    /// The captured values, if the env of the closure is built in the
+    /// caller.
+    captures: Option<Vec<Value>>,
+}
+
+fn function_size(fun: &Function) -> usize {
+    fun.iter_ebb().map(|ebb| fun.iter_op(ebb).count()).sum()
+}
+
+fn call_site(fun: &Function, def_use: &DefUse, op: Op) -> Option<CallSite> {
+    match fun.op_kind(op) {
+        OpKind::Apply { tail_call: false } => {
+            // Like in the other passes, the exception may only be passed
+            // along the exception edge.
+            let writes = fun.op_writes(op);
+            if writes.len() != 2 || fun.op_branches(op).len() != 1 {
+                return None;
+            }
+            let escapes = def_use.value_uses(writes[1]).iter().any(|u| match u {
+                ValueUse::EbbCall(call, _) => fun.ebb_call_source(*call) != op,
+                ValueUse::Op(_, _) => true,
+            });
+            if escapes {
+                return None;
+            }
+        },
+        OpKind::Apply { tail_call: true } => (),
+        _ => return None,
+    }
+
+    let reads = fun.op_reads(op);
+    let def = match def_use.value_definition(reads[0]) {
+        Some(ValueDefinition::Op(def)) => def,
+        _ => return None,
+    };
+
+    match fun.op_kind(def) {
+        OpKind::CaptureNamedFunction(ident) if ident.lambda.is_none() => {
+            Some(CallSite {
+                op: op,
+                callee: ident.clone(),
+                args: reads[1..].to_vec(),
+                captures: None,
+            })
+        },
+        OpKind::BindClosure { ident } => {
+            let env = fun.op_reads(def)[0];
+            let mut args = vec![env];
+            args.extend(reads[1..].iter().cloned());
+
+            let captures = match def_use.value_definition(env) {
+                Some(ValueDefinition::Op(env_def)) => match fun.op_kind(env_def) {
+                    OpKind::MakeClosureEnv { .. } =>
+                        Some(fun.op_reads(env_def).to_vec()),
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            Some(CallSite {
+                op: op,
+                callee: ident.clone(),
+                args: args,
+                captures: captures,
+            })
+        },
+        _ => None,
+    }
+}
+
+fn call_sites(fun: &Function) -> Vec<CallSite> {
+    let def_use = fun.def_use();
+    let mut sites = Vec::new();
+    for ebb in fun.iter_ebb() {
+        for op in fun.iter_op(ebb) {
+            if let Some(site) = call_site(fun, &def_use, op) {
+                sites.push(site);
+            }
+        }
+    }
+    sites
+}
+
+/// Ebbs reachable from the entry, every Ebb after its dominators.
+fn reverse_postorder(fun: &Function) -> Vec<Ebb> {
+    let cfg = fun.gen_cfg();
+    let mut dfs = DfsPostOrder::new(&cfg.graph, cfg.ebbs[&fun.ebb_entry()]);
+    let mut order = Vec::new();
+    while let Some(node) = dfs.next(&cfg.graph) {
+        if let CfgNode::Ebb(ebb) = &cfg.graph[node] {
+            order.push(*ebb);
+        }
+    }
+    order.reverse();
+    order
+}
+
+/// Where control goes when the inlined function returns.
+enum Return {
+    /// The call was a tail call, return from the caller.
+    Tail,
+    /// Jump to `ok` with the result, or along the exception edge of the
+    /// call with `err` replaced by the exception.
+    Edges {
+        ok: Ebb,
+        exc_target: Ebb,
+        exc_args: Vec<Value>,
+        err: Value,
+    },
+}
+
+impl Return {
+
+    fn return_ok(&self, b: &mut FunctionBuilder, value: Value) {
+        match self {
+            Return::Tail => b.op_return_ok(value),
+            Return::Edges { ok, .. } => {
+                let call = b.create_ebb_call(*ok, &[value]);
+                b.op_jump(call);
+            },
+        }
+    }
+
+    fn exception_call(&self, b: &mut FunctionBuilder, value: Value) -> ::eir::EbbCall {
+        match self {
+            Return::Tail => unreachable!(),
+            Return::Edges { exc_target, exc_args, err, .. } => {
+                let args: Vec<Value> = exc_args.iter()
+                    .map(|a| if a == err { value } else { *a })
+                    .collect();
+                b.create_ebb_call(*exc_target, &args)
+            },
+        }
+    }
+
+    fn return_throw(&self, b: &mut FunctionBuilder, value: Value) {
+        match self {
+            Return::Tail => b.op_return_throw(value),
+            Return::Edges { .. } => {
+                let call = self.exception_call(b, value);
+                b.op_jump(call);
+            },
+        }
+    }
+
+}
+
+/// Copies an op of the callee into the caller, with returns and tail
+/// calls going to `ret`.
+fn clone_op(cloner: &mut FunctionCloner, b: &mut FunctionBuilder, op: Op,
+            ret: &Return, captures: Option<&Vec<Value>>) {
+    let callee = cloner.src();
+    let kind = callee.op_kind(op);
+
+    match (kind, ret) {
+        (OpKind::ReturnOk, _) => {
+            let value = cloner.map_value(b, callee.op_reads(op)[0]);
+            ret.return_ok(b, value);
+        },
+        (OpKind::ReturnThrow, _) => {
+            let value = cloner.map_value(b, callee.op_reads(op)[0]);
+            ret.return_throw(b, value);
+        },
+
+        // A tail call in the callee is a regular call in the caller
+        (OpKind::Call { tail_call: true }, Return::Edges { .. }) => {
+            let reads = cloner.map_values(b, callee.op_reads(op));
+            let (ok, err) = b.op_call(reads[0], reads[1], &reads[2..]);
+            let exc = ret.exception_call(b, err);
+            b.add_op_ebb_call(exc);
+            ret.return_ok(b, ok);
+        },
+        (OpKind::Apply { tail_call: true }, Return::Edges { .. }) => {
+            let reads = cloner.map_values(b, callee.op_reads(op));
+            let (ok, err) = b.op_apply(reads[0], &reads[1..]);
+            let exc = ret.exception_call(b, err);
+            b.add_op_ebb_call(exc);
+            ret.return_ok(b, ok);
+        },
+
+        // The env is built in the caller, use the captured values
+        // directly.
+        (OpKind::UnpackEnv, _) if captures.is_some() => {
+            let captures = captures.unwrap();
+            let writes = callee.op_writes(op);
+            assert!(writes.len() == captures.len());
+            for (write, capture) in writes.iter().zip(captures.iter()) {
+                cloner.insert_value(*write, *capture);
+            }
+        },
+
+        _ => cloner.clone_op(b, op),
+    }
+}
+
+/// Replaces the call with a copy of the body of the callee.
+fn inline_call(b: &mut FunctionBuilder, site: &CallSite, callee: &Function) {
+    let ebb = b.function().op_ebb(site.op);
+
+    let ret = match b.function().op_kind(site.op) {
+        OpKind::Apply { tail_call: true } => Return::Tail,
+        _ => {
+            let (ok, err, exc_target, exc_args) = {
+                let fun = b.function();
+                let writes = fun.op_writes(site.op);
+                let exc = fun.op_branches(site.op)[0];
+                (writes[0], writes[1], fun.ebb_call_target(exc),
+                 fun.ebb_call_args(exc).to_vec())
+            };
+
+            // The result is passed to the ops after the call
+            b.position_after(site.op);
+            let cont = b.ebb_split();
+            let result = b.add_ebb_argument(cont);
+            b.replace_all_uses(ok, result);
+
+            Return::Edges {
+                ok: cont,
+                exc_target: exc_target,
+                exc_args: exc_args,
+                err: err,
+            }
+        },
+    };
+
+    b.position_after(site.op);
+    b.remove_op(site.op);
+
+    let mut cloner = FunctionCloner::new(callee);
+
+    // The entry arguments are the call arguments
+    let entry = callee.ebb_entry();
+    for (param, arg) in callee.ebb_args(entry).iter().zip(site.args.iter()) {
+        cloner.insert_value(*param, *arg);
+    }
+
+    b.position_at_end(ebb);
+    let new_entry = b.insert_ebb();
+    cloner.insert_ebb(entry, new_entry);
+    let call = b.create_ebb_call(new_entry, &[]);
+    b.op_jump(call);
+
+    for src in reverse_postorder(callee) {
+        let dst = cloner.map_ebb(b, src);
+        b.position_at_end(dst);
+        for op in callee.iter_op(src) {
+            clone_op(&mut cloner, b, op, &ret, site.captures.as_ref());
+        }
+    }
+    cloner.finish();
+}
+
+fn inline_into(module: &mut Module, ident: &FunctionIdent,
+               recursive: &HashSet<FunctionIdent>,
+               options: &InlineOptions) -> bool {
+    let mut caller = module.functions.remove(ident).unwrap();
+    let ops: Vec<Op> = call_sites(&caller).iter().map(|s| s.op).collect();
+    let mut size = function_size(&caller);
+    let mut changed = false;
+
+    {
+        let mut b = FunctionBuilder::new(&mut caller);
+        for op in ops {
+            // Inlining replaces the results of earlier calls, look at
+            // the call again.
+            let site = {
+                let fun = b.function();
+                match call_site(fun, &fun.def_use(), op) {
+                    Some(site) => site,
+                    None => continue,
+                }
+            };
+
+            if recursive.contains(&site.callee) { continue; }
+            let callee = match module.functions.get(&site.callee) {
+                Some(callee) => callee,
+                None => continue,
+            };
+            if callee.ebb_args(callee.ebb_entry()).len() != site.args.len() {
+                continue;
+            }
+
+            let callee_size = function_size(callee);
+            if callee_size > options.max_callee_size
+                || size + callee_size > options.max_caller_size {
+                continue;
+            }
+
+            inline_call(&mut b, &site, callee);
+            size += callee_size;
+            changed = true;
+        }
+    }
+
+    module.functions.insert(ident.clone(), caller);
+    changed
+}
+
+/// Inlines calls to small functions and closures of the module.
+///
+/// Only `Apply`s of a function captured or bound as a closure in the
+/// same function are considered, remote calls are never inlined. The
+/// size of a function is the number of ops in it, see `InlineOptions`.
+///
+/// Functions are inlined into in an order where callees come before
+/// their callers, so a callee has already had its own calls inlined.
+/// Functions which are part of a recursion cycle are never inlined.
+///
+/// Returns the functions that were changed. The inlined code is not
+/// optimized, the cleanup pipeline should be run on these.
+pub fn inline_module(module: &mut Module, options: &InlineOptions) -> Vec<FunctionIdent> {
+    let mut idents: Vec<FunctionIdent> = module.functions.keys().cloned().collect();
+    idents.sort_by_key(|ident| ident.to_string());
+
+    let mut graph: Graph<FunctionIdent, ()> = Graph::new();
+    let mut nodes = HashMap::new();
+    for ident in idents.iter() {
+        nodes.insert(ident.clone(), graph.add_node(ident.clone()));
+    }
+    for ident in idents.iter() {
+        for site in call_sites(&module.functions[ident]) {
+            if let Some(callee) = nodes.get(&site.callee) {
+                graph.add_edge(nodes[ident], *callee, ());
+            }
+        }
+    }
+
+    // Strongly connected components, callees before callers
+    let sccs = tarjan_scc(&graph);
+
+    let mut recursive = HashSet::new();
+    for scc in sccs.iter() {
+        if scc.len() > 1 || graph.find_edge(scc[0], scc[0]).is_some() {
+            for node in scc.iter() {
+                recursive.insert(graph[*node].clone());
+            }
+        }
+    }
+
+    let mut changed = Vec::new();
+    for scc in sccs.iter() {
+        for node in scc.iter() {
+            let ident = graph[*node].clone();
+            if inline_into(module, &ident, &recursive, options) {
+                changed.push(ident);
+            }
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use super::{ inline_module, InlineOptions };
+    use ::eir::{ Module, ModuleEnvs, Function, FunctionBuilder, FunctionIdent };
+    use ::eir::Atom;
+    use ::eir::op::OpKind;
+
+    fn ident(name: &str, arity: usize) -> FunctionIdent {
+        FunctionIdent {
+            module: Atom::from_str("test"),
+            name: Atom::from_str(name),
+            arity: arity,
+            lambda: None,
+        }
+    }
+
+    /// `fun(X) -> X.`
+    fn identity() -> Function {
+        let mut fun = Function::new(ident("identity", 1));
+        {
+            let mut b = FunctionBuilder::new(&mut fun);
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+            b.op_return_ok(arg);
+        }
+        fun
+    }
+
+    /// `fun(X) -> Name(X).`, applying a captured function
+    fn caller(name: &str, callee: FunctionIdent) -> Function {
+        let mut fun = Function::new(ident(name, 1));
+        {
+            let mut b = FunctionBuilder::new(&mut fun);
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+
+            let exc = b.insert_ebb();
+            let exc_arg = b.add_ebb_argument(exc);
+            let ret = b.insert_ebb();
+            let ret_arg = b.add_ebb_argument(ret);
+
+            b.position_at_end(entry);
+            let captured = b.op_capture_named_function(callee);
+            let (ok, err) = b.op_apply(captured, &[arg]);
+            let call = b.create_ebb_call(exc, &[err]);
+            b.add_op_ebb_call(call);
+            let call = b.create_ebb_call(ret, &[ok]);
+            b.op_jump(call);
+
+            b.position_at_end(exc);
+            b.op_return_throw(exc_arg);
+            b.position_at_end(ret);
+            b.op_return_ok(ret_arg);
+        }
+        fun
+    }
+
+    fn module(functions: Vec<Function>) -> Module {
+        let mut map = HashMap::new();
+        for fun in functions {
+            map.insert(fun.ident().clone(), fun);
+        }
+        Module {
+            name: Atom::from_str("test"),
+            envs: ModuleEnvs::new(),
+            functions: map,
+        }
+    }
+
+    fn count_applies(fun: &Function) -> usize {
+        fun.iter_ebb()
+            .flat_map(|ebb| fun.iter_op(ebb))
+            .filter(|op| match fun.op_kind(*op) {
+                OpKind::Apply { .. } => true,
+                _ => false,
+            })
+            .count()
+    }
+
+    #[test]
+    fn inline_small_function() {
+        let mut module = module(vec![
+            identity(),
+            caller("caller", ident("identity", 1)),
+        ]);
+
+        let changed = inline_module(&mut module, &InlineOptions::default());
+        assert!(changed == vec![ident("caller", 1)]);
+
+        let fun = &module.functions[&ident("caller", 1)];
+        assert!(count_applies(fun) == 0);
+        fun.validate();
+    }
+
+    #[test]
+    fn skip_recursion() {
+        let mut module = module(vec![
+            caller("a", ident("b", 1)),
+            caller("b", ident("a", 1)),
+            caller("c", ident("c", 1)),
+        ]);
+
+        let changed = inline_module(&mut module, &InlineOptions::default());
+        assert!(changed.len() == 0);
+    }
+
+    #[test]
+    fn respect_size_limit() {
+        let mut module = module(vec![
+            identity(),
+            caller("caller", ident("identity", 1)),
+        ]);
+
+        let options = InlineOptions {
+            max_callee_size: 0,
+            ..InlineOptions::default()
+        };
+        assert!(inline_module(&mut module, &options).len() == 0);
+        let fun = &module.functions[&ident("caller", 1)];
+        assert!(count_applies(fun) == 1);
+    }
+
+    #[test]
+    fn inline_is_opt_in() {
+        let text = "module 'test' ['f'/1, 'g'/1] attributes []
+'f'/1 =
+    fun (_0) ->
+\tlet <_1> = apply 'g'/1(_0)
+\tin {_1}
+'g'/1 =
+    fun (_0) ->
+\t{_0}
+end
+";
+        let parsed = ::parser::parse(text).unwrap();
+
+        let module = ::ir::from_parsed(&parsed.0);
+        assert!(count_applies(&module.functions[&ident("f", 1)]) == 1);
+
+        let options = ::ir::lir::pass::PassOptions {
+            validate: true,
+            inline: Some(InlineOptions::default()),
+            ..Default::default()
+        };
+        let module = ::ir::from_parsed_with_options(&parsed.0, options);
+        assert!(count_applies(&module.functions[&ident("f", 1)]) == 0);
+    }
+
+}
@@ -0,0 +1,499 @@
+use std::collections::HashMap;
+use std::time::{ Duration, Instant };
+
+use ::petgraph::algo::dominators::{ simple_fast, Dominators };
+use ::petgraph::graph::NodeIndex;
+
+use ::eir::{ Function, FunctionBuilder, FunctionCfg };
+use ::eir::fun::live::{ calculate_live_values, LiveValues };
+use ::eir::fun::def_use::{ calculate_def_use, DefUse };
+
+use super::InlineOptions;
+
+/// A pass over a single function.
+///
+/// Returns true if the function was changed. When a pass reports a
+/// change, every cached analysis is thrown away.
+pub type PassFn = fn(&mut FunctionBuilder, &mut Analyses) -> bool;
+
+/// Analyses of a function, calculated on demand and cached until a pass
+/// changes the function.
+#[derive(Default)]
+pub struct Analyses {
+    cfg: Option<FunctionCfg>,
+    dominators: Option<Dominators<NodeIndex>>,
+    live: Option<LiveValues>,
+    def_use: Option<DefUse>,
+}
+
+impl Analyses {
+
+    pub fn new() -> Self {
+        Analyses::default()
+    }
+
+    pub fn cfg(&mut self, fun: &Function) -> &FunctionCfg {
+        if self.cfg.is_none() {
+            self.cfg = Some(fun.gen_cfg());
+        }
+        self.cfg.as_ref().unwrap()
+    }
+
+    /// The cfg together with its dominator tree. The node indices in
+    /// the dominator tree refer to the returned cfg.
+    pub fn cfg_dominators(&mut self, fun: &Function)
+                          -> (&FunctionCfg, &Dominators<NodeIndex>) {
+        if self.dominators.is_none() {
+            let doms = {
+                let cfg = self.cfg(fun);
+                simple_fast(&cfg.graph, cfg.ebbs[&fun.ebb_entry()])
+            };
+            self.dominators = Some(doms);
+        }
+        (self.cfg.as_ref().unwrap(), self.dominators.as_ref().unwrap())
+    }
+
+    pub fn dominators(&mut self, fun: &Function) -> &Dominators<NodeIndex> {
+        self.cfg_dominators(fun).1
+    }
+
+    /// Live values at every point in the function.
+    /// Orphan blocks must have been removed before this is called.
+    pub fn live_values(&mut self, fun: &Function) -> &LiveValues {
+        if self.live.is_none() {
+            self.live = Some(calculate_live_values(fun));
+        }
+        self.live.as_ref().unwrap()
+    }
+
+    pub fn def_use(&mut self, fun: &Function) -> &DefUse {
+        if self.def_use.is_none() {
+            self.def_use = Some(calculate_def_use(fun));
+        }
+        self.def_use.as_ref().unwrap()
+    }
+
+    pub fn invalidate(&mut self) {
+        self.cfg = None;
+        self.dominators = None;
+        self.live = None;
+        self.def_use = None;
+    }
+
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PassOptions {
+    /// Run `Function::validate` after every pass.
+    pub validate: bool,
+    /// Record the time spent in each pass.
+    pub time: bool,
+    /// Print the function as Eir text before and after every run of
+    /// the pass with this name.
+    pub dump: Option<String>,
+    /// Inline small functions of the module, see `inline_module`. Off
+    /// unless set.
+    pub inline: Option<InlineOptions>,
+    /// Add the optimization passes to the pipelines, see
+    /// `lowering_pipeline`. Off unless set.
+    pub optimize: bool,
+}
+
+/// Runs a pipeline of named passes over functions.
+///
+/// Passes are registered under a name, and the pipeline is built by
+/// adding registered names in the order they should run. The same pass
+/// may appear several times in the pipeline.
+pub struct PassManager {
+    options: PassOptions,
+    registry: HashMap<String, PassFn>,
+    pipeline: Vec<String>,
+    timings: Vec<(String, Duration)>,
+}
+
+impl PassManager {
+
+    pub fn new(options: PassOptions) -> Self {
+        PassManager {
+            options: options,
+            registry: HashMap::new(),
+            pipeline: Vec::new(),
+            timings: Vec::new(),
+        }
+    }
+
+    /// Creates a manager with the standard passes registered, but
+    /// with an empty pipeline.
+    pub fn with_standard_passes(options: PassOptions) -> Self {
+        let mut manager = PassManager::new(options);
+        manager.register("remove_orphan_blocks", remove_orphan_blocks_pass);
+        manager.register("compile_pattern", compile_pattern_pass);
+        manager.register("propagate_atomics", propagate_atomics_pass);
+        manager.register("simplify_branches", simplify_branches_pass);
+        manager.register("promote_primops", promote_primops_pass);
+        manager.register("promote_tail_calls", promote_tail_calls_pass);
+        manager.register("tail_calls_to_loops", tail_calls_to_loops_pass);
+        manager.register("propagate_copies", propagate_copies_pass);
+        manager.register("remove_dead_code", remove_dead_code_pass);
+        manager
+    }
+
+    /// The pipeline used when lowering a module. Orphan blocks are
+    /// removed, patterns compiled and the branches left behind
+    /// simplified.
+    ///
+    /// With `PassOptions::optimize`, atomics and copies are propagated,
+    /// tail calls promoted and turned into loops and dead code removed
+    /// as well.
+    ///
+    /// `promote_primops` is registered but not part of this pipeline or
+    /// `cleanup_pipeline`. It is IR-only, no backend runs the promoted
+    /// ops yet. A backend that does can add it after `simplify_branches`.
+    pub fn lowering_pipeline(options: PassOptions) -> Self {
+        let optimize = options.optimize;
+        let mut manager = PassManager::with_standard_passes(options);
+        manager.add_pass("remove_orphan_blocks");
+        manager.add_pass("compile_pattern");
+        manager.add_cleanup_passes(optimize);
+        manager
+    }
+
+    /// The pipeline run on functions which had other functions inlined
+    /// into them. Patterns are already compiled at this point. Takes
+    /// `PassOptions::optimize` the same way as `lowering_pipeline`.
+    pub fn cleanup_pipeline(options: PassOptions) -> Self {
+        let optimize = options.optimize;
+        let mut manager = PassManager::with_standard_passes(options);
+        manager.add_cleanup_passes(optimize);
+        manager
+    }
+
+    fn add_cleanup_passes(&mut self, optimize: bool) {
+        if optimize {
+            self.add_pass("propagate_atomics");
+        }
+        self.add_pass("simplify_branches");
+        if optimize {
+            self.add_pass("promote_tail_calls");
+            self.add_pass("tail_calls_to_loops");
+        }
+        self.add_pass("remove_orphan_blocks");
+        if optimize {
+            self.add_pass("propagate_copies");
+            self.add_pass("remove_dead_code");
+        }
+    }
+
+    pub fn options(&self) -> &PassOptions {
+        &self.options
+    }
+
+    /// Registers a pass under the given name. Registering a name a
+    /// second time replaces the previous pass.
+    pub fn register(&mut self, name: &str, pass: PassFn) {
+        self.registry.insert(name.to_string(), pass);
+    }
+
+    /// Appends the named pass to the pipeline.
+    pub fn add_pass(&mut self, name: &str) {
+        assert!(self.registry.contains_key(name), "unknown pass {}", name);
+        self.pipeline.push(name.to_string());
+    }
+
+    pub fn pipeline(&self) -> &[String] {
+        &self.pipeline
+    }
+
+    /// Runs the full pipeline over the function.
+    /// Returns true if any pass changed the function.
+    pub fn run(&mut self, b: &mut FunctionBuilder) -> bool {
+        let mut analyses = Analyses::new();
+        let mut changed = false;
+        for idx in 0..self.pipeline.len() {
+            let name = self.pipeline[idx].clone();
+            changed |= self.run_pass(&name, b, &mut analyses);
+        }
+        changed
+    }
+
+    /// Runs a single registered pass, honoring the dump, timing and
+    /// validation options.
+    pub fn run_pass(&mut self, name: &str, b: &mut FunctionBuilder,
+                    analyses: &mut Analyses) -> bool {
+        let pass = *self.registry.get(name)
+            .unwrap_or_else(|| panic!("unknown pass {}", name));
+        let dump = self.options.dump.as_ref().map(|d| d == name).unwrap_or(false);
+
+        if dump {
+            println!("DUMP: {} before {}", b.function().ident(), name);
+            println!("{}", b.function().to_text());
+        }
+
+        let start = Instant::now();
+        let changed = pass(b, analyses);
+        let elapsed = start.elapsed();
+
+        if changed {
+            analyses.invalidate();
+        }
+
+        if self.options.time {
+            match self.timings.iter().position(|t| t.0 == name) {
+                Some(idx) => self.timings[idx].1 += elapsed,
+                None => self.timings.push((name.to_string(), elapsed)),
+            }
+        }
+
+        if dump {
+            println!("DUMP: {} after {} (changed: {})",
+                     b.function().ident(), name, changed);
+            println!("{}", b.function().to_text());
+        }
+        if self.options.validate {
+            b.function().validate();
+        }
+        changed
+    }
+
+    /// Total time spent in each pass, in the order the passes first
+    /// ran. Only recorded when the `time` option is set.
+    pub fn timings(&self) -> &[(String, Duration)] {
+        &self.timings
+    }
+
+    pub fn print_timings(&self) {
+        for (name, duration) in self.timings.iter() {
+            println!("{:>24}: {:?}", name, duration);
+        }
+    }
+
+}
+
+fn remove_orphan_blocks_pass(b: &mut FunctionBuilder, _a: &mut Analyses) -> bool {
+    super::remove_orphan_blocks(b)
+}
+
+fn compile_pattern_pass(b: &mut FunctionBuilder, _a: &mut Analyses) -> bool {
+    super::compile_pattern(b)
+}
+
+fn propagate_atomics_pass(b: &mut FunctionBuilder, a: &mut Analyses) -> bool {
+    let def_use = a.def_use(b.function());
+    super::propagate_atomics_with_def_use(b, def_use)
+}
+
+fn simplify_branches_pass(b: &mut FunctionBuilder, _a: &mut Analyses) -> bool {
+    super::simplify_branches(b)
+}
+
+fn promote_primops_pass(b: &mut FunctionBuilder, a: &mut Analyses) -> bool {
+    let def_use = a.def_use(b.function());
+    super::promote_primops_with_def_use(b, def_use)
+}
+
+fn promote_tail_calls_pass(b: &mut FunctionBuilder, _a: &mut Analyses) -> bool {
+    super::promote_tail_calls(b)
+}
+
+fn tail_calls_to_loops_pass(b: &mut FunctionBuilder, a: &mut Analyses) -> bool {
+    let def_use = a.def_use(b.function());
+    super::tail_calls_to_loops_with_def_use(b, def_use)
+}
+
+fn propagate_copies_pass(b: &mut FunctionBuilder, _a: &mut Analyses) -> bool {
+    super::propagate_copies(b)
+}
+
+fn remove_dead_code_pass(b: &mut FunctionBuilder, a: &mut Analyses) -> bool {
+    let def_use = a.def_use(b.function());
+    super::remove_dead_code_with_def_use(b, def_use)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ PassManager, PassOptions, Analyses };
+    use ::eir::{ Function, FunctionBuilder, FunctionIdent, AtomicTerm };
+    use ::eir::op::OpKind;
+    use ::eir::Atom;
+
+    fn make_function() -> Function {
+        let ident = FunctionIdent {
+            module: Atom::from_str("test"),
+            name: Atom::from_str("fun"),
+            arity: 1,
+            lambda: None,
+        };
+        let mut fun = Function::new(ident);
+        {
+            let mut b = FunctionBuilder::new(&mut fun);
+
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+            b.op_return_ok(arg);
+
+            let orphan = b.insert_ebb();
+            b.position_at_end(orphan);
+            let ok = b.create_atomic(AtomicTerm::Atom(Atom::from_str("ok")));
+            b.op_return_ok(ok);
+        }
+        fun
+    }
+
+    /// Returns `erlang:hd(X)`, rethrowing its exception.
+    fn make_call_function() -> Function {
+        let ident = FunctionIdent {
+            module: Atom::from_str("test"),
+            name: Atom::from_str("fun"),
+            arity: 1,
+            lambda: None,
+        };
+        let mut fun = Function::new(ident);
+        {
+            let mut b = FunctionBuilder::new(&mut fun);
+
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+
+            let err_ebb = b.insert_ebb();
+            let err_arg = b.add_ebb_argument(err_ebb);
+
+            b.position_at_end(entry);
+            let module = b.create_atomic(AtomicTerm::Atom(Atom::from_str("erlang")));
+            let name = b.create_atomic(AtomicTerm::Atom(Atom::from_str("hd")));
+            let (ok, err) = b.op_call(module, name, &[arg]);
+            let call = b.create_ebb_call(err_ebb, &[err]);
+            b.add_op_ebb_call(call);
+            b.op_return_ok(ok);
+
+            b.position_at_end(err_ebb);
+            b.op_return_throw(err_arg);
+        }
+        fun
+    }
+
+    fn fill_analyses(b: &mut FunctionBuilder, a: &mut Analyses) -> bool {
+        a.dominators(b.function());
+        false
+    }
+
+    fn expect_cached(_b: &mut FunctionBuilder, a: &mut Analyses) -> bool {
+        assert!(a.cfg.is_some());
+        assert!(a.dominators.is_some());
+        true
+    }
+
+    fn expect_invalidated(_b: &mut FunctionBuilder, a: &mut Analyses) -> bool {
+        assert!(a.cfg.is_none());
+        assert!(a.dominators.is_none());
+        false
+    }
+
+    #[test]
+    fn runs_pipeline() {
+        let mut fun = make_function();
+        assert!(fun.iter_ebb().count() == 2);
+
+        let mut manager = PassManager::with_standard_passes(PassOptions {
+            validate: true,
+            ..PassOptions::default()
+        });
+        manager.add_pass("remove_orphan_blocks");
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(manager.run(&mut b));
+        assert!(b.function().iter_ebb().count() == 1);
+        assert!(!manager.run(&mut b));
+    }
+
+    #[test]
+    fn optimization_is_opt_in() {
+        let lowering = PassManager::lowering_pipeline(PassOptions::default());
+        assert!(lowering.pipeline() == &["remove_orphan_blocks", "compile_pattern",
+                                         "simplify_branches", "remove_orphan_blocks"]);
+
+        let options = PassOptions {
+            optimize: true,
+            ..PassOptions::default()
+        };
+        let lowering = PassManager::lowering_pipeline(options);
+        assert!(lowering.pipeline().iter().any(|p| p == "remove_dead_code"));
+    }
+
+    #[test]
+    fn reports_no_change() {
+        let mut fun = make_function();
+
+        let mut manager = PassManager::with_standard_passes(PassOptions::default());
+        manager.add_pass("compile_pattern");
+        manager.add_pass("promote_primops");
+        manager.add_pass("tail_calls_to_loops");
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(!manager.run(&mut b));
+    }
+
+    #[test]
+    fn primops_are_opt_in() {
+        let options = PassOptions::default();
+        let lowering = PassManager::lowering_pipeline(options.clone());
+        let cleanup = PassManager::cleanup_pipeline(options);
+        assert!(lowering.pipeline().iter().all(|p| p != "promote_primops"));
+        assert!(cleanup.pipeline().iter().all(|p| p != "promote_primops"));
+
+        let mut fun = make_call_function();
+        let mut manager = lowering;
+        manager.add_pass("promote_primops");
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(manager.run(&mut b));
+        let fun = b.function();
+        let op = fun.iter_op(fun.ebb_entry()).next().unwrap();
+        match fun.op_kind(op) {
+            OpKind::PrimOp(name) => assert!(name.as_str() == "hd"),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn invalidates_analyses() {
+        let mut fun = make_function();
+
+        let mut manager = PassManager::new(PassOptions::default());
+        manager.register("fill", fill_analyses);
+        manager.register("expect_cached", expect_cached);
+        manager.register("expect_invalidated", expect_invalidated);
+        manager.add_pass("fill");
+        manager.add_pass("expect_cached");
+        manager.add_pass("expect_invalidated");
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(manager.run(&mut b));
+    }
+
+    #[test]
+    fn records_timings() {
+        let mut fun = make_function();
+
+        let mut manager = PassManager::new(PassOptions {
+            time: true,
+            ..PassOptions::default()
+        });
+        manager.register("fill", fill_analyses);
+        manager.add_pass("fill");
+        manager.add_pass("fill");
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        manager.run(&mut b);
+        assert!(manager.timings().len() == 1);
+        assert!(manager.timings()[0].0 == "fill");
+    }
+
+    #[test]
+    #[should_panic]
+    fn unknown_pass() {
+        let mut manager = PassManager::new(PassOptions::default());
+        manager.add_pass("no_such_pass");
+    }
+
+}
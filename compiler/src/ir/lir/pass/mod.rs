@@ -1,5 +1,5 @@
-//mod propagate_atomics;
-//pub use self::propagate_atomics::propagate_atomics;
+pub mod propagate_atomics;
+pub use self::propagate_atomics::{ propagate_atomics, propagate_atomics_with_def_use };
 
 mod compile_pattern;
 pub use self::compile_pattern::compile_pattern;
@@ -10,8 +10,23 @@ pub use self::simplify_branches::simplify_branches;
 mod remove_orphan_blocks;
 pub use self::remove_orphan_blocks::remove_orphan_blocks;
 
-//mod promote_tail_calls;
-//pub use self::promote_tail_calls::promote_tail_calls;
-//
-//mod promote_primops;
-//pub use self::promote_primops::promote_primops;
+mod promote_tail_calls;
+pub use self::promote_tail_calls::promote_tail_calls;
+
+mod tail_calls_to_loops;
+pub use self::tail_calls_to_loops::{ tail_calls_to_loops, tail_calls_to_loops_with_def_use };
+
+mod promote_primops;
+pub use self::promote_primops::{ promote_primops, promote_primops_with_def_use };
+
+mod propagate_copies;
+pub use self::propagate_copies::propagate_copies;
+
+mod remove_dead_code;
+pub use self::remove_dead_code::{ remove_dead_code, remove_dead_code_with_def_use };
+
+mod inline;
+pub use self::inline::{ inline_module, InlineOptions };
+
+mod manager;
+pub use self::manager::{ PassManager, PassOptions, PassFn, Analyses };
@@ -1,9 +1,225 @@
-use ::eir::cfg::{ FunctionCfg, EdgeN, LabelN };
+use ::eir::{ Function, FunctionBuilder, Op, Value, Atom };
+use ::eir::{ ConstantTerm, AtomicTerm };
+use ::eir::op::{ OpKind, BifPrimOp, PrimOpFailure, bif_primop };
+use ::eir::fun::{ DefUse, ValueUse };
 
-pub fn promote_primops(cfg: &mut FunctionCfg) {
+fn constant_atom(fun: &Function, value: Value) -> Option<&Atom> {
+    if !fun.value_is_constant(value) {
+        return None;
+    }
+    match fun.value_constant(value) {
+        ConstantTerm::Atomic(AtomicTerm::Atom(atom)) => Some(atom),
+        _ => None,
+    }
+}
+
+/// The primop a call can be replaced with, if any.
+fn call_primop(fun: &Function, def_use: &DefUse, op: Op) -> Option<&'static BifPrimOp> {
+    match fun.op_kind(op) {
+        OpKind::Call { tail_call: false } => (),
+        _ => return None,
+    }
+    if fun.op_writes(op).len() != 2 || fun.op_branches(op).len() != 1 {
+        return None;
+    }
+
+    let reads = fun.op_reads(op);
+    let module = constant_atom(fun, reads[0])?;
+    let name = constant_atom(fun, reads[1])?;
+    if module.as_str() != "erlang" {
+        return None;
+    }
+    let primop = bif_primop(name.as_str(), reads.len() - 2)?;
+
+    // A primop which can't fail has no exception value. The exception
+    // of the call may only have been passed along the exception edge.
+    if primop.failure == PrimOpFailure::Never {
+        let err = fun.op_writes(op)[1];
+        let escapes = def_use.value_uses(err).iter().any(|u| match u {
+            ValueUse::EbbCall(call, _) => fun.ebb_call_source(*call) != op,
+            ValueUse::Op(_, _) => true,
+        });
+        if escapes {
+            return None;
+        }
+    }
+
+    Some(primop)
+}
+
+fn promote(b: &mut FunctionBuilder, op: Op, primop: &BifPrimOp) {
+    let (args, branch) = {
+        let fun = b.function();
+        (fun.op_reads(op)[2..].to_vec(), fun.op_branches(op)[0])
+    };
+
+    let line = b.function().op_line(op);
+    let mut writes = Vec::new();
+    b.position_after(op);
+    b.remove_op_take_writes(op, &mut writes);
+    let mut writes = writes.into_iter();
+    let ok = writes.next().unwrap();
+    let err = writes.next().unwrap();
+
+    b.set_source_line(line);
+    b.op_build_start(OpKind::PrimOp(Atom::from_str(primop.name)));
+    for arg in args {
+        b.op_build_read(arg);
+    }
+    b.op_build_write_token(ok);
+    if primop.failure != PrimOpFailure::Never {
+        b.op_build_write_token(err);
+        b.op_build_ebb_call(branch);
+    }
+    b.op_build_end();
+    b.set_source_line(None);
+}
+
+/// Replaces calls to pure BIFs in the `erlang` module with `PrimOp`s.
+///
+/// Only calls where both the module and the function name are constant
+/// atoms are promoted. See `eir::op::BIF_PRIMOPS` for the shape of the
+/// resulting ops. Calls to BIFs that can never fail lose their exception
+/// edge, and handlers that are no longer reachable are removed.
+/// Tail calls are left alone.
+///
+/// This pass is IR-only. The resulting ops are understood by the IR
+/// passes and the printer, but no backend, the interpreter included,
+/// executes a `PrimOp`. This is why it is not part of the standard
+/// pipelines.
+///
+/// Returns true if any call was promoted.
+pub fn promote_primops(b: &mut FunctionBuilder) -> bool {
+    let def_use = b.function().def_use();
+    promote_primops_with_def_use(b, &def_use)
+}
+
+/// `promote_primops` with the def-use information of the function as
+/// it is before the pass.
+pub fn promote_primops_with_def_use(b: &mut FunctionBuilder, def_use: &DefUse) -> bool {
+    let candidates: Vec<(Op, &'static BifPrimOp)> = {
+        let fun = b.function();
+        let mut candidates = Vec::new();
+        for ebb in fun.iter_ebb() {
+            for op in fun.iter_op(ebb) {
+                if let Some(primop) = call_primop(fun, def_use, op) {
+                    candidates.push((op, primop));
+                }
+            }
+        }
+        candidates
+    };
+
+    let mut removed_edge = false;
+    for (op, primop) in candidates.iter() {
+        removed_edge |= primop.failure == PrimOpFailure::Never;
+        promote(b, *op, primop);
+    }
+
+    if removed_edge {
+        super::remove_orphan_blocks(b);
+    }
+    candidates.len() > 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::promote_primops;
+    use ::eir::{ Function, FunctionBuilder, FunctionIdent, AtomicTerm, Value };
+    use ::eir::op::OpKind;
+    use ::eir::Atom;
+
+    fn new_function() -> Function {
+        let ident = FunctionIdent {
+            module: Atom::from_str("test"),
+            name: Atom::from_str("fun"),
+            arity: 1,
+            lambda: None,
+        };
+        Function::new(ident)
+    }
+
+    fn atom(b: &mut FunctionBuilder, name: &str) -> Value {
+        b.create_atomic(AtomicTerm::Atom(Atom::from_str(name)))
+    }
+
+    /// Builds a function returning `module:name(arg)`, with the
+    /// exception edge going to a handler that rethrows.
+    fn build(fun: &mut Function, module: &str, name: &str) {
+        let mut b = FunctionBuilder::new(fun);
+
+        let entry = b.insert_ebb_entry();
+        let arg = b.add_ebb_argument(entry);
+        b.position_at_end(entry);
+
+        let err_ebb = b.insert_ebb();
+        let err_arg = b.add_ebb_argument(err_ebb);
+
+        b.position_at_end(entry);
+        let module = atom(&mut b, module);
+        let name = atom(&mut b, name);
+        let (ok, err) = b.op_call(module, name, &[arg]);
+        let call = b.create_ebb_call(err_ebb, &[err]);
+        b.add_op_ebb_call(call);
+        b.op_return_ok(ok);
+
+        b.position_at_end(err_ebb);
+        b.op_return_throw(err_arg);
+    }
+
+    fn first_op_kind(fun: &Function) -> OpKind {
+        let entry = fun.ebb_entry();
+        let op = fun.iter_op(entry).next().unwrap();
+        fun.op_kind(op).clone()
+    }
+
+    #[test]
+    fn promote_infallible() {
+        let mut fun = new_function();
+        build(&mut fun, "erlang", "is_list");
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(promote_primops(&mut b));
+
+        let fun = b.function();
+        match first_op_kind(fun) {
+            OpKind::PrimOp(name) => assert!(name.as_str() == "is_list"),
+            _ => panic!(),
+        }
+        let op = fun.iter_op(fun.ebb_entry()).next().unwrap();
+        assert!(fun.op_writes(op).len() == 1);
+        assert!(fun.op_branches(op).len() == 0);
+        assert!(fun.op_kind(op).num_jumps() == None);
+        assert!(fun.iter_ebb().count() == 1);
+    }
+
+    #[test]
+    fn promote_fallible() {
+        let mut fun = new_function();
+        build(&mut fun, "erlang", "hd");
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(promote_primops(&mut b));
+
+        let fun = b.function();
+        let op = fun.iter_op(fun.ebb_entry()).next().unwrap();
+        assert!(fun.op_writes(op).len() == 2);
+        assert!(fun.op_branches(op).len() == 1);
+        assert!(fun.op_kind(op).num_jumps() == Some(2));
+        assert!(fun.iter_ebb().count() == 2);
+    }
+
+    #[test]
+    fn keep_unknown_call() {
+        let mut fun = new_function();
+        build(&mut fun, "lists", "reverse");
 
-    //for node in cfg.nodes() {
-    //    let node_inner = node.inner.borrow_mut();
-    //}
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(!promote_primops(&mut b));
+        match first_op_kind(b.function()) {
+            OpKind::Call { tail_call: false } => (),
+            _ => panic!(),
+        }
+    }
 
 }
@@ -1,110 +1,260 @@
-use ::eir::{ SSAVariable, Source };
-use ::eir::cfg::{ FunctionCfg, EdgeN, LabelN };
-use ::eir::op::{ OpKind };
+use ::eir::{ Function, FunctionBuilder, Op, Ebb, EbbCall, Value };
+use ::eir::op::OpKind;
 use std::collections::HashSet;
 
-fn does_return_val(cfg: &FunctionCfg, in_edge: EdgeN, val: SSAVariable) -> bool {
-    let label = cfg.graph.edge_to(in_edge);
-    let block_container = &cfg.graph[label];
-    let block = block_container.inner.borrow();
-    assert!(block.ops.len() > 0);
-    let last = block.ops.last().unwrap();
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Return {
+    Ok,
+    Throw,
+}
 
-    let mut val = val;
+/// Follows a value forward through moves and jumps, checking that it
+/// ends up returned unchanged from the function.
+struct Forward<'a> {
+    fun: &'a Function,
+    kind: Return,
+    aliases: HashSet<Value>,
+    visited: HashSet<Ebb>,
+}
 
-    // Follow potential phi node
-    if block.phi_nodes.len() > 0 {
-        if block.phi_nodes.len() != 1 {
-            return false;
+impl<'a> Forward<'a> {
+
+    fn new(fun: &'a Function, kind: Return, value: Value) -> Self {
+        let mut aliases = HashSet::new();
+        aliases.insert(value);
+        Forward {
+            fun: fun,
+            kind: kind,
+            aliases: aliases,
+            visited: HashSet::new(),
         }
-        let phi = &block.phi_nodes[0];
-        let entry = phi.entries.iter()
-            .find(|(l, _s)| *l == in_edge).unwrap();
-        if entry.1 == val {
-            val = phi.ssa;
-        } else {
+    }
+
+    fn follow_edge(&mut self, call: EbbCall) -> bool {
+        let fun = self.fun;
+        let target = fun.ebb_call_target(call);
+        if !self.visited.insert(target) {
             return false;
         }
+
+        let args = fun.ebb_call_args(call);
+        let params = fun.ebb_args(target);
+        assert!(args.len() == params.len());
+        for (arg, param) in args.iter().zip(params.iter()) {
+            if self.aliases.contains(arg) {
+                self.aliases.insert(*param);
+            }
+        }
+
+        match fun.iter_op(target).next() {
+            Some(first) => self.follow_ops(first),
+            None => false,
+        }
     }
 
-    // Validate ops are not logic
-    for op in block.ops.iter() {
-        if op.kind.is_logic() {
-            return false;
+    fn follow_ops(&mut self, first: Op) -> bool {
+        let fun = self.fun;
+        let mut curr = Some(first);
+        while let Some(op) = curr {
+            match fun.op_kind(op) {
+                OpKind::Move => {
+                    if self.aliases.contains(&fun.op_reads(op)[0]) {
+                        self.aliases.insert(fun.op_writes(op)[0]);
+                    }
+                },
+                OpKind::TombstoneSSA(_) => (),
+                OpKind::ReturnOk =>
+                    return self.kind == Return::Ok
+                    && self.aliases.contains(&fun.op_reads(op)[0]),
+                OpKind::ReturnThrow =>
+                    return self.kind == Return::Throw
+                    && self.aliases.contains(&fun.op_reads(op)[0]),
+                OpKind::Jump =>
+                    return self.follow_edge(fun.op_branches(op)[0]),
+                _ => return false,
+            }
+            curr = fun.op_after(op);
+        }
+        false
+    }
+
+}
+
+/// A call is in tail position if its result is returned directly, and
+/// any exception it throws is rethrown without being caught.
+fn is_tail_position(fun: &Function, op: Op) -> bool {
+    let writes = fun.op_writes(op);
+    let branches = fun.op_branches(op);
+    if writes.len() != 2 || branches.len() != 1 {
+        return false;
+    }
+
+    let returns_ok = match fun.op_after(op) {
+        Some(next) => Forward::new(fun, Return::Ok, writes[0]).follow_ops(next),
+        None => false,
+    };
+    returns_ok && Forward::new(fun, Return::Throw, writes[1])
+        .follow_edge(branches[0])
+}
+
+fn promote(b: &mut FunctionBuilder, op: Op) {
+    let (kind, reads, rest) = {
+        let fun = b.function();
+        let mut rest = Vec::new();
+        let mut curr = fun.op_after(op);
+        while let Some(next) = curr {
+            rest.push(next);
+            curr = fun.op_after(next);
         }
+        (fun.op_kind(op).clone(), fun.op_reads(op).to_vec(), rest)
+    };
+    let line = b.function().op_line(op);
+
+    b.position_after(op);
+    for next in rest {
+        b.remove_op(next);
     }
+    b.remove_op(op);
 
-    match last.kind {
-        OpKind::Jump => does_return_val(
-            cfg, block_container.outgoing[0].0, val),
-        OpKind::ReturnOk => last.reads[0] == Source::Variable(val),
-        OpKind::ReturnThrow => last.reads[0] == Source::Variable(val),
-        _ => false,
+    b.set_source_line(line);
+    match kind {
+        OpKind::Call { tail_call: false } =>
+            b.op_tail_call(reads[0], reads[1], &reads[2..]),
+        OpKind::Apply { tail_call: false } =>
+            b.op_tail_apply(reads[0], &reads[1..]),
+        _ => unreachable!(),
     }
+    b.set_source_line(None);
 }
 
-/// Promotes function calls at the end of control flow to tail calls.
+/// Turns calls whose result is returned unchanged into tail calls.
 ///
-/// Requires `propagate_atomics` to be run before.
-pub fn promote_tail_calls(cfg: &mut FunctionCfg) {
-    let mut calls = HashSet::new();
-
-    // 1. Identify all basic blocks which end in function calls
-    for block_container in cfg.graph.nodes() {
-        let block = block_container.inner.borrow();
-        let last_op = block.ops.last().unwrap();
-        match last_op.kind {
-            OpKind::Apply { .. } => {
-                calls.insert(block_container.label);
-            }
-            OpKind::Call { .. } => {
-                calls.insert(block_container.label);
+/// The ok path may pass through moves and jumps before reaching a
+/// `ReturnOk` of the call result, and the exception edge must reach a
+/// `ReturnThrow` of the exception in the same way. Blocks that are no
+/// longer reachable afterwards are removed.
+/// Returns true if any call was promoted.
+pub fn promote_tail_calls(b: &mut FunctionBuilder) -> bool {
+    let candidates: Vec<Op> = {
+        let fun = b.function();
+        let mut candidates = Vec::new();
+        for ebb in fun.iter_ebb() {
+            for op in fun.iter_op(ebb) {
+                match fun.op_kind(op) {
+                    OpKind::Call { tail_call: false } |
+                    OpKind::Apply { tail_call: false } => {
+                        if is_tail_position(fun, op) {
+                            candidates.push(op);
+                        }
+                    },
+                    _ => (),
+                }
             }
-            _ => (),
         }
+        candidates
+    };
+
+    // Only moves and jumps follow a call in tail position, so promoting
+    // one candidate never removes another.
+    for op in candidates.iter() {
+        promote(b, *op);
+    }
+
+    if candidates.len() > 0 {
+        super::remove_orphan_blocks(b);
+    }
+    candidates.len() > 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::promote_tail_calls;
+    use ::eir::{ Function, FunctionBuilder, FunctionIdent, AtomicTerm };
+    use ::eir::op::OpKind;
+    use ::eir::Atom;
+
+    fn new_function() -> Function {
+        let ident = FunctionIdent {
+            module: Atom::from_str("test"),
+            name: Atom::from_str("fun"),
+            arity: 1,
+            lambda: None,
+        };
+        Function::new(ident)
     }
 
-    let mut tail_calls = HashSet::new();
+    fn atom(b: &mut FunctionBuilder, name: &str) -> ::eir::Value {
+        b.create_atomic(AtomicTerm::Atom(Atom::from_str(name)))
+    }
+
+    fn count_calls(fun: &Function, tail: bool) -> usize {
+        fun.iter_ebb()
+            .flat_map(|ebb| fun.iter_op(ebb).collect::<Vec<_>>())
+            .filter(|op| match fun.op_kind(*op) {
+                OpKind::Call { tail_call } => *tail_call == tail,
+                _ => false,
+            })
+            .count()
+    }
 
-    // 2. Identify tail calls by following function jumps until we
-    // either find control flow logic or hit ReturnOk and ReturnThrow.
-    for call_label in calls.iter() {
-        let call_block_container = &cfg.graph[*call_label];
-        let call_block = call_block_container.inner.borrow();
-        let last_op = call_block.ops.last().unwrap();
+    /// Builds a call whose result passes through a move and a jump
+    /// before it is returned. With `catch` set, the exception edge
+    /// returns a constant instead of rethrowing.
+    fn build(fun: &mut Function, catch: bool) {
+        let mut b = FunctionBuilder::new(fun);
 
-        let ok_jump_edge = call_block_container.outgoing[0].0;
-        let returns_ok = does_return_val(
-            cfg, ok_jump_edge, last_op.writes[0]);
+        let entry = b.insert_ebb_entry();
+        let arg = b.add_ebb_argument(entry);
+        b.position_at_end(entry);
 
-        let error_jump_edge = call_block_container.outgoing[1].0;
-        let returns_error = does_return_val(
-            cfg, error_jump_edge, last_op.writes[1]);
-        //println!("{} {}", returns_ok, returns_error);
+        let ok_ebb = b.insert_ebb();
+        let ok_arg = b.add_ebb_argument(ok_ebb);
+        let err_ebb = b.insert_ebb();
+        let err_arg = b.add_ebb_argument(err_ebb);
 
-        if returns_ok && returns_error {
-            tail_calls.insert(*call_label);
+        b.position_at_end(entry);
+        let module = atom(&mut b, "foo");
+        let name = atom(&mut b, "bar");
+        let (ok, err) = b.op_call(module, name, &[arg]);
+        let call = b.create_ebb_call(err_ebb, &[err]);
+        b.add_op_ebb_call(call);
+        let moved = b.op_move(ok);
+        let call = b.create_ebb_call(ok_ebb, &[moved]);
+        b.op_jump(call);
+
+        b.position_at_end(ok_ebb);
+        b.op_return_ok(ok_arg);
+
+        b.position_at_end(err_ebb);
+        if catch {
+            let caught = atom(&mut b, "caught");
+            b.op_return_ok(caught);
+        } else {
+            b.op_return_throw(err_arg);
         }
     }
 
-    // 3. Rewrite CFG so that the tail calls are actual terminators
-    for call_label in tail_calls.iter() {
-        let (ok_edge, err_edge) = {
-            let call_block_container = &cfg.graph[*call_label];
-            let mut call_block = call_block_container.inner.borrow_mut();
-            let last_op = call_block.ops.last_mut().unwrap();
-            last_op.writes = vec![];
-            match last_op.kind {
-                OpKind::Call { ref mut tail_call } => *tail_call = true,
-                OpKind::Apply { ref mut tail_call } => *tail_call = true,
-                _ => unreachable!(),
-            }
-            (call_block_container.outgoing[0].0, call_block_container.outgoing[1].0)
-        };
-        cfg.remove_edge(ok_edge);
-        cfg.remove_edge(err_edge);
+    #[test]
+    fn promotes_returned_call() {
+        let mut fun = new_function();
+        build(&mut fun, false);
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(promote_tail_calls(&mut b));
+        assert!(count_calls(b.function(), true) == 1);
+        assert!(count_calls(b.function(), false) == 0);
+        assert!(b.function().iter_ebb().count() == 1);
+        assert!(!promote_tail_calls(&mut b));
     }
 
-    //println!("Identified tail calls: {:?}", tail_calls);
+    #[test]
+    fn keeps_caught_call() {
+        let mut fun = new_function();
+        build(&mut fun, true);
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(!promote_tail_calls(&mut b));
+        assert!(count_calls(b.function(), false) == 1);
+    }
 
 }
@@ -0,0 +1,242 @@
+//! Compile time evaluation of constant terms.
+
+use std::cmp::Ordering;
+
+use ::num_bigint::BigInt;
+use ::num_traits::{ Zero, Signed };
+
+use ::eir::{ ConstantTerm, AtomicTerm };
+use ::eir::op::ComparisonOperation;
+use ::eir::Atom;
+
+/// A constant in the subset of terms that can be evaluated at compile
+/// time. Floats are not represented in `ConstantTerm`, so they are never
+/// folded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term {
+    Integer(BigInt),
+    Atom(Atom),
+    Nil,
+    Cons(Box<Term>, Box<Term>),
+}
+
+impl Term {
+
+    pub fn from_constant(constant: &ConstantTerm) -> Option<Term> {
+        match constant {
+            ConstantTerm::Atomic(atomic) => Term::from_atomic(atomic),
+            ConstantTerm::List(head, tail) => {
+                let mut acc = Term::from_constant(tail)?;
+                for elem in head.iter().rev() {
+                    acc = Term::Cons(Box::new(Term::from_constant(elem)?),
+                                     Box::new(acc));
+                }
+                Some(acc)
+            },
+        }
+    }
+
+    pub fn from_atomic(atomic: &AtomicTerm) -> Option<Term> {
+        match atomic {
+            AtomicTerm::Integer(int) => Some(Term::Integer(int.clone())),
+            AtomicTerm::Float => None,
+            AtomicTerm::Atom(atom) => Some(Term::Atom(atom.clone())),
+            AtomicTerm::Char(chr) => Some(Term::Integer(BigInt::from(*chr as u32))),
+            AtomicTerm::String(string) => {
+                let mut acc = Term::Nil;
+                for chr in string.chars().rev() {
+                    let head = Term::Integer(BigInt::from(chr as u32));
+                    acc = Term::Cons(Box::new(head), Box::new(acc));
+                }
+                Some(acc)
+            },
+            AtomicTerm::Nil => Some(Term::Nil),
+        }
+    }
+
+    pub fn to_constant(&self) -> ConstantTerm {
+        match self {
+            Term::Integer(int) => ConstantTerm::Atomic(AtomicTerm::Integer(int.clone())),
+            Term::Atom(atom) => ConstantTerm::Atomic(AtomicTerm::Atom(atom.clone())),
+            Term::Nil => ConstantTerm::Atomic(AtomicTerm::Nil),
+            Term::Cons(_, _) => {
+                let mut head = Vec::new();
+                let mut curr = self;
+                while let Term::Cons(elem, tail) = curr {
+                    head.push(elem.to_constant());
+                    curr = &**tail;
+                }
+                ConstantTerm::List(head, Box::new(curr.to_constant()))
+            },
+        }
+    }
+
+    pub fn boolean(value: bool) -> Term {
+        Term::Atom(Atom::from(if value { "true" } else { "false" }))
+    }
+
+    pub fn as_boolean(&self) -> Option<bool> {
+        match self {
+            Term::Atom(atom) if atom.as_str() == "true" => Some(true),
+            Term::Atom(atom) if atom.as_str() == "false" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Length of a proper list.
+    pub fn list_length(&self) -> Option<usize> {
+        let mut len = 0;
+        let mut curr = self;
+        loop {
+            match curr {
+                Term::Nil => return Some(len),
+                Term::Cons(_, tail) => {
+                    len += 1;
+                    curr = &**tail;
+                },
+                _ => return None,
+            }
+        }
+    }
+
+    /// Position in the Erlang term order,
+    /// number < atom < (tuple) < nil < list.
+    fn rank(&self) -> u8 {
+        match self {
+            Term::Integer(_) => 0,
+            Term::Atom(_) => 1,
+            Term::Nil => 3,
+            Term::Cons(_, _) => 4,
+        }
+    }
+
+    /// Compares two terms in the Erlang term order.
+    pub fn compare(&self, other: &Term) -> Ordering {
+        match (self, other) {
+            (Term::Integer(a), Term::Integer(b)) => a.cmp(b),
+            (Term::Atom(a), Term::Atom(b)) => a.as_str().cmp(b.as_str()),
+            (Term::Cons(h1, t1), Term::Cons(h2, t2)) =>
+                h1.compare(h2).then_with(|| t1.compare(t2)),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+
+    /// Compares this term against any tuple in the Erlang term order.
+    pub fn compare_tuple(&self) -> Ordering {
+        self.rank().cmp(&2)
+    }
+
+}
+
+/// Whether a comparison operation holds for two terms in the given
+/// order. Since floats are never folded, `==` and `=:=` agree.
+pub fn comparison_holds(op: &ComparisonOperation, ord: Ordering) -> bool {
+    match op {
+        ComparisonOperation::Equal => ord == Ordering::Equal,
+        ComparisonOperation::ExactEqual => ord == Ordering::Equal,
+        ComparisonOperation::NotEqual => ord != Ordering::Equal,
+        ComparisonOperation::ExactNotEqual => ord != Ordering::Equal,
+        ComparisonOperation::Less => ord == Ordering::Less,
+        ComparisonOperation::LessEqual => ord != Ordering::Greater,
+        ComparisonOperation::Greater => ord == Ordering::Greater,
+        ComparisonOperation::GreaterEqual => ord != Ordering::Less,
+    }
+}
+
+/// Functions in the `erlang` module which have no side effects, and
+/// whose result only depends on their arguments.
+pub fn is_pure_bif(name: &str, arity: usize) -> bool {
+    ::eir::op::bif_primop(name, arity).is_some()
+}
+
+/// Evaluates a call to `erlang:name/arity` with constant arguments.
+///
+/// Returns `None` if the function is not known, or if the call would
+/// raise an exception. Those calls are left for the runtime.
+pub fn eval_bif(name: &str, args: &[Term]) -> Option<Term> {
+    let res = match (name, args) {
+        ("+", [Term::Integer(a)]) => Term::Integer(a.clone()),
+        ("-", [Term::Integer(a)]) => Term::Integer(-a),
+        ("+", [Term::Integer(a), Term::Integer(b)]) => Term::Integer(a + b),
+        ("-", [Term::Integer(a), Term::Integer(b)]) => Term::Integer(a - b),
+        ("*", [Term::Integer(a), Term::Integer(b)]) => Term::Integer(a * b),
+        ("div", [Term::Integer(a), Term::Integer(b)]) if !b.is_zero() =>
+            Term::Integer(a / b),
+        ("rem", [Term::Integer(a), Term::Integer(b)]) if !b.is_zero() =>
+            Term::Integer(a % b),
+        ("abs", [Term::Integer(a)]) => Term::Integer(a.abs()),
+
+        ("==", [a, b]) => Term::boolean(a == b),
+        ("=:=", [a, b]) => Term::boolean(a == b),
+        ("/=", [a, b]) => Term::boolean(a != b),
+        ("=/=", [a, b]) => Term::boolean(a != b),
+        ("<", [a, b]) => Term::boolean(a.compare(b) == Ordering::Less),
+        ("=<", [a, b]) => Term::boolean(a.compare(b) != Ordering::Greater),
+        (">", [a, b]) => Term::boolean(a.compare(b) == Ordering::Greater),
+        (">=", [a, b]) => Term::boolean(a.compare(b) != Ordering::Less),
+
+        ("not", [a]) => Term::boolean(!a.as_boolean()?),
+        ("and", [a, b]) => Term::boolean(a.as_boolean()? & b.as_boolean()?),
+        ("or", [a, b]) => Term::boolean(a.as_boolean()? | b.as_boolean()?),
+        ("xor", [a, b]) => Term::boolean(a.as_boolean()? ^ b.as_boolean()?),
+
+        ("is_atom", [a]) => Term::boolean(match a { Term::Atom(_) => true, _ => false }),
+        ("is_boolean", [a]) => Term::boolean(a.as_boolean().is_some()),
+        ("is_integer", [a]) => Term::boolean(match a { Term::Integer(_) => true, _ => false }),
+        ("is_number", [a]) => Term::boolean(match a { Term::Integer(_) => true, _ => false }),
+        ("is_list", [a]) => Term::boolean(match a {
+            Term::Nil | Term::Cons(_, _) => true,
+            _ => false,
+        }),
+        ("is_binary", [_]) | ("is_bitstring", [_]) | ("is_float", [_])
+            | ("is_function", [_]) | ("is_map", [_]) | ("is_pid", [_])
+            | ("is_port", [_]) | ("is_reference", [_]) | ("is_tuple", [_]) =>
+            Term::boolean(false),
+
+        ("hd", [Term::Cons(head, _)]) => (**head).clone(),
+        ("tl", [Term::Cons(_, tail)]) => (**tail).clone(),
+        ("length", [list]) => Term::Integer(BigInt::from(list.list_length()?)),
+
+        _ => return None,
+    };
+    Some(res)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ Term, eval_bif };
+    use ::num_bigint::BigInt;
+    use ::eir::{ ConstantTerm, AtomicTerm };
+
+    fn int(num: i64) -> Term {
+        Term::Integer(BigInt::from(num))
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert!(eval_bif("+", &[int(1), int(2)]) == Some(int(3)));
+        assert!(eval_bif("div", &[int(-7), int(2)]) == Some(int(-3)));
+        assert!(eval_bif("rem", &[int(-7), int(2)]) == Some(int(-1)));
+        assert!(eval_bif("div", &[int(1), int(0)]) == None);
+        assert!(eval_bif("+", &[int(1), Term::Nil]) == None);
+    }
+
+    #[test]
+    fn term_order() {
+        let atom = Term::Atom(::eir::Atom::from("a"));
+        assert!(eval_bif("<", &[int(100), atom.clone()]) == Some(Term::boolean(true)));
+        assert!(eval_bif("<", &[atom.clone(), Term::Nil]) == Some(Term::boolean(true)));
+        let list = Term::Cons(Box::new(int(1)), Box::new(Term::Nil));
+        assert!(eval_bif(">", &[list.clone(), Term::Nil]) == Some(Term::boolean(true)));
+        assert!(eval_bif("length", &[list]) == Some(int(1)));
+    }
+
+    #[test]
+    fn string_constants() {
+        let string = ConstantTerm::Atomic(AtomicTerm::String("ab".to_string()));
+        let term = Term::from_constant(&string).unwrap();
+        assert!(term.list_length() == Some(2));
+        assert!(Term::from_constant(&term.to_constant()) == Some(term));
+    }
+
+}
@@ -0,0 +1,755 @@
+use ::std::collections::{ HashMap, HashSet };
+
+use ::num_bigint::BigInt;
+use ::num_traits::ToPrimitive;
+
+use ::eir::{ Function, FunctionBuilder, Ebb, Op, Value, EbbCall };
+use ::eir::op::OpKind;
+use ::eir::fun::{ DefUse, ValueUse };
+
+pub mod eval;
+use self::eval::{ Term, eval_bif, is_pure_bif };
+
+/// Sparse conditional constant propagation.
+///
+/// Propagates constants through ops and Ebb arguments, starting at the
+/// entry Ebb and only following edges that can actually be taken given
+/// what is known about the values involved. Afterwards:
+///
+/// * Every use of a value which is known to be constant is replaced
+///   with that constant.
+/// * Branching ops which always go the same way are replaced with a
+///   jump, or removed if they always fall through.
+/// * Calls to pure `erlang` BIFs with constant arguments are replaced
+///   with their result.
+/// * Ebbs that can no longer be reached are removed.
+///
+/// Returns true if the function was changed.
+pub fn propagate_atomics(b: &mut FunctionBuilder) -> bool {
+    let def_use = b.function().def_use();
+    propagate_atomics_with_def_use(b, &def_use)
+}
+
+/// `propagate_atomics` with the def-use information of the function as
+/// it is before the pass.
+pub fn propagate_atomics_with_def_use(b: &mut FunctionBuilder, def_use: &DefUse) -> bool {
+    let (plan, constants) = {
+        let mut prop = Propagation::new(b.function(), def_use);
+        prop.run();
+        (prop.plan(), prop.constants())
+    };
+
+    let mut changed = plan.len() > 0;
+    apply_plan(b, plan);
+    changed |= replace_constant_uses(b, &constants);
+    changed |= super::remove_orphan_blocks(b);
+
+    changed
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Lattice {
+    /// No value has reached this point yet.
+    Undef,
+    Const(Term),
+    /// The result of a `MakeTuple` with these elements.
+    Tuple(Vec<Value>),
+    Overdefined,
+}
+
+impl Lattice {
+
+    fn join(&self, other: &Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Undef, _) => other.clone(),
+            (_, Lattice::Undef) => self.clone(),
+            _ if self == other => self.clone(),
+            _ => Lattice::Overdefined,
+        }
+    }
+
+}
+
+/// How an op which conditionally takes its branch will behave.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Outcome {
+    /// An operand is not yet known.
+    Pending,
+    /// Never takes the branch.
+    Fallthrough,
+    /// Always takes the branch.
+    Branch,
+    /// Can go either way.
+    Both,
+}
+
+enum CallOutcome {
+    Pending,
+    Unknown,
+    Const(Term),
+    /// The call returns this value, used for `element/2`.
+    Copy(Value),
+}
+
+#[derive(Debug)]
+enum Source {
+    Value(Value),
+    Const(Term),
+}
+
+#[derive(Debug)]
+enum Rewrite {
+    /// Removes the op, which has no results.
+    Remove(Op),
+    /// Replaces the op by moves into its first results.
+    Replace(Op, Vec<Source>),
+    /// Replaces a tail call by a return.
+    ReturnOk(Op, Source),
+    /// Replaces the op by a jump along its first branch.
+    TakeBranch(Op),
+}
+
+fn is_conditional(kind: &OpKind) -> bool {
+    match kind {
+        OpKind::IfTruthy => true,
+        OpKind::ComparisonOperation(_) => true,
+        OpKind::EqualAtomic(_) => true,
+        OpKind::UnpackTuple => true,
+        OpKind::UnpackListCell => true,
+        OpKind::IsMap => true,
+        OpKind::MapGet => true,
+        _ => false,
+    }
+}
+
+struct Propagation<'a> {
+    fun: &'a Function,
+    lattice: HashMap<Value, Lattice>,
+    executable: HashSet<Ebb>,
+    /// Used to find the Ebbs to revisit when a value changes.
+    def_use: &'a DefUse,
+    worklist: Vec<Ebb>,
+    queued: HashSet<Ebb>,
+}
+
+impl<'a> Propagation<'a> {
+
+    fn new(fun: &'a Function, def_use: &'a DefUse) -> Self {
+        Propagation {
+            fun: fun,
+            lattice: HashMap::new(),
+            executable: HashSet::new(),
+            def_use: def_use,
+            worklist: Vec::new(),
+            queued: HashSet::new(),
+        }
+    }
+
+    fn get(&self, value: Value) -> Lattice {
+        if self.fun.value_is_constant(value) {
+            match Term::from_constant(self.fun.value_constant(value)) {
+                Some(term) => Lattice::Const(term),
+                None => Lattice::Overdefined,
+            }
+        } else {
+            self.lattice.get(&value).cloned().unwrap_or(Lattice::Undef)
+        }
+    }
+
+    fn set(&mut self, value: Value, new: Lattice) {
+        let old = self.get(value);
+        let joined = old.join(&new);
+        if joined != old {
+            self.lattice.insert(value, joined);
+            let fun = self.fun;
+            for usage in self.def_use.value_uses(value) {
+                let op = match usage {
+                    ValueUse::Op(op, _) => *op,
+                    ValueUse::EbbCall(call, _) => fun.ebb_call_source(*call),
+                };
+                let ebb = fun.op_ebb(op);
+                if self.executable.contains(&ebb) && self.queued.insert(ebb) {
+                    self.worklist.push(ebb);
+                }
+            }
+        }
+    }
+
+    fn mark_executable(&mut self, ebb: Ebb) {
+        if self.executable.insert(ebb) && self.queued.insert(ebb) {
+            self.worklist.push(ebb);
+        }
+    }
+
+    fn mark_edge(&mut self, call: EbbCall) {
+        let fun = self.fun;
+        let target = fun.ebb_call_target(call);
+        for (arg, param) in fun.ebb_call_args(call).iter()
+            .zip(fun.ebb_args(target).iter())
+        {
+            let value = self.get(*arg);
+            self.set(*param, value);
+        }
+        self.mark_executable(target);
+    }
+
+    fn mark_branches(&mut self, op: Op) {
+        let fun = self.fun;
+        for branch in fun.op_branches(op) {
+            self.mark_edge(*branch);
+        }
+    }
+
+    fn overdefine(&mut self, op: Op) {
+        let fun = self.fun;
+        for write in fun.op_writes(op) {
+            self.set(*write, Lattice::Overdefined);
+        }
+    }
+
+    fn run(&mut self) {
+        let fun = self.fun;
+
+        let entry = fun.ebb_entry();
+        for arg in fun.ebb_args(entry) {
+            self.set(*arg, Lattice::Overdefined);
+        }
+        self.mark_executable(entry);
+
+        while let Some(ebb) = self.worklist.pop() {
+            self.queued.remove(&ebb);
+            for op in fun.iter_op(ebb) {
+                if !self.visit_op(op) { break; }
+            }
+        }
+    }
+
+    /// Updates the results and successors of the op.
+    /// Returns true if control can continue to the next op in the Ebb.
+    fn visit_op(&mut self, op: Op) -> bool {
+        let fun = self.fun;
+        let reads = fun.op_reads(op);
+        let writes = fun.op_writes(op);
+        let branches = fun.op_branches(op);
+
+        match fun.op_kind(op) {
+            OpKind::Move => {
+                let value = self.get(reads[0]);
+                self.set(writes[0], value);
+                true
+            },
+            OpKind::MakeTuple => {
+                self.set(writes[0], Lattice::Tuple(reads.to_vec()));
+                true
+            },
+            OpKind::MakeList => {
+                let value = self.make_list(reads);
+                self.set(writes[0], value);
+                true
+            },
+            OpKind::Call { tail_call: false } => {
+                match self.call_outcome(op) {
+                    CallOutcome::Pending => false,
+                    CallOutcome::Const(term) => {
+                        self.set(writes[0], Lattice::Const(term));
+                        true
+                    },
+                    CallOutcome::Copy(value) => {
+                        let value = self.get(value);
+                        self.set(writes[0], value);
+                        true
+                    },
+                    CallOutcome::Unknown => {
+                        self.overdefine(op);
+                        self.mark_branches(op);
+                        true
+                    },
+                }
+            },
+            kind if is_conditional(kind) && branches.len() == 1 => {
+                match self.outcome(op) {
+                    Outcome::Pending => false,
+                    Outcome::Fallthrough => {
+                        self.define_unpacked(op);
+                        true
+                    },
+                    Outcome::Branch => {
+                        self.mark_edge(branches[0]);
+                        false
+                    },
+                    Outcome::Both => {
+                        self.overdefine(op);
+                        self.mark_edge(branches[0]);
+                        true
+                    },
+                }
+            },
+            kind => {
+                self.overdefine(op);
+                self.mark_branches(op);
+                !kind.is_block_terminator()
+            },
+        }
+    }
+
+    fn make_list(&self, reads: &[Value]) -> Lattice {
+        // Reads are [tail, head..]
+        let mut acc = match self.get(reads[0]) {
+            Lattice::Const(term) => term,
+            Lattice::Undef => return Lattice::Undef,
+            _ => return Lattice::Overdefined,
+        };
+        for head in reads[1..].iter().rev() {
+            match self.get(*head) {
+                Lattice::Const(term) =>
+                    acc = Term::Cons(Box::new(term), Box::new(acc)),
+                Lattice::Undef => return Lattice::Undef,
+                _ => return Lattice::Overdefined,
+            }
+        }
+        Lattice::Const(acc)
+    }
+
+    /// Defines the results of a conditional op which is known to fall
+    /// through.
+    fn define_unpacked(&mut self, op: Op) {
+        let fun = self.fun;
+        let writes = fun.op_writes(op);
+        match (fun.op_kind(op), self.get(fun.op_reads(op)[0])) {
+            (OpKind::UnpackTuple, Lattice::Tuple(elems)) => {
+                for (write, elem) in writes.iter().zip(elems.iter()) {
+                    let value = self.get(*elem);
+                    self.set(*write, value);
+                }
+            },
+            (OpKind::UnpackListCell, Lattice::Const(Term::Cons(head, tail))) => {
+                self.set(writes[0], Lattice::Const(*head));
+                self.set(writes[1], Lattice::Const(*tail));
+            },
+            _ => self.overdefine(op),
+        }
+    }
+
+    fn outcome(&self, op: Op) -> Outcome {
+        let fun = self.fun;
+        let reads = fun.op_reads(op);
+        let num_writes = fun.op_writes(op).len();
+
+        let value = self.get(reads[0]);
+        if value == Lattice::Undef {
+            return Outcome::Pending;
+        }
+
+        match fun.op_kind(op) {
+            OpKind::IfTruthy => match value {
+                Lattice::Const(Term::Atom(ref atom))
+                    if atom.as_str() == "false" || atom.as_str() == "nil" =>
+                    Outcome::Branch,
+                Lattice::Const(_) | Lattice::Tuple(_) => Outcome::Fallthrough,
+                _ => Outcome::Both,
+            },
+            OpKind::EqualAtomic(atomic) => match (value, Term::from_atomic(atomic)) {
+                (Lattice::Const(ref term), Some(ref expected)) =>
+                    if term == expected { Outcome::Fallthrough } else { Outcome::Branch },
+                (Lattice::Tuple(_), _) => Outcome::Branch,
+                _ => Outcome::Both,
+            },
+            OpKind::ComparisonOperation(cmp) => {
+                let rhs = self.get(reads[1]);
+                let ord = match (&value, &rhs) {
+                    (_, Lattice::Undef) => return Outcome::Pending,
+                    (Lattice::Const(a), Lattice::Const(b)) => a.compare(b),
+                    (Lattice::Const(a), Lattice::Tuple(_)) => a.compare_tuple(),
+                    (Lattice::Tuple(_), Lattice::Const(b)) => b.compare_tuple().reverse(),
+                    (Lattice::Tuple(a), Lattice::Tuple(b)) if a == b =>
+                        ::std::cmp::Ordering::Equal,
+                    _ => return Outcome::Both,
+                };
+                if eval::comparison_holds(cmp, ord) {
+                    Outcome::Fallthrough
+                } else {
+                    Outcome::Branch
+                }
+            },
+            OpKind::UnpackTuple => match value {
+                Lattice::Tuple(ref elems) if elems.len() == num_writes =>
+                    Outcome::Fallthrough,
+                Lattice::Const(_) | Lattice::Tuple(_) => Outcome::Branch,
+                _ => Outcome::Both,
+            },
+            OpKind::UnpackListCell if num_writes == 2 => match value {
+                Lattice::Const(Term::Cons(_, _)) => Outcome::Fallthrough,
+                Lattice::Const(_) | Lattice::Tuple(_) => Outcome::Branch,
+                _ => Outcome::Both,
+            },
+            // `IsMap` and `MapGet`. This also covers `UnpackListCell`
+            // without results, which is what `op_is_map` emits.
+            // Constants and tuples are never maps.
+            _ => match value {
+                Lattice::Const(_) | Lattice::Tuple(_) => Outcome::Branch,
+                _ => Outcome::Both,
+            },
+        }
+    }
+
+    fn call_outcome(&self, op: Op) -> CallOutcome {
+        let fun = self.fun;
+        let reads = fun.op_reads(op);
+
+        let (module, name) = match (self.get(reads[0]), self.get(reads[1])) {
+            (Lattice::Const(Term::Atom(module)), Lattice::Const(Term::Atom(name))) =>
+                (module, name),
+            (Lattice::Undef, _) | (_, Lattice::Undef) => return CallOutcome::Pending,
+            _ => return CallOutcome::Unknown,
+        };
+        let name = name.as_str();
+
+        let args: Vec<Lattice> = reads[2..].iter().map(|v| self.get(*v)).collect();
+        if module.as_str() != "erlang" || !is_pure_bif(name, args.len()) {
+            return CallOutcome::Unknown;
+        }
+        if args.iter().any(|arg| *arg == Lattice::Undef) {
+            return CallOutcome::Pending;
+        }
+
+        match (name, args.as_slice()) {
+            ("element", [Lattice::Const(Term::Integer(idx)), Lattice::Tuple(elems)]) => {
+                match idx.to_usize() {
+                    Some(n) if n >= 1 && n <= elems.len() => CallOutcome::Copy(elems[n - 1]),
+                    _ => CallOutcome::Unknown,
+                }
+            },
+            ("tuple_size", [Lattice::Tuple(elems)]) =>
+                CallOutcome::Const(Term::Integer(BigInt::from(elems.len()))),
+            ("is_tuple", [Lattice::Tuple(_)]) =>
+                CallOutcome::Const(Term::boolean(true)),
+            (_, [Lattice::Tuple(_)]) if name.starts_with("is_") =>
+                CallOutcome::Const(Term::boolean(false)),
+            _ => {
+                let mut terms = Vec::with_capacity(args.len());
+                for arg in args.iter() {
+                    match arg {
+                        Lattice::Const(term) => terms.push(term.clone()),
+                        _ => return CallOutcome::Unknown,
+                    }
+                }
+                match eval_bif(name, &terms) {
+                    Some(term) => CallOutcome::Const(term),
+                    None => CallOutcome::Unknown,
+                }
+            },
+        }
+    }
+
+    /// Values that are not constants themselves, but are known to
+    /// always hold a constant.
+    fn constants(&self) -> Vec<(Value, Term)> {
+        let mut constants: Vec<_> = self.lattice.iter()
+            .filter_map(|(value, lattice)| match lattice {
+                Lattice::Const(term) => Some((*value, term.clone())),
+                _ => None,
+            })
+            .collect();
+        constants.sort_by_key(|(value, _)| *value);
+        constants
+    }
+
+    /// Decides how every reachable op should be rewritten.
+    fn plan(&self) -> Vec<Rewrite> {
+        let fun = self.fun;
+        let mut plan = Vec::new();
+
+        for ebb in fun.iter_ebb() {
+            if !self.executable.contains(&ebb) { continue; }
+            for op in fun.iter_op(ebb) {
+                let (rewrite, flows) = self.plan_op(op);
+                if let Some(rewrite) = rewrite {
+                    plan.push(rewrite);
+                }
+                if !flows { break; }
+            }
+        }
+
+        plan
+    }
+
+    fn plan_op(&self, op: Op) -> (Option<Rewrite>, bool) {
+        let fun = self.fun;
+        let kind = fun.op_kind(op);
+        match kind {
+            OpKind::Call { tail_call: false } => {
+                let source = match self.call_outcome(op) {
+                    CallOutcome::Pending => return (None, false),
+                    CallOutcome::Unknown => return (None, true),
+                    CallOutcome::Const(term) => Source::Const(term),
+                    CallOutcome::Copy(value) => Source::Value(value),
+                };
+                // The exception value is only ever passed along the
+                // exception edge. Leave the call alone if it escapes.
+                let exc = fun.op_writes(op)[1];
+                if used_outside(&self.def_use, fun, exc, op) {
+                    (None, true)
+                } else {
+                    (Some(Rewrite::Replace(op, vec![source])), true)
+                }
+            },
+            OpKind::Call { tail_call: true } => {
+                match self.call_outcome(op) {
+                    CallOutcome::Const(term) =>
+                        (Some(Rewrite::ReturnOk(op, Source::Const(term))), false),
+                    CallOutcome::Copy(value) =>
+                        (Some(Rewrite::ReturnOk(op, Source::Value(value))), false),
+                    _ => (None, false),
+                }
+            },
+            kind if is_conditional(kind) && fun.op_branches(op).len() == 1 => {
+                match self.outcome(op) {
+                    Outcome::Pending => (None, false),
+                    Outcome::Both => (None, true),
+                    Outcome::Branch => (Some(Rewrite::TakeBranch(op)), false),
+                    Outcome::Fallthrough => {
+                        let value = self.get(fun.op_reads(op)[0]);
+                        let rewrite = match (kind, value) {
+                            (OpKind::UnpackTuple, Lattice::Tuple(elems)) =>
+                                Rewrite::Replace(
+                                    op, elems.iter().map(|v| Source::Value(*v)).collect()),
+                            (OpKind::UnpackListCell, Lattice::Const(Term::Cons(head, tail))) =>
+                                Rewrite::Replace(
+                                    op, vec![Source::Const(*head), Source::Const(*tail)]),
+                            _ => {
+                                assert!(fun.op_writes(op).len() == 0);
+                                Rewrite::Remove(op)
+                            },
+                        };
+                        (Some(rewrite), true)
+                    },
+                }
+            },
+            kind => (None, !kind.is_block_terminator()),
+        }
+    }
+
+}
+
+/// Whether the value is read anywhere except in the branches of `op`.
+fn used_outside(def_use: &DefUse, fun: &Function, value: Value, op: Op) -> bool {
+    def_use.value_uses(value).iter().any(|u| match u {
+        ValueUse::EbbCall(call, _) => fun.ebb_call_source(*call) != op,
+        ValueUse::Op(_, _) => true,
+    })
+}
+
+fn source_value(b: &mut FunctionBuilder, source: &Source) -> Value {
+    match source {
+        Source::Value(value) => *value,
+        Source::Const(term) => b.create_constant(term.to_constant()),
+    }
+}
+
+/// Positions the builder right before the op.
+fn position_before(b: &mut FunctionBuilder, op: Op) {
+    match b.function().op_before(op) {
+        Some(prev) => b.position_after(prev),
+        None => {
+            let ebb = b.function().op_ebb(op);
+            b.position_at_start(ebb);
+        },
+    }
+}
+
+fn apply_plan(b: &mut FunctionBuilder, plan: Vec<Rewrite>) {
+    let mut tokens = Vec::new();
+    for rewrite in plan {
+        match rewrite {
+            Rewrite::Remove(op) => {
+                b.position_after(op);
+                b.remove_op(op);
+            },
+            Rewrite::Replace(op, sources) => {
+                position_before(b, op);
+                b.function_mut().op_remove_take_writes(op, &mut tokens);
+                for (source, token) in sources.iter().zip(tokens.drain(..)) {
+                    let value = source_value(b, source);
+                    b.op_move_write_token(value, token);
+                }
+            },
+            Rewrite::ReturnOk(op, source) => {
+                let value = source_value(b, &source);
+                b.position_after(op);
+                b.remove_op(op);
+                b.op_return_ok(value);
+            },
+            Rewrite::TakeBranch(op) => {
+                // The rest of the Ebb is split off, and is removed
+                // together with the other unreachable Ebbs.
+                b.position_after(op);
+                b.ebb_split();
+                let branch = b.function().op_branches(op)[0];
+                b.remove_op(op);
+                b.op_jump(branch);
+            },
+        }
+    }
+}
+
+/// Replaces every use of a value known to be constant with the constant
+/// itself. Returns true if any use was replaced.
+fn replace_constant_uses(b: &mut FunctionBuilder, constants: &[(Value, Term)]) -> bool {
+    let def_use = b.function().def_use();
+    let mut changed = false;
+    for (value, term) in constants.iter() {
+        if def_use.is_unused(*value) { continue; }
+        let constant = b.create_constant(term.to_constant());
+        b.replace_all_uses(*value, constant);
+        changed = true;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod test {
+    use super::propagate_atomics;
+    use ::eir::{ Function, FunctionBuilder, FunctionIdent, AtomicTerm, ConstantTerm };
+    use ::eir::op::OpKind;
+    use ::eir::Atom;
+    use ::num_bigint::BigInt;
+
+    fn new_function(arity: usize) -> Function {
+        Function::new(FunctionIdent {
+            module: Atom::from("test"),
+            name: Atom::from("fun"),
+            arity: arity,
+            lambda: None,
+        })
+    }
+
+    fn int(num: i64) -> AtomicTerm {
+        AtomicTerm::Integer(BigInt::from(num))
+    }
+
+    fn returned_constant(fun: &Function) -> Option<ConstantTerm> {
+        for ebb in fun.iter_ebb() {
+            for op in fun.iter_op(ebb) {
+                if let OpKind::ReturnOk = fun.op_kind(op) {
+                    let ret = fun.op_reads(op)[0];
+                    if fun.value_is_constant(ret) {
+                        return Some(fun.value_constant(ret).clone());
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn fold_arithmetic_through_ebb_args() {
+        let mut fun = new_function(0);
+        {
+            let mut b = FunctionBuilder::new(&mut fun);
+            let entry = b.insert_ebb_entry();
+            b.position_at_end(entry);
+
+            let exc_ebb = b.insert_ebb();
+            let exc_val = b.add_ebb_argument(exc_ebb);
+            let ret_ebb = b.insert_ebb();
+            let ret_val = b.add_ebb_argument(ret_ebb);
+
+            b.position_at_end(entry);
+            let module = b.create_atomic(AtomicTerm::Atom(Atom::from("erlang")));
+            let name = b.create_atomic(AtomicTerm::Atom(Atom::from("+")));
+            let one = b.create_atomic(int(1));
+            let two = b.create_atomic(int(2));
+            let (ok, err) = b.op_call(module, name, &[one, two]);
+            let exc_call = b.create_ebb_call(exc_ebb, &[err]);
+            b.add_op_ebb_call(exc_call);
+            let ret_call = b.create_ebb_call(ret_ebb, &[ok]);
+            b.op_jump(ret_call);
+
+            b.position_at_end(exc_ebb);
+            b.op_return_throw(exc_val);
+
+            b.position_at_end(ret_ebb);
+            b.op_return_ok(ret_val);
+        }
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(propagate_atomics(&mut b));
+        let fun = b.function();
+
+        assert!(returned_constant(fun) == Some(ConstantTerm::Atomic(int(3))));
+        // The exception handler is no longer reachable
+        assert!(fun.iter_ebb().count() == 2);
+        fun.validate();
+    }
+
+    #[test]
+    fn fold_branch_on_constant() {
+        let mut fun = new_function(1);
+        {
+            let mut b = FunctionBuilder::new(&mut fun);
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+
+            let fail_ebb = b.insert_ebb();
+            let ret_ebb = b.insert_ebb();
+            let ret_val = b.add_ebb_argument(ret_ebb);
+
+            b.position_at_end(entry);
+            let fals = b.create_atomic(AtomicTerm::Atom(Atom::from("false")));
+            let moved = b.op_move(fals);
+            let fail_call = b.create_ebb_call(fail_ebb, &[]);
+            b.op_branch_not_truthy(moved, fail_call);
+            let ret_call = b.create_ebb_call(ret_ebb, &[arg]);
+            b.op_jump(ret_call);
+
+            b.position_at_end(fail_ebb);
+            let ok = b.create_atomic(AtomicTerm::Atom(Atom::from("ok")));
+            let ret_call = b.create_ebb_call(ret_ebb, &[ok]);
+            b.op_jump(ret_call);
+
+            b.position_at_end(ret_ebb);
+            b.op_return_ok(ret_val);
+        }
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(propagate_atomics(&mut b));
+        let fun = b.function();
+
+        let ok = ConstantTerm::Atomic(AtomicTerm::Atom(Atom::from("ok")));
+        assert!(returned_constant(fun) == Some(ok));
+        fun.validate();
+
+        // Everything is folded after the first run
+        assert!(!propagate_atomics(&mut b));
+    }
+
+    #[test]
+    fn fold_element_of_known_tuple() {
+        let mut fun = new_function(1);
+        {
+            let mut b = FunctionBuilder::new(&mut fun);
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+
+            let module = b.create_atomic(AtomicTerm::Atom(Atom::from("erlang")));
+            let name = b.create_atomic(AtomicTerm::Atom(Atom::from("element")));
+            let two = b.create_atomic(int(2));
+            let tag = b.create_atomic(AtomicTerm::Atom(Atom::from("tag")));
+            let tuple = b.op_make_tuple(&[tag, arg]);
+            b.op_tail_call(module, name, &[two, tuple]);
+        }
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(propagate_atomics(&mut b));
+        let fun = b.function();
+
+        let entry = fun.ebb_entry();
+        let arg = fun.ebb_args(entry)[0];
+        let last = fun.iter_op_rev(entry).next().unwrap();
+        assert!(matches!(fun.op_kind(last), OpKind::ReturnOk));
+        assert!(fun.op_reads(last) == &[arg]);
+    }
+
+}
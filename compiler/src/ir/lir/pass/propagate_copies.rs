@@ -0,0 +1,298 @@
+use ::eir::{ Function, FunctionBuilder, Op, Ebb, EbbCall, Value };
+use ::eir::op::OpKind;
+use ::std::collections::HashMap;
+
+/// Follows `value` through the substitutions to the value it ends up
+/// as.
+fn resolve(substitutions: &HashMap<Value, Value>, mut value: Value) -> Value {
+    while let Some(next) = substitutions.get(&value) {
+        value = *next;
+    }
+    value
+}
+
+/// Records that uses of `old` become uses of `new`. Returns false if
+/// that would make a cycle, in which case nothing is recorded.
+fn substitute(substitutions: &mut HashMap<Value, Value>, old: Value, new: Value) -> bool {
+    let new = resolve(substitutions, new);
+    if new == old {
+        return false;
+    }
+    substitutions.insert(old, new);
+    true
+}
+
+/// Rewrites every use with the values the substitutions resolve to,
+/// in a single walk over the function.
+fn apply_substitutions(b: &mut FunctionBuilder, substitutions: &HashMap<Value, Value>) {
+    let resolved: HashMap<Value, Value> = substitutions.keys()
+        .map(|old| (*old, resolve(substitutions, *old)))
+        .collect();
+    b.replace_uses(&resolved);
+}
+
+fn remove_moves(b: &mut FunctionBuilder) -> bool {
+    let moves: Vec<Op> = {
+        let fun = b.function();
+        fun.iter_ebb()
+            .flat_map(|ebb| fun.iter_op(ebb))
+            .filter(|op| match fun.op_kind(*op) {
+                OpKind::Move => true,
+                _ => false,
+            })
+            .collect()
+    };
+
+    let mut substitutions = HashMap::new();
+    let mut removed = Vec::new();
+    for op in moves.iter() {
+        let fun = b.function();
+        let (src, dst) = (fun.op_reads(*op)[0], fun.op_writes(*op)[0]);
+        if substitute(&mut substitutions, dst, src) {
+            removed.push(*op);
+        }
+    }
+
+    apply_substitutions(b, &substitutions);
+    for op in removed.iter() {
+        b.remove_op(*op);
+    }
+    removed.len() > 0
+}
+
+/// The only value passed to the argument at `idx` of an Ebb, if there
+/// is one. Values the argument passes back to itself are ignored.
+fn single_source(fun: &Function, calls: &[EbbCall], arg: Value,
+                 idx: usize) -> Option<Value> {
+    let mut source = None;
+    for call in calls {
+        let value = fun.ebb_call_args(*call)[idx];
+        if value == arg { continue; }
+
+        // A value written by the branching op itself, like the exception
+        // of a call, only exists along that edge. Keep it as an argument.
+        let branching = fun.ebb_call_source(*call);
+        if fun.op_writes(branching).contains(&value) {
+            return None;
+        }
+
+        match source {
+            None => source = Some(value),
+            Some(prev) if prev == value => (),
+            Some(_) => return None,
+        }
+    }
+    source
+}
+
+fn remove_single_source_args(b: &mut FunctionBuilder) -> bool {
+    let (ebbs, incoming) = {
+        let fun = b.function();
+        let mut incoming: HashMap<Ebb, Vec<EbbCall>> = HashMap::new();
+        for ebb in fun.iter_ebb() {
+            for op in fun.iter_op(ebb) {
+                for branch in fun.op_branches(op) {
+                    incoming.entry(fun.ebb_call_target(*branch))
+                        .or_insert_with(Vec::new)
+                        .push(*branch);
+                }
+            }
+        }
+        let entry = fun.ebb_entry();
+        let ebbs: Vec<Ebb> = fun.iter_ebb().filter(|e| *e != entry).collect();
+        (ebbs, incoming)
+    };
+
+    // Uses are only rewritten at the end. Until then, calls still pass
+    // the removed arguments, which may hide a single source. The caller
+    // runs this again until nothing changes.
+    let mut substitutions = HashMap::new();
+    for ebb in ebbs {
+        let calls = match incoming.get(&ebb) {
+            Some(calls) => calls,
+            None => continue,
+        };
+
+        let num_args = b.function().ebb_args(ebb).len();
+        for idx in (0..num_args).rev() {
+            let replacement = {
+                let fun = b.function();
+                let arg = fun.ebb_args(ebb)[idx];
+                single_source(fun, calls, arg, idx).map(|v| (arg, v))
+            };
+            if let Some((arg, value)) = replacement {
+                if substitute(&mut substitutions, arg, value) {
+                    b.remove_ebb_argument(ebb, idx);
+                }
+            }
+        }
+    }
+
+    apply_substitutions(b, &substitutions);
+    substitutions.len() > 0
+}
+
+/// Removes `Move` ops and Ebb arguments which always receive the same
+/// value, replacing their uses with the original value.
+///
+/// Orphan blocks should be removed before this pass is run, otherwise
+/// their EbbCalls count as sources.
+///
+/// Returns true if anything was removed.
+pub fn propagate_copies(b: &mut FunctionBuilder) -> bool {
+    let mut changed = remove_moves(b);
+    // Removing an argument can leave a single source for another
+    loop {
+        if !remove_single_source_args(b) { break; }
+        changed = true;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod test {
+    use super::propagate_copies;
+    use ::eir::{ Function, FunctionBuilder, FunctionIdent };
+    use ::eir::op::OpKind;
+    use ::eir::Atom;
+
+    fn new_function() -> Function {
+        let ident = FunctionIdent {
+            module: Atom::from_str("test"),
+            name: Atom::from_str("fun"),
+            arity: 1,
+            lambda: None,
+        };
+        Function::new(ident)
+    }
+
+    #[test]
+    fn propagate_through_moves_and_args() {
+        let mut fun = new_function();
+        let (arg, body) = {
+            let mut b = FunctionBuilder::new(&mut fun);
+
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+
+            // body(loop_arg), only ever receives `arg` or itself
+            let body = b.insert_ebb();
+            let loop_arg = b.add_ebb_argument(body);
+
+            b.position_at_end(entry);
+            let moved = b.op_move(arg);
+            let call = b.create_ebb_call(body, &[moved]);
+            b.op_jump(call);
+
+            b.position_at_end(body);
+            let call = b.create_ebb_call(body, &[loop_arg]);
+            b.op_branch_not_truthy(loop_arg, call);
+            b.op_return_ok(loop_arg);
+
+            (arg, body)
+        };
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(propagate_copies(&mut b));
+        assert!(!propagate_copies(&mut b));
+
+        let fun = b.function();
+        assert!(fun.ebb_args(body).len() == 0);
+        for op in fun.iter_op(body) {
+            match fun.op_kind(op) {
+                OpKind::IfTruthy | OpKind::ReturnOk =>
+                    assert!(fun.op_reads(op) == &[arg]),
+                _ => panic!(),
+            }
+            for branch in fun.op_branches(op) {
+                assert!(fun.ebb_call_args(*branch).len() == 0);
+            }
+        }
+        let entry = fun.ebb_entry();
+        assert!(fun.iter_op(entry).count() == 1);
+    }
+
+    #[test]
+    fn resolve_chains() {
+        let mut fun = new_function();
+        let arg = {
+            let mut b = FunctionBuilder::new(&mut fun);
+
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+
+            // next(from_move) -> last(from_arg)
+            let next = b.insert_ebb();
+            let from_move = b.add_ebb_argument(next);
+            let last = b.insert_ebb();
+            let from_arg = b.add_ebb_argument(last);
+
+            b.position_at_end(entry);
+            let first = b.op_move(arg);
+            let second = b.op_move(first);
+            let call = b.create_ebb_call(next, &[second]);
+            b.op_jump(call);
+
+            b.position_at_end(next);
+            let call = b.create_ebb_call(last, &[from_move]);
+            b.op_jump(call);
+
+            b.position_at_end(last);
+            b.op_return_ok(from_arg);
+
+            arg
+        };
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(propagate_copies(&mut b));
+
+        let fun = b.function();
+        fun.validate();
+        for ebb in fun.iter_ebb() {
+            if ebb != fun.ebb_entry() {
+                assert!(fun.ebb_args(ebb).len() == 0);
+            }
+            for op in fun.iter_op(ebb) {
+                match fun.op_kind(op) {
+                    OpKind::Jump => assert!(fun.op_reads(op).len() == 0),
+                    OpKind::ReturnOk => assert!(fun.op_reads(op) == &[arg]),
+                    _ => panic!(),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keep_multiple_sources() {
+        let mut fun = new_function();
+        let join = {
+            let mut b = FunctionBuilder::new(&mut fun);
+
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+
+            let join = b.insert_ebb();
+            let join_arg = b.add_ebb_argument(join);
+
+            b.position_at_end(entry);
+            let other = b.create_atomic(::eir::AtomicTerm::Nil);
+            let call = b.create_ebb_call(join, &[other]);
+            b.op_branch_not_truthy(arg, call);
+            let call = b.create_ebb_call(join, &[arg]);
+            b.op_jump(call);
+
+            b.position_at_end(join);
+            b.op_return_ok(join_arg);
+
+            join
+        };
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(!propagate_copies(&mut b));
+        assert!(b.function().ebb_args(join).len() == 1);
+    }
+
+}
@@ -0,0 +1,254 @@
+use ::eir::{ Function, FunctionBuilder, Op, Ebb, EbbCall, Value };
+use ::eir::fun::{ DefUse, ValueDefinition };
+use ::std::collections::{ HashMap, HashSet };
+
+/// Ops and Ebb arguments whose values never reach an op with effects.
+struct DeadCode {
+    ops: Vec<Op>,
+    args: Vec<(Ebb, usize)>,
+}
+
+fn is_removable(fun: &Function, op: Op) -> bool {
+    fun.op_kind(op).is_pure() && fun.op_branches(op).len() == 0
+}
+
+/// Marks every value which is needed by an op that can't be removed.
+/// An Ebb argument is needed if it is read, and then so are the values
+/// passed to it by every EbbCall. Values that only flow around in
+/// cycles of Ebb arguments are never marked.
+fn find_dead_code(fun: &Function, def_use: &DefUse) -> DeadCode {
+    let mut incoming: HashMap<Ebb, Vec<EbbCall>> = HashMap::new();
+    let mut live_ops: HashSet<Op> = HashSet::new();
+    let mut stack: Vec<Value> = Vec::new();
+
+    for ebb in fun.iter_ebb() {
+        for op in fun.iter_op(ebb) {
+            for branch in fun.op_branches(op) {
+                incoming.entry(fun.ebb_call_target(*branch))
+                    .or_insert_with(Vec::new)
+                    .push(*branch);
+            }
+            if !is_removable(fun, op) {
+                live_ops.insert(op);
+                stack.extend(fun.op_reads(op).iter().cloned());
+            }
+        }
+    }
+
+    // The arguments of the entry are the function arguments
+    let entry = fun.ebb_entry();
+    stack.extend(fun.ebb_args(entry).iter().cloned());
+
+    let mut live: HashSet<Value> = HashSet::new();
+    while let Some(value) = stack.pop() {
+        if !live.insert(value) { continue; }
+        match def_use.value_definition(value) {
+            Some(ValueDefinition::Op(op)) => {
+                if live_ops.insert(op) {
+                    stack.extend(fun.op_reads(op).iter().cloned());
+                }
+            },
+            Some(ValueDefinition::EbbArg(ebb, idx)) => {
+                if let Some(calls) = incoming.get(&ebb) {
+                    for call in calls {
+                        stack.push(fun.ebb_call_args(*call)[idx]);
+                    }
+                }
+            },
+            Some(ValueDefinition::Constant) | None => (),
+        }
+    }
+
+    let mut dead = DeadCode {
+        ops: Vec::new(),
+        args: Vec::new(),
+    };
+    for ebb in fun.iter_ebb() {
+        if ebb != entry {
+            // Highest index first, so removal keeps the others valid
+            for (idx, arg) in fun.ebb_args(ebb).iter().enumerate().rev() {
+                if !live.contains(arg) {
+                    dead.args.push((ebb, idx));
+                }
+            }
+        }
+        for op in fun.iter_op(ebb) {
+            if !live_ops.contains(&op) {
+                dead.ops.push(op);
+            }
+        }
+    }
+    dead
+}
+
+/// Removes pure ops whose results are never used, and Ebb arguments
+/// which are never read, along with the values passed to them.
+///
+/// Which ops are pure is decided by `OpKind::is_pure`. Ops with
+/// branches are always kept, as are the arguments of the entry Ebb.
+/// Orphan blocks should be removed before this pass is run.
+///
+/// Returns true if anything was removed.
+pub fn remove_dead_code(b: &mut FunctionBuilder) -> bool {
+    let def_use = b.function().def_use();
+    remove_dead_code_with_def_use(b, &def_use)
+}
+
+/// `remove_dead_code` with the def-use information of the function as
+/// it is before the pass.
+pub fn remove_dead_code_with_def_use(b: &mut FunctionBuilder, def_use: &DefUse) -> bool {
+    let dead = find_dead_code(b.function(), def_use);
+
+    for op in dead.ops.iter() {
+        b.remove_op(*op);
+    }
+    for (ebb, idx) in dead.args.iter() {
+        b.remove_ebb_argument(*ebb, *idx);
+    }
+
+    dead.ops.len() > 0 || dead.args.len() > 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ remove_dead_code, find_dead_code };
+    use ::eir::{ Function, FunctionBuilder, FunctionIdent };
+    use ::eir::Atom;
+
+    fn new_function() -> Function {
+        let ident = FunctionIdent {
+            module: Atom::from_str("test"),
+            name: Atom::from_str("fun"),
+            arity: 1,
+            lambda: None,
+        };
+        Function::new(ident)
+    }
+
+    #[test]
+    fn remove_unused_values() {
+        let mut fun = new_function();
+        let (entry, body) = {
+            let mut b = FunctionBuilder::new(&mut fun);
+
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+
+            // body(unused, ret, looped)
+            let body = b.insert_ebb();
+            b.add_ebb_argument(body);
+            let ret = b.add_ebb_argument(body);
+            let looped = b.add_ebb_argument(body);
+
+            b.position_at_end(entry);
+            let tuple = b.op_make_tuple(&[arg]);
+            let call = b.create_ebb_call(body, &[tuple, arg, arg]);
+            b.op_jump(call);
+
+            // The third argument is only passed back to itself
+            b.position_at_end(body);
+            let moved = b.op_move(looped);
+            let call = b.create_ebb_call(body, &[ret, ret, moved]);
+            b.op_branch_not_truthy(ret, call);
+            b.op_return_ok(ret);
+
+            (entry, body)
+        };
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(remove_dead_code(&mut b));
+
+        let fun = b.function();
+        assert!(fun.ebb_args(entry).len() == 1);
+        assert!(fun.ebb_args(body).len() == 1);
+        assert!(fun.iter_op(entry).count() == 1);
+        assert!(fun.iter_op(body).count() == 2);
+        for ebb in fun.iter_ebb() {
+            for op in fun.iter_op(ebb) {
+                for branch in fun.op_branches(op) {
+                    assert!(fun.ebb_call_args(*branch).len() == 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn find_dead_op_and_arg() {
+        let mut fun = new_function();
+        let (entry, next, kept) = {
+            let mut b = FunctionBuilder::new(&mut fun);
+
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+
+            // next(unused, ret)
+            let next = b.insert_ebb();
+            b.add_ebb_argument(next);
+            let ret = b.add_ebb_argument(next);
+
+            b.position_at_end(entry);
+            let dropped = b.op_make_tuple(&[arg]);
+            let kept = b.op_make_tuple(&[arg]);
+            let call = b.create_ebb_call(next, &[dropped, kept]);
+            b.op_jump(call);
+
+            b.position_at_end(next);
+            b.op_return_ok(ret);
+
+            (entry, next, kept)
+        };
+
+        let entry_ops: Vec<_> = fun.iter_op(entry).collect();
+        let dead = find_dead_code(&fun, &fun.def_use());
+        assert!(dead.ops == vec![entry_ops[0]]);
+        assert!(dead.args == vec![(next, 0)]);
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(remove_dead_code(&mut b));
+        let fun = b.function();
+        assert!(fun.iter_op(entry).collect::<Vec<_>>() == &entry_ops[1..]);
+        let jump = fun.op_branches(entry_ops[2])[0];
+        assert!(fun.ebb_call_args(jump) == &[kept]);
+        assert!(fun.ebb_args(next).len() == 1);
+    }
+
+    // Does not get through `from_parsed` yet. `application_controller`
+    // builds maps, which `FunctionBuilder::op_make_map` does not
+    // implement, and the CPS stage panics on the loops made by
+    // `tail_calls_to_loops` in `compile`.
+    #[test]
+    #[ignore]
+    fn otp_modules() {
+        use std::fs;
+        use std::io::Read;
+
+        let files = [
+            "../test_data/compile.core",
+            "../test_data/gen_server.core",
+            "../test_data/application_controller.core",
+        ];
+        for file in files.iter() {
+            println!("File: {}", file);
+            let mut f = fs::File::open(file).unwrap();
+            let mut contents = String::new();
+            f.read_to_string(&mut contents).unwrap();
+
+            let res = ::parser::parse(&contents).unwrap();
+            let options = ::ir::lir::pass::PassOptions {
+                validate: true,
+                optimize: true,
+                ..Default::default()
+            };
+            let module = ::ir::from_parsed_with_options(&res.0, options);
+
+            for fun in module.functions.values() {
+                let dead = find_dead_code(fun, &fun.def_use());
+                assert!(dead.ops.len() == 0, "dead ops in {}", fun.ident());
+                assert!(dead.args.len() == 0, "dead args in {}", fun.ident());
+            }
+        }
+    }
+
+}
@@ -14,12 +14,13 @@ use ::matches::matches;
 
 use ::std::collections::{ HashMap, HashSet };
 
-pub fn simplify_branches(b: &mut FunctionBuilder) {
-    remove_constant_branches(b);
-    merge_jump_chains(b);
+pub fn simplify_branches(b: &mut FunctionBuilder) -> bool {
+    let removed = remove_constant_branches(b);
+    let merged = merge_jump_chains(b);
+    removed || merged
 }
 
-pub fn remove_constant_branches(b: &mut FunctionBuilder) {
+pub fn remove_constant_branches(b: &mut FunctionBuilder) -> bool {
     // Remove if_truthy ops which always go one way
 
     let nil = Atom::from_str("nil");
@@ -48,19 +49,24 @@ pub fn remove_constant_branches(b: &mut FunctionBuilder) {
 
     }
 
+    let changed = to_remove.len() > 0;
     for (op, res) in to_remove {
         b.position_after(op);
         let cont_ebb = b.ebb_split();
         let false_branch = b.function().op_branches(op)[0];
+        let line = b.function().op_line(op);
         b.remove_op(op);
+        b.set_source_line(line);
         if res {
             let call = b.create_ebb_call(cont_ebb, &[]);
             b.op_jump(call);
         } else {
             b.op_jump(false_branch);
         }
+        b.set_source_line(None);
     }
 
+    changed
 }
 
 //pub fn remove_constant_branches(cfg: &mut FunctionCfg) {
@@ -117,8 +123,9 @@ pub fn remove_constant_branches(b: &mut FunctionBuilder) {
 //    new_in_edges: Vec<(EdgeN, LabelN)>,
 //}
 
-fn merge_jump_chains(b: &mut FunctionBuilder) {
+fn merge_jump_chains(b: &mut FunctionBuilder) -> bool {
     let cfg = b.function().gen_cfg();
+    let mut changed = false;
 
     // Find candidate jumps
     // Candidate jumps:
@@ -151,11 +158,13 @@ fn merge_jump_chains(b: &mut FunctionBuilder) {
                     b.function_mut().op_remove(*op);
                     b.position_at_end(origin_ebb);
                     b.ebb_concat(target_ebb, &args_buf);
+                    changed = true;
                 }
             }
         }
     }
 
+    changed
 }
 
 
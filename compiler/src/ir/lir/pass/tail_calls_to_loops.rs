@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use ::eir::{ Function, FunctionBuilder, Op, Value };
+use ::eir::op::OpKind;
+use ::eir::fun::{ DefUse, ValueDefinition };
+
+/// The arguments of a tail call to the function itself, if the op is
+/// one. Only an `Apply` of the captured function counts.
+///
+/// A remote call with the module and name of the function is not a
+/// self call. It always goes to the latest version of the module, which
+/// is how a running process picks up reloaded code.
+fn self_tail_call_args(fun: &Function, def_use: &DefUse, op: Op) -> Option<Vec<Value>> {
+    let ident = fun.ident();
+    let reads = fun.op_reads(op);
+
+    match fun.op_kind(op) {
+        OpKind::Apply { tail_call: true } => (),
+        _ => return None,
+    }
+    let def = match def_use.value_definition(reads[0]) {
+        Some(ValueDefinition::Op(def)) => def,
+        _ => return None,
+    };
+    match fun.op_kind(def) {
+        OpKind::CaptureNamedFunction(captured) if captured == ident => (),
+        _ => return None,
+    }
+
+    let args = &reads[1..];
+    if args.len() != ident.arity {
+        return None;
+    }
+    Some(args.to_vec())
+}
+
+/// Turns tail calls of a function to itself into jumps to a loop
+/// header.
+///
+/// The ops of the entry Ebb are moved into a new header Ebb, which takes
+/// the function arguments as Ebb arguments. The entry then only jumps
+/// to the header, and every self tail call becomes a jump to the header
+/// with the call arguments. Lambdas are left alone, as they are never
+/// called by name. Remote calls to the function are kept, see
+/// `self_tail_call_args`.
+///
+/// Returns true if any call was replaced.
+pub fn tail_calls_to_loops(b: &mut FunctionBuilder) -> bool {
+    let def_use = b.function().def_use();
+    tail_calls_to_loops_with_def_use(b, &def_use)
+}
+
+/// `tail_calls_to_loops` with the def-use information of the function
+/// as it is before the pass.
+pub fn tail_calls_to_loops_with_def_use(b: &mut FunctionBuilder, def_use: &DefUse) -> bool {
+    if b.function().ident().lambda.is_some() {
+        return false;
+    }
+
+    let candidates: Vec<(Op, Vec<Value>)> = {
+        let fun = b.function();
+        let mut candidates = Vec::new();
+        for ebb in fun.iter_ebb() {
+            for op in fun.iter_op(ebb) {
+                if let Some(args) = self_tail_call_args(fun, def_use, op) {
+                    candidates.push((op, args));
+                }
+            }
+        }
+        candidates
+    };
+    if candidates.len() == 0 {
+        return false;
+    }
+
+    // Move the body into the loop header. The uses of the entry
+    // arguments are replaced with the header arguments, which includes
+    // the collected call arguments.
+    let entry = b.function().ebb_entry();
+    let entry_args = b.function().ebb_args(entry).to_vec();
+    b.position_at_start(entry);
+    let header = b.ebb_split();
+    let mut params = HashMap::new();
+    for arg in entry_args.iter() {
+        let param = b.add_ebb_argument(header);
+        b.replace_all_uses(*arg, param);
+        params.insert(*arg, param);
+    }
+    let call = b.create_ebb_call(header, &entry_args);
+    b.op_jump(call);
+
+    for (op, args) in candidates {
+        let args: Vec<Value> = args.iter()
+            .map(|arg| params.get(arg).cloned().unwrap_or(*arg))
+            .collect();
+        let line = b.function().op_line(op);
+        b.position_after(op);
+        b.remove_op(op);
+        b.set_source_line(line);
+        let call = b.create_ebb_call(header, &args);
+        b.op_jump(call);
+        b.set_source_line(None);
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::tail_calls_to_loops;
+    use ::eir::{ Function, FunctionBuilder, FunctionIdent, AtomicTerm, Value };
+    use ::eir::op::OpKind;
+    use ::eir::Atom;
+
+    fn ident() -> FunctionIdent {
+        FunctionIdent {
+            module: Atom::from_str("test"),
+            name: Atom::from_str("fun"),
+            arity: 1,
+            lambda: None,
+        }
+    }
+
+    fn atom(b: &mut FunctionBuilder, name: &str) -> Value {
+        b.create_atomic(AtomicTerm::Atom(Atom::from_str(name)))
+    }
+
+    /// `fun(X) -> case X of false -> ok; _ -> Module:Name(X) end.`,
+    /// with the tail call made through `make_call`.
+    fn build<F>(fun: &mut Function, make_call: F)
+    where F: Fn(&mut FunctionBuilder, Value) {
+        let mut b = FunctionBuilder::new(fun);
+
+        let entry = b.insert_ebb_entry();
+        let arg = b.add_ebb_argument(entry);
+        b.position_at_end(entry);
+
+        let done = b.insert_ebb();
+
+        b.position_at_end(entry);
+        let call = b.create_ebb_call(done, &[]);
+        b.op_branch_not_truthy(arg, call);
+        make_call(&mut b, arg);
+
+        b.position_at_end(done);
+        let ok = atom(&mut b, "ok");
+        b.op_return_ok(ok);
+    }
+
+    fn count_tail_calls(fun: &Function) -> usize {
+        fun.iter_ebb()
+            .flat_map(|ebb| fun.iter_op(ebb))
+            .filter(|op| match fun.op_kind(*op) {
+                OpKind::Call { tail_call: true } => true,
+                OpKind::Apply { tail_call: true } => true,
+                _ => false,
+            })
+            .count()
+    }
+
+    fn assert_loop(fun: &Function) {
+        fun.validate();
+        assert!(count_tail_calls(fun) == 0);
+
+        // The entry only jumps to the header, with the argument
+        let entry = fun.ebb_entry();
+        let ops: Vec<_> = fun.iter_op(entry).collect();
+        assert!(ops.len() == 1);
+        let header_call = fun.op_branches(ops[0])[0];
+        assert!(fun.ebb_call_args(header_call) == fun.ebb_args(entry));
+
+        // Which is jumped back to from the body
+        let header = fun.ebb_call_target(header_call);
+        let back_edges = fun.iter_ebb()
+            .flat_map(|ebb| fun.iter_op(ebb))
+            .filter(|op| match fun.op_kind(*op) {
+                OpKind::Jump => fun.ebb_call_target(fun.op_branches(*op)[0]) == header,
+                _ => false,
+            })
+            .count();
+        assert!(back_edges == 2);
+
+        // The loop passes on the header argument, not the entry one
+        let loop_call = fun.iter_op(header)
+            .filter(|op| match fun.op_kind(*op) {
+                OpKind::Jump => true,
+                _ => false,
+            })
+            .map(|op| fun.op_branches(op)[0])
+            .find(|call| fun.ebb_call_target(*call) == header)
+            .unwrap();
+        assert!(fun.ebb_call_args(loop_call) == fun.ebb_args(header));
+    }
+
+    #[test]
+    fn keep_remote_self_call() {
+        // A remote call to the function itself may reach a newer version
+        // of the module, so it is kept.
+        let mut fun = Function::new(ident());
+        build(&mut fun, |b, arg| {
+            let module = atom(b, "test");
+            let name = atom(b, "fun");
+            b.op_tail_call(module, name, &[arg]);
+        });
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(!tail_calls_to_loops(&mut b));
+        assert!(count_tail_calls(b.function()) == 1);
+    }
+
+    #[test]
+    fn self_apply_to_loop() {
+        let mut fun = Function::new(ident());
+        build(&mut fun, |b, arg| {
+            let captured = b.op_capture_named_function(ident());
+            b.op_tail_apply(captured, &[arg]);
+        });
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(tail_calls_to_loops(&mut b));
+        assert_loop(b.function());
+        assert!(!tail_calls_to_loops(&mut b));
+    }
+
+    #[test]
+    fn keep_other_calls() {
+        let mut fun = Function::new(ident());
+        build(&mut fun, |b, arg| {
+            let module = atom(b, "test");
+            let name = atom(b, "other");
+            b.op_tail_call(module, name, &[arg]);
+        });
+
+        let mut b = FunctionBuilder::new(&mut fun);
+        assert!(!tail_calls_to_loops(&mut b));
+        assert!(count_tail_calls(b.function()) == 1);
+    }
+
+}
@@ -78,7 +78,23 @@ impl AFunctionName {
     }
 }
 
+/// Lowers a parsed module to Eir with the default options. The CFG is
+/// validated after every pass, and no functions are inlined or
+/// optimized.
 pub fn from_parsed(parsed: &parser::Module) -> ::eir::Module {
+    let options = ::ir::lir::pass::PassOptions {
+        validate: true,
+        ..Default::default()
+    };
+    from_parsed_with_options(parsed, options)
+}
+
+/// Lowers a parsed module to Eir. Inlining only happens when
+/// `options.inline` is set, and the optimization passes only run when
+/// `options.optimize` is.
+pub fn from_parsed_with_options(parsed: &parser::Module,
+                                options: ::ir::lir::pass::PassOptions)
+                                -> ::eir::Module {
     println!("STAGE: From parsed");
     let mut module = ::ir::hir::from_parsed::from_parsed(parsed);
 
@@ -126,8 +142,9 @@ pub fn from_parsed(parsed: &parser::Module) -> ::eir::Module {
         .map(|f| f.ident.clone()).collect();
     let mut eir_module = module.to_eir();
 
-    // Validate CFG between each major pass.
-    let hardass_validate = true;
+    let inline = options.inline.clone();
+    let mut passes = ::ir::lir::pass::PassManager::lowering_pipeline(
+        options.clone());
 
     println!("STAGE: Functionwise");
     for fun_ident in fun_idents.iter() {
@@ -135,42 +152,28 @@ pub fn from_parsed(parsed: &parser::Module) -> ::eir::Module {
         println!("Function: {}", function.ident());
 
         let mut builder = FunctionBuilder::new(&mut function);
+        passes.run(&mut builder);
+    }
 
-        //if hardass_validate { builder.function().validate() }
-
-        // Remove orphans in generated LIR
-        ::ir::lir::pass::remove_orphan_blocks(&mut builder);
-        if hardass_validate { builder.function().validate() }
-
-        // Propagate atomics
-        //::ir::lir::pass::propagate_atomics(function);
-        //if hardass_validate { function.validate() }
-
-        // Promote tail calls
-        // AFTER propagate atomics
-        //::ir::lir::pass::promote_tail_calls(function);
-        // REQUIRED after promote tail calls
-        //::ir::lir::pass::remove_orphan_blocks(function);
-        //if hardass_validate { function.validate() }
-
-        //::ir::lir::pass::simplify_branches(function);
-        //::ir::lir::pass::remove_orphan_blocks(function);
-        //if hardass_validate { function.validate() }
-
-        //let mut out = ::std::fs::File::create("immediate1.dot").unwrap();
-        //::ir::lir::to_dot::function_to_dot(
-        //    fun_ident, lir_mut, &mut out).unwrap();
+    let mut cleanup = ::ir::lir::pass::PassManager::cleanup_pipeline(options);
+    if let Some(inline) = inline {
+        println!("STAGE: Inline");
+        let changed = ::ir::lir::pass::inline_module(&mut eir_module, &inline);
+        for fun_ident in changed.iter() {
+            let mut function = eir_module.functions.get_mut(fun_ident).unwrap();
+            let mut builder = FunctionBuilder::new(&mut function);
+            cleanup.run(&mut builder);
+        }
+    }
 
-        ::ir::lir::pass::compile_pattern(&mut builder);
-        //::ir::lir::pass::propagate_atomics(function);
-        ::ir::lir::pass::simplify_branches(&mut builder);
-        ::ir::lir::pass::remove_orphan_blocks(&mut builder);
-        //if hardass_validate { function.validate() }
+    println!("STAGE: CPS transform");
+    for fun_ident in fun_idents.iter() {
+        let function = &eir_module.functions[fun_ident];
 
         //lir_mut.compress_numbering();
-        builder.function().validate();
+        function.validate();
 
-        ::cps_transform::cps_transform(&builder.function(), &mut eir_module.envs);
+        ::cps_transform::cps_transform(function, &mut eir_module.envs);
 
         //let live = builder.function().live_values();
         //let entry = builder.function().ebb_entry();
@@ -183,5 +186,11 @@ pub fn from_parsed(parsed: &parser::Module) -> ::eir::Module {
 
     }
 
+    if passes.options().time {
+        println!("STAGE: Pass timings");
+        passes.print_timings();
+        cleanup.print_timings();
+    }
+
     eir_module
 }
@@ -1,5 +1,6 @@
 use ::regex::Regex;
 use std::str::CharIndices;
+use std::collections::HashMap;
 
 lazy_static! {
     static ref TEST: Regex = Regex::new("...").unwrap();
@@ -529,6 +530,43 @@ fn test_variables() {
     assert!(tok.next_token() == None);
 }
 
+/// Lines from `%% Line N` comments, keyed on the offset of the first
+/// token after the comment. This is how erlc prints the line
+/// annotations of Core Erlang.
+pub fn line_comments(text: &str) -> HashMap<usize, u32> {
+    let mut lines = HashMap::new();
+    let mut pending = None;
+    let mut offset = 0;
+    for line in text.split('\n') {
+        let trimmed = line.trim_start();
+        let start = offset + (line.len() - trimmed.len());
+        offset += line.len() + 1;
+
+        if trimmed.starts_with('%') {
+            if trimmed.starts_with("%% Line ") {
+                pending = trimmed[8..].trim().parse().ok();
+            }
+            continue;
+        }
+        if trimmed.is_empty() {
+            continue;
+        }
+        if let Some(num) = pending.take() {
+            lines.insert(start, num);
+        }
+    }
+    lines
+}
+
+#[test]
+fn test_line_comments() {
+    let text = "'f'/0 =\n    %% Line 3\n    fun () ->\n\t%% Line 4\n\t'ok'\n";
+    let lines = line_comments(text);
+    assert!(lines.len() == 2);
+    assert!(lines[&text.find("fun").unwrap()] == 3);
+    assert!(lines[&text.find("'ok'").unwrap()] == 4);
+}
+
 #[test]
 fn test_lex_compile_core_file() {
     use ::std::io::Read;
@@ -1,6 +1,7 @@
 pub use ::{ Variable, Atom };
 use ::ir::AVariable;
 use ::eir::FunctionIdent;
+use ::num_traits::ToPrimitive;
 
 mod grammar;
 mod lex;
@@ -8,9 +9,10 @@ mod lex;
 pub fn parse<'input>(text: &'input str) -> Result<Annotated<Module>,
         ::lalrpop_util::ParseError<usize, lex::Tok<'input>, ()>> {
 
+    let lines = lex::line_comments(text);
     let tokenizer = lex::Tokenizer::new(text);
     let parser = grammar::AnnotatedModuleParser::new();
-    parser.parse(text, tokenizer)
+    parser.parse(text, &lines, tokenizer)
 }
 
 #[test]
@@ -24,6 +26,30 @@ fn parse_otp_compiler_compile() {
     parse(&s).unwrap();
 }
 
+#[test]
+fn parse_line_annotations() {
+    let text = "module 'test' ['f'/1] attributes []
+'f'/1 =
+    %% Line 3
+    fun (_0) ->
+\t%% Line 4
+\tcase _0 of
+\t  <'a'> when 'true' ->
+\t      ( call 'foo':'bar'
+\t\t    ()
+\t\t-| [5] )
+\t  <_1> when 'true' ->
+\t      _1
+\tend
+end
+";
+    let parsed = parse(text).unwrap();
+    let def = &parsed.0.definitions[0];
+    assert!(def.fun.line() == Some(3));
+    assert!(def.fun.0.body.0[0].line() == Some(4));
+    assert!(def.fun.0.body.0[0].1.len() == 1);
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum MapExactAssoc {
     Exact,
@@ -31,11 +57,29 @@ pub enum MapExactAssoc {
 }
 
 #[derive(Debug, Clone)]
-pub struct Annotated<I>(pub I, pub Vec<()>);
+pub struct Annotated<I>(pub I, pub Vec<Constant>);
 impl<I> Annotated<I> {
     fn empty(inner: I) -> Self {
         Annotated(inner, Vec::new())
     }
+    /// `line` comes from a `%% Line` comment in front of the item.
+    fn with_line(inner: I, mut annotations: Vec<Constant>,
+                 line: Option<&u32>) -> Self {
+        if let Some(line) = line {
+            annotations.push(Constant::Atomic(AtomicTerm::Integer((*line).into())));
+        }
+        Annotated(inner, annotations)
+    }
+    /// Source line of the item. Integers in the annotations of Core
+    /// Erlang are line numbers.
+    pub fn line(&self) -> Option<u32> {
+        self.1.iter()
+            .filter_map(|a| match a {
+                Constant::Atomic(AtomicTerm::Integer(num)) => num.to_u32(),
+                _ => None,
+            })
+            .next()
+    }
 }
 
 #[derive(Debug, Clone)]
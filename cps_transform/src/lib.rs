@@ -20,62 +20,9 @@ use util::pooled_entity_set::PooledEntitySet;
 use eir::{ Function, FunctionBuilder };
 use eir::op::OpKind;
 use eir::{ ModuleEnvs, ClosureEnv };
-use eir::{ Ebb, Op, Value, EbbCall };
+use eir::{ Op, Value, EbbCall };
 use eir::fun::live::LiveValues;
-
-fn copy_op(
-    src_fun: &Function,
-    src_op: Op,
-    b: &mut FunctionBuilder,
-    // Map from source function => dest function
-    val_map: &mut HashMap<Value, Value>,
-    ebb_map: &mut HashMap<Ebb, Ebb>,
-) {
-    let kind = src_fun.op_kind(src_op);
-    b.op_build_start(kind.clone());
-
-    for write in src_fun.op_writes(src_op) {
-        let new = b.op_build_write();
-        val_map.insert(*write, new);
-    }
-    for read in src_fun.op_reads(src_op) {
-        if src_fun.value_is_constant(*read) {
-            let value = b.create_constant(src_fun.value_constant(*read).clone());
-            b.op_build_read(value);
-        } else {
-            b.op_build_read(val_map[read]);
-        }
-    }
-    for branch in src_fun.op_branches(src_op) {
-        let old_target = src_fun.ebb_call_target(*branch);
-
-        let new = if let Some(ebb) = ebb_map.get(&old_target) {
-            *ebb
-        } else {
-            let new = b.insert_ebb();
-            ebb_map.insert(old_target, new);
-            for arg in src_fun.ebb_args(old_target) {
-                let val = b.add_ebb_argument(new);
-                val_map.insert(*arg, val);
-            }
-            new
-        };
-
-        let mut buf = Vec::new();
-        for arg in src_fun.ebb_call_args(*branch) {
-            if src_fun.value_is_constant(*arg) {
-                let value = b.create_constant(src_fun.value_constant(*arg).clone());
-                buf.push(value);
-            } else {
-                buf.push(val_map[arg]);
-            }
-        }
-        let call = b.create_ebb_call(new, &buf);
-        b.op_build_ebb_call(call);
-    }
-
-    b.op_build_end();
-}
+use eir::fun::FunctionCloner;
 
 #[derive(Debug, Copy, Clone)]
 enum ContSite {
@@ -83,18 +30,6 @@ enum ContSite {
     Op(Op),
 }
 
-fn copy_read(src_fun: &Function, b: &mut FunctionBuilder,
-             val_map: &HashMap<Value, Value>,
-             src_val: Value) -> Value {
-    if src_fun.value_is_constant(src_val) {
-        let value = b.create_constant(
-            src_fun.value_constant(src_val).clone());
-        value
-    } else {
-        val_map[&src_val]
-    }
-}
-
 fn gen_chunk(
     src_fun: &Function,
     site: ContSite,
@@ -117,8 +52,7 @@ fn gen_chunk(
     let mut b = FunctionBuilder::new(&mut fun);
 
     let mut to_process = VecDeque::new();
-    let mut val_map = HashMap::new();
-    let mut ebb_map = HashMap::new();
+    let mut cloner = FunctionCloner::new(src_fun);
     let mut handled_ops = HashSet::new();
     // Temp
     let mut call_renames: HashMap<Value, Value> = HashMap::new();
@@ -178,7 +112,7 @@ fn gen_chunk(
             },
         };
         let src_first_ebb = src_fun.op_ebb(src_first_op);
-        ebb_map.insert(src_first_ebb, entry_ebb);
+        cloner.insert_ebb(src_first_ebb, entry_ebb);
 
         // Argument for environment
         let env_val = b.add_ebb_argument(entry_ebb);
@@ -186,7 +120,7 @@ fn gen_chunk(
         // Argument for result
         let res_val = b.add_ebb_argument(entry_ebb);
         if let Some(v) = result_src_val {
-            val_map.insert(v, res_val);
+            cloner.insert_value(v, res_val);
         }
 
         let mut new_env_vars = Vec::new();
@@ -199,7 +133,7 @@ fn gen_chunk(
 
         // Insert mappings for all in env
         for (src, dst) in env_vals.iter().zip(new_env_vars.iter().skip(2)) {
-            val_map.insert(*src, *dst);
+            cloner.insert_value(*src, *dst);
         }
 
     } else {
@@ -208,7 +142,7 @@ fn gen_chunk(
         // Get Op and Ebb, insert binding
         src_first_op = if let ContSite::Op(op) = site { op } else { panic!() };
         let src_first_ebb = src_fun.op_ebb(src_first_op);
-        ebb_map.insert(src_first_ebb, entry_ebb);
+        cloner.insert_ebb(src_first_ebb, entry_ebb);
 
         // Arguments for continuations
         ok_ret_cont = b.add_ebb_argument(entry_ebb);
@@ -217,7 +151,7 @@ fn gen_chunk(
         // Entry Ebb arguments, insert bindings
         for arg in src_fun.ebb_args(src_first_ebb) {
             let val = b.add_ebb_argument(entry_ebb);
-            val_map.insert(*arg, val);
+            cloner.insert_value(*arg, val);
         }
 
     }
@@ -232,7 +166,7 @@ fn gen_chunk(
         handled_ops.insert(src_op);
 
         let src_ebb = src_fun.op_ebb(src_op);
-        b.position_at_end(ebb_map[&src_ebb]);
+        b.position_at_end(cloner.map().ebbs[&src_ebb]);
 
         // If we hit a continuation site
         if cont_sites.contains(&src_op) {
@@ -264,7 +198,7 @@ fn gen_chunk(
                 if live == ok_val {
                     continue;
                 }
-                buf.push(val_map[&live]);
+                buf.push(cloner.map().values[&live]);
             }
             let env_idx = env_idx_gen.add();
             env_idx_gen.env_set_captures_num(env_idx, buf.len());
@@ -310,7 +244,7 @@ fn gen_chunk(
                     renamed_nok_val = Some(live);
                     continue;
                 }
-                buf.push(val_map[&renamed]);
+                buf.push(cloner.map().values[&renamed]);
             }
             let env_idx = env_idx_gen.add();
             env_idx_gen.env_set_captures_num(env_idx, buf.len());
@@ -338,20 +272,20 @@ fn gen_chunk(
                     let reads = src_fun.op_reads(src_op);
 
                     for read in reads.iter().skip(1) {
-                        buf.push(copy_read(src_fun, &mut b, &val_map, *read));
+                        buf.push(cloner.map_value(&mut b, *read));
                     }
 
-                    b.op_tail_apply(val_map[&reads[0]], &buf);
+                    b.op_tail_apply(cloner.map().values[&reads[0]], &buf);
                 },
                 OpKind::Call { tail_call: false } => {
                     let reads = src_fun.op_reads(src_op);
 
                     for read in reads.iter().skip(2) {
-                        buf.push(copy_read(src_fun, &mut b, &val_map, *read));
+                        buf.push(cloner.map_value(&mut b, *read));
                     }
 
-                    let name_val = copy_read(src_fun, &mut b, &val_map, reads[0]);
-                    let module_val = copy_read(src_fun, &mut b, &val_map, reads[1]);
+                    let name_val = cloner.map_value(&mut b, reads[0]);
+                    let module_val = cloner.map_value(&mut b, reads[1]);
 
                     b.op_tail_call(name_val, module_val, &buf);
                 },
@@ -366,20 +300,46 @@ fn gen_chunk(
         match kind {
             // Call the return continuation
             OpKind::ReturnOk => {
-                b.position_at_end(ebb_map[&src_ebb]);
+                b.position_at_end(cloner.map().ebbs[&src_ebb]);
                 let res = src_fun.op_reads(src_op)[0];
-                b.op_tail_apply(ok_ret_cont, &[val_map[&res]]);
+                b.op_tail_apply(ok_ret_cont, &[cloner.map().values[&res]]);
             },
             // Call the throw continuation
             OpKind::ReturnThrow => {
-                b.position_at_end(ebb_map[&src_ebb]);
+                b.position_at_end(cloner.map().ebbs[&src_ebb]);
                 let res = src_fun.op_reads(src_op)[0];
-                b.op_tail_apply(err_ret_cont, &[val_map[&res]]);
+                b.op_tail_apply(err_ret_cont, &[cloner.map().values[&res]]);
+            },
+            // Tail calls pass on our own return continuations
+            OpKind::Apply { tail_call: true } => {
+                b.position_at_end(cloner.map().ebbs[&src_ebb]);
+                let reads = src_fun.op_reads(src_op);
+
+                let mut buf = vec![ok_ret_cont, err_ret_cont];
+                for read in reads.iter().skip(1) {
+                    buf.push(cloner.map_value(&mut b, *read));
+                }
+
+                let fun_val = cloner.map_value(&mut b, reads[0]);
+                b.op_tail_apply(fun_val, &buf);
+            },
+            OpKind::Call { tail_call: true } => {
+                b.position_at_end(cloner.map().ebbs[&src_ebb]);
+                let reads = src_fun.op_reads(src_op);
+
+                let mut buf = vec![ok_ret_cont, err_ret_cont];
+                for read in reads.iter().skip(2) {
+                    buf.push(cloner.map_value(&mut b, *read));
+                }
+
+                let name_val = cloner.map_value(&mut b, reads[0]);
+                let module_val = cloner.map_value(&mut b, reads[1]);
+                b.op_tail_call(name_val, module_val, &buf);
             },
             // If this is a normal Op, copy it and add outgoing edges to
             // processing queue
             _ => {
-                copy_op(src_fun, src_op, &mut b, &mut val_map, &mut ebb_map);
+                cloner.clone_op(&mut b, src_op);
 
                 // Add outgoing edges to processing queue
                 if let Some(next_op) = src_fun.op_after(src_op) {
@@ -396,6 +356,7 @@ fn gen_chunk(
 
     }
 
+    cloner.finish();
     println!("{}", b.function().to_text());
 
 }
@@ -411,10 +372,10 @@ pub fn cps_transform(
     for op in live.flow_live.keys() {
         let kind = src_fun.op_kind(*op);
         match kind {
-            OpKind::Call { .. } => {
+            OpKind::Call { tail_call: false } => {
                 cont_sites.insert(*op);
             },
-            OpKind::Apply { .. } => {
+            OpKind::Apply { tail_call: false } => {
                 cont_sites.insert(*op);
             },
             _ => (),
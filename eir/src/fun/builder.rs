@@ -8,6 +8,7 @@ use crate::Clause;
 use crate::op::{ OpKind, ComparisonOperation };
 
 use matches::assert_matches;
+use std::collections::HashMap;
 use ::cranelift_entity::{ EntityList };
 
 #[derive(Copy, Clone, Debug)]
@@ -36,6 +37,9 @@ pub struct FunctionBuilder<'a> {
 
     state: BuilderState,
 
+    /// Line recorded for every op inserted.
+    source_line: Option<u32>,
+
     val_buf: Option<Vec<Value>>,
 }
 
@@ -59,6 +63,13 @@ impl<'a> FunctionBuilder<'a> {
         result
     }
 
+    /// Adds a write of a value taken from a removed op.
+    pub fn op_build_write_token(&mut self, token: WriteToken) -> Value {
+        let op = self.state.building_op();
+        self.fun.ops[op].writes.push(token.0, &mut self.fun.value_pool);
+        token.0
+    }
+
     pub fn op_build_read(&mut self, val: Value) {
         let op = self.state.building_op();
         self.fun.ops[op].reads.push(val, &mut self.fun.value_pool);
@@ -67,6 +78,8 @@ impl<'a> FunctionBuilder<'a> {
     pub fn op_build_ebb_call(&mut self, call: EbbCall) {
         let op = self.state.building_op();
         self.fun.ops[op].ebb_calls.push(call, &mut self.fun.ebb_call_pool);
+        assert!(self.fun.ebb_calls[call].source.is_none());
+        self.fun.ebb_calls[call].source = Some(op);
     }
 
     pub fn op_build_end(&mut self) {
@@ -88,6 +101,8 @@ impl<'a> FunctionBuilder<'a> {
 
             state: BuilderState::Build,
 
+            source_line: None,
+
             val_buf: Some(Vec::new()),
         }
     }
@@ -117,6 +132,7 @@ impl<'a> FunctionBuilder<'a> {
         self.assert_not_terminated();
 
         let op = self.fun.ops.push(data);
+        self.fun.op_lines[op] = self.source_line;
         self.fun.layout.insert_op_after(
             self.current_ebb.unwrap(), self.current_op, op);
 
@@ -144,6 +160,16 @@ impl<'a> FunctionBuilder<'a> {
         self.fun.layout.remove_op(op);
     }
 
+    /// Removes the op like `remove_op`, and takes its writes so that
+    /// they can be defined by a replacement op.
+    pub fn remove_op_take_writes(&mut self, op: Op, writes: &mut Vec<WriteToken>) {
+        writes.clear();
+        for write in self.fun.op_writes(op) {
+            writes.push(WriteToken(*write));
+        }
+        self.remove_op(op);
+    }
+
     pub fn assert_not_terminated(&self) {
         if let Some(op) = self.current_op {
             assert!(!self.fun.ops[op].kind.is_block_terminator());
@@ -172,13 +198,20 @@ impl<'a> FunctionBuilder<'a> {
         self.fun.layout.concat_ebb(current, next);
     }
 
+    /// Moves the ops after the current position into a new Ebb, inserted
+    /// after the current one. When positioned at the start of an Ebb,
+    /// every op is moved. No jump is added.
     pub fn ebb_split(&mut self) -> Ebb {
         assert!(self.state == BuilderState::Build);
-        let op = self.current_op.unwrap();
-        assert!(!self.fun.op_kind(op).is_block_terminator());
+        if let Some(op) = self.current_op {
+            assert!(!self.fun.op_kind(op).is_block_terminator());
+        }
 
         let new_ebb = self.insert_ebb();
-        self.fun.layout.split_ebb_into(op, new_ebb);
+        match self.current_op {
+            Some(op) => self.fun.layout.split_ebb_into(op, new_ebb),
+            None => self.fun.layout.move_ops_into(self.current_ebb.unwrap(), new_ebb),
+        }
 
         new_ebb
     }
@@ -213,6 +246,28 @@ impl<'a> FunctionBuilder<'a> {
         value
     }
 
+    /// Removes the argument at `idx` from the Ebb, together with the
+    /// matching argument of every EbbCall to it.
+    pub fn remove_ebb_argument(&mut self, ebb: Ebb, idx: usize) {
+        self.fun.ebbs[ebb].arguments.remove(idx, &mut self.fun.value_pool);
+        for call in self.fun.ebb_calls.values_mut() {
+            if call.target == ebb && call.source.is_some() {
+                call.values.remove(idx, &mut self.fun.value_pool);
+            }
+        }
+    }
+
+    /// Replaces every use of `old`, both in op reads and in EbbCall
+    /// arguments, with `new`. Definitions of `old` are left alone.
+    pub fn replace_all_uses(&mut self, old: Value, new: Value) {
+        self.fun.replace_all_uses(old, new);
+    }
+
+    /// See `Function::replace_uses`.
+    pub fn replace_uses(&mut self, map: &HashMap<Value, Value>) {
+        self.fun.replace_uses(map);
+    }
+
     pub fn position_at_end(&mut self, ebb: Ebb) {
         assert!(self.state == BuilderState::Build);
         self.current_ebb = Some(ebb);
@@ -233,6 +288,14 @@ impl<'a> FunctionBuilder<'a> {
         self.current_op = Some(op);
     }
 
+    /// Sets the source line of the ops inserted from now on.
+    pub fn set_source_line(&mut self, line: Option<u32>) {
+        self.source_line = line;
+    }
+    pub fn source_line(&self) -> Option<u32> {
+        self.source_line
+    }
+
     pub fn current_ebb(&self) -> Ebb {
         self.current_ebb.unwrap()
     }
@@ -812,6 +875,17 @@ impl<'a> FunctionBuilder<'a> {
         result
     }
 
+    pub fn op_receive_finish(&mut self, structure: Value) {
+        let reads = EntityList::from_slice(&[structure], &mut self.fun.value_pool);
+
+        self.insert_op(OpData {
+            kind: OpKind::ReceiveFinish,
+            reads: reads,
+            writes: EntityList::new(),
+            ebb_calls: EntityList::new(),
+        });
+    }
+
     pub fn op_unreachable(&mut self) {
         self.insert_op(OpData {
             kind: OpKind::Unreachable,
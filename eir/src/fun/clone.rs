@@ -0,0 +1,309 @@
+use std::collections::{ HashMap, HashSet };
+
+use crate::op::OpKind;
+
+use super::{ Function, FunctionBuilder, WriteToken };
+use super::{ Ebb, Op, Value };
+
+/// The mapping from values and Ebbs of the source function to the
+/// destination function.
+#[derive(Debug, Default)]
+pub struct CloneMap {
+    pub values: HashMap<Value, Value>,
+    pub ebbs: HashMap<Ebb, Ebb>,
+}
+
+/// # Function cloner
+/// Copies ops and Ebbs of a source function into a `FunctionBuilder`.
+///
+/// Values and Ebbs are mapped through a `CloneMap`. Entries can be added
+/// up front to substitute values, like the arguments of an inlined
+/// function, or to copy into Ebbs that already exist. Constants are
+/// copied into the destination the first time they are used.
+///
+/// Ebbs which are not in the map are inserted after the current Ebb of
+/// the builder when they are first referenced, with fresh arguments.
+///
+/// Values may be used before the op or Ebb defining them is copied. A
+/// placeholder is created for them, which becomes the definition once
+/// the definition is copied. `finish` checks that every placeholder got
+/// defined.
+pub struct FunctionCloner<'a> {
+    src: &'a Function,
+    map: CloneMap,
+    forward: HashSet<Value>,
+}
+
+impl<'a> FunctionCloner<'a> {
+
+    pub fn new(src: &'a Function) -> Self {
+        FunctionCloner::with_map(src, CloneMap::default())
+    }
+
+    pub fn with_map(src: &'a Function, map: CloneMap) -> Self {
+        FunctionCloner {
+            src: src,
+            map: map,
+            forward: HashSet::new(),
+        }
+    }
+
+    pub fn src(&self) -> &'a Function {
+        self.src
+    }
+
+    pub fn map(&self) -> &CloneMap {
+        &self.map
+    }
+
+    /// Substitutes `dst` for every use of `src`.
+    pub fn insert_value(&mut self, src: Value, dst: Value) {
+        self.map.values.insert(src, dst);
+    }
+
+    /// Makes ops of the `src` Ebb be copied into `dst`. The arguments of
+    /// `src` are not mapped.
+    pub fn insert_ebb(&mut self, src: Ebb, dst: Ebb) {
+        self.map.ebbs.insert(src, dst);
+    }
+
+    /// The destination value for a source value.
+    pub fn map_value(&mut self, b: &mut FunctionBuilder, value: Value) -> Value {
+        if let Some(mapped) = self.map.values.get(&value) {
+            return *mapped;
+        }
+
+        let mapped = if self.src.value_is_constant(value) {
+            b.create_constant(self.src.value_constant(value).clone())
+        } else {
+            let placeholder = b.function_mut().new_variable();
+            self.forward.insert(placeholder);
+            placeholder
+        };
+        self.map.values.insert(value, mapped);
+        mapped
+    }
+
+    pub fn map_values(&mut self, b: &mut FunctionBuilder, values: &[Value]) -> Vec<Value> {
+        values.iter().map(|v| self.map_value(b, *v)).collect()
+    }
+
+    /// The destination Ebb for a source Ebb, created if needed.
+    pub fn map_ebb(&mut self, b: &mut FunctionBuilder, ebb: Ebb) -> Ebb {
+        if let Some(mapped) = self.map.ebbs.get(&ebb) {
+            return *mapped;
+        }
+
+        let new = b.insert_ebb();
+        for arg in self.src.ebb_args(ebb) {
+            match self.take_forward(*arg) {
+                Some(value) => {
+                    let fun = b.function_mut();
+                    fun.ebbs[new].arguments.push(value, &mut fun.value_pool);
+                },
+                None => {
+                    let value = b.add_ebb_argument(new);
+                    self.map.values.insert(*arg, value);
+                },
+            }
+        }
+        self.map.ebbs.insert(ebb, new);
+        new
+    }
+
+    /// The placeholder of a value that has been used before its
+    /// definition was copied.
+    fn take_forward(&mut self, value: Value) -> Option<Value> {
+        let mapped = *self.map.values.get(&value)?;
+        if self.forward.remove(&mapped) {
+            Some(mapped)
+        } else {
+            None
+        }
+    }
+
+    /// Copies a single op at the current position of the builder.
+    /// Branch targets are mapped with `map_ebb`. The copy keeps the
+    /// source line of the original.
+    pub fn clone_op(&mut self, b: &mut FunctionBuilder, op: Op) {
+        let src = self.src;
+
+        let kind = match src.op_kind(op) {
+            OpKind::TombstoneSSA(value) => OpKind::TombstoneSSA(self.map_value(b, *value)),
+            kind => kind.clone(),
+        };
+        let reads = self.map_values(b, src.op_reads(op));
+        let branches: Vec<_> = src.op_branches(op).iter()
+            .map(|branch| {
+                let target = self.map_ebb(b, src.ebb_call_target(*branch));
+                let args = self.map_values(b, src.ebb_call_args(*branch));
+                b.create_ebb_call(target, &args)
+            })
+            .collect();
+
+        let line = b.source_line();
+        b.set_source_line(src.op_line(op));
+        b.op_build_start(kind);
+        b.set_source_line(line);
+        for write in src.op_writes(op) {
+            match self.take_forward(*write) {
+                Some(value) => {
+                    b.op_build_write_token(WriteToken(value));
+                },
+                None => {
+                    let value = b.op_build_write();
+                    self.map.values.insert(*write, value);
+                },
+            }
+        }
+        for read in reads {
+            b.op_build_read(read);
+        }
+        for branch in branches {
+            b.op_build_ebb_call(branch);
+        }
+        b.op_build_end();
+    }
+
+    /// Copies every op of the given Ebbs, in order. Each Ebb is copied
+    /// to the end of its destination Ebb.
+    pub fn clone_ebbs(&mut self, b: &mut FunctionBuilder, ebbs: &[Ebb]) {
+        for ebb in ebbs {
+            let dst = self.map_ebb(b, *ebb);
+            b.position_at_end(dst);
+            for op in self.src.iter_op(*ebb) {
+                self.clone_op(b, op);
+            }
+        }
+    }
+
+    /// Returns the mapping. Panics if a value was used but its
+    /// definition was never copied.
+    pub fn finish(self) -> CloneMap {
+        assert!(self.forward.is_empty(), "values used but never defined");
+        self.map
+    }
+
+}
+
+/// Copies the whole of `src` into the empty function of the builder.
+/// The entry Ebb of `src` becomes the entry of the new function, with
+/// the same number of arguments.
+pub fn clone_function(src: &Function, b: &mut FunctionBuilder) -> CloneMap {
+    let mut cloner = FunctionCloner::new(src);
+
+    let src_entry = src.ebb_entry();
+    let entry = b.insert_ebb_entry();
+    for arg in src.ebb_args(src_entry) {
+        let value = b.add_ebb_argument(entry);
+        cloner.insert_value(*arg, value);
+    }
+    cloner.insert_ebb(src_entry, entry);
+    b.position_at_end(entry);
+
+    let ebbs: Vec<Ebb> = src.iter_ebb().collect();
+    cloner.clone_ebbs(b, &ebbs);
+    cloner.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::clone_function;
+    use crate::{ Function, FunctionBuilder, FunctionIdent, Atom, AtomicTerm };
+
+    fn ident() -> FunctionIdent {
+        FunctionIdent {
+            module: Atom::from_str("test"),
+            name: Atom::from_str("fun"),
+            arity: 1,
+            lambda: None,
+        }
+    }
+
+    #[test]
+    fn clone_whole_function() {
+        let mut src = Function::new(ident());
+        let (ret, ret_arg) = {
+            let mut b = FunctionBuilder::new(&mut src);
+
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+
+            let ret = b.insert_ebb();
+            let ret_arg = b.add_ebb_argument(ret);
+
+            b.position_at_end(entry);
+            let nil = b.create_atomic(AtomicTerm::Nil);
+            b.set_source_line(Some(3));
+            let tuple = b.op_make_tuple(&[arg, nil]);
+            b.set_source_line(None);
+            let call = b.create_ebb_call(ret, &[tuple]);
+            b.op_jump(call);
+
+            b.position_at_end(ret);
+            b.op_return_ok(ret_arg);
+
+            (ret, ret_arg)
+        };
+
+        let mut dst = Function::new(ident());
+        let map = {
+            let mut b = FunctionBuilder::new(&mut dst);
+            clone_function(&src, &mut b)
+        };
+        dst.validate();
+
+        assert!(dst.iter_ebb().count() == 2);
+        assert!(dst.iter_constants().count() == 1);
+
+        let lines: Vec<_> = dst.iter_op(dst.ebb_entry())
+            .map(|op| dst.op_line(op))
+            .collect();
+        assert!(lines == vec![Some(3), None]);
+
+        let new_ret = map.ebbs[&ret];
+        assert!(dst.ebb_args(new_ret) == &[map.values[&ret_arg]]);
+        let op = dst.iter_op(new_ret).next().unwrap();
+        assert!(dst.op_reads(op) == &[map.values[&ret_arg]]);
+    }
+
+    #[test]
+    fn clone_use_before_definition() {
+        // Layout is entry, use, def. The value read in `use` is only
+        // defined by the op in `def`.
+        let mut src = Function::new(ident());
+        {
+            let mut b = FunctionBuilder::new(&mut src);
+
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+
+            let def = b.insert_ebb();
+            b.position_at_end(entry);
+            let use_ = b.insert_ebb();
+
+            b.position_at_end(entry);
+            let call = b.create_ebb_call(def, &[]);
+            b.op_jump(call);
+
+            b.position_at_end(def);
+            let tuple = b.op_make_tuple(&[arg]);
+            let call = b.create_ebb_call(use_, &[]);
+            b.op_jump(call);
+
+            b.position_at_end(use_);
+            b.op_return_ok(tuple);
+        }
+
+        let mut dst = Function::new(ident());
+        let map = {
+            let mut b = FunctionBuilder::new(&mut dst);
+            clone_function(&src, &mut b)
+        };
+        dst.validate();
+        assert!(map.ebbs.len() == 3);
+    }
+
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use crate::Function;
+use crate::{ Op, Ebb, Value, EbbCall };
+
+/// Where a value is defined.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ValueDefinition {
+    /// Written by the op.
+    Op(Op),
+    /// Argument number `usize` of the Ebb.
+    EbbArg(Ebb, usize),
+    /// A constant, visible in the whole function.
+    Constant,
+}
+
+/// A single use of a value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ValueUse {
+    /// Read number `usize` of the op.
+    Op(Op, usize),
+    /// Argument number `usize` passed by the EbbCall.
+    EbbCall(EbbCall, usize),
+}
+
+/// # Def-use chains
+/// The definition and every use of each value in a function.
+///
+/// Only ops and Ebbs that are in the layout of the function are taken
+/// into account. This is a snapshot, it is not updated when the function
+/// is changed.
+#[derive(Debug)]
+pub struct DefUse {
+    definitions: HashMap<Value, ValueDefinition>,
+    uses: HashMap<Value, Vec<ValueUse>>,
+}
+
+impl DefUse {
+
+    pub fn value_definition(&self, value: Value) -> Option<ValueDefinition> {
+        self.definitions.get(&value).cloned()
+    }
+
+    pub fn value_uses(&self, value: Value) -> &[ValueUse] {
+        self.uses.get(&value).map(|u| u.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn is_unused(&self, value: Value) -> bool {
+        self.value_uses(value).len() == 0
+    }
+
+    /// Ops which read the value, in layout order. An op reading the
+    /// value several times is returned once per read.
+    pub fn reading_ops<'a>(&'a self, value: Value) -> impl Iterator<Item = Op> + 'a {
+        self.value_uses(value).iter().filter_map(|u| match u {
+            ValueUse::Op(op, _) => Some(*op),
+            ValueUse::EbbCall(_, _) => None,
+        })
+    }
+
+}
+
+pub fn calculate_def_use(fun: &Function) -> DefUse {
+    let mut definitions = HashMap::new();
+    let mut uses: HashMap<Value, Vec<ValueUse>> = HashMap::new();
+
+    for constant in fun.iter_constants() {
+        definitions.insert(*constant, ValueDefinition::Constant);
+    }
+
+    for ebb in fun.iter_ebb() {
+        for (idx, arg) in fun.ebb_args(ebb).iter().enumerate() {
+            definitions.insert(*arg, ValueDefinition::EbbArg(ebb, idx));
+        }
+        for op in fun.iter_op(ebb) {
+            for write in fun.op_writes(op) {
+                definitions.insert(*write, ValueDefinition::Op(op));
+            }
+            for (idx, read) in fun.op_reads(op).iter().enumerate() {
+                uses.entry(*read).or_insert_with(Vec::new)
+                    .push(ValueUse::Op(op, idx));
+            }
+            for branch in fun.op_branches(op) {
+                for (idx, arg) in fun.ebb_call_args(*branch).iter().enumerate() {
+                    uses.entry(*arg).or_insert_with(Vec::new)
+                        .push(ValueUse::EbbCall(*branch, idx));
+                }
+            }
+        }
+    }
+
+    DefUse {
+        definitions: definitions,
+        uses: uses,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ ValueDefinition, ValueUse };
+    use crate::{ Function, FunctionBuilder, FunctionIdent, Atom, AtomicTerm };
+
+    #[test]
+    fn def_use_chains() {
+        let ident = FunctionIdent {
+            module: Atom::from_str("test"),
+            name: Atom::from_str("fun"),
+            arity: 1,
+            lambda: None,
+        };
+        let mut fun = Function::new(ident);
+
+        let (entry, ret, arg, tuple, unused, nil) = {
+            let mut b = FunctionBuilder::new(&mut fun);
+
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+
+            let ret = b.insert_ebb();
+            let ret_arg = b.add_ebb_argument(ret);
+
+            b.position_at_end(entry);
+            let nil = b.create_atomic(AtomicTerm::Nil);
+            let tuple = b.op_make_tuple(&[arg, nil]);
+            let unused = b.op_move(tuple);
+            let call = b.create_ebb_call(ret, &[tuple]);
+            b.op_jump(call);
+
+            b.position_at_end(ret);
+            b.op_return_ok(ret_arg);
+
+            (entry, ret, arg, tuple, unused, nil)
+        };
+
+        let def_use = fun.def_use();
+        assert!(def_use.value_definition(arg) == Some(ValueDefinition::EbbArg(entry, 0)));
+        assert!(def_use.value_definition(nil) == Some(ValueDefinition::Constant));
+        match def_use.value_definition(tuple) {
+            Some(ValueDefinition::Op(op)) =>
+                assert!(fun.op_writes(op) == &[tuple]),
+            _ => panic!(),
+        }
+
+        assert!(def_use.is_unused(unused));
+        assert!(def_use.reading_ops(arg).count() == 1);
+        let tuple_uses = def_use.value_uses(tuple);
+        assert!(tuple_uses.len() == 2);
+        match tuple_uses[1] {
+            ValueUse::EbbCall(call, 0) =>
+                assert!(fun.ebb_call_target(call) == ret),
+            _ => panic!(),
+        }
+
+        {
+            let mut b = FunctionBuilder::new(&mut fun);
+            b.replace_all_uses(tuple, nil);
+        }
+        let def_use = fun.def_use();
+        assert!(def_use.is_unused(tuple));
+        assert!(def_use.value_uses(nil).len() == 3);
+    }
+
+}
@@ -145,6 +145,24 @@ impl Layout {
         self.ebbs[ebb2].last_op = last_op;
     }
 
+    /// Moves every op of `ebb1` into the empty `ebb2`.
+    pub fn move_ops_into(&mut self, ebb1: Ebb, ebb2: Ebb) {
+        assert!(ebb1 != ebb2);
+        assert!(self.ebbs[ebb2].first_op.is_none());
+
+        let mut curr_op = self.ebbs[ebb1].first_op;
+        while let Some(curr_op_i) = curr_op {
+            assert!(self.ops[curr_op_i].ebb == Some(ebb1));
+            self.ops[curr_op_i].ebb = Some(ebb2);
+            curr_op = self.ops[curr_op_i].next;
+        }
+
+        self.ebbs[ebb2].first_op = self.ebbs[ebb1].first_op;
+        self.ebbs[ebb2].last_op = self.ebbs[ebb1].last_op;
+        self.ebbs[ebb1].first_op = None;
+        self.ebbs[ebb1].last_op = None;
+    }
+
     pub fn remove_op(&mut self, op: Op) {
         //println!("remove_op {} {:?}", self.idx, op);
         let prev = self.ops[op].prev;
@@ -22,6 +22,12 @@ pub use layout::Layout;
 
 pub mod live;
 
+pub mod def_use;
+pub use def_use::{ DefUse, ValueDefinition, ValueUse };
+
+pub mod clone;
+pub use clone::{ FunctionCloner, CloneMap, clone_function };
+
 /// Basic block in function
 #[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Ebb(u32);
@@ -135,6 +141,9 @@ pub struct Function {
     layout: Layout,
 
     ops: PrimaryMap<Op, OpData>,
+    /// Source line of each op, from the Core Erlang annotations of the
+    /// expression it was lowered from.
+    op_lines: SecondaryMap<Op, Option<u32>>,
     ebbs: PrimaryMap<Ebb, EbbData>,
     values: PrimaryMap<Value, ValueType>,
     ebb_calls: PrimaryMap<EbbCall, EbbCallData>,
@@ -156,6 +165,7 @@ impl Function {
             layout: Layout::new(),
 
             ops: PrimaryMap::new(),
+            op_lines: SecondaryMap::new(),
             ebbs: PrimaryMap::new(),
             values: PrimaryMap::new(),
             ebb_calls: PrimaryMap::new(),
@@ -231,6 +241,9 @@ impl Function {
     pub fn ebb_call_set_target(&mut self, call: EbbCall, ebb: Ebb) {
         self.ebb_calls[call].target = ebb;
     }
+    pub fn ebb_call_set_arg(&mut self, call: EbbCall, idx: usize, value: Value) {
+        self.ebb_calls[call].values.as_mut_slice(&mut self.value_pool)[idx] = value;
+    }
 
     pub fn op_kind<'a>(&'a self, op: Op) -> &'a OpKind {
         &self.ops[op].kind
@@ -244,6 +257,15 @@ impl Function {
     pub fn op_branches<'a>(&'a self, op: Op) -> &[EbbCall] {
         self.ops[op].ebb_calls.as_slice(&self.ebb_call_pool)
     }
+    pub fn op_set_read(&mut self, op: Op, idx: usize, value: Value) {
+        self.ops[op].reads.as_mut_slice(&mut self.value_pool)[idx] = value;
+    }
+    pub fn op_line(&self, op: Op) -> Option<u32> {
+        self.op_lines[op]
+    }
+    pub fn op_set_line(&mut self, op: Op, line: Option<u32>) {
+        self.op_lines[op] = line;
+    }
     pub fn op_ebb(&self, op: Op) -> Ebb {
         self.layout.ops[op].ebb.unwrap()
     }
@@ -344,6 +366,59 @@ impl Function {
         self::live::calculate_live_values(self)
     }
 
+    pub fn def_use(&self) -> self::def_use::DefUse {
+        self::def_use::calculate_def_use(self)
+    }
+
+    /// Replaces every use of `old`, both in op reads and in EbbCall
+    /// arguments, with `new`. Definitions of `old` are left alone.
+    pub fn replace_all_uses(&mut self, old: Value, new: Value) {
+        for op in self.ops.values_mut() {
+            for read in op.reads.as_mut_slice(&mut self.value_pool) {
+                if *read == old {
+                    *read = new;
+                }
+            }
+            if let OpKind::TombstoneSSA(value) = &mut op.kind {
+                if *value == old {
+                    *value = new;
+                }
+            }
+        }
+        for call in self.ebb_calls.values_mut() {
+            for arg in call.values.as_mut_slice(&mut self.value_pool) {
+                if *arg == old {
+                    *arg = new;
+                }
+            }
+        }
+    }
+
+    /// Replaces every use of a key of `map`, both in op reads and in
+    /// EbbCall arguments, with the value it maps to. The function is
+    /// walked once, so the map is not applied transitively.
+    pub fn replace_uses(&mut self, map: &HashMap<Value, Value>) {
+        for op in self.ops.values_mut() {
+            for read in op.reads.as_mut_slice(&mut self.value_pool) {
+                if let Some(new) = map.get(read) {
+                    *read = *new;
+                }
+            }
+            if let OpKind::TombstoneSSA(value) = &mut op.kind {
+                if let Some(new) = map.get(value) {
+                    *value = *new;
+                }
+            }
+        }
+        for call in self.ebb_calls.values_mut() {
+            for arg in call.values.as_mut_slice(&mut self.value_pool) {
+                if let Some(new) = map.get(arg) {
+                    *arg = *new;
+                }
+            }
+        }
+    }
+
     pub fn to_text(&self) -> String {
         use crate::text::ToEirText;
 
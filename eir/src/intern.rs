@@ -1,22 +1,22 @@
-use ::string_intern::{ Validator, Symbol };
-use ::std::str::FromStr;
-use ::std::fmt::Display;
+use ::std::collections::HashSet;
+use ::std::fmt::{ Debug, Display };
+use ::std::hash::{ Hash, Hasher };
+use ::std::sync::{ Arc, Mutex };
 
 lazy_static::lazy_static! {
     pub static ref FALSE: Atom = Atom::from("false");
     pub static ref NIL: Atom = Atom::from("false");
-}
 
-struct AtomSymbol;
-impl Validator for AtomSymbol {
-    type Err = ::std::string::ParseError;
-    fn validate_symbol(_val: &str) -> Result<(), Self::Err> {
-        Ok(())
-    }
+    /// Every atom created so far, shared between all threads. Atoms are
+    /// never freed.
+    static ref ATOMS: Mutex<HashSet<Arc<str>>> = Mutex::new(HashSet::new());
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct Atom(Symbol<AtomSymbol>);
+/// An interned string. Equal atoms share the same allocation, so they
+/// are compared by pointer. Atoms can be created and used from any
+/// thread.
+#[derive(Clone)]
+pub struct Atom(Arc<str>);
 
 impl Display for Atom {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
@@ -24,14 +24,39 @@ impl Display for Atom {
     }
 }
 
+impl Debug for Atom {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "Atom({:?})", &*self.0)
+    }
+}
+
+impl PartialEq for Atom {
+    fn eq(&self, other: &Atom) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl Eq for Atom {}
+
+impl Hash for Atom {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
 impl Atom {
 
     pub fn from(string: &'static str) -> Self {
-        Atom(Symbol::from(string))
+        Atom::from_str(string)
     }
 
     pub fn from_str(string: &str) -> Self {
-        Atom(FromStr::from_str(string).unwrap())
+        let mut atoms = ATOMS.lock().unwrap();
+        if let Some(atom) = atoms.get(string) {
+            return Atom(atom.clone());
+        }
+        let atom: Arc<str> = Arc::from(string);
+        atoms.insert(atom.clone());
+        Atom(atom)
     }
 
     pub fn as_str(&self) -> &str {
@@ -41,3 +66,21 @@ impl Atom {
 }
 
 pub type Variable = Atom;
+
+#[cfg(test)]
+mod test {
+    use super::Atom;
+
+    #[test]
+    fn interned_across_threads() {
+        let handles: Vec<_> = (0..4)
+            .map(|_| ::std::thread::spawn(|| Atom::from_str("shared_atom")))
+            .collect();
+        let local = Atom::from_str("shared_atom");
+        for handle in handles {
+            assert!(handle.join().unwrap() == local);
+        }
+        assert!(Atom::from_str("other_atom") != local);
+    }
+
+}
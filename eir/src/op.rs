@@ -233,6 +233,14 @@ impl OpKind {
             OpKind::UnpackMapItem => Some(2),
             OpKind::EqualAtomic(_) => Some(2),
             OpKind::MapGet => Some(2),
+            // Like a call if the primop can fail, a plain op otherwise
+            OpKind::PrimOp(ref name) => BIF_PRIMOPS.iter()
+                .find(|p| p.name == name.as_str())
+                .and_then(|p| if p.failure == PrimOpFailure::Never {
+                    None
+                } else {
+                    Some(2)
+                }),
 
             _ => None,
         }
@@ -248,6 +256,26 @@ impl OpKind {
         }
     }
 
+    /// Whether the op has no effect other than defining its writes.
+    /// A pure op whose writes are never read can be removed.
+    pub fn is_pure(&self) -> bool {
+        match self {
+            OpKind::Move => true,
+            OpKind::MakeTuple => true,
+            OpKind::MakeList => true,
+            OpKind::PackValueList => true,
+            OpKind::UnpackValueList => true,
+            OpKind::CaptureNamedFunction(_) => true,
+            OpKind::MakeClosureEnv { .. } => true,
+            OpKind::BindClosure { .. } => true,
+            OpKind::MakeNoValue => true,
+            OpKind::PrimOp(name) => BIF_PRIMOPS.iter().any(|p| {
+                p.name == name.as_str() && p.failure == PrimOpFailure::Never
+            }),
+            _ => false,
+        }
+    }
+
     pub fn is_block_terminator(&self) -> bool {
         match self {
             OpKind::Call { tail_call: true } => true,
@@ -266,3 +294,65 @@ impl OpKind {
     }
 
 }
+
+/// How a BIF lowered to a `PrimOp` can fail.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PrimOpFailure {
+    /// Never raises an exception.
+    Never,
+    /// Raises `badarg` when given arguments of the wrong type.
+    Badarg,
+    /// Raises `badarith` on non-numeric arguments or division by zero.
+    Badarith,
+}
+
+#[derive(Debug)]
+pub struct BifPrimOp {
+    pub name: &'static str,
+    pub arity: usize,
+    pub failure: PrimOpFailure,
+}
+
+macro_rules! bif_primops {
+    ($($name:expr, $arity:expr => $failure:ident;)*) => {
+        &[$(BifPrimOp {
+            name: $name,
+            arity: $arity,
+            failure: PrimOpFailure::$failure,
+        }),*]
+    };
+}
+
+/// BIFs in the `erlang` module which can be lowered to a `PrimOp`.
+/// They have no side effects, and their result only depends on their
+/// arguments.
+///
+/// A `PrimOp` named after one of these takes the call arguments as
+/// reads. If the BIF can never fail, the op has a single write, the
+/// result. Otherwise it has two writes, the result and the exception,
+/// and a single branch which is taken on failure, like a `Call`.
+pub const BIF_PRIMOPS: &[BifPrimOp] = bif_primops! {
+    "+", 1 => Badarith; "-", 1 => Badarith;
+    "+", 2 => Badarith; "-", 2 => Badarith; "*", 2 => Badarith;
+    "div", 2 => Badarith; "rem", 2 => Badarith; "abs", 1 => Badarg;
+
+    "==", 2 => Never; "/=", 2 => Never; "=<", 2 => Never; "<", 2 => Never;
+    ">=", 2 => Never; ">", 2 => Never; "=:=", 2 => Never; "=/=", 2 => Never;
+
+    "not", 1 => Badarg; "and", 2 => Badarg; "or", 2 => Badarg;
+    "xor", 2 => Badarg;
+
+    "is_atom", 1 => Never; "is_binary", 1 => Never;
+    "is_bitstring", 1 => Never; "is_boolean", 1 => Never;
+    "is_float", 1 => Never; "is_function", 1 => Never;
+    "is_integer", 1 => Never; "is_list", 1 => Never; "is_map", 1 => Never;
+    "is_number", 1 => Never; "is_pid", 1 => Never; "is_port", 1 => Never;
+    "is_reference", 1 => Never; "is_tuple", 1 => Never;
+
+    "hd", 1 => Badarg; "tl", 1 => Badarg; "length", 1 => Badarg;
+    "element", 2 => Badarg; "tuple_size", 1 => Badarg;
+};
+
+pub fn bif_primop(name: &str, arity: usize) -> Option<&'static BifPrimOp> {
+    BIF_PRIMOPS.iter().find(|p| p.name == name && p.arity == arity)
+}
@@ -1,5 +1,5 @@
-use crate::Function;
-use super::printer::ToEirTextFun;
+use crate::{ Function, Ebb, Op };
+use super::printer::{ ToEirTextFun, format_ebb_label };
 
 const DOT_BREAK: &str = "<br align=\"left\" />";
 
@@ -15,8 +15,27 @@ fn format_label(label: &str) -> String {
         .replace("\n", DOT_BREAK)
 }
 
+/// Extra information drawn on top of the control flow graph, like
+/// coverage or profiling data. Nothing is added by default.
+pub trait DotOverlay {
+    /// Node attributes added to the Ebb, like `style=filled`.
+    fn ebb_attributes(&self, _ebb: Ebb) -> Option<String> { None }
+    /// Line shown under the label of the Ebb.
+    fn ebb_note(&self, _ebb: Ebb) -> Option<String> { None }
+    /// Shown in front of the op.
+    fn op_note(&self, _op: Op) -> Option<String> { None }
+}
+
+pub struct NoOverlay;
+impl DotOverlay for NoOverlay {}
+
 use std::io::Write;
 pub fn function_to_dot(fun: &Function, w: &mut Write) -> ::std::io::Result<()> {
+    function_to_dot_with_overlay(fun, &NoOverlay, w)
+}
+
+pub fn function_to_dot_with_overlay(fun: &Function, overlay: &DotOverlay,
+                                    w: &mut Write) -> ::std::io::Result<()> {
 
     write!(w, "digraph g {{\n")?;
     write!(w, "node [labeljust=\"l\", shape=record, fontname=\"Courier New\"]\n")?;
@@ -40,12 +59,27 @@ pub fn function_to_dot(fun: &Function, w: &mut Write) -> ::std::io::Result<()> {
         write!(w, "blk_{} [ label=<", ebb)?;
 
         buf.clear();
-        ebb.to_eir_text_fun(fun, 0, &mut buf).unwrap();
+        format_ebb_label(ebb, fun, &mut buf)?;
+        if let Some(note) = overlay.ebb_note(ebb) {
+            write!(buf, "{}\n", note)?;
+        }
+        for op in fun.iter_op(ebb) {
+            write!(buf, "    ")?;
+            if let Some(note) = overlay.op_note(op) {
+                write!(buf, "{} | ", note)?;
+            }
+            op.to_eir_text_fun(fun, 0, &mut buf)?;
+            write!(buf, "\n")?;
+        }
         let text = std::str::from_utf8(&buf).unwrap();
         let text = format_label(text);
         write!(w, "{}", text)?;
 
-        write!(w, "> ];\n")?;
+        write!(w, ">")?;
+        if let Some(attributes) = overlay.ebb_attributes(ebb) {
+            write!(w, ", {}", attributes)?;
+        }
+        write!(w, " ];\n")?;
 
         for op in fun.iter_op(ebb) {
             for branch in fun.op_branches(op) {
@@ -60,3 +94,59 @@ pub fn function_to_dot(fun: &Function, w: &mut Write) -> ::std::io::Result<()> {
     write!(w, "}}\n")?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::{ DotOverlay, function_to_dot, function_to_dot_with_overlay };
+    use crate::{ Function, FunctionBuilder, FunctionIdent, Atom, Ebb, Op };
+
+    fn build() -> Function {
+        let ident = FunctionIdent {
+            module: Atom::from_str("test"),
+            name: Atom::from_str("fun"),
+            arity: 1,
+            lambda: None,
+        };
+        let mut fun = Function::new(ident);
+        {
+            let mut b = FunctionBuilder::new(&mut fun);
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+            let tuple = b.op_make_tuple(&[arg]);
+            b.op_return_ok(tuple);
+        }
+        fun
+    }
+
+    struct Hits;
+    impl DotOverlay for Hits {
+        fn ebb_attributes(&self, _ebb: Ebb) -> Option<String> {
+            Some("style=filled".to_string())
+        }
+        fn ebb_note(&self, _ebb: Ebb) -> Option<String> {
+            Some("entered 2 times".to_string())
+        }
+        fn op_note(&self, _op: Op) -> Option<String> {
+            Some("2".to_string())
+        }
+    }
+
+    #[test]
+    fn overlay_annotates_ebbs_and_ops() {
+        let fun = build();
+
+        let mut plain = Vec::new();
+        function_to_dot(&fun, &mut plain).unwrap();
+        let plain = String::from_utf8(plain).unwrap();
+        assert!(!plain.contains("style=filled"));
+
+        let mut out = Vec::new();
+        function_to_dot_with_overlay(&fun, &Hits, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("entered 2 times"));
+        assert!(out.contains(", style=filled ];"));
+        assert!(out.matches("2 \\| ").count() == 2);
+    }
+
+}
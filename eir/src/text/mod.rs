@@ -2,4 +2,4 @@ pub mod printer;
 pub use printer::{ ToEirText, ToEirTextFun };
 
 pub mod dot_printer;
-pub use dot_printer::function_to_dot;
+pub use dot_printer::{ function_to_dot, function_to_dot_with_overlay, DotOverlay };
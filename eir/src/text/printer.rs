@@ -197,7 +197,7 @@ impl ToEirTextFun for EbbCall {
     }
 }
 
-fn format_ebb_label(ebb: Ebb, fun: &Function, out: &mut Write) -> std::io::Result<()> {
+pub(crate) fn format_ebb_label(ebb: Ebb, fun: &Function, out: &mut Write) -> std::io::Result<()> {
     write!(out, "B{}", ebb.index())?;
     let args = fun.ebb_args(ebb);
     if args.len() > 0 {
@@ -290,266 +290,273 @@ impl ToEirTextFun for Ebb {
 
         for op in fun.iter_op(*self) {
             write_indent(out, indent+1)?;
+            op.to_eir_text_fun(fun, indent, out)?;
+            write!(out, "\n")?;
+        }
 
-            let writes = fun.op_writes(op);
-            format_value_list(writes, fun, out)?;
-            if writes.len() > 0 { write!(out, " = ")?; }
-
-            let reads = fun.op_reads(op);
-            let branches = fun.op_branches(op);
-
-            let sig = (reads.len(), writes.len(), branches.len());
-
-            let kind = fun.op_kind(op);
-            let mut default_reads = true;
-            let mut default_branch = true;
-            match kind {
-                OpKind::Jump => {
-                    assert!(sig == (0, 0, 1));
-                    write!(out, "jump ")?;
-                    branches[0].to_eir_text_fun(fun, indent, out)?;
-                    default_branch = false;
-                },
-                OpKind::PackValueList => {
-                    assert_matches!(sig, (_, 1, 0));
-                    write!(out, "pack_value_list")?;
-                },
-                OpKind::UnpackValueList => {
-                    assert_matches!(sig, (1, _, 0));
-                    write!(out, "unpack_value_list")?;
-                },
-                OpKind::CaseStart { clauses } => {
-                    assert_matches!(sig, (_, 1, 1));
-                    write!(out, "case_start on: ")?;
-                    format_value(reads[0], fun, out)?;
-                    write!(out, ", values: [")?;
-                    format_value_list(&reads[1..], fun, out)?;
-                    write!(out, "] {{\n")?;
-
-                    for clause in clauses {
-                        write_indent(out, indent+2)?;
-
-                        write!(out, "clause assigns: [")?;
-                        for (idx, assign) in clause.assigns.iter().enumerate() {
-                            if idx != 0 {
-                                write!(out, ", ")?;
-                            }
-                            write!(out, "A{}", assign.0)?;
-                        }
-                        write!(out, "] {{\n")?;
+        Ok(())
+    }
+}
 
-                        for pattern in clause.patterns.iter() {
-                            write_indent(out, indent+3)?;
-                            write!(out, "pattern ")?;
-                            pattern.node.to_eir_text(indent, out)?;
-                            write!(out, ";\n")?;
+impl ToEirTextFun for Op {
+    fn to_eir_text_fun(&self, fun: &Function, indent: usize, out: &mut Write) -> std::io::Result<()> {
+        let op = *self;
+
+        let writes = fun.op_writes(op);
+        format_value_list(writes, fun, out)?;
+        if writes.len() > 0 { write!(out, " = ")?; }
+
+        let reads = fun.op_reads(op);
+        let branches = fun.op_branches(op);
+
+        let sig = (reads.len(), writes.len(), branches.len());
+
+        let kind = fun.op_kind(op);
+        let mut default_reads = true;
+        let mut default_branch = true;
+        match kind {
+            OpKind::Jump => {
+                assert!(sig == (0, 0, 1));
+                write!(out, "jump ")?;
+                branches[0].to_eir_text_fun(fun, indent, out)?;
+                default_branch = false;
+            },
+            OpKind::PackValueList => {
+                assert_matches!(sig, (_, 1, 0));
+                write!(out, "pack_value_list")?;
+            },
+            OpKind::UnpackValueList => {
+                assert_matches!(sig, (1, _, 0));
+                write!(out, "unpack_value_list")?;
+            },
+            OpKind::CaseStart { clauses } => {
+                assert_matches!(sig, (_, 1, 1));
+                write!(out, "case_start on: ")?;
+                format_value(reads[0], fun, out)?;
+                write!(out, ", values: [")?;
+                format_value_list(&reads[1..], fun, out)?;
+                write!(out, "] {{\n")?;
+
+                for clause in clauses {
+                    write_indent(out, indent+2)?;
+
+                    write!(out, "clause assigns: [")?;
+                    for (idx, assign) in clause.assigns.iter().enumerate() {
+                        if idx != 0 {
+                            write!(out, ", ")?;
                         }
-
-                        write_indent(out, indent+2)?;
-                        write!(out, "}};\n")?;
+                        write!(out, "A{}", assign.0)?;
                     }
+                    write!(out, "] {{\n")?;
 
-                    write_indent(out, indent+1)?;
-                    write!(out, "}}")?;
-                    default_reads = false;
-                },
-                OpKind::Case(num_clauses) => {
-                    assert_matches!(sig, (1, 0, _));
-                    assert!(sig.2 == num_clauses + 1);
-                    write!(out, "case_body")?;
-                },
-                OpKind::CaseGuardOk => {
-                    assert_matches!(sig, (1, 0, 0));
-                    write!(out, "case_guard_ok")?;
-                },
-                OpKind::CaseGuardFail { .. } => {
-                    assert_matches!(sig, (1, 0, 1));
-                    write!(out, "case_guard_fail")?;
-                },
-                OpKind::IfTruthy => {
-                    assert_matches!(sig, (1, 0, 1));
-                    write!(out, "if_truthy ")?;
-                    format_value(reads[0], fun, out)?;
-                    write!(out, " else ")?;
-                    format_branches(branches, fun, indent, out)?;
-                    default_reads = false;
-                    default_branch = false;
-                },
-                OpKind::MakeTuple => {
-                    assert_matches!(sig, (_, 1, 0));
-                    write!(out, "make_tuple")?;
-                },
-                OpKind::UnpackTuple => {
-                    assert_matches!(sig, (1, _, 1));
-                    write!(out, "unpack_tuple")?;
-                },
-                OpKind::MakeList => {
-                    assert_matches!(sig, (_, 1, 0));
-                    write!(out, "make_list ")?;
-
-                    write!(out, "[")?;
-                    format_value_list(&reads[1..], fun, out)?;
-                    write!(out, " | ")?;
-                    format_value_list(&[reads[0]], fun, out)?;
-                    write!(out, "]")?;
-
-                    default_reads = false;
-                },
-                OpKind::MakeBinary => {
-                    write!(out, "make_binary")?;
-                },
-                OpKind::UnpackListCell => {
-                    write!(out, "unpack_list_cell")?;
-                },
-                OpKind::MakeNoValue => {
-                    write!(out, "make_no_value")?;
-                },
-                OpKind::Call { tail_call } => {
-                    if *tail_call {
-                        assert_matches!(sig, (_, 0, 0));
-                        write!(out, "tail_call")?;
-                    } else {
-                        assert_matches!(sig, (_, 2, 1));
-                        write!(out, "call")?;
+                    for pattern in clause.patterns.iter() {
+                        write_indent(out, indent+3)?;
+                        write!(out, "pattern ")?;
+                        pattern.node.to_eir_text(indent, out)?;
+                        write!(out, ";\n")?;
                     }
 
-                    write!(out, " ")?;
-                    format_value(reads[0], fun, out)?;
-                    write!(out, ":")?;
-                    format_value(reads[1], fun, out)?;
+                    write_indent(out, indent+2)?;
+                    write!(out, "}};\n")?;
+                }
 
-                    write!(out, "(")?;
-                    format_value_list(&reads[2..], fun, out)?;
-                    write!(out, ")")?;
+                write_indent(out, indent+1)?;
+                write!(out, "}}")?;
+                default_reads = false;
+            },
+            OpKind::Case(num_clauses) => {
+                assert_matches!(sig, (1, 0, _));
+                assert!(sig.2 == num_clauses + 1);
+                write!(out, "case_body")?;
+            },
+            OpKind::CaseGuardOk => {
+                assert_matches!(sig, (1, 0, 0));
+                write!(out, "case_guard_ok")?;
+            },
+            OpKind::CaseGuardFail { .. } => {
+                assert_matches!(sig, (1, 0, 1));
+                write!(out, "case_guard_fail")?;
+            },
+            OpKind::IfTruthy => {
+                assert_matches!(sig, (1, 0, 1));
+                write!(out, "if_truthy ")?;
+                format_value(reads[0], fun, out)?;
+                write!(out, " else ")?;
+                format_branches(branches, fun, indent, out)?;
+                default_reads = false;
+                default_branch = false;
+            },
+            OpKind::MakeTuple => {
+                assert_matches!(sig, (_, 1, 0));
+                write!(out, "make_tuple")?;
+            },
+            OpKind::UnpackTuple => {
+                assert_matches!(sig, (1, _, 1));
+                write!(out, "unpack_tuple")?;
+            },
+            OpKind::MakeList => {
+                assert_matches!(sig, (_, 1, 0));
+                write!(out, "make_list ")?;
 
-                    if !*tail_call {
-                        write!(out, " except ")?;
-                        format_branches(branches, fun, indent, out)?;
-                    }
+                write!(out, "[")?;
+                format_value_list(&reads[1..], fun, out)?;
+                write!(out, " | ")?;
+                format_value_list(&[reads[0]], fun, out)?;
+                write!(out, "]")?;
 
-                    default_reads = false;
-                    default_branch = false;
-                },
-                OpKind::Apply { tail_call } => {
-                    if *tail_call {
-                        assert_matches!(sig, (_, 0, 0));
-                        write!(out, "tail_apply")?;
-                    } else {
-                        assert_matches!(sig, (_, 2, 1));
-                        write!(out, "apply")?;
-                    }
+                default_reads = false;
+            },
+            OpKind::MakeBinary => {
+                write!(out, "make_binary")?;
+            },
+            OpKind::UnpackListCell => {
+                write!(out, "unpack_list_cell")?;
+            },
+            OpKind::MakeNoValue => {
+                write!(out, "make_no_value")?;
+            },
+            OpKind::Call { tail_call } => {
+                if *tail_call {
+                    assert_matches!(sig, (_, 0, 0));
+                    write!(out, "tail_call")?;
+                } else {
+                    assert_matches!(sig, (_, 2, 1));
+                    write!(out, "call")?;
+                }
 
-                    write!(out, " ")?;
-                    format_value(reads[0], fun, out)?;
+                write!(out, " ")?;
+                format_value(reads[0], fun, out)?;
+                write!(out, ":")?;
+                format_value(reads[1], fun, out)?;
 
-                    write!(out, "(")?;
-                    format_value_list(&reads[1..], fun, out)?;
-                    write!(out, ")")?;
+                write!(out, "(")?;
+                format_value_list(&reads[2..], fun, out)?;
+                write!(out, ")")?;
 
-                    if !*tail_call {
-                        write!(out, " except ")?;
-                        format_branches(branches, fun, indent, out)?;
-                    }
+                if !*tail_call {
+                    write!(out, " except ")?;
+                    format_branches(branches, fun, indent, out)?;
+                }
 
-                    default_reads = false;
-                    default_branch = false;
-                },
-                OpKind::ReceiveStart => {
-                    assert_matches!(sig, (1, 1, 1));
-                    write!(out, "receive_start")?;
-                },
-                OpKind::ReceiveGetMessage => {
-                    assert_matches!(sig, (1, 1, 0));
-                    write!(out, "receive_get_message")?;
-                },
-                OpKind::ReceiveWait => {
-                    assert_matches!(sig, (1, 0, 2));
-                    write!(out, "receive_wait")?;
-                },
-                OpKind::UnpackEnv => {
-                    assert_matches!(sig, (1, _, 0));
-                    write!(out, "unpack_env")?;
-                },
-                OpKind::MakeClosureEnv { env_idx } => {
-                    assert_matches!(sig, (_, 1, 0));
-                    write!(out, "pack_env E{}", env_idx.index())?;
-                },
-                OpKind::CaseValues =>
-                    write!(out, "case_values")?,
-                OpKind::ReturnThrow => {
-                    assert_matches!(sig, (1, 0, 0));
-                    write!(out, "return_throw")?;
-                },
-                OpKind::ReturnOk => {
-                    assert_matches!(sig, (1, 0, 0));
-                    write!(out, "return_ok")?;
-                },
-                OpKind::BindClosure { ident } => {
-                    assert_matches!(sig, (1, 1, 0));
-                    write!(out, "bind_closure ")?;
-                    ident.to_eir_text(indent, out)?;
-                    write!(out, " with")?;
-                },
-                OpKind::CaptureNamedFunction(ident) => {
-                    assert_matches!(sig, (0, 1, 0));
-                    write!(out, "capture_function ")?;
-                    ident.to_eir_text(indent, out)?;
-                },
-                OpKind::ExcTrace => {
-                    assert_matches!(sig, (1, 1, 0));
-                    write!(out, "exc_trace")?;
-                },
-                OpKind::Unreachable => {
-                    assert_matches!(sig, (0, 0, 0));
-                    write!(out, "unreachable")?;
-                },
-                OpKind::ComparisonOperation(oper) => {
-                    assert_matches!(sig, (2, 0, 1));
-                    write!(out, "compare ")?;
-                    match oper {
-                        ComparisonOperation::Equal => {
-                            write!(out, "equal")?;
-                        }
-                        _ => unimplemented!(),
-                    }
-                },
-                OpKind::Move => {
-                    assert_matches!(sig, (1, 1, 0));
-                    write!(out, "move")?;
-                },
-                OpKind::PrimOp(atom) => {
-                    write!(out, "prim_op {}", atom)?;
-                },
-                _ => {
-                    unimplemented!("ToEirText unimplemented for: {:?}", kind);
-                },
-            }
+                default_reads = false;
+                default_branch = false;
+            },
+            OpKind::Apply { tail_call } => {
+                if *tail_call {
+                    assert_matches!(sig, (_, 0, 0));
+                    write!(out, "tail_apply")?;
+                } else {
+                    assert_matches!(sig, (_, 2, 1));
+                    write!(out, "apply")?;
+                }
 
-            if default_reads && reads.len() > 0 {
                 write!(out, " ")?;
-                if reads.len() > 1 {
-                    write!(out, "[")?;
+                format_value(reads[0], fun, out)?;
+
+                write!(out, "(")?;
+                format_value_list(&reads[1..], fun, out)?;
+                write!(out, ")")?;
+
+                if !*tail_call {
+                    write!(out, " except ")?;
+                    format_branches(branches, fun, indent, out)?;
                 }
-                format_value_list(reads, fun, out)?;
-                if reads.len() > 1 {
-                    write!(out, "]")?;
+
+                default_reads = false;
+                default_branch = false;
+            },
+            OpKind::ReceiveStart => {
+                assert_matches!(sig, (1, 1, 1));
+                write!(out, "receive_start")?;
+            },
+            OpKind::ReceiveGetMessage => {
+                assert_matches!(sig, (1, 1, 0));
+                write!(out, "receive_get_message")?;
+            },
+            OpKind::ReceiveWait => {
+                assert_matches!(sig, (1, 0, 2));
+                write!(out, "receive_wait")?;
+            },
+            OpKind::ReceiveFinish => {
+                assert_matches!(sig, (1, 0, 0));
+                write!(out, "receive_finish")?;
+            },
+            OpKind::UnpackEnv => {
+                assert_matches!(sig, (1, _, 0));
+                write!(out, "unpack_env")?;
+            },
+            OpKind::MakeClosureEnv { env_idx } => {
+                assert_matches!(sig, (_, 1, 0));
+                write!(out, "pack_env E{}", env_idx.index())?;
+            },
+            OpKind::CaseValues =>
+                write!(out, "case_values")?,
+            OpKind::ReturnThrow => {
+                assert_matches!(sig, (1, 0, 0));
+                write!(out, "return_throw")?;
+            },
+            OpKind::ReturnOk => {
+                assert_matches!(sig, (1, 0, 0));
+                write!(out, "return_ok")?;
+            },
+            OpKind::BindClosure { ident } => {
+                assert_matches!(sig, (1, 1, 0));
+                write!(out, "bind_closure ")?;
+                ident.to_eir_text(indent, out)?;
+                write!(out, " with")?;
+            },
+            OpKind::CaptureNamedFunction(ident) => {
+                assert_matches!(sig, (0, 1, 0));
+                write!(out, "capture_function ")?;
+                ident.to_eir_text(indent, out)?;
+            },
+            OpKind::ExcTrace => {
+                assert_matches!(sig, (1, 1, 0));
+                write!(out, "exc_trace")?;
+            },
+            OpKind::Unreachable => {
+                assert_matches!(sig, (0, 0, 0));
+                write!(out, "unreachable")?;
+            },
+            OpKind::ComparisonOperation(oper) => {
+                assert_matches!(sig, (2, 0, 1));
+                write!(out, "compare ")?;
+                match oper {
+                    ComparisonOperation::Equal => {
+                        write!(out, "equal")?;
+                    }
+                    _ => unimplemented!(),
                 }
-            }
+            },
+            OpKind::Move => {
+                assert_matches!(sig, (1, 1, 0));
+                write!(out, "move")?;
+            },
+            OpKind::PrimOp(atom) => {
+                write!(out, "prim_op {}", atom)?;
+            },
+            _ => {
+                unimplemented!("ToEirText unimplemented for: {:?}", kind);
+            },
+        }
 
-            if default_branch && branches.len() > 0 {
-                write!(out, " branch ")?;
-                format_branches(branches, fun, indent, out)?;
+        if default_reads && reads.len() > 0 {
+            write!(out, " ")?;
+            if reads.len() > 1 {
+                write!(out, "[")?;
             }
+            format_value_list(reads, fun, out)?;
+            if reads.len() > 1 {
+                write!(out, "]")?;
+            }
+        }
 
-            write!(out, ";\n")?;
+        if default_branch && branches.len() > 0 {
+            write!(out, " branch ")?;
+            format_branches(branches, fun, indent, out)?;
         }
 
+        write!(out, ";")?;
         Ok(())
     }
 }
-
-//impl ToEirTextFun for Op {
-//    fn to_eir_text_fun(&self, fun: &Function, out: &mut Write) -> std::io::Result<()> {
-//
-//    }
-//}
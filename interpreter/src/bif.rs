@@ -0,0 +1,208 @@
+//! Native functions with typed Rust signatures.
+//!
+//! Any function whose arguments implement `FromTerm` and which returns
+//! a `BifResult` of something implementing `IntoTerm` can be registered
+//! with `NativeModule::add_bif`. The arity is taken from the signature,
+//! and arguments that fail to decode raise `badarg` before the function
+//! is called. A function raises an error by returning a `BifError` with
+//! the reason, or `Badarg` through `?`.
+//!
+//! ```ignore
+//! fn size(map: Map) -> BifResult<usize> {
+//!     Ok(map.len())
+//! }
+//! module.add_bif("size", size);
+//! ```
+//!
+//! Functions that need the VM or the calling process take them as their
+//! first two arguments, and are registered the same way.
+
+use std::marker::PhantomData;
+
+use ::vm::VMState;
+use ::term::Term;
+use ::process::{ CallReturn, ProcessContext };
+use ::convert::{ IntoTerm, FromTerm, ConvertError };
+
+/// Raised when a typed BIF is called with arguments it does not accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Badarg;
+
+impl From<ConvertError> for Badarg {
+    fn from(_err: ConvertError) -> Badarg {
+        Badarg
+    }
+}
+
+/// Error raised by a typed BIF, with the reason of the exception.
+#[derive(Debug, Clone)]
+pub struct BifError(pub Term);
+
+impl From<Badarg> for BifError {
+    fn from(_err: Badarg) -> BifError {
+        BifError(Term::new_atom("badarg"))
+    }
+}
+
+pub type BifResult<T> = Result<T, BifError>;
+
+/// Marker for functions taking only decoded arguments.
+pub struct Plain<A>(PhantomData<A>);
+/// Marker for functions taking the VM and the calling process first.
+pub struct WithContext<A>(PhantomData<A>);
+
+/// A function that can be registered as a native function. Implemented
+/// for functions of up to six decoded arguments, see the module docs.
+pub trait TypedBif<Marker>: Send + Sync + 'static {
+    fn arity(&self) -> usize;
+    fn call(&self, vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn;
+}
+
+fn badarg(proc: &mut ProcessContext) -> CallReturn {
+    proc.raise(Term::new_atom("badarg"))
+}
+
+fn to_call_return<R: IntoTerm>(proc: &mut ProcessContext, result: BifResult<R>) -> CallReturn {
+    match result {
+        Ok(ret) => CallReturn::Return { term: ret.into_term() },
+        Err(BifError(reason)) => proc.raise(reason),
+    }
+}
+
+macro_rules! typed_bif_impls {
+    ($($arg:ident $val:ident $idx:tt),*) => {
+        impl<Fun, R, $($arg),*> TypedBif<Plain<($($arg,)*)>> for Fun
+            where Fun: Fn($($arg),*) -> BifResult<R> + Send + Sync + 'static,
+                  R: IntoTerm, $($arg: FromTerm),*
+        {
+            fn arity(&self) -> usize {
+                <[&str]>::len(&[$(stringify!($arg)),*])
+            }
+            fn call(&self, _vm: &VMState, proc: &mut ProcessContext,
+                    args: &[Term]) -> CallReturn {
+                if args.len() != self.arity() {
+                    return badarg(proc);
+                }
+                $(
+                    let $val = match $arg::from_term(&args[$idx]) {
+                        Ok(val) => val,
+                        Err(_) => return badarg(proc),
+                    };
+                )*
+                to_call_return(proc, self($($val),*))
+            }
+        }
+
+        impl<Fun, R, $($arg),*> TypedBif<WithContext<($($arg,)*)>> for Fun
+            where Fun: Fn(&VMState, &mut ProcessContext $(, $arg)*) -> BifResult<R>
+                       + Send + Sync + 'static,
+                  R: IntoTerm, $($arg: FromTerm),*
+        {
+            fn arity(&self) -> usize {
+                <[&str]>::len(&[$(stringify!($arg)),*])
+            }
+            fn call(&self, vm: &VMState, proc: &mut ProcessContext,
+                    args: &[Term]) -> CallReturn {
+                if args.len() != self.arity() {
+                    return badarg(proc);
+                }
+                $(
+                    let $val = match $arg::from_term(&args[$idx]) {
+                        Ok(val) => val,
+                        Err(_) => return badarg(proc),
+                    };
+                )*
+                let result = self(vm, proc $(, $val)*);
+                to_call_return(proc, result)
+            }
+        }
+    };
+}
+
+typed_bif_impls!();
+typed_bif_impls!(A a 0);
+typed_bif_impls!(A a 0, B b 1);
+typed_bif_impls!(A a 0, B b 1, C c 2);
+typed_bif_impls!(A a 0, B b 1, C c 2, D d 3);
+typed_bif_impls!(A a 0, B b 1, C c 2, D d 3, E e 4);
+typed_bif_impls!(A a 0, B b 1, C c 2, D d 3, E e 4, F f 5);
+
+#[cfg(test)]
+mod test {
+    use super::{ BifResult, BifError, Badarg, TypedBif };
+    use ::module::NativeModule;
+    use ::term::{ Term, ErlEq };
+    use ::vm::VMState;
+    use ::process::{ CallReturn, ProcessContext };
+    use ::Pid;
+
+    fn add(a: i64, b: i64) -> BifResult<i64> {
+        Ok(a + b)
+    }
+
+    fn first(list: Vec<Term>) -> BifResult<Term> {
+        Ok(list.into_iter().next().ok_or(Badarg)?)
+    }
+
+    fn not_found(key: Term) -> BifResult<Term> {
+        Err(BifError(Term::Tuple(vec![Term::new_atom("badkey"), key])))
+    }
+
+    #[test]
+    fn arity_from_signature() {
+        let mut module = NativeModule::new("test".to_string());
+        module.add_bif("add", add);
+        module.add_bif("first", first);
+        assert!(module.functions.contains_key(&("add".to_string(), 2)));
+        assert!(module.functions.contains_key(&("first".to_string(), 1)));
+    }
+
+    fn call<M, F: TypedBif<M>>(fun: F, args: &[Term]) -> (CallReturn, Option<Term>) {
+        let vm = VMState::new();
+        let mut proc = ProcessContext::new(Pid(0));
+        let ret = fun.call(&vm, &mut proc, args);
+        (ret, proc.exception().cloned())
+    }
+
+    fn is_badarg(result: (CallReturn, Option<Term>)) -> bool {
+        match result {
+            (CallReturn::Throw, Some(Term::Atom(ref atom))) => atom.as_str() == "badarg",
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn decoded_call() {
+        match call(add, &[Term::new_i64(1), Term::new_i64(2)]) {
+            (CallReturn::Return { term }, None) => assert!(term.as_i64() == Some(3)),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn wrong_types_raise_badarg() {
+        assert!(is_badarg(call(add, &[Term::new_i64(1), Term::new_atom("two")])));
+        assert!(is_badarg(call(first, &[Term::new_i64(1)])));
+    }
+
+    #[test]
+    fn wrong_arity_raises_badarg() {
+        assert!(is_badarg(call(add, &[Term::new_i64(1)])));
+        assert!(is_badarg(call(add, &[Term::new_i64(1), Term::new_i64(2), Term::new_i64(3)])));
+    }
+
+    #[test]
+    fn returned_badarg_is_raised() {
+        assert!(is_badarg(call(first, &[Term::Nil])));
+    }
+
+    #[test]
+    fn returned_reason_is_raised() {
+        let expected = Term::Tuple(vec![Term::new_atom("badkey"), Term::new_i64(1)]);
+        match call(not_found, &[Term::new_i64(1)]) {
+            (CallReturn::Throw, Some(reason)) => assert!(reason.erl_eq(&expected)),
+            _ => panic!(),
+        }
+    }
+
+}
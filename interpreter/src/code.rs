@@ -0,0 +1,148 @@
+//! Two-version code loading.
+//!
+//! Every module has at most a current and an old version. Loading a
+//! module makes the current version old, and fails if there already is
+//! old code that has not been purged. Each loaded version gets a unique
+//! number, which stack frames and local funs hold on to, so they keep
+//! running the version they started in while fully qualified calls go
+//! to the current one.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ::module::ModuleType;
+
+#[derive(Clone)]
+pub struct ModuleVersion {
+    pub version: u64,
+    pub module: Arc<ModuleType>,
+}
+
+#[derive(Default)]
+struct ModuleEntry {
+    current: Option<ModuleVersion>,
+    old: Option<ModuleVersion>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    /// The module has old code which must be purged first.
+    NotPurged,
+}
+
+#[derive(Default)]
+pub struct CodeTable {
+    modules: HashMap<String, ModuleEntry>,
+    next_version: u64,
+}
+
+impl CodeTable {
+
+    pub fn new() -> Self {
+        CodeTable::default()
+    }
+
+    /// Loads `module` as the current version of `name`, making the
+    /// previous current version old. Returns the new version number.
+    pub fn load(&mut self, name: &str, module: ModuleType) -> Result<u64, LoadError> {
+        let version = self.next_version;
+        let entry = self.modules.entry(name.to_string()).or_insert_with(ModuleEntry::default);
+        if entry.old.is_some() {
+            return Err(LoadError::NotPurged);
+        }
+        self.next_version += 1;
+        entry.old = entry.current.take();
+        entry.current = Some(ModuleVersion {
+            version: version,
+            module: Arc::new(module),
+        });
+        Ok(version)
+    }
+
+    /// Makes the current version of the module old, so that it can no
+    /// longer be called with fully qualified calls. Fails if there is
+    /// no current version, or if old code is still present.
+    pub fn delete(&mut self, name: &str) -> bool {
+        match self.modules.get_mut(name) {
+            Some(entry) if entry.current.is_some() && entry.old.is_none() => {
+                entry.old = entry.current.take();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes the old version of the module, returning it.
+    pub fn purge(&mut self, name: &str) -> Option<ModuleVersion> {
+        let entry = self.modules.get_mut(name)?;
+        let old = entry.old.take();
+        if entry.current.is_none() {
+            self.modules.remove(name);
+        }
+        old
+    }
+
+    pub fn current(&self, name: &str) -> Option<&ModuleVersion> {
+        self.modules.get(name).and_then(|e| e.current.as_ref())
+    }
+
+    pub fn old(&self, name: &str) -> Option<&ModuleVersion> {
+        self.modules.get(name).and_then(|e| e.old.as_ref())
+    }
+
+    pub(crate) fn current_mut(&mut self, name: &str) -> Option<&mut ModuleVersion> {
+        self.modules.get_mut(name).and_then(|e| e.current.as_mut())
+    }
+
+    /// Looks up the given version of the module, or the current one if
+    /// no version is given.
+    pub fn get(&self, name: &str, version: Option<u64>) -> Option<&ModuleVersion> {
+        let entry = self.modules.get(name)?;
+        match version {
+            None => entry.current.as_ref(),
+            Some(version) => entry.current.iter().chain(entry.old.iter())
+                .find(|v| v.version == version),
+        }
+    }
+
+    pub fn is_loaded(&self, name: &str) -> bool {
+        self.current(name).is_some()
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ CodeTable, LoadError };
+    use ::module::{ ModuleType, NativeModule };
+
+    fn native(name: &str) -> ModuleType {
+        ModuleType::Native(NativeModule::new(name.to_string()))
+    }
+
+    #[test]
+    fn two_versions() {
+        let mut code = CodeTable::new();
+        let v1 = code.load("m", native("m")).unwrap();
+        let v2 = code.load("m", native("m")).unwrap();
+        assert!(code.current("m").unwrap().version == v2);
+        assert!(code.get("m", Some(v1)).is_some());
+        assert!(code.load("m", native("m")).unwrap_err() == LoadError::NotPurged);
+
+        assert!(code.purge("m").unwrap().version == v1);
+        assert!(code.get("m", Some(v1)).is_none());
+        assert!(code.load("m", native("m")).is_ok());
+    }
+
+    #[test]
+    fn delete_then_purge() {
+        let mut code = CodeTable::new();
+        code.load("m", native("m")).unwrap();
+        assert!(code.delete("m"));
+        assert!(!code.is_loaded("m"));
+        assert!(!code.delete("m"));
+        assert!(code.purge("m").is_some());
+        assert!(code.old("m").is_none());
+    }
+
+}
@@ -0,0 +1,439 @@
+//! Conversions between `Term` and Rust types, for host code embedding
+//! the interpreter.
+//!
+//! | Rust                    | Erlang                     |
+//! |-------------------------|----------------------------|
+//! | integers, `BigInt`      | integer                    |
+//! | `f64`                   | float                      |
+//! | `bool`                  | `true` / `false`           |
+//! | `()`                    | `ok`                       |
+//! | `String`, `&str`        | charlist                   |
+//! | `Binary`                | binary                     |
+//! | `Vec<T>`                | proper list                |
+//! | tuples                  | tuple                      |
+//! | `HashMap<K, V>`         | map                        |
+//! | `Option<T>`             | `{ok, T}` / `error`        |
+//! | `Result<T, E>`          | `{ok, T}` / `{error, E}`   |
+//!
+//! Decoding a `String` accepts both charlists and binaries.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+use ::num_bigint::BigInt;
+use ::num_traits::cast::ToPrimitive;
+
+use ::Atom;
+use ::term::{ Term, Pid, Reference };
+use ::map::Map;
+use ::process::CallReturn;
+
+#[cfg(feature = "serde_term")]
+mod term_serde;
+#[cfg(feature = "serde_term")]
+pub use self::term_serde::{ to_term, from_term, TermSerdeError };
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConvertError {
+    pub expected: &'static str,
+    pub found: String,
+}
+
+impl ConvertError {
+
+    pub fn new(expected: &'static str, found: &Term) -> Self {
+        ConvertError {
+            expected: expected,
+            found: format!("{}", found.to_doc().pretty(80)),
+        }
+    }
+
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected {}, found {}", self.expected, self.found)
+    }
+}
+
+impl ::std::error::Error for ConvertError {}
+
+pub type ConvertResult<T> = Result<T, ConvertError>;
+
+pub trait IntoTerm {
+    fn into_term(self) -> Term;
+}
+
+pub trait FromTerm: Sized {
+    fn from_term(term: &Term) -> ConvertResult<Self>;
+}
+
+impl Term {
+
+    /// Decodes the term into a Rust value.
+    pub fn decode<T: FromTerm>(&self) -> ConvertResult<T> {
+        T::from_term(self)
+    }
+
+}
+
+impl CallReturn {
+
+    /// Decodes the returned term. A throw is reported as an error.
+    pub fn decode<T: FromTerm>(&self) -> ConvertResult<T> {
+        match self {
+            CallReturn::Return { term } => T::from_term(term),
+            CallReturn::Throw => Err(ConvertError {
+                expected: "return",
+                found: "throw".to_string(),
+            }),
+        }
+    }
+
+}
+
+/// Wrapper for byte data that converts to and from an Erlang binary.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Binary(pub Vec<u8>);
+
+impl IntoTerm for Term {
+    fn into_term(self) -> Term {
+        self
+    }
+}
+impl FromTerm for Term {
+    fn from_term(term: &Term) -> ConvertResult<Self> {
+        Ok(term.clone())
+    }
+}
+
+macro_rules! int_conversions {
+    ($($typ:ty => $to:ident),*) => {
+        $(
+            impl IntoTerm for $typ {
+                fn into_term(self) -> Term {
+                    Term::Integer(self.into())
+                }
+            }
+            impl FromTerm for $typ {
+                fn from_term(term: &Term) -> ConvertResult<Self> {
+                    match term {
+                        Term::Integer(int) => int.$to()
+                            .ok_or_else(|| ConvertError::new(stringify!($typ), term)),
+                        _ => Err(ConvertError::new(stringify!($typ), term)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+int_conversions!(
+    i8 => to_i8, i16 => to_i16, i32 => to_i32, i64 => to_i64, isize => to_isize,
+    u8 => to_u8, u16 => to_u16, u32 => to_u32, u64 => to_u64, usize => to_usize
+);
+
+impl IntoTerm for BigInt {
+    fn into_term(self) -> Term {
+        Term::Integer(self)
+    }
+}
+impl FromTerm for BigInt {
+    fn from_term(term: &Term) -> ConvertResult<Self> {
+        match term {
+            Term::Integer(int) => Ok(int.clone()),
+            _ => Err(ConvertError::new("integer", term)),
+        }
+    }
+}
+
+impl IntoTerm for f64 {
+    fn into_term(self) -> Term {
+        Term::Float(self)
+    }
+}
+impl FromTerm for f64 {
+    fn from_term(term: &Term) -> ConvertResult<Self> {
+        match term {
+            Term::Float(num) => Ok(*num),
+            _ => Err(ConvertError::new("float", term)),
+        }
+    }
+}
+
+impl IntoTerm for bool {
+    fn into_term(self) -> Term {
+        Term::new_bool(self)
+    }
+}
+impl FromTerm for bool {
+    fn from_term(term: &Term) -> ConvertResult<Self> {
+        term.as_boolean().ok_or_else(|| ConvertError::new("boolean", term))
+    }
+}
+
+impl IntoTerm for () {
+    fn into_term(self) -> Term {
+        Term::new_atom("ok")
+    }
+}
+impl FromTerm for () {
+    fn from_term(term: &Term) -> ConvertResult<Self> {
+        match term {
+            Term::Atom(atom) if atom.as_str() == "ok" => Ok(()),
+            _ => Err(ConvertError::new("ok", term)),
+        }
+    }
+}
+
+impl IntoTerm for Atom {
+    fn into_term(self) -> Term {
+        Term::Atom(self)
+    }
+}
+impl FromTerm for Atom {
+    fn from_term(term: &Term) -> ConvertResult<Self> {
+        term.as_atom().ok_or_else(|| ConvertError::new("atom", term))
+    }
+}
+
+impl IntoTerm for Pid {
+    fn into_term(self) -> Term {
+        Term::Pid(self)
+    }
+}
+impl FromTerm for Pid {
+    fn from_term(term: &Term) -> ConvertResult<Self> {
+        match term {
+            Term::Pid(pid) => Ok(*pid),
+            _ => Err(ConvertError::new("pid", term)),
+        }
+    }
+}
+
+impl IntoTerm for Reference {
+    fn into_term(self) -> Term {
+        Term::Reference(self)
+    }
+}
+impl FromTerm for Reference {
+    fn from_term(term: &Term) -> ConvertResult<Self> {
+        match term {
+            Term::Reference(reference) => Ok(*reference),
+            _ => Err(ConvertError::new("reference", term)),
+        }
+    }
+}
+
+impl<'a> IntoTerm for &'a str {
+    fn into_term(self) -> Term {
+        let chars: Vec<_> = self.chars().map(|c| Term::new_i64(c as i64)).collect();
+        Term::List(chars, Box::new(Term::Nil))
+    }
+}
+impl IntoTerm for String {
+    fn into_term(self) -> Term {
+        self.as_str().into_term()
+    }
+}
+impl FromTerm for String {
+    fn from_term(term: &Term) -> ConvertResult<Self> {
+        match term {
+            Term::Binary(bin) => String::from_utf8(bin.clone())
+                .map_err(|_| ConvertError::new("utf8 binary", term)),
+            _ => {
+                let list = term.as_list()
+                    .ok_or_else(|| ConvertError::new("string", term))?;
+                list.iter()
+                    .map(|c| c.as_i64().and_then(|c| ::std::char::from_u32(c as u32)))
+                    .collect::<Option<String>>()
+                    .ok_or_else(|| ConvertError::new("string", term))
+            }
+        }
+    }
+}
+
+impl IntoTerm for Binary {
+    fn into_term(self) -> Term {
+        Term::Binary(self.0)
+    }
+}
+impl FromTerm for Binary {
+    fn from_term(term: &Term) -> ConvertResult<Self> {
+        match term {
+            Term::Binary(bin) => Ok(Binary(bin.clone())),
+            _ => Err(ConvertError::new("binary", term)),
+        }
+    }
+}
+
+impl<T: IntoTerm> IntoTerm for Vec<T> {
+    fn into_term(self) -> Term {
+        let items: Vec<_> = self.into_iter().map(|i| i.into_term()).collect();
+        Term::List(items, Box::new(Term::Nil))
+    }
+}
+impl<T: FromTerm> FromTerm for Vec<T> {
+    fn from_term(term: &Term) -> ConvertResult<Self> {
+        let list = term.as_list().ok_or_else(|| ConvertError::new("list", term))?;
+        list.iter().map(T::from_term).collect()
+    }
+}
+
+impl<K, V> IntoTerm for HashMap<K, V> where K: IntoTerm, V: IntoTerm {
+    fn into_term(self) -> Term {
+        let entries = self.into_iter()
+            .map(|(k, v)| (k.into_term(), v.into_term()))
+            .collect();
+        Term::Map(Map::from_entries(entries))
+    }
+}
+impl<K, V> FromTerm for HashMap<K, V> where K: FromTerm + Eq + Hash, V: FromTerm {
+    fn from_term(term: &Term) -> ConvertResult<Self> {
+        match term {
+            Term::Map(map) => map.iter()
+                .map(|(k, v)| Ok((K::from_term(k)?, V::from_term(v)?)))
+                .collect(),
+            _ => Err(ConvertError::new("map", term)),
+        }
+    }
+}
+
+impl IntoTerm for Map {
+    fn into_term(self) -> Term {
+        Term::Map(self)
+    }
+}
+impl FromTerm for Map {
+    fn from_term(term: &Term) -> ConvertResult<Self> {
+        match term {
+            Term::Map(map) => Ok(map.clone()),
+            _ => Err(ConvertError::new("map", term)),
+        }
+    }
+}
+
+fn tagged(tag: &str, term: Term) -> Term {
+    Term::Tuple(vec![Term::new_atom(tag), term])
+}
+
+/// Matches `{Tag, Value}` and returns `Value`.
+fn untag<'a>(tag: &str, term: &'a Term) -> Option<&'a Term> {
+    match term {
+        Term::Tuple(items) if items.len() == 2 => match items[0] {
+            Term::Atom(ref atom) if atom.as_str() == tag => Some(&items[1]),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl<T: IntoTerm> IntoTerm for Option<T> {
+    fn into_term(self) -> Term {
+        match self {
+            Some(val) => tagged("ok", val.into_term()),
+            None => Term::new_atom("error"),
+        }
+    }
+}
+impl<T: FromTerm> FromTerm for Option<T> {
+    fn from_term(term: &Term) -> ConvertResult<Self> {
+        if let Some(val) = untag("ok", term) {
+            return Ok(Some(T::from_term(val)?));
+        }
+        match term {
+            Term::Atom(atom) if atom.as_str() == "error" => Ok(None),
+            _ => Err(ConvertError::new("{ok, _} or error", term)),
+        }
+    }
+}
+
+impl<T: IntoTerm, E: IntoTerm> IntoTerm for Result<T, E> {
+    fn into_term(self) -> Term {
+        match self {
+            Ok(val) => tagged("ok", val.into_term()),
+            Err(err) => tagged("error", err.into_term()),
+        }
+    }
+}
+impl<T: FromTerm, E: FromTerm> FromTerm for Result<T, E> {
+    fn from_term(term: &Term) -> ConvertResult<Self> {
+        if let Some(val) = untag("ok", term) {
+            Ok(Ok(T::from_term(val)?))
+        } else if let Some(err) = untag("error", term) {
+            Ok(Err(E::from_term(err)?))
+        } else {
+            Err(ConvertError::new("{ok, _} or {error, _}", term))
+        }
+    }
+}
+
+macro_rules! tuple_conversions {
+    ($len:expr => $($name:ident $idx:tt),*) => {
+        impl<$($name: IntoTerm),*> IntoTerm for ($($name,)*) {
+            fn into_term(self) -> Term {
+                Term::Tuple(vec![$(self.$idx.into_term()),*])
+            }
+        }
+        impl<$($name: FromTerm),*> FromTerm for ($($name,)*) {
+            fn from_term(term: &Term) -> ConvertResult<Self> {
+                match term {
+                    Term::Tuple(items) if items.len() == $len =>
+                        Ok(($($name::from_term(&items[$idx])?,)*)),
+                    _ => Err(ConvertError::new(concat!("tuple of size ", $len), term)),
+                }
+            }
+        }
+    };
+}
+
+tuple_conversions!(1 => A 0);
+tuple_conversions!(2 => A 0, B 1);
+tuple_conversions!(3 => A 0, B 1, C 2);
+tuple_conversions!(4 => A 0, B 1, C 2, D 3);
+tuple_conversions!(5 => A 0, B 1, C 2, D 3, E 4);
+tuple_conversions!(6 => A 0, B 1, C 2, D 3, E 4, F 5);
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{ IntoTerm, Binary };
+    use ::term::{ Term, ErlExactEq };
+    use ::Atom;
+
+    #[test]
+    fn roundtrip() {
+        let term = (1u8, "abc", Binary(vec![1, 2]), vec![true, false]).into_term();
+        let back: (u8, String, Binary, Vec<bool>) = term.decode().unwrap();
+        assert!(back == (1, "abc".to_string(), Binary(vec![1, 2]), vec![true, false]));
+
+        let mut map = HashMap::new();
+        map.insert(Atom::from_str("a"), -5i64);
+        let back: HashMap<Atom, i64> = map.clone().into_term().decode().unwrap();
+        assert!(back == map);
+    }
+
+    #[test]
+    fn ok_error_tuples() {
+        let ok: Result<u32, Atom> = Ok(3);
+        let term = ok.into_term();
+        assert!(term.erl_exact_eq(&Term::Tuple(vec![Term::new_atom("ok"), Term::new_i64(3)])));
+
+        let found: Option<u32> = Term::new_atom("error").decode().unwrap();
+        assert!(found == None);
+        let err: Result<Result<u32, String>, _> =
+            Term::Tuple(vec![Term::new_atom("error"), Term::new_atom("x")]).decode();
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert!(Term::new_i64(300).decode::<u8>().is_err());
+        assert!(Term::new_atom("a").decode::<i64>().is_err());
+        let string: String = Term::Binary(b"hi".to_vec()).decode().unwrap();
+        assert!(string == "hi");
+    }
+
+}
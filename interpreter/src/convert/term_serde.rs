@@ -0,0 +1,611 @@
+//! Serde support for `Term`, enabled with the `serde_term` feature.
+//!
+//! Structs become maps with atom keys, unit enum variants become atoms,
+//! and other variants become tuples tagged with the variant name.
+//! Strings are serialized as binaries, but both binaries and charlists
+//! are accepted when deserializing.
+
+use std::fmt;
+
+use ::serde::ser::{ self, Serialize };
+use ::serde::de::{ self, Deserialize, Visitor, IntoDeserializer };
+use ::num_traits::cast::ToPrimitive;
+
+use ::term::Term;
+use ::map::Map;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TermSerdeError(pub String);
+
+impl fmt::Display for TermSerdeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl ::std::error::Error for TermSerdeError {}
+
+impl ser::Error for TermSerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        TermSerdeError(msg.to_string())
+    }
+}
+
+impl de::Error for TermSerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        TermSerdeError(msg.to_string())
+    }
+}
+
+type Result<T> = ::std::result::Result<T, TermSerdeError>;
+
+pub fn to_term<T: Serialize + ?Sized>(value: &T) -> Result<Term> {
+    value.serialize(TermSerializer)
+}
+
+pub fn from_term<'de, T: Deserialize<'de>>(term: &'de Term) -> Result<T> {
+    T::deserialize(TermDeserializer { term: term })
+}
+
+fn proper_list(items: Vec<Term>) -> Term {
+    Term::List(items, Box::new(Term::Nil))
+}
+
+struct TermSerializer;
+
+struct SeqSerializer {
+    tag: Option<&'static str>,
+    tuple: bool,
+    items: Vec<Term>,
+}
+
+impl SeqSerializer {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.items.push(value.serialize(TermSerializer)?);
+        Ok(())
+    }
+    fn finish(self) -> Term {
+        if !self.tuple {
+            return proper_list(self.items);
+        }
+        let mut items = self.items;
+        if let Some(tag) = self.tag {
+            items.insert(0, Term::new_atom(tag));
+        }
+        Term::Tuple(items)
+    }
+}
+
+struct MapSerializer {
+    tag: Option<&'static str>,
+    entries: Vec<(Term, Term)>,
+    next_key: Option<Term>,
+}
+
+impl MapSerializer {
+    fn finish(self) -> Term {
+        let map = Term::Map(Map::from_entries(self.entries));
+        match self.tag {
+            Some(tag) => Term::Tuple(vec![Term::new_atom(tag), map]),
+            None => map,
+        }
+    }
+}
+
+impl ser::Serializer for TermSerializer {
+    type Ok = Term;
+    type Error = TermSerdeError;
+
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = MapSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<Term> { Ok(Term::new_bool(v)) }
+    fn serialize_i8(self, v: i8) -> Result<Term> { Ok(Term::Integer(v.into())) }
+    fn serialize_i16(self, v: i16) -> Result<Term> { Ok(Term::Integer(v.into())) }
+    fn serialize_i32(self, v: i32) -> Result<Term> { Ok(Term::Integer(v.into())) }
+    fn serialize_i64(self, v: i64) -> Result<Term> { Ok(Term::Integer(v.into())) }
+    fn serialize_u8(self, v: u8) -> Result<Term> { Ok(Term::Integer(v.into())) }
+    fn serialize_u16(self, v: u16) -> Result<Term> { Ok(Term::Integer(v.into())) }
+    fn serialize_u32(self, v: u32) -> Result<Term> { Ok(Term::Integer(v.into())) }
+    fn serialize_u64(self, v: u64) -> Result<Term> { Ok(Term::Integer(v.into())) }
+    fn serialize_f32(self, v: f32) -> Result<Term> { Ok(Term::Float(v as f64)) }
+    fn serialize_f64(self, v: f64) -> Result<Term> { Ok(Term::Float(v)) }
+    fn serialize_char(self, v: char) -> Result<Term> { Ok(Term::new_i64(v as i64)) }
+
+    fn serialize_str(self, v: &str) -> Result<Term> {
+        Ok(Term::Binary(v.as_bytes().to_vec()))
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<Term> {
+        Ok(Term::Binary(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Term> {
+        Ok(Term::new_atom("undefined"))
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Term> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Term> {
+        Ok(Term::Tuple(vec![]))
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Term> {
+        self.serialize_unit()
+    }
+    fn serialize_unit_variant(self, _name: &'static str, _idx: u32,
+                              variant: &'static str) -> Result<Term> {
+        Ok(Term::new_atom(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self, _name: &'static str, value: &T) -> Result<Term> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self, _name: &'static str, _idx: u32, variant: &'static str,
+        value: &T) -> Result<Term> {
+        Ok(Term::Tuple(vec![Term::new_atom(variant), value.serialize(self)?]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer> {
+        Ok(SeqSerializer {
+            tag: None,
+            tuple: false,
+            items: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer> {
+        Ok(SeqSerializer { tag: None, tuple: true, items: Vec::with_capacity(len) })
+    }
+    fn serialize_tuple_struct(self, _name: &'static str,
+                              len: usize) -> Result<SeqSerializer> {
+        self.serialize_tuple(len)
+    }
+    fn serialize_tuple_variant(self, _name: &'static str, _idx: u32,
+                               variant: &'static str,
+                               len: usize) -> Result<SeqSerializer> {
+        Ok(SeqSerializer {
+            tag: Some(variant),
+            tuple: true,
+            items: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapSerializer> {
+        Ok(MapSerializer { tag: None, entries: Vec::new(), next_key: None })
+    }
+    fn serialize_struct(self, _name: &'static str,
+                        _len: usize) -> Result<MapSerializer> {
+        self.serialize_map(None)
+    }
+    fn serialize_struct_variant(self, _name: &'static str, _idx: u32,
+                                variant: &'static str,
+                                _len: usize) -> Result<MapSerializer> {
+        Ok(MapSerializer { tag: Some(variant), entries: Vec::new(), next_key: None })
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Term;
+    type Error = TermSerdeError;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+    fn end(self) -> Result<Term> { Ok(self.finish()) }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Term;
+    type Error = TermSerdeError;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+    fn end(self) -> Result<Term> { Ok(self.finish()) }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Term;
+    type Error = TermSerdeError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+    fn end(self) -> Result<Term> { Ok(self.finish()) }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer {
+    type Ok = Term;
+    type Error = TermSerdeError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+    fn end(self) -> Result<Term> { Ok(self.finish()) }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Term;
+    type Error = TermSerdeError;
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.next_key = Some(key.serialize(TermSerializer)?);
+        Ok(())
+    }
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self.next_key.take()
+            .ok_or_else(|| TermSerdeError("map value without key".to_string()))?;
+        self.entries.push((key, value.serialize(TermSerializer)?));
+        Ok(())
+    }
+    fn end(self) -> Result<Term> { Ok(self.finish()) }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Term;
+    type Error = TermSerdeError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str,
+                                              value: &T) -> Result<()> {
+        self.entries.push((Term::new_atom(key), value.serialize(TermSerializer)?));
+        Ok(())
+    }
+    fn end(self) -> Result<Term> { Ok(self.finish()) }
+}
+
+impl ser::SerializeStructVariant for MapSerializer {
+    type Ok = Term;
+    type Error = TermSerdeError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str,
+                                              value: &T) -> Result<()> {
+        self.entries.push((Term::new_atom(key), value.serialize(TermSerializer)?));
+        Ok(())
+    }
+    fn end(self) -> Result<Term> { Ok(self.finish()) }
+}
+
+#[derive(Clone, Copy)]
+struct TermDeserializer<'de> {
+    term: &'de Term,
+}
+
+impl<'de> TermDeserializer<'de> {
+
+    fn error(&self, expected: &str) -> TermSerdeError {
+        TermSerdeError(format!("expected {}, found {}", expected,
+                               self.term.to_doc().pretty(80)))
+    }
+
+    /// Decodes a string from either a binary or a charlist.
+    fn string(&self) -> Result<String> {
+        match self.term {
+            Term::Binary(bin) => String::from_utf8(bin.clone())
+                .map_err(|_| self.error("utf8 binary")),
+            _ => {
+                let list = self.term.as_list().ok_or_else(|| self.error("string"))?;
+                list.iter()
+                    .map(|c| c.as_i64().and_then(|c| ::std::char::from_u32(c as u32)))
+                    .collect::<Option<String>>()
+                    .ok_or_else(|| self.error("string"))
+            }
+        }
+    }
+
+}
+
+macro_rules! deserialize_int {
+    ($($method:ident => $visit:ident $to:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+                match self.term {
+                    Term::Integer(int) => visitor.$visit(
+                        int.$to().ok_or_else(|| self.error("integer in range"))?),
+                    _ => Err(self.error("integer")),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for TermDeserializer<'de> {
+    type Error = TermSerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.term {
+            Term::Integer(int) => match int.to_i64() {
+                Some(num) => visitor.visit_i64(num),
+                None => match int.to_u64() {
+                    Some(num) => visitor.visit_u64(num),
+                    None => Err(self.error("integer within 64 bits")),
+                },
+            },
+            Term::Float(num) => visitor.visit_f64(*num),
+            Term::Atom(atom) => match atom.as_str() {
+                "true" => visitor.visit_bool(true),
+                "false" => visitor.visit_bool(false),
+                "undefined" => visitor.visit_none(),
+                name => visitor.visit_str(name),
+            },
+            Term::Binary(bin) => visitor.visit_bytes(bin),
+            Term::Nil | Term::List(_, _) => self.deserialize_seq(visitor),
+            Term::Tuple(items) if items.len() == 0 => visitor.visit_unit(),
+            Term::Tuple(_) => self.deserialize_seq(visitor),
+            Term::Map(_) => self.deserialize_map(visitor),
+            _ => Err(self.error("serializable term")),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_bool(self.term.as_boolean().ok_or_else(|| self.error("boolean"))?)
+    }
+
+    deserialize_int!(
+        deserialize_i8 => visit_i8 to_i8,
+        deserialize_i16 => visit_i16 to_i16,
+        deserialize_i32 => visit_i32 to_i32,
+        deserialize_i64 => visit_i64 to_i64,
+        deserialize_u8 => visit_u8 to_u8,
+        deserialize_u16 => visit_u16 to_u16,
+        deserialize_u32 => visit_u32 to_u32,
+        deserialize_u64 => visit_u64 to_u64
+    );
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_f64(visitor)
+    }
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.term {
+            Term::Float(num) => visitor.visit_f64(*num),
+            Term::Integer(int) => visitor.visit_f64(
+                int.to_f64().ok_or_else(|| self.error("float"))?),
+            _ => Err(self.error("float")),
+        }
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let chr = self.term.as_i64()
+            .and_then(|c| ::std::char::from_u32(c as u32))
+            .ok_or_else(|| self.error("character"))?;
+        visitor.visit_char(chr)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_string(visitor)
+    }
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.term {
+            Term::Atom(atom) => visitor.visit_str(atom.as_str()),
+            _ => visitor.visit_string(self.string()?),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.term {
+            Term::Binary(bin) => visitor.visit_borrowed_bytes(bin),
+            _ => Err(self.error("binary")),
+        }
+    }
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.term {
+            Term::Atom(atom) if atom.as_str() == "undefined" => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.term {
+            Term::Tuple(items) if items.len() == 0 => visitor.visit_unit(),
+            _ => Err(self.error("{}")),
+        }
+    }
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str,
+                                                visitor: V) -> Result<V::Value> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str,
+                                                   visitor: V) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let items: Vec<&'de Term> = match self.term {
+            Term::Tuple(items) => items.iter().collect(),
+            Term::Nil => vec![],
+            Term::List(head, tail) => match **tail {
+                Term::Nil => head.iter().collect(),
+                _ => return Err(self.error("proper list")),
+            },
+            _ => return Err(self.error("list")),
+        };
+        visitor.visit_seq(SeqAccess { items: items.into_iter() })
+    }
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize,
+                                          visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize,
+                                                 visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.term {
+            Term::Map(map) => visitor.visit_map(MapAccess {
+                entries: map.iter(),
+                value: None,
+            }),
+            _ => Err(self.error("map")),
+        }
+    }
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str,
+                                           _fields: &'static [&'static str],
+                                           visitor: V) -> Result<V::Value> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str,
+                                         _variants: &'static [&'static str],
+                                         visitor: V) -> Result<V::Value> {
+        match self.term {
+            Term::Atom(atom) => visitor.visit_enum(atom.as_str().into_deserializer()),
+            Term::Tuple(items) if items.len() >= 2 => {
+                let variant = items[0].as_atom().ok_or_else(|| self.error("variant tag"))?;
+                visitor.visit_enum(EnumAccess {
+                    variant: variant.as_str().to_string(),
+                    values: &items[1..],
+                })
+            }
+            _ => Err(self.error("enum")),
+        }
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+}
+
+struct SeqAccess<'de> {
+    items: ::std::vec::IntoIter<&'de Term>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess<'de> {
+    type Error = TermSerdeError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+        where T: de::DeserializeSeed<'de> {
+        match self.items.next() {
+            Some(term) => seed.deserialize(TermDeserializer { term: term }).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+struct MapAccess<'de> {
+    entries: ::map::Iter<'de>,
+    value: Option<&'de Term>,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess<'de> {
+    type Error = TermSerdeError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+        where K: de::DeserializeSeed<'de> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(TermDeserializer { term: key }).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+        where V: de::DeserializeSeed<'de> {
+        let value = self.value.take()
+            .ok_or_else(|| TermSerdeError("map key without value".to_string()))?;
+        seed.deserialize(TermDeserializer { term: value })
+    }
+}
+
+/// A variant tagged tuple, `{Variant, Values...}`.
+struct EnumAccess<'de> {
+    variant: String,
+    values: &'de [Term],
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess<'de> {
+    type Error = TermSerdeError;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self)>
+        where V: de::DeserializeSeed<'de> {
+        let deserializer: de::value::StringDeserializer<TermSerdeError> =
+            self.variant.clone().into_deserializer();
+        Ok((seed.deserialize(deserializer)?, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for EnumAccess<'de> {
+    type Error = TermSerdeError;
+
+    fn unit_variant(self) -> Result<()> {
+        Err(TermSerdeError(format!("unit variant {} given values", self.variant)))
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+        where T: de::DeserializeSeed<'de> {
+        if self.values.len() != 1 {
+            return Err(TermSerdeError(format!("expected 1 value for {}", self.variant)));
+        }
+        seed.deserialize(TermDeserializer { term: &self.values[0] })
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(SeqAccess {
+            items: self.values.iter().collect::<Vec<_>>().into_iter(),
+        })
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str],
+                                       visitor: V) -> Result<V::Value> {
+        if self.values.len() != 1 {
+            return Err(TermSerdeError(format!("expected a map for {}", self.variant)));
+        }
+        de::Deserializer::deserialize_map(TermDeserializer { term: &self.values[0] }, visitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use ::serde::{ Serialize, Deserialize };
+    use super::{ to_term, from_term };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Rect(u32, u32),
+        Named { name: String },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Scene {
+        shapes: Vec<Shape>,
+        tags: HashMap<String, i64>,
+        parent: Option<u32>,
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut tags = HashMap::new();
+        tags.insert("depth".to_string(), -3);
+        let scene = Scene {
+            shapes: vec![
+                Shape::Empty,
+                Shape::Circle(1.5),
+                Shape::Rect(2, 3),
+                Shape::Named { name: "x".to_string() },
+            ],
+            tags: tags,
+            parent: None,
+        };
+        let term = to_term(&scene).unwrap();
+        let back: Scene = from_term(&term).unwrap();
+        assert!(back == scene);
+    }
+
+}
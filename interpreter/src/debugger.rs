@@ -0,0 +1,263 @@
+//! Step debugger for the interpreter.
+//!
+//! A `Debugger` is attached to a `VMState`. Before every op of an Erlang
+//! frame is executed, the process checks the debugger's breakpoints and
+//! step mode. When it decides to stop, the `DebugFrontend` is invoked
+//! synchronously with a `DebugContext` that can be used to inspect the
+//! current frame, the call stack and the mailbox of the process. The
+//! frontend returns the `StepMode` to resume with.
+
+use ::{ Atom, FunctionIdent, SSAVariable, LabelN, OpKind };
+use ::term::{ Term, Pid };
+use ::vm::VMState;
+use ::process::StackFrame;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Stops when the function is entered.
+    Function(FunctionIdent),
+    /// Stops before the first op of the block.
+    Block(FunctionIdent, LabelN),
+    /// Stops before the op with the given index in the block.
+    Op(FunctionIdent, LabelN, usize),
+}
+
+impl Breakpoint {
+
+    fn matches(&self, function: &FunctionIdent, block: LabelN,
+               op: usize, entry: bool) -> bool {
+        match *self {
+            Breakpoint::Function(ref ident) =>
+                entry && ident == function,
+            Breakpoint::Block(ref ident, label) =>
+                op == 0 && label == block && ident == function,
+            Breakpoint::Op(ref ident, label, op_num) =>
+                op_num == op && label == block && ident == function,
+        }
+    }
+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepMode {
+    /// Run until a breakpoint is hit.
+    Continue,
+    /// Stop before the next op, in whatever frame it is.
+    Op,
+    /// Stop before the next op at or above the given stack depth.
+    /// Calls made in between are run to completion.
+    Over { depth: usize },
+    /// Stop before the next op above the given stack depth, after
+    /// the current frame has returned.
+    Out { depth: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// Breakpoint with the given id was hit.
+    Breakpoint(usize),
+    /// The step requested by the last `StepMode` completed.
+    Step,
+}
+
+/// Summary of a single frame on the call stack.
+#[derive(Debug, Clone)]
+pub struct FrameInfo {
+    pub module: Atom,
+    pub function: FunctionIdent,
+    /// `None` for native frames.
+    pub block: Option<LabelN>,
+}
+
+pub struct DebugContext<'a> {
+    pub vm: &'a VMState,
+    pub pid: Pid,
+    pub reason: StopReason,
+    pub block: LabelN,
+    pub op_num: usize,
+    pub op: &'a OpKind,
+    /// Call stack, outermost frame first. The last entry is the
+    /// current frame.
+    pub stack: &'a [FrameInfo],
+    frame: &'a StackFrame,
+}
+
+impl<'a> DebugContext<'a> {
+
+    pub fn module(&self) -> &Atom {
+        &self.frame.module
+    }
+
+    pub fn function(&self) -> &FunctionIdent {
+        &self.frame.function
+    }
+
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Values currently bound in the frame, sorted by variable.
+    pub fn variables(&self) -> Vec<(SSAVariable, &Term)> {
+        let mut vars: Vec<_> = self.frame.variables.iter()
+            .map(|(k, v)| (*k, v))
+            .collect();
+        vars.sort_by_key(|(k, _)| *k);
+        vars
+    }
+
+    pub fn variable(&self, var: SSAVariable) -> Option<&Term> {
+        self.frame.variables.get(&var)
+    }
+
+    /// Messages waiting in the mailbox of the process.
+    pub fn mailbox(&self) -> Vec<(Term, Term)> {
+        self.vm.mailbox(self.pid)
+            .map(|m| m.lock().unwrap().messages().to_vec())
+            .unwrap_or_else(Vec::new)
+    }
+
+}
+
+/// Driven by the debugger whenever execution stops.
+///
+/// The frontend must not touch `VMState::debugger` while it is being
+/// called, breakpoints are instead passed in directly.
+pub trait DebugFrontend: Send {
+    fn stopped(&mut self, ctx: &DebugContext,
+               breakpoints: &mut Breakpoints) -> StepMode;
+}
+
+#[derive(Debug, Default)]
+pub struct Breakpoints {
+    entries: Vec<Option<Breakpoint>>,
+}
+
+impl Breakpoints {
+
+    pub fn add(&mut self, breakpoint: Breakpoint) -> usize {
+        self.entries.push(Some(breakpoint));
+        self.entries.len() - 1
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        self.entries.get_mut(id).and_then(|b| b.take()).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Breakpoint)> {
+        self.entries.iter().enumerate()
+            .filter_map(|(id, b)| b.as_ref().map(|b| (id, b)))
+    }
+
+    fn hit(&self, function: &FunctionIdent, block: LabelN,
+           op: usize, entry: bool) -> Option<usize> {
+        self.iter()
+            .find(|(_, b)| b.matches(function, block, op, entry))
+            .map(|(id, _)| id)
+    }
+
+}
+
+pub struct Debugger {
+    pub breakpoints: Breakpoints,
+    mode: StepMode,
+    frontend: Box<dyn DebugFrontend>,
+}
+
+impl Debugger {
+
+    /// Creates a debugger that stops before the very first op executed.
+    pub fn new(frontend: Box<dyn DebugFrontend>) -> Self {
+        Debugger {
+            breakpoints: Breakpoints::default(),
+            mode: StepMode::Op,
+            frontend: frontend,
+        }
+    }
+
+    pub fn mode(&self) -> StepMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: StepMode) {
+        self.mode = mode;
+    }
+
+    fn stop_reason(&self, function: &FunctionIdent, block: LabelN,
+                   op: usize, entry: bool, depth: usize) -> Option<StopReason> {
+        if let Some(id) = self.breakpoints.hit(function, block, op, entry) {
+            return Some(StopReason::Breakpoint(id));
+        }
+        let step_done = match self.mode {
+            StepMode::Continue => false,
+            StepMode::Op => true,
+            StepMode::Over { depth: d } => depth <= d,
+            StepMode::Out { depth: d } => depth < d,
+        };
+        if step_done {
+            Some(StopReason::Step)
+        } else {
+            None
+        }
+    }
+
+    /// Called by the process before an op in an Erlang frame is run.
+    /// `callers` is the call stack below the current frame.
+    pub(crate) fn before_op(&mut self, vm: &VMState, pid: Pid,
+                            callers: &[FrameInfo], frame: &StackFrame,
+                            op_num: usize, op: &OpKind) {
+        let entry = op_num == 0 && frame.prev_basic_block.is_none();
+        let depth = callers.len() + 1;
+        let reason = match self.stop_reason(&frame.function, frame.basic_block,
+                                            op_num, entry, depth) {
+            Some(reason) => reason,
+            None => return,
+        };
+
+        let mut stack = callers.to_vec();
+        stack.push(FrameInfo {
+            module: frame.module.clone(),
+            function: frame.function.clone(),
+            block: Some(frame.basic_block),
+        });
+
+        let ctx = DebugContext {
+            vm: vm,
+            pid: pid,
+            reason: reason,
+            block: frame.basic_block,
+            op_num: op_num,
+            op: op,
+            stack: &stack,
+            frame: frame,
+        };
+        self.mode = self.frontend.stopped(&ctx, &mut self.breakpoints);
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ Breakpoints, Breakpoint };
+    use ::{ Atom, FunctionIdent };
+
+    fn ident(name: &str) -> FunctionIdent {
+        FunctionIdent {
+            module: Atom::from_str("test"),
+            name: Atom::from_str(name),
+            arity: 0,
+            lambda: None,
+        }
+    }
+
+    #[test]
+    fn breakpoint_ids_are_stable() {
+        let mut bps = Breakpoints::default();
+        let a = bps.add(Breakpoint::Function(ident("a")));
+        let b = bps.add(Breakpoint::Function(ident("b")));
+        assert!(bps.remove(a));
+        assert!(!bps.remove(a));
+        let ids: Vec<_> = bps.iter().map(|(id, _)| id).collect();
+        assert!(ids == vec![b]);
+    }
+
+}
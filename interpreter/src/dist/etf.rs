@@ -0,0 +1,513 @@
+//! The Erlang external term format.
+//!
+//! Pids and references are written with the name of the node that
+//! encodes them. When decoding, the ones naming the decoding node come
+//! back as local `Term::Pid`/`Term::Reference`, the others as
+//! `Term::RemotePid`/`Term::RemoteReference`.
+//!
+//! Funs with an environment are written as `NEW_FUN_EXT`. The receiving
+//! node needs the name and environment of the lambda to find it again,
+//! which travel as an extra `{Name, Env}` tuple in front of the free
+//! variables.
+
+use std::fmt;
+
+use ::num_bigint::{ BigInt, Sign };
+use ::num_traits::cast::ToPrimitive;
+
+use ::Atom;
+use ::term::{ Term, Pid, Reference, BoundLambdaEnv };
+use ::map::Map;
+use ::code::CodeTable;
+use ::module::ModuleType;
+
+pub const VERSION: u8 = 131;
+
+const NEW_FLOAT_EXT: u8 = 70;
+const NEW_PID_EXT: u8 = 88;
+const NEWER_REFERENCE_EXT: u8 = 90;
+const SMALL_INTEGER_EXT: u8 = 97;
+const INTEGER_EXT: u8 = 98;
+const ATOM_EXT: u8 = 100;
+const SMALL_TUPLE_EXT: u8 = 104;
+const LARGE_TUPLE_EXT: u8 = 105;
+const NIL_EXT: u8 = 106;
+const STRING_EXT: u8 = 107;
+const LIST_EXT: u8 = 108;
+const BINARY_EXT: u8 = 109;
+const SMALL_BIG_EXT: u8 = 110;
+const LARGE_BIG_EXT: u8 = 111;
+const NEW_FUN_EXT: u8 = 112;
+const EXPORT_EXT: u8 = 113;
+const MAP_EXT: u8 = 116;
+const ATOM_UTF8_EXT: u8 = 118;
+const SMALL_ATOM_UTF8_EXT: u8 = 119;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EtfError {
+    UnexpectedEnd,
+    BadVersion(u8),
+    UnknownTag(u8),
+    BadAtom,
+    /// The term can not be sent to another node.
+    Unsupported(&'static str),
+    /// A fun refers to code that is not loaded on the decoding node.
+    UnknownFun(String),
+}
+
+impl fmt::Display for EtfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EtfError::UnexpectedEnd => write!(f, "unexpected end of data"),
+            EtfError::BadVersion(v) => write!(f, "bad version byte {}", v),
+            EtfError::UnknownTag(t) => write!(f, "unknown tag {}", t),
+            EtfError::BadAtom => write!(f, "atom is not valid utf8"),
+            EtfError::Unsupported(what) => write!(f, "{} can not be encoded", what),
+            EtfError::UnknownFun(fun) => write!(f, "unknown fun {}", fun),
+        }
+    }
+}
+
+pub type EtfResult<T> = Result<T, EtfError>;
+
+/// Encodes `term` as sent from `node`, including the version byte.
+pub fn encode(term: &Term, node: &Atom) -> EtfResult<Vec<u8>> {
+    let mut buf = vec![VERSION];
+    encode_term(term, node, &mut buf)?;
+    Ok(buf)
+}
+
+/// Decodes a single term received by `node`. `code` is used to find
+/// the lambdas of funs, which can not be decoded without it.
+pub fn decode(bytes: &[u8], node: &Atom, code: Option<&CodeTable>) -> EtfResult<Term> {
+    let mut decoder = Decoder::new(bytes, node, code);
+    let term = decoder.next_term()?;
+    if !decoder.is_empty() {
+        return Err(EtfError::UnknownTag(bytes[decoder.pos]));
+    }
+    Ok(term)
+}
+
+fn put_u16(buf: &mut Vec<u8>, num: u16) {
+    buf.extend_from_slice(&[(num >> 8) as u8, num as u8]);
+}
+
+fn put_u32(buf: &mut Vec<u8>, num: u32) {
+    buf.extend_from_slice(&[(num >> 24) as u8, (num >> 16) as u8, (num >> 8) as u8, num as u8]);
+}
+
+fn put_atom(buf: &mut Vec<u8>, atom: &str) {
+    if atom.len() < 256 {
+        buf.push(SMALL_ATOM_UTF8_EXT);
+        buf.push(atom.len() as u8);
+    } else {
+        buf.push(ATOM_UTF8_EXT);
+        put_u16(buf, atom.len() as u16);
+    }
+    buf.extend_from_slice(atom.as_bytes());
+}
+
+fn put_int(buf: &mut Vec<u8>, int: &BigInt) {
+    if let Some(small) = int.to_u8() {
+        buf.push(SMALL_INTEGER_EXT);
+        buf.push(small);
+    } else if let Some(num) = int.to_i32() {
+        buf.push(INTEGER_EXT);
+        put_u32(buf, num as u32);
+    } else {
+        let (sign, digits) = int.to_bytes_le();
+        if digits.len() < 256 {
+            buf.push(SMALL_BIG_EXT);
+            buf.push(digits.len() as u8);
+        } else {
+            buf.push(LARGE_BIG_EXT);
+            put_u32(buf, digits.len() as u32);
+        }
+        buf.push(if sign == Sign::Minus { 1 } else { 0 });
+        buf.extend_from_slice(&digits);
+    }
+}
+
+fn put_tuple_header(buf: &mut Vec<u8>, len: usize) {
+    if len < 256 {
+        buf.push(SMALL_TUPLE_EXT);
+        buf.push(len as u8);
+    } else {
+        buf.push(LARGE_TUPLE_EXT);
+        put_u32(buf, len as u32);
+    }
+}
+
+fn put_pid(buf: &mut Vec<u8>, node: &Atom, pid: Pid) {
+    buf.push(NEW_PID_EXT);
+    put_atom(buf, node.as_str());
+    put_u32(buf, pid.0 as u32);
+    put_u32(buf, 0);
+    put_u32(buf, 0);
+}
+
+fn put_reference(buf: &mut Vec<u8>, node: &Atom, reference: Reference) {
+    buf.push(NEWER_REFERENCE_EXT);
+    put_u16(buf, 2);
+    put_atom(buf, node.as_str());
+    put_u32(buf, 0);
+    put_u32(buf, reference.0 as u32);
+    put_u32(buf, (reference.0 as u64 >> 32) as u32);
+}
+
+fn encode_term(term: &Term, node: &Atom, buf: &mut Vec<u8>) -> EtfResult<()> {
+    match term {
+        Term::Nil => buf.push(NIL_EXT),
+        Term::Integer(int) => put_int(buf, int),
+        Term::Float(num) => {
+            buf.push(NEW_FLOAT_EXT);
+            let bits = num.to_bits();
+            put_u32(buf, (bits >> 32) as u32);
+            put_u32(buf, bits as u32);
+        }
+        Term::Atom(atom) => put_atom(buf, atom.as_str()),
+        Term::Tuple(items) => {
+            put_tuple_header(buf, items.len());
+            for item in items {
+                encode_term(item, node, buf)?;
+            }
+        }
+        Term::List(_, _) => {
+            let (head, tail) = term.as_inproper_list();
+            if head.len() == 0 {
+                return encode_term(&tail, node, buf);
+            }
+            buf.push(LIST_EXT);
+            put_u32(buf, head.len() as u32);
+            for item in head.iter() {
+                encode_term(item, node, buf)?;
+            }
+            encode_term(&tail, node, buf)?;
+        }
+        Term::Map(map) => {
+            buf.push(MAP_EXT);
+            put_u32(buf, map.len() as u32);
+            for (key, value) in map.iter() {
+                encode_term(key, node, buf)?;
+                encode_term(value, node, buf)?;
+            }
+        }
+        Term::Binary(bin) => {
+            buf.push(BINARY_EXT);
+            put_u32(buf, bin.len() as u32);
+            buf.extend_from_slice(bin);
+        }
+        Term::Pid(pid) => put_pid(buf, node, *pid),
+        Term::RemotePid(other, pid) => put_pid(buf, other, *pid),
+        Term::Reference(reference) => put_reference(buf, node, *reference),
+        Term::RemoteReference(other, reference) => put_reference(buf, other, *reference),
+        Term::CapturedFunction { module, fun_name, arity, .. } => {
+            buf.push(EXPORT_EXT);
+            put_atom(buf, module.as_str());
+            put_atom(buf, fun_name.as_str());
+            put_int(buf, &BigInt::from(*arity));
+        }
+        Term::BoundLambda { module, fun_name, arity, lambda, bound_env, .. } => {
+            let mut body = Vec::new();
+            // The environment is passed as the first argument, and not
+            // counted in the arity of the fun.
+            body.push((*arity - 1) as u8);
+            body.extend_from_slice(&[0; 16]);
+            put_u32(&mut body, lambda.1 as u32);
+            put_u32(&mut body, bound_env.vars.len() as u32 + 1);
+            put_atom(&mut body, module.as_str());
+            put_int(&mut body, &BigInt::from(0));
+            put_int(&mut body, &BigInt::from(0));
+            put_pid(&mut body, node, Pid(0));
+            put_tuple_header(&mut body, 2);
+            put_atom(&mut body, fun_name.as_str());
+            put_atom(&mut body, &lambda.0.to_string());
+            for var in bound_env.vars.iter() {
+                encode_term(var, node, &mut body)?;
+            }
+            buf.push(NEW_FUN_EXT);
+            put_u32(buf, body.len() as u32 + 4);
+            buf.extend_from_slice(&body);
+        }
+        Term::LambdaEnv(_) | Term::CaseContext(_)
+            | Term::ReceiveContext(_) | Term::ValueList(_) =>
+            return Err(EtfError::Unsupported("internal term")),
+    }
+    Ok(())
+}
+
+/// Reads consecutive terms, each with its own version byte.
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    node: &'a Atom,
+    code: Option<&'a CodeTable>,
+}
+
+impl<'a> Decoder<'a> {
+
+    pub fn new(bytes: &'a [u8], node: &'a Atom, code: Option<&'a CodeTable>) -> Self {
+        Decoder {
+            bytes: bytes,
+            pos: 0,
+            node: node,
+            code: code,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    pub fn next_term(&mut self) -> EtfResult<Term> {
+        let version = self.u8()?;
+        if version != VERSION {
+            return Err(EtfError::BadVersion(version));
+        }
+        self.term()
+    }
+
+    fn take(&mut self, len: usize) -> EtfResult<&'a [u8]> {
+        if self.pos + len > self.bytes.len() {
+            return Err(EtfError::UnexpectedEnd);
+        }
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> EtfResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> EtfResult<u16> {
+        let b = self.take(2)?;
+        Ok((b[0] as u16) << 8 | b[1] as u16)
+    }
+
+    fn u32(&mut self) -> EtfResult<u32> {
+        let b = self.take(4)?;
+        Ok((b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32)
+    }
+
+    fn atom_body(&mut self, len: usize) -> EtfResult<Atom> {
+        let bytes = self.take(len)?;
+        let string = ::std::str::from_utf8(bytes).map_err(|_| EtfError::BadAtom)?;
+        Ok(Atom::from_str(string))
+    }
+
+    fn atom(&mut self) -> EtfResult<Atom> {
+        match self.term()? {
+            Term::Atom(atom) => Ok(atom),
+            _ => Err(EtfError::BadAtom),
+        }
+    }
+
+    fn usize_term(&mut self) -> EtfResult<usize> {
+        self.term()?.as_usize().ok_or(EtfError::Unsupported("fun index"))
+    }
+
+    fn pid(&mut self, node: Atom, id: u32) -> Term {
+        if node == *self.node {
+            Term::Pid(Pid(id as usize))
+        } else {
+            Term::RemotePid(node, Pid(id as usize))
+        }
+    }
+
+    fn terms(&mut self, len: usize) -> EtfResult<Vec<Term>> {
+        (0..len).map(|_| self.term()).collect()
+    }
+
+    fn term(&mut self) -> EtfResult<Term> {
+        let tag = self.u8()?;
+        match tag {
+            SMALL_INTEGER_EXT => Ok(Term::new_i64(self.u8()? as i64)),
+            INTEGER_EXT => Ok(Term::new_i64(self.u32()? as i32 as i64)),
+            SMALL_BIG_EXT | LARGE_BIG_EXT => {
+                let len = if tag == SMALL_BIG_EXT {
+                    self.u8()? as usize
+                } else {
+                    self.u32()? as usize
+                };
+                let sign = if self.u8()? == 0 { Sign::Plus } else { Sign::Minus };
+                Ok(Term::Integer(BigInt::from_bytes_le(sign, self.take(len)?)))
+            }
+            NEW_FLOAT_EXT => {
+                let bits = (self.u32()? as u64) << 32 | self.u32()? as u64;
+                Ok(Term::Float(f64::from_bits(bits)))
+            }
+            ATOM_EXT | ATOM_UTF8_EXT => {
+                let len = self.u16()? as usize;
+                Ok(Term::Atom(self.atom_body(len)?))
+            }
+            SMALL_ATOM_UTF8_EXT => {
+                let len = self.u8()? as usize;
+                Ok(Term::Atom(self.atom_body(len)?))
+            }
+            SMALL_TUPLE_EXT => {
+                let len = self.u8()? as usize;
+                Ok(Term::Tuple(self.terms(len)?))
+            }
+            LARGE_TUPLE_EXT => {
+                let len = self.u32()? as usize;
+                Ok(Term::Tuple(self.terms(len)?))
+            }
+            NIL_EXT => Ok(Term::Nil),
+            STRING_EXT => {
+                let len = self.u16()? as usize;
+                let chars = self.take(len)?.iter().map(|c| Term::new_i64(*c as i64)).collect();
+                Ok(Term::List(chars, Box::new(Term::Nil)))
+            }
+            LIST_EXT => {
+                let len = self.u32()? as usize;
+                let head = self.terms(len)?;
+                let tail = self.term()?;
+                Ok(Term::List(head, Box::new(tail)))
+            }
+            BINARY_EXT => {
+                let len = self.u32()? as usize;
+                Ok(Term::Binary(self.take(len)?.to_vec()))
+            }
+            MAP_EXT => {
+                let len = self.u32()? as usize;
+                let mut entries = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let key = self.term()?;
+                    let value = self.term()?;
+                    entries.push((key, value));
+                }
+                Ok(Term::Map(Map::from_entries(entries)))
+            }
+            NEW_PID_EXT => {
+                let node = self.atom()?;
+                let id = self.u32()?;
+                self.take(8)?;
+                Ok(self.pid(node, id))
+            }
+            NEWER_REFERENCE_EXT => {
+                let len = self.u16()? as usize;
+                let node = self.atom()?;
+                self.u32()?;
+                let mut ids = Vec::with_capacity(len);
+                for _ in 0..len {
+                    ids.push(self.u32()? as u64);
+                }
+                let id = ids.get(0).cloned().unwrap_or(0)
+                    | ids.get(1).cloned().unwrap_or(0) << 32;
+                let reference = Reference(id as usize);
+                if node == *self.node {
+                    Ok(Term::Reference(reference))
+                } else {
+                    Ok(Term::RemoteReference(node, reference))
+                }
+            }
+            EXPORT_EXT => {
+                let module = self.atom()?;
+                let fun_name = self.atom()?;
+                let arity = self.usize_term()?;
+                Ok(Term::CapturedFunction {
+                    module: module,
+                    fun_name: fun_name,
+                    arity: arity,
+                    version: None,
+                })
+            }
+            NEW_FUN_EXT => self.fun(),
+            _ => Err(EtfError::UnknownTag(tag)),
+        }
+    }
+
+    fn fun(&mut self) -> EtfResult<Term> {
+        let _size = self.u32()?;
+        let arity = self.u8()? as usize + 1;
+        self.take(16)?;
+        let index = self.u32()? as usize;
+        let num_free = self.u32()? as usize;
+        let module = self.atom()?;
+        self.term()?;
+        self.term()?;
+        self.term()?;
+
+        let (fun_name, env_name) = match self.term()? {
+            Term::Tuple(ref items) if items.len() == 2 =>
+                match (items[0].as_atom(), items[1].as_atom()) {
+                    (Some(name), Some(env)) => (name, env),
+                    _ => return Err(EtfError::Unsupported("fun")),
+                },
+            _ => return Err(EtfError::Unsupported("fun")),
+        };
+        let vars = self.terms(num_free.saturating_sub(1))?;
+
+        let unknown = || EtfError::UnknownFun(format!("{}:{}/{}", module, fun_name, arity));
+        let loaded = self.code.and_then(|c| c.current(module.as_str())).ok_or_else(unknown)?;
+        let lambda = match *loaded.module {
+            ModuleType::Erlang(ref erl_module, _) => erl_module.functions.keys()
+                .filter(|ident| ident.name == fun_name && ident.arity == arity)
+                .filter_map(|ident| ident.lambda)
+                .find(|lambda| lambda.1 == index && lambda.0.to_string() == env_name.as_str()),
+            ModuleType::Native(_) => None,
+        };
+        let lambda = lambda.ok_or_else(unknown)?;
+
+        Ok(Term::BoundLambda {
+            module: module,
+            fun_name: fun_name,
+            arity: arity,
+            lambda: lambda,
+            version: loaded.version,
+            bound_env: BoundLambdaEnv {
+                env: lambda.0,
+                vars: vars,
+            },
+        })
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ encode, decode };
+    use ::Atom;
+    use ::term::{ Term, Pid, Reference, ErlExactEq };
+    use ::map::Map;
+
+    fn roundtrip(term: Term, from: &str, to: &str) -> Term {
+        let bytes = encode(&term, &Atom::from_str(from)).unwrap();
+        decode(&bytes, &Atom::from_str(to), None).unwrap()
+    }
+
+    #[test]
+    fn plain_terms() {
+        let big: ::num_bigint::BigInt = "-123456789012345678901234567890".parse().unwrap();
+        let term = Term::Tuple(vec![
+            Term::new_i64(7),
+            Term::new_i64(-70000),
+            Term::Integer(big),
+            Term::Float(1.5),
+            Term::new_atom("héllo"),
+            Term::List(vec![Term::new_i64(1)], Box::new(Term::new_atom("tail"))),
+            Term::Nil,
+            Term::Binary(vec![0, 255]),
+            Term::Map(Map::new().insert(Term::new_atom("k"), Term::new_i64(300))),
+        ]);
+        assert!(roundtrip(term.clone(), "a@host", "b@host").erl_exact_eq(&term));
+    }
+
+    #[test]
+    fn pids_follow_node() {
+        let term = Term::Tuple(vec![Term::Pid(Pid(3)), Term::Reference(Reference(9))]);
+
+        let remote = roundtrip(term.clone(), "a@host", "b@host");
+        let expected = Term::Tuple(vec![
+            Term::RemotePid(Atom::from_str("a@host"), Pid(3)),
+            Term::RemoteReference(Atom::from_str("a@host"), Reference(9)),
+        ]);
+        assert!(remote.erl_exact_eq(&expected));
+
+        // Sent back to the node they came from, they become local again.
+        let back = roundtrip(remote, "b@host", "a@host");
+        assert!(back.erl_exact_eq(&term));
+    }
+
+}
@@ -0,0 +1,747 @@
+//! Several VMs running as the nodes of a cluster in one process.
+//!
+//! Nodes only talk to each other through packets of terms in the
+//! external term format, laid out like the control messages of the
+//! Erlang distribution protocol. The cluster delivers packets between
+//! scheduler rounds, and the connection between two nodes can be cut to
+//! simulate a network partition.
+//!
+//! ```ignore
+//! let mut cluster = Cluster::new();
+//! cluster.add_node("a@host", vm_a);
+//! cluster.add_node("b@host", vm_b);
+//! cluster.partition("a@host", "b@host");
+//! let ret = cluster.call("a@host", "test", "ping", vec![]);
+//! ```
+
+pub mod etf;
+
+use std::collections::{ HashSet, VecDeque };
+use std::sync::{ Arc, Mutex, Weak };
+
+use ::Atom;
+use ::vm::{ VMState, WatchType };
+use ::term::{ Term, Pid, ErlExactEq };
+use ::process::CallReturn;
+
+use self::etf::EtfResult;
+
+const LINK: i64 = 1;
+const EXIT: i64 = 3;
+const UNLINK: i64 = 4;
+const REG_SEND: i64 = 6;
+const MONITOR_P: i64 = 19;
+const MONITOR_P_EXIT: i64 = 21;
+const SEND_SENDER: i64 = 22;
+
+struct Packet {
+    from: Atom,
+    to: Atom,
+    /// Control message, optionally followed by the message itself.
+    data: Vec<u8>,
+}
+
+struct Network {
+    nodes: Vec<(Atom, Weak<VMState>)>,
+    /// Pairs of nodes that can not reach each other, ordered by name.
+    partitions: HashSet<(Atom, Atom)>,
+    in_flight: VecDeque<Packet>,
+}
+
+fn node_pair(a: &Atom, b: &Atom) -> (Atom, Atom) {
+    if a.as_str() <= b.as_str() {
+        (a.clone(), b.clone())
+    } else {
+        (b.clone(), a.clone())
+    }
+}
+
+impl Network {
+
+    fn connected(&self, a: &Atom, b: &Atom) -> bool {
+        a != b
+            && self.nodes.iter().any(|(n, _)| n == b)
+            && !self.partitions.contains(&node_pair(a, b))
+    }
+
+    fn peer(&self, name: &Atom) -> Option<Arc<VMState>> {
+        self.nodes.iter()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, vm)| vm.upgrade())
+    }
+
+}
+
+/// A link or monitor a local process placed on a process on another
+/// node.
+struct RemoteWatch {
+    watcher: Pid,
+    node: Atom,
+    /// The remote pid, or `{Name, Node}` for monitors by name.
+    target: Term,
+    kind: WatchType,
+}
+
+/// Distribution state of a VM that is part of a `Cluster`.
+pub(crate) struct NodeState {
+    pub name: Atom,
+    network: Arc<Mutex<Network>>,
+    outgoing: Vec<RemoteWatch>,
+    /// Processes that called `monitor_node/2`, with the node.
+    node_monitors: Vec<(Pid, Atom)>,
+}
+
+fn is_on_node(term: &Term, node: &Atom) -> bool {
+    match term {
+        Term::RemotePid(n, _) => n == node,
+        _ => false,
+    }
+}
+
+fn remove_watch(vm: &VMState, pid: Pid, watcher: &Term, kind: WatchType) {
+    if let Some(watches) = vm.watches.lock().unwrap().get_mut(&pid) {
+        watches.retain(|(w, k)| *k != kind || !w.erl_exact_eq(watcher));
+    }
+}
+
+fn add_watch(vm: &VMState, pid: Pid, watcher: Term, kind: WatchType) {
+    vm.watches.lock().unwrap().entry(pid).or_insert_with(Vec::new).push((watcher, kind));
+}
+
+/// Whether `node` is a node this VM can currently reach.
+pub(crate) fn is_connected(vm: &VMState, node: &Atom) -> bool {
+    match *vm.dist.lock().unwrap() {
+        Some(ref dist) => dist.network.lock().unwrap().connected(&dist.name, node),
+        None => false,
+    }
+}
+
+/// The nodes this VM can currently reach.
+pub(crate) fn connected_nodes(vm: &VMState) -> Vec<Atom> {
+    match *vm.dist.lock().unwrap() {
+        Some(ref dist) => {
+            let network = dist.network.lock().unwrap();
+            let nodes = network.nodes.iter()
+                .map(|(n, _)| n.clone())
+                .filter(|n| network.connected(&dist.name, n))
+                .collect();
+            nodes
+        }
+        None => vec![],
+    }
+}
+
+/// Queues a packet for `to`. Packets to nodes that can not be reached
+/// are dropped, like on a real network.
+fn send_packet(vm: &VMState, to: &Atom, control: Term, message: Option<&Term>) -> EtfResult<()> {
+    let dist = vm.dist.lock().unwrap();
+    let dist = match *dist {
+        Some(ref dist) => dist,
+        None => return Ok(()),
+    };
+    let mut data = etf::encode(&control, &dist.name)?;
+    if let Some(message) = message {
+        data.extend(etf::encode(message, &dist.name)?);
+    }
+
+    let mut network = dist.network.lock().unwrap();
+    if network.connected(&dist.name, to) {
+        network.in_flight.push_back(Packet {
+            from: dist.name.clone(),
+            to: to.clone(),
+            data: data,
+        });
+    }
+    Ok(())
+}
+
+/// Sends a message to a remote process, given by pid or registered
+/// name.
+pub(crate) fn send(vm: &VMState, from: Pid, node: &Atom, to: &Term,
+                   message: &Term) -> EtfResult<()> {
+    let control = match to {
+        Term::Atom(_) => Term::Tuple(vec![
+            Term::new_i64(REG_SEND),
+            Term::Pid(from),
+            Term::new_atom(""),
+            to.clone(),
+        ]),
+        _ => Term::Tuple(vec![Term::new_i64(SEND_SENDER), Term::Pid(from), to.clone()]),
+    };
+    send_packet(vm, node, control, Some(message))
+}
+
+/// Links a local process to a process on another node.
+pub(crate) fn link(vm: &VMState, from: Pid, node: &Atom, to: &Term) -> EtfResult<()> {
+    if !is_connected(vm, node) {
+        vm.signal_exit(from, to.clone(), Term::new_atom("noconnection"));
+        return Ok(());
+    }
+    add_watch(vm, from, to.clone(), WatchType::Link);
+    vm.dist.lock().unwrap().as_mut().unwrap().outgoing.push(RemoteWatch {
+        watcher: from,
+        node: node.clone(),
+        target: to.clone(),
+        kind: WatchType::Link,
+    });
+    let control = Term::Tuple(vec![Term::new_i64(LINK), Term::Pid(from), to.clone()]);
+    send_packet(vm, node, control, None)
+}
+
+pub(crate) fn unlink(vm: &VMState, from: Pid, node: &Atom, to: &Term) -> EtfResult<()> {
+    remove_watch(vm, from, to, WatchType::Link);
+    vm.dist.lock().unwrap().as_mut().unwrap().outgoing.retain(|w| {
+        w.watcher != from || w.kind != WatchType::Link || !w.target.erl_exact_eq(to)
+    });
+    let control = Term::Tuple(vec![Term::new_i64(UNLINK), Term::Pid(from), to.clone()]);
+    send_packet(vm, node, control, None)
+}
+
+/// Monitors a process on another node. `target` is a remote pid or a
+/// `{Name, Node}` tuple.
+pub(crate) fn monitor(vm: &VMState, from: Pid, node: &Atom, target: &Term) -> EtfResult<Term> {
+    let reference = vm.ref_gen.lock().unwrap().next();
+    if !is_connected(vm, node) {
+        vm.signal_down(from, Term::Reference(reference), target.clone(),
+                       Term::new_atom("noconnection"));
+        return Ok(Term::Reference(reference));
+    }
+    vm.dist.lock().unwrap().as_mut().unwrap().outgoing.push(RemoteWatch {
+        watcher: from,
+        node: node.clone(),
+        target: target.clone(),
+        kind: WatchType::Monitor(reference),
+    });
+    let remote = match target {
+        Term::Tuple(ref items) => items[0].clone(),
+        _ => target.clone(),
+    };
+    let control = Term::Tuple(vec![
+        Term::new_i64(MONITOR_P),
+        Term::Pid(from),
+        remote,
+        Term::Reference(reference),
+    ]);
+    send_packet(vm, node, control, None)?;
+    Ok(Term::Reference(reference))
+}
+
+/// Sends `{nodedown, Node}` to the process when the node goes down, or
+/// right away if it can not be reached.
+pub(crate) fn monitor_node(vm: &VMState, from: Pid, node: &Atom, enable: bool) {
+    if !enable {
+        if let Some(ref mut dist) = *vm.dist.lock().unwrap() {
+            dist.node_monitors.retain(|(p, n)| *p != from || n != node);
+        }
+        return;
+    }
+    if is_connected(vm, node) {
+        vm.dist.lock().unwrap().as_mut().unwrap().node_monitors.push((from, node.clone()));
+    } else {
+        let message = Term::Tuple(vec![Term::new_atom("nodedown"), Term::Atom(node.clone())]);
+        vm.deliver_message(Term::Pid(from), from, message);
+    }
+}
+
+/// Hands a term to a connected node directly instead of through the
+/// packet queue, for synchronous operations like remote spawn. Returns
+/// the node along with the term as decoded there.
+pub(crate) fn transfer(vm: &VMState, node: &Atom, term: &Term) -> Option<(Arc<VMState>, Term)> {
+    let (peer, data) = {
+        let dist = vm.dist.lock().unwrap();
+        let dist = dist.as_ref()?;
+        let network = dist.network.lock().unwrap();
+        if !network.connected(&dist.name, node) {
+            return None;
+        }
+        let peer = network.peer(node)?;
+        let data = etf::encode(term, &dist.name).ok()?;
+        (peer, data)
+    };
+    let decoded = {
+        let code = peer.modules.read().unwrap();
+        let decoded = etf::decode(&data, node, Some(&*code)).ok()?;
+        decoded
+    };
+    Some((peer, decoded))
+}
+
+/// Tells a watcher on another node that a local process exited.
+pub(crate) fn send_exit(vm: &VMState, from: Pid, watcher: &Term, kind: WatchType,
+                        reason: &Term) {
+    let node = match watcher {
+        Term::RemotePid(node, _) => node.clone(),
+        _ => return,
+    };
+    let control = match kind {
+        WatchType::Link => Term::Tuple(vec![
+            Term::new_i64(EXIT),
+            Term::Pid(from),
+            watcher.clone(),
+            reason.clone(),
+        ]),
+        WatchType::Monitor(reference) => Term::Tuple(vec![
+            Term::new_i64(MONITOR_P_EXIT),
+            Term::Pid(from),
+            watcher.clone(),
+            Term::RemoteReference(node.clone(), reference),
+            reason.clone(),
+        ]),
+    };
+    // Reasons holding terms that can not be sent are replaced.
+    if send_packet(vm, &node, control.clone(), None).is_err() {
+        if let Term::Tuple(mut items) = control {
+            *items.last_mut().unwrap() = Term::new_atom("error");
+            let _ = send_packet(vm, &node, Term::Tuple(items), None);
+        }
+    }
+}
+
+/// Drops the remote links, monitors and node monitors of a process
+/// that exited.
+pub(crate) fn forget_process(vm: &VMState, pid: Pid) {
+    if let Some(ref mut dist) = *vm.dist.lock().unwrap() {
+        dist.outgoing.retain(|w| w.watcher != pid);
+        dist.node_monitors.retain(|(p, _)| *p != pid);
+    }
+}
+
+/// What the process monitors on other nodes.
+pub(crate) fn remote_monitors(vm: &VMState, pid: Pid) -> Vec<Term> {
+    match *vm.dist.lock().unwrap() {
+        Some(ref dist) => dist.outgoing.iter()
+            .filter(|w| w.watcher == pid && w.kind != WatchType::Link)
+            .map(|w| w.target.clone())
+            .collect(),
+        None => vec![],
+    }
+}
+
+fn control_message(term: Term) -> Option<(i64, Vec<Term>)> {
+    match term {
+        Term::Tuple(items) => {
+            let op = items.get(0)?.as_i64()?;
+            Some((op, items))
+        }
+        _ => None,
+    }
+}
+
+/// Handles a packet received from node `from`. Malformed packets are
+/// dropped.
+fn receive(vm: &VMState, from: &Atom, data: &[u8]) {
+    let node = vm.node_name();
+    let (control, message) = {
+        let code = vm.modules.read().unwrap();
+        let mut decoder = etf::Decoder::new(data, &node, Some(&*code));
+        let control = match decoder.next_term() {
+            Ok(control) => control,
+            Err(_) => return,
+        };
+        let message = if decoder.is_empty() {
+            None
+        } else {
+            match decoder.next_term() {
+                Ok(message) => Some(message),
+                Err(_) => return,
+            }
+        };
+        (control, message)
+    };
+    let (op, items) = match control_message(control) {
+        Some(control) => control,
+        None => return,
+    };
+
+    match (op, items.len(), message) {
+        (SEND_SENDER, 3, Some(message)) => {
+            if let Term::Pid(to) = items[2] {
+                vm.deliver_message(items[1].clone(), to, message);
+            }
+        }
+        (REG_SEND, 4, Some(message)) => {
+            let to = items[3].as_atom().and_then(|name| vm.whereis(&name));
+            if let Some(to) = to {
+                vm.deliver_message(items[1].clone(), to, message);
+            }
+        }
+        (LINK, 3, None) => {
+            if let Term::Pid(to) = items[2] {
+                if vm.is_alive(to) {
+                    add_watch(vm, to, items[1].clone(), WatchType::Link);
+                } else {
+                    let control = Term::Tuple(vec![
+                        Term::new_i64(EXIT),
+                        items[2].clone(),
+                        items[1].clone(),
+                        Term::new_atom("noproc"),
+                    ]);
+                    let _ = send_packet(vm, from, control, None);
+                }
+            }
+        }
+        (UNLINK, 3, None) => {
+            if let Term::Pid(to) = items[2] {
+                remove_watch(vm, to, &items[1], WatchType::Link);
+            }
+        }
+        (EXIT, 4, None) => {
+            if let Term::Pid(to) = items[2] {
+                remove_watch(vm, to, &items[1], WatchType::Link);
+                vm.dist.lock().unwrap().as_mut().unwrap().outgoing.retain(|w| {
+                    w.watcher != to || w.kind != WatchType::Link
+                        || !w.target.erl_exact_eq(&items[1])
+                });
+                vm.signal_exit(to, items[1].clone(), items[3].clone());
+            }
+        }
+        (MONITOR_P, 4, None) => {
+            let reference = match items[3] {
+                Term::RemoteReference(_, reference) => reference,
+                _ => return,
+            };
+            let target = match items[2] {
+                Term::Pid(pid) => Some(pid),
+                Term::Atom(ref name) => vm.whereis(name),
+                _ => None,
+            };
+            match target {
+                Some(pid) if vm.is_alive(pid) =>
+                    add_watch(vm, pid, items[1].clone(), WatchType::Monitor(reference)),
+                _ => {
+                    let control = Term::Tuple(vec![
+                        Term::new_i64(MONITOR_P_EXIT),
+                        items[2].clone(),
+                        items[1].clone(),
+                        items[3].clone(),
+                        Term::new_atom("noproc"),
+                    ]);
+                    let _ = send_packet(vm, from, control, None);
+                }
+            }
+        }
+        (MONITOR_P_EXIT, 5, None) => {
+            let (to, reference) = match (&items[2], &items[3]) {
+                (Term::Pid(to), Term::Reference(reference)) => (*to, *reference),
+                _ => return,
+            };
+            let watch = {
+                let mut dist = vm.dist.lock().unwrap();
+                let outgoing = &mut dist.as_mut().unwrap().outgoing;
+                let pos = outgoing.iter().position(|w| {
+                    w.watcher == to && w.kind == WatchType::Monitor(reference)
+                });
+                let watch = pos.map(|pos| outgoing.remove(pos));
+                watch
+            };
+            // The DOWN message names what was monitored, which is
+            // `{Name, Node}` for monitors by name.
+            if let Some(watch) = watch {
+                vm.signal_down(to, Term::Reference(reference), watch.target, items[4].clone());
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Handles the connection to `node` going down. Processes monitoring
+/// the node get `{nodedown, Node}`, and links and monitors to processes
+/// on it fire with reason `noconnection`.
+fn node_down(vm: &VMState, node: &Atom) {
+    let (watches, monitors) = {
+        let mut dist = vm.dist.lock().unwrap();
+        let dist = dist.as_mut().unwrap();
+        let (watches, outgoing): (Vec<RemoteWatch>, _) = dist.outgoing.drain(..)
+            .partition(|w| w.node == *node);
+        dist.outgoing = outgoing;
+        let (monitors, node_monitors): (Vec<(Pid, Atom)>, _) = dist.node_monitors.drain(..)
+            .partition(|(_, n)| n == node);
+        dist.node_monitors = node_monitors;
+        (watches, monitors)
+    };
+
+    for (pid, _) in monitors {
+        let message = Term::Tuple(vec![Term::new_atom("nodedown"), Term::Atom(node.clone())]);
+        vm.deliver_message(Term::Pid(pid), pid, message);
+    }
+
+    for watches in vm.watches.lock().unwrap().values_mut() {
+        watches.retain(|(watcher, _)| !is_on_node(watcher, node));
+    }
+
+    let reason = Term::new_atom("noconnection");
+    for watch in watches {
+        match watch.kind {
+            WatchType::Link => {
+                remove_watch(vm, watch.watcher, &watch.target, WatchType::Link);
+                vm.signal_exit(watch.watcher, watch.target, reason.clone());
+            }
+            WatchType::Monitor(reference) => {
+                vm.signal_down(watch.watcher, Term::Reference(reference),
+                               watch.target, reason.clone());
+            }
+        }
+    }
+}
+
+/// A set of nodes running in the same process, connected by a
+/// simulated network.
+pub struct Cluster {
+    nodes: Vec<Arc<VMState>>,
+    network: Arc<Mutex<Network>>,
+}
+
+impl Cluster {
+
+    pub fn new() -> Self {
+        Cluster {
+            nodes: Vec::new(),
+            network: Arc::new(Mutex::new(Network {
+                nodes: Vec::new(),
+                partitions: HashSet::new(),
+                in_flight: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Adds the VM as a node called `name`, connected to every other
+    /// node.
+    pub fn add_node(&mut self, name: &str, vm: VMState) -> Arc<VMState> {
+        let name = Atom::from_str(name);
+        assert!(self.node(name.as_str()).is_none(), "duplicate node {}", name);
+        *vm.dist.lock().unwrap() = Some(NodeState {
+            name: name.clone(),
+            network: self.network.clone(),
+            outgoing: Vec::new(),
+            node_monitors: Vec::new(),
+        });
+        let vm = Arc::new(vm);
+        self.network.lock().unwrap().nodes.push((name, Arc::downgrade(&vm)));
+        self.nodes.push(vm.clone());
+        vm
+    }
+
+    pub fn node(&self, name: &str) -> Option<&Arc<VMState>> {
+        self.nodes.iter().find(|vm| vm.node_name().as_str() == name)
+    }
+
+    pub fn is_connected(&self, a: &str, b: &str) -> bool {
+        self.network.lock().unwrap().connected(&Atom::from_str(a), &Atom::from_str(b))
+    }
+
+    /// Cuts the connection between two nodes. Packets in flight between
+    /// them are lost, and each side sees the other one go down.
+    pub fn partition(&self, a: &str, b: &str) {
+        let (a, b) = (Atom::from_str(a), Atom::from_str(b));
+        {
+            let mut network = self.network.lock().unwrap();
+            if !network.connected(&a, &b) {
+                return;
+            }
+            network.partitions.insert(node_pair(&a, &b));
+            network.in_flight.retain(|p| {
+                !((p.from == a && p.to == b) || (p.from == b && p.to == a))
+            });
+        }
+        node_down(self.node(a.as_str()).unwrap(), &b);
+        node_down(self.node(b.as_str()).unwrap(), &a);
+    }
+
+    /// Restores the connection between two nodes.
+    pub fn heal(&self, a: &str, b: &str) {
+        let pair = node_pair(&Atom::from_str(a), &Atom::from_str(b));
+        self.network.lock().unwrap().partitions.remove(&pair);
+    }
+
+    /// Delivers every packet in flight. Returns false if there were
+    /// none.
+    pub fn deliver_packets(&self) -> bool {
+        let mut any = false;
+        loop {
+            let packet = self.network.lock().unwrap().in_flight.pop_front();
+            let packet = match packet {
+                Some(packet) => packet,
+                None => return any,
+            };
+            any = true;
+            if let Some(vm) = self.node(packet.to.as_str()) {
+                receive(vm, &packet.from, &packet.data);
+            }
+        }
+    }
+
+    /// Calls `module:fun(args)` on the given node, running every node
+    /// of the cluster until it returns. When all processes are blocked,
+    /// the virtual clocks of all nodes move forward together.
+    pub fn call(&self, node: &str, module: &str, fun: &str, args: Vec<Term>) -> CallReturn {
+        let vm = self.node(node).unwrap_or_else(|| panic!("unknown node {}", node)).clone();
+        let pid = vm.start(module, fun, args);
+
+        loop {
+            let mut all_blocked = true;
+            for node in self.nodes.iter() {
+                if !node.run_round() {
+                    all_blocked = false;
+                }
+            }
+            if self.deliver_packets() {
+                all_blocked = false;
+            }
+            if vm.is_finished(pid) {
+                break;
+            }
+            if all_blocked {
+                let next = self.nodes.iter().filter_map(|n| n.next_wakeup()).min();
+                if let Some(time) = next {
+                    for node in self.nodes.iter() {
+                        node.advance_clock_to(time);
+                    }
+                }
+            }
+        }
+
+        for node in self.nodes.iter() {
+            node.flush_trace();
+        }
+        vm.take_result(pid).unwrap()
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ Cluster, monitor, monitor_node };
+    use ::Atom;
+    use ::vm::VMState;
+    use ::term::{ Term, Pid, ErlExactEq };
+
+    fn node() -> VMState {
+        let mut vm = VMState::new();
+        vm.add_native_module(::erl_lib::make_erlang());
+        vm.add_native_module(::erl_lib::make_timer());
+        vm
+    }
+
+    fn sleeper(vm: &VMState) -> Pid {
+        vm.start("timer", "sleep", vec![Term::new_atom("infinity")])
+    }
+
+    fn has_message(vm: &VMState, pid: Pid, message: &Term) -> bool {
+        vm.mailbox(pid).unwrap().lock().unwrap().messages().iter()
+            .any(|(_, m)| m.erl_exact_eq(message))
+    }
+
+    #[test]
+    fn send_to_registered_name() {
+        let mut cluster = Cluster::new();
+        let a = cluster.add_node("a@host", node());
+        let b = cluster.add_node("b@host", node());
+
+        let pid = sleeper(&b);
+        b.registered.lock().unwrap().insert(Atom::from_str("server"), pid);
+
+        let dest = Term::Tuple(vec![Term::new_atom("server"), Term::new_atom("b@host")]);
+        let message = Term::Tuple(vec![Term::new_atom("hello"), Term::new_i64(1)]);
+        cluster.call("a@host", "erlang", "send", vec![dest, message.clone()]);
+
+        let messages = b.mailbox(pid).unwrap().lock().unwrap().messages().to_vec();
+        assert!(messages.len() == 1);
+        let (ref from, ref received) = messages[0];
+        assert!(received.erl_exact_eq(&message));
+        assert!(from.erl_exact_eq(&Term::RemotePid(a.node_name(), Pid(0))));
+    }
+
+    #[test]
+    fn remote_spawn() {
+        let mut cluster = Cluster::new();
+        cluster.add_node("a@host", node());
+        let b = cluster.add_node("b@host", node());
+
+        let args = vec![
+            Term::new_atom("b@host"),
+            Term::new_atom("timer"),
+            Term::new_atom("sleep"),
+            Term::List(vec![Term::new_atom("infinity")], Box::new(Term::Nil)),
+        ];
+        let ret = cluster.call("a@host", "erlang", "spawn", args);
+        match *ret.unwrap_return() {
+            Term::RemotePid(ref node, pid) => {
+                assert!(node.as_str() == "b@host");
+                assert!(b.is_alive(pid));
+            }
+            _ => panic!(),
+        }
+
+        let nodes = cluster.call("a@host", "erlang", "nodes", vec![]);
+        let expected = Term::List(vec![Term::new_atom("b@host")], Box::new(Term::Nil));
+        assert!(nodes.unwrap_return().erl_exact_eq(&expected));
+    }
+
+    #[test]
+    fn remote_monitor_down() {
+        let mut cluster = Cluster::new();
+        let a = cluster.add_node("a@host", node());
+        let b = cluster.add_node("b@host", node());
+
+        let watcher = sleeper(&a);
+        let target = sleeper(&b);
+        let b_name = b.node_name();
+        let remote = Term::RemotePid(b_name.clone(), target);
+        let reference = monitor(&a, watcher, &b_name, &remote).unwrap();
+        cluster.deliver_packets();
+
+        b.process(target).unwrap().lock().unwrap().kill(&b, Term::new_atom("boom"));
+        cluster.deliver_packets();
+
+        let down = Term::Tuple(vec![
+            Term::new_atom("DOWN"),
+            reference,
+            Term::new_atom("process"),
+            remote,
+            Term::new_atom("boom"),
+        ]);
+        assert!(has_message(&a, watcher, &down));
+    }
+
+    #[test]
+    fn partition() {
+        let mut cluster = Cluster::new();
+        let a = cluster.add_node("a@host", node());
+        let b = cluster.add_node("b@host", node());
+
+        let watcher = sleeper(&a);
+        let target = sleeper(&b);
+        let b_name = b.node_name();
+        let remote = Term::RemotePid(b_name.clone(), target);
+        let reference = monitor(&a, watcher, &b_name, &remote).unwrap();
+        monitor_node(&a, watcher, &b_name, true);
+        cluster.deliver_packets();
+        assert!(b.watches.lock().unwrap()[&target].len() == 1);
+
+        cluster.partition("a@host", "b@host");
+        assert!(!cluster.is_connected("a@host", "b@host"));
+        assert!(b.watches.lock().unwrap()[&target].len() == 0);
+
+        let nodedown = Term::Tuple(vec![Term::new_atom("nodedown"), Term::Atom(b_name.clone())]);
+        assert!(has_message(&a, watcher, &nodedown));
+        let down = Term::Tuple(vec![
+            Term::new_atom("DOWN"),
+            reference,
+            Term::new_atom("process"),
+            remote,
+            Term::new_atom("noconnection"),
+        ]);
+        assert!(has_message(&a, watcher, &down));
+
+        // Messages across the partition are lost.
+        let message = Term::new_atom("lost");
+        let dest = Term::RemotePid(b_name, target);
+        cluster.call("a@host", "erlang", "send", vec![dest.clone(), message.clone()]);
+        assert!(!has_message(&b, target, &message));
+
+        cluster.heal("a@host", "b@host");
+        cluster.call("a@host", "erlang", "send", vec![dest, message.clone()]);
+        assert!(has_message(&b, target, &message));
+    }
+
+}
@@ -0,0 +1,61 @@
+use ::Atom;
+use ::module::{ NativeModule, ModuleType };
+use ::vm::VMState;
+use ::term::Term;
+use ::process::ProcessContext;
+use ::code::LoadError;
+use ::bif::{ BifResult, Badarg };
+use ::convert::Binary;
+
+fn error(reason: &str) -> Term {
+    Term::Tuple(vec![Term::new_atom("error"), Term::new_atom(reason)])
+}
+
+/// The binary is Core Erlang source, which is compiled on load.
+fn load_binary(vm: &VMState, _proc: &mut ProcessContext, module: Atom,
+               _file: Term, source: Binary) -> BifResult<Term> {
+    let text = String::from_utf8(source.0).map_err(|_| Badarg)?;
+    let parsed = match ::core_erlang_compiler::parser::parse(&text) {
+        Ok(parsed) => parsed,
+        Err(_) => return Ok(error("badfile")),
+    };
+    let ir = ::core_erlang_compiler::ir::from_parsed(&parsed.0);
+    if ir.name != module {
+        return Ok(error("badfile"));
+    }
+
+    match vm.load_module(module.as_str(), ModuleType::Erlang(ir, None)) {
+        Ok(_) => Ok(Term::Tuple(vec![Term::new_atom("module"), Term::Atom(module)])),
+        Err(LoadError::NotPurged) => Ok(error("not_purged")),
+    }
+}
+
+fn purge(vm: &VMState, proc: &mut ProcessContext, module: Atom) -> BifResult<bool> {
+    Ok(vm.purge_module(Some(proc), module.as_str()).unwrap_or(false))
+}
+
+fn soft_purge(vm: &VMState, proc: &mut ProcessContext, module: Atom) -> BifResult<bool> {
+    Ok(vm.soft_purge_module(Some(&*proc), module.as_str()))
+}
+
+fn delete(vm: &VMState, _proc: &mut ProcessContext, module: Atom) -> BifResult<bool> {
+    Ok(vm.modules.write().unwrap().delete(module.as_str()))
+}
+
+fn is_loaded(vm: &VMState, _proc: &mut ProcessContext, module: Atom) -> BifResult<Term> {
+    if vm.modules.read().unwrap().is_loaded(module.as_str()) {
+        Ok(Term::Tuple(vec![Term::new_atom("file"), Term::new_atom("loaded")]))
+    } else {
+        Ok(Term::new_bool(false))
+    }
+}
+
+pub fn make_code() -> NativeModule {
+    let mut module = NativeModule::new("code".to_string());
+    module.add_bif("load_binary", load_binary);
+    module.add_bif("purge", purge);
+    module.add_bif("soft_purge", soft_purge);
+    module.add_bif("delete", delete);
+    module.add_bif("is_loaded", is_loaded);
+    module
+}
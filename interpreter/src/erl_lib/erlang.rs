@@ -1,16 +1,17 @@
-use ::{ FunctionIdent };
+use ::{ Atom, FunctionIdent };
 use ::vm::{ VMState, WatchType };
 use ::module::NativeModule;
 use ::term::{ Term, Pid, Reference };
 use ::process::{ CallReturn, ProcessContext };
+use ::timer::Timer;
+use ::bif::{ BifResult, Badarg };
+use ::map::Map;
 
 use ::num_bigint::{ BigInt, Sign };
+use ::num_traits::ToPrimitive;
 
 use term::{ ErlEq, ErlExactEq, ErlOrd };
 
-use std::rc::Rc;
-use std::cell::RefCell;
-
 fn bignum_to_f64(n: &BigInt) -> Option<f64> {
     // ieee float layout:
     // 1b sign
@@ -247,53 +248,19 @@ fn is_function(_vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> Call
     }
 }
 
-fn base_spawn(vm: &VMState, ident: &FunctionIdent, args: Vec<Term>) -> Pid {
-    let new_pid = {
-        let mut processes = vm.processes.borrow();
-        Pid(processes.len())
-    };
-
-    let process = ProcessContext::new(new_pid);
-
-    let orig_pid = ::trace::get_pid();
-    ::trace::set_pid(new_pid);
-    let frame = process.make_call_stackframe(
-        vm,
-        ident.module.clone(),
-        ident.clone(),
-        args
-    );
-    ::trace::set_pid(orig_pid);
-
-    let stack_i = process.stack.clone();
-    let mut stack = stack_i.borrow_mut();
-    stack.push(frame);
-
-    {
-        let mut processes = vm.processes.borrow_mut();
-        processes.push(Rc::new(RefCell::new(process)));
-    }
-
-    {
-        let mut mailboxes = vm.mailboxes.borrow_mut();
-        mailboxes.insert(new_pid, ::mailbox::Mailbox::new());
-    }
-
-    new_pid
-}
-
-fn base_spawn_term(vm: &VMState, callable: &Term, mut args: Vec<Term>) -> Pid {
+fn base_spawn_term(vm: &VMState, parent: Option<Pid>, callable: &Term,
+                   mut args: Vec<Term>) -> Pid {
     match callable {
-        Term::CapturedFunction { module, fun_name, arity } => {
+        Term::CapturedFunction { module, fun_name, arity, version } => {
             let ident = FunctionIdent {
                 module: module.clone(),
                 name: fun_name.clone(),
                 arity: *arity,
                 lambda: None,
             };
-            base_spawn(vm, &ident, args)
+            vm.spawn_process(parent, &ident, *version, args)
         }
-        Term::BoundLambda { module, fun_name, arity, lambda, bound_env } => {
+        Term::BoundLambda { module, fun_name, arity, lambda, version, bound_env } => {
             let ident = FunctionIdent {
                 module: module.clone(),
                 name: fun_name.clone(),
@@ -301,31 +268,51 @@ fn base_spawn_term(vm: &VMState, callable: &Term, mut args: Vec<Term>) -> Pid {
                 lambda: Some(*lambda),
             };
             args.insert(0, Term::LambdaEnv(bound_env.clone()));
-            base_spawn(vm, &ident, args)
+            vm.spawn_process(parent, &ident, Some(*version), args)
         },
         _ => panic!(),
     }
 }
 
 fn base_monitor(vm: &VMState, proc: &mut ProcessContext, other: Pid) -> Reference {
-    let monitor_ref = vm.ref_gen.borrow_mut().next();
-    let mut watches = vm.watches.borrow_mut();
+    let monitor_ref = vm.ref_gen.lock().unwrap().next();
+    if !vm.is_alive(other) {
+        vm.signal_down(proc.pid, Term::Reference(monitor_ref), Term::Pid(other),
+                       Term::new_atom("noproc"));
+        return monitor_ref;
+    }
+
+    let mut watches = vm.watches.lock().unwrap();
 
     if !watches.contains_key(&other) {
         watches.insert(other, Vec::new());
     }
     let for_proc = watches.get_mut(&other).unwrap();
 
-    for_proc.push((proc.pid, WatchType::Monitor(monitor_ref)));
+    for_proc.push((Term::Pid(proc.pid), WatchType::Monitor(monitor_ref)));
 
     monitor_ref
 }
 
-fn spawn_1(vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+/// Monitors a process registered on the local node.
+fn monitor_name(vm: &VMState, proc: &mut ProcessContext, name: Atom) -> Reference {
+    match vm.whereis(&name) {
+        Some(pid) => base_monitor(vm, proc, pid),
+        None => {
+            let monitor_ref = vm.ref_gen.lock().unwrap().next();
+            let target = Term::Tuple(vec![Term::Atom(name), Term::Atom(vm.node_name())]);
+            vm.signal_down(proc.pid, Term::Reference(monitor_ref), target,
+                           Term::new_atom("noproc"));
+            monitor_ref
+        }
+    }
+}
+
+fn spawn_1(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
     assert!(args.len() == 1);
     let fun_term = &args[0];
 
-    let new_pid = base_spawn_term(vm, fun_term, vec![]);
+    let new_pid = base_spawn_term(vm, Some(proc.pid), fun_term, vec![]);
     CallReturn::Return { term: Term::Pid(new_pid) }
 }
 
@@ -333,7 +320,7 @@ fn spawn_monitor_1(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> Ca
     assert!(args.len() == 1);
     let fun_term = &args[0];
 
-    let new_pid = base_spawn_term(vm, fun_term, vec![]);
+    let new_pid = base_spawn_term(vm, Some(proc.pid), fun_term, vec![]);
     let monitor_ref = base_monitor(vm, proc, new_pid);
 
     let term = Term::Tuple(vec![
@@ -346,17 +333,192 @@ fn spawn_monitor_1(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> Ca
 fn monitor_2(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
     assert!(args.len() == 2);
     if args[0].erl_eq(&Term::new_atom("process")) {
-        if let Term::Pid(pid) = args[1] {
-            let monitor_ref = base_monitor(vm, proc, pid);
-            CallReturn::Return { term: Term::Reference(monitor_ref) }
-        } else {
-            CallReturn::Throw
-        }
+        let monitor_ref = match args[1] {
+            Term::Pid(pid) => Term::Reference(base_monitor(vm, proc, pid)),
+            Term::Atom(ref name) => Term::Reference(monitor_name(vm, proc, name.clone())),
+            Term::RemotePid(ref node, _) => {
+                match ::dist::monitor(vm, proc.pid, node, &args[1]) {
+                    Ok(monitor_ref) => monitor_ref,
+                    Err(_) => return CallReturn::Throw,
+                }
+            }
+            Term::Tuple(ref items) if items.len() == 2 => {
+                let (name, node) = match (items[0].as_atom(), items[1].as_atom()) {
+                    (Some(name), Some(node)) => (name, node),
+                    _ => return CallReturn::Throw,
+                };
+                if node == vm.node_name() {
+                    Term::Reference(monitor_name(vm, proc, name))
+                } else {
+                    match ::dist::monitor(vm, proc.pid, &node, &args[1]) {
+                        Ok(monitor_ref) => monitor_ref,
+                        Err(_) => return CallReturn::Throw,
+                    }
+                }
+            }
+            _ => return CallReturn::Throw,
+        };
+        CallReturn::Return { term: monitor_ref }
     } else {
         unimplemented!()
     }
 }
 
+fn is_linked(vm: &VMState, pid: Pid, other: &Term) -> bool {
+    vm.watches.lock().unwrap().get(&pid)
+        .map(|w| w.iter().any(|(p, k)| *k == WatchType::Link && p.erl_exact_eq(other)))
+        .unwrap_or(false)
+}
+
+fn link(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 1);
+    if is_linked(vm, proc.pid, &args[0]) {
+        return CallReturn::Return { term: Term::new_bool(true) };
+    }
+    match args[0] {
+        Term::Pid(pid) if pid == proc.pid => (),
+        Term::Pid(pid) => {
+            if !vm.is_alive(pid) {
+                return CallReturn::Throw;
+            }
+            let mut watches = vm.watches.lock().unwrap();
+            watches.entry(pid).or_insert_with(Vec::new)
+                .push((Term::Pid(proc.pid), WatchType::Link));
+            watches.entry(proc.pid).or_insert_with(Vec::new)
+                .push((Term::Pid(pid), WatchType::Link));
+        }
+        Term::RemotePid(ref node, _) => {
+            if ::dist::link(vm, proc.pid, node, &args[0]).is_err() {
+                return CallReturn::Throw;
+            }
+        }
+        _ => return CallReturn::Throw,
+    }
+    CallReturn::Return { term: Term::new_bool(true) }
+}
+
+fn unlink(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 1);
+    match args[0] {
+        Term::Pid(pid) => {
+            let mut watches = vm.watches.lock().unwrap();
+            let pairs = [(pid, Term::Pid(proc.pid)), (proc.pid, Term::Pid(pid))];
+            for (watched, watcher) in pairs.iter() {
+                if let Some(watches) = watches.get_mut(watched) {
+                    watches.retain(|(p, k)| *k != WatchType::Link || !p.erl_exact_eq(watcher));
+                }
+            }
+        }
+        Term::RemotePid(ref node, _) => {
+            if ::dist::unlink(vm, proc.pid, node, &args[0]).is_err() {
+                return CallReturn::Throw;
+            }
+        }
+        _ => return CallReturn::Throw,
+    }
+    CallReturn::Return { term: Term::new_bool(true) }
+}
+
+fn spawn_3(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 3);
+    match base_spawn_mfa(vm, Some(proc.pid), args) {
+        Some(pid) => CallReturn::Return { term: Term::Pid(pid) },
+        None => CallReturn::Throw,
+    }
+}
+
+fn base_spawn_mfa(vm: &VMState, parent: Option<Pid>, mfa: &[Term]) -> Option<Pid> {
+    let module = mfa[0].as_atom()?;
+    let fun_name = mfa[1].as_atom()?;
+    let args = mfa[2].as_list()?;
+    if !vm.modules.read().unwrap().is_loaded(module.as_str()) {
+        return None;
+    }
+    let ident = FunctionIdent {
+        module: module,
+        name: fun_name,
+        arity: args.len(),
+        lambda: None,
+    };
+    Some(vm.spawn_process(parent, &ident, None, args))
+}
+
+/// Spawns a fun on another node. The fun is passed in the external term
+/// format, and must exist in the code loaded on that node.
+fn spawn_2(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 2);
+    let node = if let Some(node) = args[0].as_atom() { node } else {
+        return CallReturn::Throw;
+    };
+    if node == vm.node_name() {
+        return spawn_1(vm, proc, &args[1..]);
+    }
+    match ::dist::transfer(vm, &node, &args[1]) {
+        Some((peer, fun)) => match fun {
+            Term::CapturedFunction { .. } | Term::BoundLambda { .. } => {
+                let pid = base_spawn_term(&peer, None, &fun, vec![]);
+                CallReturn::Return { term: Term::RemotePid(node, pid) }
+            }
+            _ => CallReturn::Throw,
+        },
+        None => CallReturn::Throw,
+    }
+}
+
+fn spawn_4(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 4);
+    let node = if let Some(node) = args[0].as_atom() { node } else {
+        return CallReturn::Throw;
+    };
+    if node == vm.node_name() {
+        return spawn_3(vm, proc, &args[1..]);
+    }
+    let mfa = Term::Tuple(args[1..].to_vec());
+    let pid = match ::dist::transfer(vm, &node, &mfa) {
+        Some((peer, Term::Tuple(ref mfa))) => base_spawn_mfa(&peer, None, mfa),
+        _ => None,
+    };
+    match pid {
+        Some(pid) => CallReturn::Return { term: Term::RemotePid(node, pid) },
+        None => CallReturn::Throw,
+    }
+}
+
+fn node_0(vm: &VMState, _proc: &mut ProcessContext) -> BifResult<Atom> {
+    Ok(vm.node_name())
+}
+
+fn nodes_0(vm: &VMState, _proc: &mut ProcessContext) -> BifResult<Vec<Atom>> {
+    Ok(::dist::connected_nodes(vm))
+}
+
+fn monitor_node(vm: &VMState, proc: &mut ProcessContext, node: Atom,
+                enable: bool) -> BifResult<bool> {
+    ::dist::monitor_node(vm, proc.pid, &node, enable);
+    Ok(true)
+}
+
+fn register(vm: &VMState, _proc: &mut ProcessContext, name: Atom,
+            pid: Pid) -> BifResult<bool> {
+    let taken = vm.registered.lock().unwrap().iter().any(|(n, p)| *n == name || *p == pid);
+    if taken || name.as_str() == "undefined" || !vm.is_alive(pid) {
+        return Err(Badarg.into());
+    }
+    vm.registered.lock().unwrap().insert(name, pid);
+    Ok(true)
+}
+
+fn unregister(vm: &VMState, _proc: &mut ProcessContext, name: Atom) -> BifResult<bool> {
+    vm.registered.lock().unwrap().remove(&name).map(|_| true).ok_or(Badarg.into())
+}
+
+fn whereis(vm: &VMState, _proc: &mut ProcessContext, name: Atom) -> BifResult<Term> {
+    match vm.whereis(&name) {
+        Some(pid) => Ok(Term::Pid(pid)),
+        None => Ok(Term::new_atom("undefined")),
+    }
+}
+
 fn not(_vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
     assert!(args.len() == 1);
     if let Some(b) = args[0].as_boolean() {
@@ -446,19 +608,307 @@ fn erl_self(_vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallRetu
     CallReturn::Return { term: Term::Pid(proc.pid) }
 }
 
+fn is_map(_vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 1);
+    match args[0] {
+        Term::Map(_) => CallReturn::Return { term: Term::new_bool(true) },
+        _ => CallReturn::Return { term: Term::new_bool(false) },
+    }
+}
+
+fn map_size(_vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 1);
+    if let Term::Map(ref map) = args[0] {
+        CallReturn::Return { term: Term::new_i64(map.len() as i64) }
+    } else {
+        CallReturn::Throw
+    }
+}
+
+fn map_get(_vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 2);
+    if let Term::Map(ref map) = args[1] {
+        if let Some(value) = map.get(&args[0]) {
+            return CallReturn::Return { term: value.clone() };
+        }
+    }
+    CallReturn::Throw
+}
+
+fn is_map_key(_vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 2);
+    if let Term::Map(ref map) = args[1] {
+        CallReturn::Return { term: Term::new_bool(map.contains_key(&args[0])) }
+    } else {
+        CallReturn::Throw
+    }
+}
+
+/// Sends to a pid, a registered name, or `{Name, Node}`. Messages to
+/// other nodes are dropped if the node can not be reached.
+fn send(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 2);
+    let message = &args[1];
+    let sent = match args[0] {
+        Term::Pid(to) => {
+            vm.deliver_message(Term::Pid(proc.pid), to, message.clone());
+            true
+        }
+        Term::RemotePid(ref node, _) =>
+            ::dist::send(vm, proc.pid, node, &args[0], message).is_ok(),
+        Term::Atom(ref name) => match vm.whereis(name) {
+            Some(to) => {
+                vm.deliver_message(Term::Pid(proc.pid), to, message.clone());
+                true
+            }
+            None => false,
+        },
+        Term::Tuple(ref items) if items.len() == 2 => {
+            match (items[0].as_atom(), items[1].as_atom()) {
+                (Some(name), Some(ref node)) if *node == vm.node_name() => {
+                    match vm.whereis(&name) {
+                        Some(to) => {
+                            vm.deliver_message(Term::Pid(proc.pid), to, message.clone());
+                            true
+                        }
+                        None => false,
+                    }
+                }
+                (Some(name), Some(node)) =>
+                    ::dist::send(vm, proc.pid, &node, &Term::Atom(name), message).is_ok(),
+                _ => false,
+            }
+        }
+        _ => false,
+    };
+
+    if sent {
+        CallReturn::Return { term: message.clone() }
+    } else {
+        CallReturn::Throw
+    }
+}
+
+/// Reads a proplist of `{Option, Bool}` pairs.
+fn bool_options(term: &Term) -> Option<Vec<(String, bool)>> {
+    let mut options = Vec::new();
+    for option in term.as_list()? {
+        match option {
+            Term::Tuple(ref items) if items.len() == 2 => {
+                let key = items[0].as_atom()?;
+                options.push((key.as_str().to_string(), items[1].as_boolean()?));
+            }
+            _ => return None,
+        }
+    }
+    Some(options)
+}
+
+fn get_option(options: &[(String, bool)], key: &str, default: bool) -> bool {
+    options.iter().rev()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| *v)
+        .unwrap_or(default)
+}
+
+fn base_start_timer(vm: &VMState, proc: &mut ProcessContext, args: &[Term],
+                    wrap_timeout: bool) -> CallReturn {
+    let time = match args[0].as_i64() {
+        Some(time) if time >= 0 => time as u64,
+        _ => return CallReturn::Throw,
+    };
+    let dest = if let Term::Pid(pid) = args[1] { pid } else {
+        return CallReturn::Throw;
+    };
+    let abs = if args.len() == 4 {
+        match bool_options(&args[3]) {
+            Some(options) => get_option(&options, "abs", false),
+            None => return CallReturn::Throw,
+        }
+    } else {
+        false
+    };
+
+    let now = vm.timers.lock().unwrap().now();
+    let fire_at = if abs { time } else { now + time };
+
+    let reference = vm.ref_gen.lock().unwrap().next();
+    let message = if wrap_timeout {
+        Term::Tuple(vec![
+            Term::new_atom("timeout"),
+            Term::Reference(reference),
+            args[2].clone(),
+        ])
+    } else {
+        args[2].clone()
+    };
+    vm.timers.lock().unwrap().start(fire_at, Timer {
+        reference: reference,
+        owner: proc.pid,
+        dest: dest,
+        message: message,
+    });
+
+    CallReturn::Return { term: Term::Reference(reference) }
+}
+
+fn send_after(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 3 || args.len() == 4);
+    base_start_timer(vm, proc, args, false)
+}
+
+fn start_timer(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 3 || args.len() == 4);
+    base_start_timer(vm, proc, args, true)
+}
+
+fn remaining_term(remaining: Option<u64>) -> Term {
+    match remaining {
+        Some(ms) => Term::new_i64(ms as i64),
+        None => Term::new_bool(false),
+    }
+}
+
+fn cancel_timer(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 1 || args.len() == 2);
+    let reference = if let Term::Reference(reference) = args[0] { reference } else {
+        return CallReturn::Throw;
+    };
+    let options = if args.len() == 2 {
+        match bool_options(&args[1]) {
+            Some(options) => options,
+            None => return CallReturn::Throw,
+        }
+    } else {
+        vec![]
+    };
+
+    let result = remaining_term(vm.timers.lock().unwrap().cancel(reference));
+    if !get_option(&options, "info", true) {
+        CallReturn::Return { term: Term::new_atom("ok") }
+    } else if get_option(&options, "async", false) {
+        let message = Term::Tuple(vec![
+            Term::new_atom("cancel_timer"),
+            Term::Reference(reference),
+            result,
+        ]);
+        let mailbox = vm.mailbox(proc.pid).unwrap();
+        mailbox.lock().unwrap().push(Term::Pid(proc.pid), message);
+        CallReturn::Return { term: Term::new_atom("ok") }
+    } else {
+        CallReturn::Return { term: result }
+    }
+}
+
+fn read_timer(vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 1);
+    let reference = if let Term::Reference(reference) = args[0] { reference } else {
+        return CallReturn::Throw;
+    };
+    let remaining = vm.timers.lock().unwrap().read(reference);
+    CallReturn::Return { term: remaining_term(remaining) }
+}
+
+/// The `max_heap_size` setting as `process_flag/2` returns it. Only
+/// killing the process is supported.
+fn max_heap_size_term(words: Option<usize>) -> Term {
+    Term::Map(Map::from_entries(vec![
+        (Term::new_atom("size"), Term::new_i64(words.unwrap_or(0) as i64)),
+        (Term::new_atom("kill"), Term::new_bool(true)),
+        (Term::new_atom("error_logger"), Term::new_bool(false)),
+    ]))
+}
+
 fn process_flag(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
     assert!(args.len() == 2);
     if args[0].erl_eq(&Term::new_atom("trap_exit")) {
-        let mut mailboxes = vm.mailboxes.borrow_mut();
-        let mailbox = &mut mailboxes.get_mut(&proc.pid).unwrap();
+        let mailbox = vm.mailbox(proc.pid).unwrap();
+        let mut mailbox = mailbox.lock().unwrap();
         let old_trap_exits = mailbox.get_trap_exits();
         mailbox.set_trap_exits(args[1].as_boolean().unwrap());
         CallReturn::Return { term: Term::new_bool(old_trap_exits) }
+    } else if args[0].erl_eq(&Term::new_atom("max_heap_size")) {
+        // Either the size in words, or a map with the size under `size`.
+        let size = match args[1] {
+            Term::Map(ref map) => map.get(&Term::new_atom("size")).cloned(),
+            ref size => Some(size.clone()),
+        };
+        let words = match size {
+            Some(Term::Integer(ref size)) => size.to_usize(),
+            _ => None,
+        };
+        let words = match words {
+            Some(words) => words,
+            None => return CallReturn::Throw,
+        };
+        let old = proc.max_heap_size;
+        proc.max_heap_size = if words == 0 { None } else { Some(words) };
+        CallReturn::Return { term: max_heap_size_term(old) }
     } else {
         unimplemented!()
     }
 }
 
+fn put(_vm: &VMState, proc: &mut ProcessContext, key: Term, value: Term) -> BifResult<Term> {
+    let old = proc.put_dictionary(key, value);
+    Ok(old.unwrap_or_else(|| Term::new_atom("undefined")))
+}
+
+fn get_1(_vm: &VMState, proc: &mut ProcessContext, key: Term) -> BifResult<Term> {
+    let value = proc.dictionary().iter()
+        .find(|(k, _)| k.erl_exact_eq(&key))
+        .map(|(_, v)| v.clone());
+    Ok(value.unwrap_or_else(|| Term::new_atom("undefined")))
+}
+
+fn get_0(_vm: &VMState, proc: &mut ProcessContext) -> BifResult<Term> {
+    let pairs = proc.dictionary().iter()
+        .map(|(k, v)| Term::Tuple(vec![k.clone(), v.clone()]))
+        .collect();
+    Ok(Term::List(pairs, Box::new(Term::Nil)))
+}
+
+fn erase_1(_vm: &VMState, proc: &mut ProcessContext, key: Term) -> BifResult<Term> {
+    let old = proc.erase_dictionary(&key);
+    Ok(old.unwrap_or_else(|| Term::new_atom("undefined")))
+}
+
+fn process_info_1(vm: &VMState, proc: &mut ProcessContext, pid: Pid) -> BifResult<Term> {
+    match vm.collect_process_info(Some(&*proc), pid) {
+        Some(info) => Ok(Term::List(info.default_items(), Box::new(Term::Nil))),
+        None => Ok(Term::new_atom("undefined")),
+    }
+}
+
+/// Takes a single item or a list of items. For a single item, a process
+/// without a registered name gives `[]` instead of a tuple.
+fn process_info_2(vm: &VMState, proc: &mut ProcessContext, pid: Pid,
+                  items: Term) -> BifResult<Term> {
+    let info = match vm.collect_process_info(Some(&*proc), pid) {
+        Some(info) => info,
+        None => return Ok(Term::new_atom("undefined")),
+    };
+    let item_tuple = |item: &Term| -> BifResult<Term> {
+        let name = item.as_atom().ok_or(Badarg)?;
+        let value = info.item(name.as_str()).ok_or(Badarg)?;
+        Ok(Term::Tuple(vec![item.clone(), value]))
+    };
+    match items {
+        Term::Atom(ref name) if name.as_str() == "registered_name"
+            && info.registered_name.is_none() => Ok(Term::Nil),
+        Term::Atom(_) => item_tuple(&items),
+        _ => {
+            let items = items.as_list().ok_or(Badarg)?;
+            let tuples = items.iter().map(item_tuple).collect::<BifResult<Vec<_>>>()?;
+            Ok(Term::List(tuples, Box::new(Term::Nil)))
+        }
+    }
+}
+
+fn processes_0(vm: &VMState, _proc: &mut ProcessContext) -> BifResult<Vec<Pid>> {
+    Ok(vm.live_processes())
+}
+
 pub fn make_erlang() -> NativeModule {
     let mut module = NativeModule::new("erlang".to_string());
     module.add_fun("+".to_string(), 2, Box::new(add));
@@ -472,6 +922,10 @@ pub fn make_erlang() -> NativeModule {
     module.add_fun("is_atom".to_string(), 1, Box::new(is_atom));
     module.add_fun("is_binary".to_string(), 1, Box::new(is_binary));
     module.add_fun("is_integer".to_string(), 1, Box::new(is_integer));
+    module.add_fun("is_map".to_string(), 1, Box::new(is_map));
+    module.add_fun("map_size".to_string(), 1, Box::new(map_size));
+    module.add_fun("map_get".to_string(), 2, Box::new(map_get));
+    module.add_fun("is_map_key".to_string(), 2, Box::new(is_map_key));
     module.add_fun("and".to_string(), 2, Box::new(and));
     module.add_fun("or".to_string(), 2, Box::new(or));
     module.add_fun("tuple_size".to_string(), 1, Box::new(tuple_size));
@@ -484,7 +938,34 @@ pub fn make_erlang() -> NativeModule {
     module.add_fun("element".to_string(), 2, Box::new(element));
     module.add_fun("self".to_string(), 0, Box::new(erl_self));
     module.add_fun("spawn".to_string(), 1, Box::new(spawn_1));
+    module.add_fun("spawn".to_string(), 2, Box::new(spawn_2));
+    module.add_fun("spawn".to_string(), 3, Box::new(spawn_3));
+    module.add_fun("spawn".to_string(), 4, Box::new(spawn_4));
+    module.add_fun("link".to_string(), 1, Box::new(link));
+    module.add_fun("unlink".to_string(), 1, Box::new(unlink));
     module.add_fun("monitor".to_string(), 2, Box::new(monitor_2));
+    module.add_fun("send".to_string(), 2, Box::new(send));
+    module.add_fun("send_after".to_string(), 3, Box::new(send_after));
+    module.add_fun("send_after".to_string(), 4, Box::new(send_after));
+    module.add_fun("start_timer".to_string(), 3, Box::new(start_timer));
+    module.add_fun("start_timer".to_string(), 4, Box::new(start_timer));
+    module.add_fun("cancel_timer".to_string(), 1, Box::new(cancel_timer));
+    module.add_fun("cancel_timer".to_string(), 2, Box::new(cancel_timer));
+    module.add_fun("read_timer".to_string(), 1, Box::new(read_timer));
+    module.add_fun("!".to_string(), 2, Box::new(send));
     module.add_fun("process_flag".to_string(), 2, Box::new(process_flag));
+    module.add_bif("put", put);
+    module.add_bif("get", get_1);
+    module.add_bif("get", get_0);
+    module.add_bif("erase", erase_1);
+    module.add_bif("process_info", process_info_1);
+    module.add_bif("process_info", process_info_2);
+    module.add_bif("processes", processes_0);
+    module.add_bif("node", node_0);
+    module.add_bif("nodes", nodes_0);
+    module.add_bif("monitor_node", monitor_node);
+    module.add_bif("register", register);
+    module.add_bif("unregister", unregister);
+    module.add_bif("whereis", whereis);
     module
 }
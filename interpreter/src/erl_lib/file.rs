@@ -2,16 +2,260 @@ use ::module::NativeModule;
 use ::vm::VMState;
 use ::term::Term;
 use ::process::{ CallReturn, ProcessContext };
+use ::filesystem::{ FsError, OpenFile };
 
-fn delete(_vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
-    assert!(args.len() == 1);
-    // TODO
-    let n = vec![Term::new_atom("error"), Term::new_atom("enoent")];
+use super::io_lib::chardata_to_string;
+
+fn path_arg(term: &Term) -> Option<String> {
+    match term {
+        Term::Atom(atom) => Some(atom.as_str().to_string()),
+        _ => chardata_to_string(term),
+    }
+}
+
+fn push_iodata(term: &Term, buf: &mut Vec<u8>) -> bool {
+    match term {
+        Term::Nil => true,
+        Term::Integer(_) => {
+            match term.as_i64() {
+                Some(byte) if byte >= 0 && byte <= 255 => {
+                    buf.push(byte as u8);
+                    true
+                }
+                _ => false,
+            }
+        }
+        Term::Binary(bin) => {
+            buf.extend_from_slice(bin);
+            true
+        }
+        Term::List(head, tail) =>
+            head.iter().all(|item| push_iodata(item, buf)) && push_iodata(tail, buf),
+        _ => false,
+    }
+}
+
+fn iodata_arg(term: &Term) -> Option<Vec<u8>> {
+    let mut buf = Vec::new();
+    if push_iodata(term, &mut buf) {
+        Some(buf)
+    } else {
+        None
+    }
+}
+
+fn ok() -> CallReturn {
+    CallReturn::Return { term: Term::new_atom("ok") }
+}
+
+fn ok_tuple(term: Term) -> CallReturn {
+    CallReturn::Return { term: Term::Tuple(vec![Term::new_atom("ok"), term]) }
+}
+
+fn error(reason: &str) -> CallReturn {
+    let n = vec![Term::new_atom("error"), Term::new_atom(reason)];
     CallReturn::Return { term: Term::Tuple(n) }
 }
 
-pub fn make_time() -> NativeModule {
+fn fs_error(err: FsError) -> CallReturn {
+    error(err.reason())
+}
+
+fn data_term(data: Vec<u8>, binary: bool) -> Term {
+    if binary {
+        Term::Binary(data)
+    } else {
+        let bytes: Vec<_> = data.iter().map(|b| Term::new_i64(*b as i64)).collect();
+        Term::List(bytes, Box::new(Term::Nil))
+    }
+}
+
+fn read_file(vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 1);
+    let path = if let Some(path) = path_arg(&args[0]) { path } else {
+        return CallReturn::Throw;
+    };
+    match vm.filesystem.lock().unwrap().read(&path) {
+        Ok(data) => ok_tuple(Term::Binary(data)),
+        Err(err) => fs_error(err),
+    }
+}
+
+fn write_file(vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 2);
+    let (path, data) = match (path_arg(&args[0]), iodata_arg(&args[1])) {
+        (Some(path), Some(data)) => (path, data),
+        _ => return CallReturn::Throw,
+    };
+    match vm.filesystem.lock().unwrap().write(&path, &data) {
+        Ok(()) => ok(),
+        Err(err) => fs_error(err),
+    }
+}
+
+fn delete(vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 1);
+    let path = if let Some(path) = path_arg(&args[0]) { path } else {
+        return CallReturn::Throw;
+    };
+    match vm.filesystem.lock().unwrap().delete(&path) {
+        Ok(()) => ok(),
+        Err(err) => fs_error(err),
+    }
+}
+
+fn make_dir(vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 1);
+    let path = if let Some(path) = path_arg(&args[0]) { path } else {
+        return CallReturn::Throw;
+    };
+    match vm.filesystem.lock().unwrap().make_dir(&path) {
+        Ok(()) => ok(),
+        Err(err) => fs_error(err),
+    }
+}
+
+fn list_dir(vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 1);
+    let path = if let Some(path) = path_arg(&args[0]) { path } else {
+        return CallReturn::Throw;
+    };
+    match vm.filesystem.lock().unwrap().list_dir(&path) {
+        Ok(entries) => {
+            let names: Vec<_> = entries.iter()
+                .map(|e| super::io_lib::string_to_term(e))
+                .collect();
+            ok_tuple(Term::List(names, Box::new(Term::Nil)))
+        }
+        Err(err) => fs_error(err),
+    }
+}
+
+fn open(vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 2);
+    let path = if let Some(path) = path_arg(&args[0]) { path } else {
+        return CallReturn::Throw;
+    };
+    let modes = if let Some(modes) = args[1].as_list() { modes } else {
+        return CallReturn::Throw;
+    };
+
+    let mut file = OpenFile::new(path);
+    let mut truncate = false;
+    for mode in modes.iter() {
+        match mode.as_atom().as_ref().map(|a| a.as_str()) {
+            Some("read") => file.read = true,
+            Some("write") => {
+                file.write = true;
+                truncate = true;
+            }
+            Some("append") => {
+                file.write = true;
+                file.append = true;
+            }
+            Some("binary") => file.binary = true,
+            Some("raw") => (),
+            _ => return CallReturn::Throw,
+        }
+    }
+    if !file.write {
+        file.read = true;
+    }
+    // Opening with both read and write keeps the contents.
+    if file.read || file.append {
+        truncate = false;
+    }
+
+    let mut fs = vm.filesystem.lock().unwrap();
+    if truncate {
+        if let Err(err) = fs.write(&file.path, &[]) {
+            return fs_error(err);
+        }
+    } else {
+        match fs.read(&file.path) {
+            Ok(data) => file.data = data,
+            Err(FsError::NotFound) if file.write => {
+                if let Err(err) = fs.write(&file.path, &[]) {
+                    return fs_error(err);
+                }
+            }
+            Err(err) => return fs_error(err),
+        }
+    }
+    // `write` takes the open files before the filesystem.
+    drop(fs);
+
+    let handle = vm.ref_gen.lock().unwrap().next();
+    vm.open_files.lock().unwrap().insert(handle, file);
+    ok_tuple(Term::Reference(handle))
+}
+
+fn read(vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 2);
+    let (handle, len) = match (&args[0], args[1].as_i64()) {
+        (Term::Reference(handle), Some(len)) if len >= 0 => (*handle, len as usize),
+        _ => return CallReturn::Throw,
+    };
+    let mut open_files = vm.open_files.lock().unwrap();
+    let file = match open_files.get_mut(&handle) {
+        Some(file) => file,
+        None => return error("einval"),
+    };
+    if !file.read {
+        return error("ebadf");
+    }
+    if file.position >= file.data.len() {
+        return CallReturn::Return { term: Term::new_atom("eof") };
+    }
+    let end = ::std::cmp::min(file.position + len, file.data.len());
+    let data = file.data[file.position..end].to_vec();
+    file.position = end;
+    ok_tuple(data_term(data, file.binary))
+}
+
+fn write(vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 2);
+    let (handle, data) = match (&args[0], iodata_arg(&args[1])) {
+        (Term::Reference(handle), Some(data)) => (*handle, data),
+        _ => return CallReturn::Throw,
+    };
+    let mut open_files = vm.open_files.lock().unwrap();
+    let file = match open_files.get_mut(&handle) {
+        Some(file) => file,
+        None => return error("einval"),
+    };
+    if !file.write {
+        return error("ebadf");
+    }
+    file.write_at_position(&data);
+    match vm.filesystem.lock().unwrap().write(&file.path, &file.data) {
+        Ok(()) => ok(),
+        Err(err) => fs_error(err),
+    }
+}
+
+fn close(vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 1);
+    let handle = if let Term::Reference(handle) = args[0] { handle } else {
+        return CallReturn::Throw;
+    };
+    if vm.open_files.lock().unwrap().remove(&handle).is_some() {
+        ok()
+    } else {
+        error("einval")
+    }
+}
+
+pub fn make_file() -> NativeModule {
     let mut module = NativeModule::new("file".to_string());
+    module.add_fun("read_file".to_string(), 1, Box::new(read_file));
+    module.add_fun("write_file".to_string(), 2, Box::new(write_file));
     module.add_fun("delete".to_string(), 1, Box::new(delete));
+    module.add_fun("make_dir".to_string(), 1, Box::new(make_dir));
+    module.add_fun("list_dir".to_string(), 1, Box::new(list_dir));
+    module.add_fun("open".to_string(), 2, Box::new(open));
+    module.add_fun("read".to_string(), 2, Box::new(read));
+    module.add_fun("write".to_string(), 2, Box::new(write));
+    module.add_fun("close".to_string(), 1, Box::new(close));
     module
 }
@@ -0,0 +1,96 @@
+use ::module::NativeModule;
+use ::vm::VMState;
+use ::term::Term;
+use ::process::{ CallReturn, ProcessContext };
+
+use super::io_lib::{ format_args, chardata_to_string };
+
+fn ok() -> CallReturn {
+    CallReturn::Return { term: Term::new_atom("ok") }
+}
+
+fn is_device(term: &Term) -> bool {
+    match term {
+        Term::Atom(atom) => atom.as_str() == "standard_io" || atom.as_str() == "user",
+        Term::Pid(_) => true,
+        _ => false,
+    }
+}
+
+fn format_1(vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 1);
+    match format_args(&args[0], &Term::Nil) {
+        Some(string) => {
+            vm.output.lock().unwrap().write(&string);
+            ok()
+        }
+        None => CallReturn::Throw,
+    }
+}
+
+fn format_2(vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 2);
+    match format_args(&args[0], &args[1]) {
+        Some(string) => {
+            vm.output.lock().unwrap().write(&string);
+            ok()
+        }
+        None => CallReturn::Throw,
+    }
+}
+
+fn format_3(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 3);
+    if !is_device(&args[0]) {
+        return CallReturn::Throw;
+    }
+    format_2(vm, proc, &args[1..])
+}
+
+fn put_chars_1(vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 1);
+    match chardata_to_string(&args[0]) {
+        Some(string) => {
+            vm.output.lock().unwrap().write(&string);
+            ok()
+        }
+        None => CallReturn::Throw,
+    }
+}
+
+fn put_chars_2(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 2);
+    if !is_device(&args[0]) {
+        return CallReturn::Throw;
+    }
+    put_chars_1(vm, proc, &args[1..])
+}
+
+fn nl_0(vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 0);
+    vm.output.lock().unwrap().write("\n");
+    ok()
+}
+
+fn nl_1(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 1);
+    if !is_device(&args[0]) {
+        return CallReturn::Throw;
+    }
+    nl_0(vm, proc, &[])
+}
+
+pub fn make_io() -> NativeModule {
+    let mut module = NativeModule::new("io".to_string());
+    module.add_fun("format".to_string(), 1, Box::new(format_1));
+    module.add_fun("format".to_string(), 2, Box::new(format_2));
+    module.add_fun("format".to_string(), 3, Box::new(format_3));
+    module.add_fun("fwrite".to_string(), 1, Box::new(format_1));
+    module.add_fun("fwrite".to_string(), 2, Box::new(format_2));
+    module.add_fun("fwrite".to_string(), 3, Box::new(format_3));
+    module.add_fun("put_chars".to_string(), 1, Box::new(put_chars_1));
+    module.add_fun("put_chars".to_string(), 2, Box::new(put_chars_2));
+    module.add_fun("nl".to_string(), 0, Box::new(nl_0));
+    module.add_fun("nl".to_string(), 1, Box::new(nl_1));
+    module
+}
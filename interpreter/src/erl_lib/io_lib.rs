@@ -0,0 +1,454 @@
+use ::module::NativeModule;
+use ::vm::VMState;
+use ::term::Term;
+use ::process::{ CallReturn, ProcessContext };
+
+use ::num_bigint::BigInt;
+use ::num_traits::ToPrimitive;
+
+const RESERVED_WORDS: &[&str] = &[
+    "after", "and", "andalso", "band", "begin", "bnot", "bor", "bsl", "bsr",
+    "bxor", "case", "catch", "cond", "div", "end", "fun", "if", "let", "not",
+    "of", "or", "orelse", "receive", "rem", "try", "when", "xor",
+];
+
+fn push_chardata(term: &Term, buf: &mut String) -> bool {
+    match term {
+        Term::Nil => true,
+        Term::Integer(int) => {
+            match int.to_u32().and_then(::std::char::from_u32) {
+                Some(chr) => {
+                    buf.push(chr);
+                    true
+                }
+                None => false,
+            }
+        }
+        Term::Binary(bin) => {
+            buf.push_str(&String::from_utf8_lossy(bin));
+            true
+        }
+        Term::List(head, tail) => {
+            head.iter().all(|item| push_chardata(item, buf))
+                && push_chardata(tail, buf)
+        }
+        _ => false,
+    }
+}
+
+/// Flattens a possibly deep list of characters and binaries.
+pub fn chardata_to_string(term: &Term) -> Option<String> {
+    let mut buf = String::new();
+    if push_chardata(term, &mut buf) {
+        Some(buf)
+    } else {
+        None
+    }
+}
+
+pub fn string_to_term(string: &str) -> Term {
+    let chars: Vec<_> = string.chars()
+        .map(|c| Term::new_i64(c as i64))
+        .collect();
+    Term::List(chars, Box::new(Term::Nil))
+}
+
+fn write_atom(atom: &str, buf: &mut String) {
+    let mut chars = atom.chars();
+    let bare = match chars.next() {
+        Some(first) => first.is_ascii_lowercase()
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '@')
+            && !RESERVED_WORDS.contains(&atom),
+        None => false,
+    };
+    if bare {
+        buf.push_str(atom);
+    } else {
+        buf.push('\'');
+        for c in atom.chars() {
+            match c {
+                '\'' => buf.push_str("\\'"),
+                '\\' => buf.push_str("\\\\"),
+                '\n' => buf.push_str("\\n"),
+                _ => buf.push(c),
+            }
+        }
+        buf.push('\'');
+    }
+}
+
+fn write_float(num: f64, buf: &mut String) {
+    // Debug formatting gives the shortest representation that
+    // round trips, and always includes a fraction.
+    buf.push_str(&format!("{:?}", num));
+}
+
+fn printable_string(term: &Term) -> Option<String> {
+    let list = term.as_list()?;
+    if list.len() == 0 {
+        return None;
+    }
+    let mut buf = String::new();
+    for item in list.iter() {
+        let chr = item.as_i64()
+            .and_then(|i| ::std::char::from_u32(i as u32))?;
+        if chr.is_control() && !"\n\r\t\x0b\x08\x0c\x1b".contains(chr) {
+            return None;
+        }
+        buf.push(chr);
+    }
+    Some(buf)
+}
+
+fn write_string(string: &str, buf: &mut String) {
+    buf.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            _ => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
+
+fn write_seq<'a, I>(items: I, buf: &mut String, pretty: bool)
+where I: Iterator<Item = &'a Term> {
+    for (idx, item) in items.enumerate() {
+        if idx != 0 {
+            buf.push(',');
+        }
+        write_term(item, buf, pretty);
+    }
+}
+
+/// Writes `term` in Erlang syntax. With `pretty`, printable lists and
+/// binaries are written as strings, as done by `~p`.
+pub fn write_term(term: &Term, buf: &mut String, pretty: bool) {
+    match term {
+        Term::Nil => buf.push_str("[]"),
+        Term::Integer(int) => buf.push_str(&int.to_string()),
+        Term::Float(num) => write_float(*num, buf),
+        Term::Atom(atom) => write_atom(atom.as_str(), buf),
+        Term::Tuple(items) => {
+            buf.push('{');
+            write_seq(items.iter(), buf, pretty);
+            buf.push('}');
+        }
+        Term::List(_, _) => {
+            if pretty {
+                if let Some(string) = printable_string(term) {
+                    write_string(&string, buf);
+                    return;
+                }
+            }
+            let (head, tail) = term.as_inproper_list();
+            buf.push('[');
+            write_seq(head.iter(), buf, pretty);
+            if let Term::Nil = tail {
+            } else {
+                buf.push('|');
+                write_term(&tail, buf, pretty);
+            }
+            buf.push(']');
+        }
+        Term::Map(map) => {
+            buf.push_str("#{");
+            for (idx, (key, value)) in map.iter().enumerate() {
+                if idx != 0 {
+                    buf.push(',');
+                }
+                write_term(key, buf, pretty);
+                buf.push_str(" => ");
+                write_term(value, buf, pretty);
+            }
+            buf.push('}');
+        }
+        Term::Binary(bin) => {
+            buf.push_str("<<");
+            let utf = ::std::str::from_utf8(bin).ok()
+                .filter(|s| s.len() > 0 && !s.chars().any(|c| c.is_control()));
+            match utf {
+                Some(string) if pretty => write_string(string, buf),
+                _ => {
+                    let bytes: Vec<_> = bin.iter().map(|b| b.to_string()).collect();
+                    buf.push_str(&bytes.join(","));
+                }
+            }
+            buf.push_str(">>");
+        }
+        Term::Pid(pid) => buf.push_str(&format!("<0.{}.0>", pid.0)),
+        Term::Reference(refe) => buf.push_str(&format!("#Ref<0.0.0.{}>", refe.0)),
+        Term::RemotePid(node, pid) => buf.push_str(&format!("<{}.{}.0>", node, pid.0)),
+        Term::RemoteReference(node, refe) =>
+            buf.push_str(&format!("#Ref<{}.0.0.{}>", node, refe.0)),
+        Term::CapturedFunction { module, fun_name, arity, .. } =>
+            buf.push_str(&format!("fun {}:{}/{}", module, fun_name, arity)),
+        Term::BoundLambda { module, fun_name, arity, .. } =>
+            buf.push_str(&format!("#Fun<{}.{}.{}>", module, fun_name, arity)),
+        _ => buf.push_str(&format!("{:?}", term)),
+    }
+}
+
+fn float_arg(term: &Term) -> Option<f64> {
+    match term {
+        Term::Float(num) => Some(*num),
+        _ => None,
+    }
+}
+
+/// Formats like `~e`: `digits` significant digits and a signed exponent.
+/// Infinities and NaN have no exponent and are rejected.
+fn format_exp(num: f64, digits: usize) -> Option<String> {
+    if !num.is_finite() {
+        return None;
+    }
+    let formatted = format!("{:.*e}", digits.max(1) - 1, num);
+    let mut split = formatted.splitn(2, 'e');
+    let mantissa = split.next()?;
+    let exponent = split.next()?;
+    if exponent.starts_with('-') {
+        Some(format!("{}e{}", mantissa, exponent))
+    } else {
+        Some(format!("{}e+{}", mantissa, exponent))
+    }
+}
+
+fn format_int(int: &BigInt, base: u32, upper: bool) -> String {
+    let string = int.to_str_radix(base);
+    if upper {
+        string.to_uppercase()
+    } else {
+        string
+    }
+}
+
+/// Pads `text` to `width` columns. Numbers and terms that do not fit
+/// are replaced with `*`, strings are truncated.
+fn pad(text: String, width: Option<usize>, left: bool, pad_char: char,
+       truncate: bool) -> String {
+    let width = match width {
+        Some(width) => width,
+        None => return text,
+    };
+    let len = text.chars().count();
+    if len > width {
+        if truncate {
+            text.chars().take(width).collect()
+        } else {
+            ::std::iter::repeat('*').take(width).collect()
+        }
+    } else {
+        let padding: String = ::std::iter::repeat(pad_char).take(width - len).collect();
+        if left {
+            text + &padding
+        } else {
+            padding + &text
+        }
+    }
+}
+
+/// Implements the control sequences of `io_lib:format/2`. Returns
+/// `None` on a malformed format string or mismatched arguments.
+pub fn format(fmt: &str, args: &[Term]) -> Option<String> {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = fmt.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            out.push(c);
+            continue;
+        }
+
+        let mut left = false;
+        if chars.peek() == Some(&'-') {
+            chars.next();
+            left = true;
+        }
+
+        let mut read_num = |chars: &mut ::std::iter::Peekable<::std::str::Chars>|
+                            -> Option<Option<usize>> {
+            if chars.peek() == Some(&'*') {
+                chars.next();
+                let num = args.next()?.as_i64()?;
+                return Some(Some(num as usize));
+            }
+            let mut num = None;
+            while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                chars.next();
+                num = Some(num.unwrap_or(0) * 10 + digit as usize);
+            }
+            Some(num)
+        };
+
+        let width = read_num(&mut chars)?;
+        let mut precision = None;
+        let mut pad_char = ' ';
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            precision = read_num(&mut chars)?;
+            if chars.peek() == Some(&'.') {
+                chars.next();
+                pad_char = chars.next()?;
+            }
+        }
+
+        // Unicode translation and list detection modifiers.
+        let mut no_strings = false;
+        while chars.peek() == Some(&'t') || chars.peek() == Some(&'l') {
+            if chars.next() == Some('l') {
+                no_strings = true;
+            }
+        }
+
+        let control = chars.next()?;
+        let text = match control {
+            '~' => pad("~".to_string(), width, left, pad_char, false),
+            'n' => "\n".to_string(),
+            'c' => {
+                let chr = ::std::char::from_u32(args.next()?.as_i64()? as u32)?;
+                let count = precision.or(width).unwrap_or(1);
+                let text: String = ::std::iter::repeat(chr).take(count).collect();
+                pad(text, width, left, pad_char, true)
+            }
+            's' => {
+                let arg = args.next()?;
+                let mut text = match arg {
+                    Term::Atom(atom) => atom.as_str().to_string(),
+                    _ => chardata_to_string(arg)?,
+                };
+                if let Some(precision) = precision {
+                    text = text.chars().take(precision).collect();
+                }
+                pad(text, width, left, pad_char, true)
+            }
+            'w' | 'p' => {
+                let mut text = String::new();
+                write_term(args.next()?, &mut text, control == 'p' && !no_strings);
+                pad(text, width, left, pad_char, false)
+            }
+            'b' | 'B' => {
+                let int = match args.next()? {
+                    Term::Integer(int) => int,
+                    _ => return None,
+                };
+                let base = precision.unwrap_or(10);
+                if base < 2 || base > 36 {
+                    return None;
+                }
+                let text = format_int(int, base as u32, control == 'B');
+                pad(text, width, left, pad_char, false)
+            }
+            'e' => {
+                let num = float_arg(args.next()?)?;
+                let text = format_exp(num, precision.unwrap_or(6))?;
+                pad(text, width, left, pad_char, false)
+            }
+            'f' => {
+                let num = float_arg(args.next()?)?;
+                let text = format!("{:.*}", precision.unwrap_or(6), num);
+                pad(text, width, left, pad_char, false)
+            }
+            'g' => {
+                let num = float_arg(args.next()?)?;
+                let text = if num.abs() >= 0.1 && num.abs() < 10000.0 {
+                    format!("{:.*}", precision.unwrap_or(6), num)
+                } else {
+                    format_exp(num, precision.unwrap_or(6))?
+                };
+                pad(text, width, left, pad_char, false)
+            }
+            'i' => {
+                args.next()?;
+                String::new()
+            }
+            _ => return None,
+        };
+        out.push_str(&text);
+    }
+
+    if args.next().is_some() {
+        return None;
+    }
+    Some(out)
+}
+
+/// Reads the format string and argument list of a `format` call.
+pub fn format_args(fmt: &Term, args: &Term) -> Option<String> {
+    let fmt = match fmt {
+        Term::Atom(atom) => atom.as_str().to_string(),
+        _ => chardata_to_string(fmt)?,
+    };
+    let args = args.as_list()?;
+    format(&fmt, &args)
+}
+
+fn format_2(_vm: &VMState, _proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 2);
+    match format_args(&args[0], &args[1]) {
+        Some(string) => CallReturn::Return { term: string_to_term(&string) },
+        None => CallReturn::Throw,
+    }
+}
+
+pub fn make_io_lib() -> NativeModule {
+    let mut module = NativeModule::new("io_lib".to_string());
+    module.add_fun("format".to_string(), 2, Box::new(format_2));
+    module
+}
+
+#[cfg(test)]
+mod test {
+    use super::format;
+    use ::term::Term;
+    use ::Map;
+
+    fn string(s: &str) -> Term {
+        super::string_to_term(s)
+    }
+
+    #[test]
+    fn write_and_print() {
+        let list = Term::List(vec![Term::new_i64(1), Term::new_atom("Ab")],
+                              Box::new(Term::new_i64(2)));
+        assert!(format("~w", &[list]).unwrap() == "[1,'Ab'|2]");
+        assert!(format("~p ~w", &[string("hi"), string("hi")]).unwrap()
+                == "\"hi\" [104,105]");
+        let map = Map::new().insert(Term::new_atom("a"), Term::Tuple(vec![]));
+        assert!(format("~p", &[Term::Map(map)]).unwrap() == "#{a => {}}");
+    }
+
+    #[test]
+    fn padding_and_precision() {
+        assert!(format("~5s|~-5s|", &[string("ab"), string("cd")]).unwrap()
+                == "   ab|cd   |");
+        assert!(format("~.3s", &[string("abcdef")]).unwrap() == "abc");
+        assert!(format("~3w", &[Term::new_i64(12345)]).unwrap() == "***");
+        assert!(format("~5.2.0f", &[Term::Float(3.14159)]).unwrap() == "03.14");
+        assert!(format("~*c", &[Term::new_i64(3), Term::new_i64('x' as i64)]).unwrap()
+                == "xxx");
+    }
+
+    #[test]
+    fn numbers() {
+        assert!(format("~.16b ~.16B", &[Term::new_i64(255), Term::new_i64(255)]).unwrap()
+                == "ff FF");
+        assert!(format("~e", &[Term::Float(1.0)]).unwrap() == "1.00000e+0");
+        assert!(format("~.3e", &[Term::Float(0.0123)]).unwrap() == "1.23e-2");
+        assert!(format("~f~n", &[Term::Float(1.5)]).unwrap() == "1.500000\n");
+    }
+
+    #[test]
+    fn bad_args() {
+        assert!(format("~w", &[]).is_none());
+        assert!(format("~w", &[Term::Nil, Term::Nil]).is_none());
+        assert!(format("~f", &[Term::new_i64(1)]).is_none());
+        assert!(format("~e", &[Term::Float(::std::f64::INFINITY)]).is_none());
+        assert!(format("~g", &[Term::Float(::std::f64::NAN)]).is_none());
+    }
+
+}
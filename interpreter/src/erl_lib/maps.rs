@@ -0,0 +1,139 @@
+use ::module::NativeModule;
+use ::term::Term;
+use ::map::Map;
+use ::bif::{ BifResult, BifError };
+
+/// Maps are taken as terms, so that anything else raises
+/// `{badmap, Term}` like in Erlang instead of `badarg`.
+fn as_map(term: Term) -> Result<Map, BifError> {
+    match term {
+        Term::Map(map) => Ok(map),
+        other => Err(BifError(Term::Tuple(vec![Term::new_atom("badmap"), other]))),
+    }
+}
+
+fn badkey(key: Term) -> BifError {
+    BifError(Term::Tuple(vec![Term::new_atom("badkey"), key]))
+}
+
+fn new() -> BifResult<Map> {
+    Ok(Map::new())
+}
+
+fn get_2(key: Term, map: Term) -> BifResult<Term> {
+    let map = as_map(map)?;
+    map.get(&key).cloned().ok_or_else(|| badkey(key))
+}
+
+fn get_3(key: Term, map: Term, default: Term) -> BifResult<Term> {
+    let map = as_map(map)?;
+    Ok(map.get(&key).cloned().unwrap_or(default))
+}
+
+fn find(key: Term, map: Term) -> BifResult<Option<Term>> {
+    let map = as_map(map)?;
+    Ok(map.get(&key).cloned())
+}
+
+fn is_key(key: Term, map: Term) -> BifResult<bool> {
+    let map = as_map(map)?;
+    Ok(map.contains_key(&key))
+}
+
+fn put(key: Term, value: Term, map: Term) -> BifResult<Map> {
+    let map = as_map(map)?;
+    Ok(map.insert(key, value))
+}
+
+fn update(key: Term, value: Term, map: Term) -> BifResult<Map> {
+    let map = as_map(map)?;
+    map.update(key.clone(), value).ok_or_else(|| badkey(key))
+}
+
+fn remove(key: Term, map: Term) -> BifResult<Map> {
+    let map = as_map(map)?;
+    Ok(map.remove(&key))
+}
+
+fn merge(m1: Term, m2: Term) -> BifResult<Map> {
+    let mut new = as_map(m1)?;
+    let m2 = as_map(m2)?;
+    for (key, value) in m2.iter() {
+        new = new.insert(key.clone(), value.clone());
+    }
+    Ok(new)
+}
+
+fn size(map: Term) -> BifResult<usize> {
+    let map = as_map(map)?;
+    Ok(map.len())
+}
+
+fn keys(map: Term) -> BifResult<Vec<Term>> {
+    let map = as_map(map)?;
+    Ok(map.keys().cloned().collect())
+}
+
+fn values(map: Term) -> BifResult<Vec<Term>> {
+    let map = as_map(map)?;
+    Ok(map.values().cloned().collect())
+}
+
+fn to_list(map: Term) -> BifResult<Vec<(Term, Term)>> {
+    let map = as_map(map)?;
+    Ok(map.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+}
+
+fn from_list(entries: Vec<(Term, Term)>) -> BifResult<Map> {
+    Ok(Map::from_entries(entries))
+}
+
+pub fn make_maps() -> NativeModule {
+    let mut module = NativeModule::new("maps".to_string());
+    module.add_bif("new", new);
+    module.add_bif("get", get_2);
+    module.add_bif("get", get_3);
+    module.add_bif("find", find);
+    module.add_bif("is_key", is_key);
+    module.add_bif("put", put);
+    module.add_bif("update", update);
+    module.add_bif("remove", remove);
+    module.add_bif("merge", merge);
+    module.add_bif("size", size);
+    module.add_bif("keys", keys);
+    module.add_bif("values", values);
+    module.add_bif("to_list", to_list);
+    module.add_bif("from_list", from_list);
+    module
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ get_2, update };
+    use ::bif::BifError;
+    use ::map::Map;
+    use ::term::{ Term, ErlEq };
+
+    fn reason(error: Option<BifError>, expected: &[Term]) -> bool {
+        match error {
+            Some(BifError(reason)) => reason.erl_eq(&Term::Tuple(expected.to_vec())),
+            None => false,
+        }
+    }
+
+    #[test]
+    fn missing_key_raises_badkey() {
+        let map = Term::Map(Map::new());
+        let badkey = [Term::new_atom("badkey"), Term::new_atom("a")];
+        assert!(reason(get_2(Term::new_atom("a"), map.clone()).err(), &badkey));
+        assert!(reason(update(Term::new_atom("a"), Term::Nil, map).err(), &badkey));
+    }
+
+    #[test]
+    fn non_map_raises_badmap() {
+        let badmap = [Term::new_atom("badmap"), Term::new_i64(1)];
+        assert!(reason(get_2(Term::new_atom("a"), Term::new_i64(1)).err(), &badmap));
+        assert!(reason(update(Term::new_atom("a"), Term::Nil, Term::new_i64(1)).err(), &badmap));
+    }
+
+}
@@ -7,5 +7,20 @@ pub use self::os::make_os;
 mod lists;
 pub use self::lists::make_lists;
 
+mod maps;
+pub use self::maps::make_maps;
+
+mod io_lib;
+pub use self::io_lib::make_io_lib;
+
+mod io;
+pub use self::io::make_io;
+
+mod timer;
+pub use self::timer::make_timer;
+
 mod file;
-pub use self::file::make_time;
+pub use self::file::make_file;
+
+mod code;
+pub use self::code::make_code;
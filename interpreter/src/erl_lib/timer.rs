@@ -0,0 +1,24 @@
+use ::module::NativeModule;
+use ::vm::VMState;
+use ::term::Term;
+use ::process::{ CallReturn, ProcessContext };
+
+fn sleep(vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+    assert!(args.len() == 1);
+    let wake = match args[0] {
+        Term::Atom(ref atom) if atom.as_str() == "infinity" => ::std::u64::MAX,
+        _ => match args[0].as_i64() {
+            Some(time) if time >= 0 => vm.timers.lock().unwrap().now() + time as u64,
+            _ => return CallReturn::Throw,
+        },
+    };
+    // The scheduler suspends the process once this call returns.
+    proc.sleep_until = Some(wake);
+    CallReturn::Return { term: Term::new_atom("ok") }
+}
+
+pub fn make_timer() -> NativeModule {
+    let mut module = NativeModule::new("timer".to_string());
+    module.add_fun("sleep".to_string(), 1, Box::new(sleep));
+    module
+}
@@ -1,7 +1,10 @@
 extern crate tempdir;
 
 use ::std::io::{ Read, Write };
-use ::{ VMState, Term };
+use ::std::sync::{ Arc, Mutex };
+use ::{ VMState, Term, Atom, FunctionIdent };
+use ::{ Debugger, DebugFrontend, DebugContext, Breakpoint, Breakpoints,
+        StepMode, StopReason };
 use eir::Module;
 use ::term::ErlEq;
 
@@ -52,6 +55,9 @@ fn ctx_from_erl(erlang_code: &str) -> VMState {
     let mut ctx = VMState::new();
 
     ctx.add_native_module(::erl_lib::make_erlang());
+    ctx.add_native_module(::erl_lib::make_io());
+    ctx.add_native_module(::erl_lib::make_io_lib());
+    ctx.add_native_module(::erl_lib::make_timer());
     ctx.add_erlang_module(module);
 
     ctx
@@ -155,6 +161,272 @@ fn factorial() {
     println!("Res: {:?}", result);
 }
 
+const IO_FORMAT_ERL: &str = r##"
+-module(test).
+-export([greet/1]).
+
+greet(Name) ->
+    io:format("Hello ~s, ~p!~n", [Name, {1, two}]),
+    ok.
+
+    "##;
+
+#[test]
+fn io_format_captured() {
+    let mut ctx = ctx_from_erl(IO_FORMAT_ERL);
+    ctx.output.lock().unwrap().set_capture(true);
+
+    let args = vec![Term::new_atom("world")];
+    ctx.call("test", "greet", args);
+
+    assert!(ctx.output.lock().unwrap().take() == "Hello world, {1,two}!\n");
+}
+
+const TIMER_ERL: &str = r##"
+-module(test).
+-export([sleep_then_read/0]).
+
+sleep_then_read() ->
+    Ref = erlang:send_after(100, self(), hello),
+    timer:sleep(30),
+    erlang:read_timer(Ref).
+
+    "##;
+
+#[test]
+fn virtual_clock_sleep() {
+    let mut ctx = ctx_from_erl(TIMER_ERL);
+
+    let result = ctx.call("test", "sleep_then_read", vec![]);
+    assert!(result.unwrap_return().erl_eq(&Term::new_i64(70)));
+    assert!(ctx.timers.lock().unwrap().now() == 30);
+}
+
+const RECEIVE_AFTER_ERL: &str = r##"
+-module(test).
+-export([wait/0, wait_for_timer/0]).
+
+wait() ->
+    receive
+        Msg -> Msg
+    after 50 -> timeout
+    end.
+
+wait_for_timer() ->
+    erlang:send_after(20, self(), hello),
+    wait().
+"##;
+
+#[test]
+fn receive_after_advances_clock() {
+    let mut ctx = ctx_from_erl(RECEIVE_AFTER_ERL);
+    let result = ctx.call("test", "wait", vec![]);
+    assert!(result.unwrap_return().erl_eq(&Term::new_atom("timeout")));
+    assert!(ctx.timers.lock().unwrap().now() == 50);
+
+    let mut ctx = ctx_from_erl(RECEIVE_AFTER_ERL);
+    let result = ctx.call("test", "wait_for_timer", vec![]);
+    assert!(result.unwrap_return().erl_eq(&Term::new_atom("hello")));
+    assert!(ctx.timers.lock().unwrap().now() == 20);
+}
+
+const UPGRADE_ERL: &str = r##"
+-module(test).
+-export([upgrade/1, version/0]).
+
+version() -> 1.
+
+upgrade(Core) ->
+    F = fun() -> version() end,
+    {module, test} = code:load_binary(test, "test.core", Core),
+    {F(), test:version(), version()}.
+"##;
+
+#[test]
+fn hot_code_upgrade() {
+    let mut ctx = ctx_from_erl(UPGRADE_ERL);
+    ctx.add_native_module(::erl_lib::make_code());
+
+    let new_core = erl_to_core(&UPGRADE_ERL.replace("version() -> 1.", "version() -> 2."));
+    let result = ctx.call("test", "upgrade", vec![Term::Binary(new_core.into_bytes())]);
+    let expected = Term::Tuple(vec![
+        Term::new_i64(1), Term::new_i64(2), Term::new_i64(1)]);
+    assert!(result.unwrap_return().erl_eq(&expected));
+
+    // Nothing runs the old version anymore.
+    assert!(ctx.purge_module(None, "test") == Some(false));
+    assert!(ctx.purge_module(None, "test") == None);
+}
+
+const RECEIVE_ERL: &str = r##"
+-module(test).
+-export([recv/0]).
+
+recv() ->
+    receive
+        Msg -> Msg
+    end.
+"##;
+
+#[test]
+fn trace_message_receive() {
+    let mut ctx = ctx_from_erl(RECEIVE_ERL);
+    let sink = ::trace::MemoryTraceSink::new();
+    ctx.add_trace_sink(::trace::TraceFilter::all(), Box::new(sink.clone()));
+
+    let pid = ctx.start("test", "recv", vec![]);
+    ctx.deliver_message(Term::Pid(pid), pid, Term::new_atom("hello"));
+    for _ in 0..100 {
+        if ctx.is_finished(pid) {
+            break;
+        }
+        ctx.run_round();
+    }
+    let result = ctx.take_result(pid).unwrap();
+    assert!(result.unwrap_return().erl_eq(&Term::new_atom("hello")));
+
+    let received: Vec<_> = sink.events().iter()
+        .filter_map(|event| match event.kind {
+            ::trace::TraceEventKind::MessageReceive { from, ref message } =>
+                Some((from, message.clone())),
+            _ => None,
+        })
+        .collect();
+    assert!(received.len() == 1);
+    assert!(received[0].0 == pid);
+    assert!(received[0].1.erl_eq(&Term::new_atom("hello")));
+
+    // The message was taken out of the mailbox
+    let left = ctx.mailbox(pid)
+        .map(|mailbox| mailbox.lock().unwrap().messages().len());
+    assert!(left.unwrap_or(0) == 0);
+}
+
+const EXACT_EQ_ERL: &str = r##"
+-module(test).
+-export([eq/2]).
+
+eq(A, B) -> A =:= B.
+"##;
+
+#[test]
+fn trace_compare_warning() {
+    let mut ctx = ctx_from_erl(EXACT_EQ_ERL);
+    let sink = ::trace::MemoryTraceSink::new();
+    ctx.add_trace_sink(::trace::TraceFilter::all(), Box::new(sink.clone()));
+
+    let tuple = Term::Tuple(vec![Term::new_atom("a")]);
+    ctx.call("test", "eq", vec![tuple.clone(), tuple]);
+
+    let warnings: Vec<_> = sink.events().iter()
+        .filter_map(|event| match event.kind {
+            ::trace::TraceEventKind::Warning { ref text, ref args } =>
+                Some((text.clone(), args.len())),
+            _ => None,
+        })
+        .collect();
+    assert!(warnings.len() == 1);
+    assert!(warnings[0].0.contains("ErlExactEq"));
+    assert!(warnings[0].1 == 2);
+}
+
+const SELECTIVE_RECEIVE_ERL: &str = r##"
+-module(test).
+-export([recv/0]).
+
+recv() ->
+    A = receive {ok, Msg} -> Msg end,
+    B = receive Other -> Other after 0 -> empty end,
+    C = receive Last -> Last after 0 -> empty end,
+    {A, B, C}.
+"##;
+
+#[test]
+fn selective_receive() {
+    let mut ctx = ctx_from_erl(SELECTIVE_RECEIVE_ERL);
+
+    let pid = ctx.start("test", "recv", vec![]);
+    ctx.deliver_message(Term::Pid(pid), pid, Term::new_atom("other"));
+    ctx.deliver_message(Term::Pid(pid), pid, Term::Tuple(vec![
+        Term::new_atom("ok"), Term::new_atom("hello")]));
+    for _ in 0..100 {
+        if ctx.is_finished(pid) {
+            break;
+        }
+        ctx.run_round();
+    }
+
+    // The matched message is taken out first, the one skipped over
+    // stays in the mailbox for the next receive.
+    let expected = Term::Tuple(vec![
+        Term::new_atom("hello"), Term::new_atom("other"), Term::new_atom("empty")]);
+    let result = ctx.take_result(pid).unwrap();
+    assert!(result.unwrap_return().erl_eq(&expected));
+}
+
+/// Records every stop, and resumes with the given modes in order.
+struct ScriptedFrontend {
+    replies: Vec<StepMode>,
+    stops: Arc<Mutex<Vec<(String, StopReason, usize)>>>,
+}
+
+impl DebugFrontend for ScriptedFrontend {
+    fn stopped(&mut self, ctx: &DebugContext, breakpoints: &mut Breakpoints) -> StepMode {
+        let mut stops = self.stops.lock().unwrap();
+        if stops.len() == 0 {
+            breakpoints.add(Breakpoint::Function(FunctionIdent {
+                module: Atom::from_str("test"),
+                name: Atom::from_str("add"),
+                arity: 2,
+                lambda: None,
+            }));
+        }
+        stops.push((ctx.function().name.as_str().to_string(), ctx.reason, ctx.depth()));
+        if self.replies.len() == 0 {
+            StepMode::Continue
+        } else {
+            self.replies.remove(0)
+        }
+    }
+}
+
+#[test]
+fn debugger_step_break_continue() {
+    let mut ctx = ctx_from_erl(TEST_ERL_1);
+
+    let stops = Arc::new(Mutex::new(Vec::new()));
+    let frontend = ScriptedFrontend {
+        replies: vec![
+            StepMode::Op,
+            StepMode::Continue,
+            StepMode::Out { depth: 2 },
+            StepMode::Continue,
+        ],
+        stops: stops.clone(),
+    };
+    ctx.attach_debugger(Debugger::new(Box::new(frontend)));
+
+    let args = vec![Term::new_i64(1), Term::new_i64(2), Term::new_i64(3)];
+    let result = ctx.call("test", "add_two", args);
+    assert!(result.unwrap_return().erl_eq(&Term::Integer(6.into())));
+
+    let stops = stops.lock().unwrap();
+    let expected = vec![
+        // Stops before the first op, then steps to the next one
+        ("add_two", StopReason::Step, 1),
+        ("add_two", StopReason::Step, 1),
+        // Continues to the breakpoint, and steps out of `add`
+        ("add", StopReason::Breakpoint(0), 2),
+        ("add_two", StopReason::Step, 1),
+        // Continues to the second call
+        ("add", StopReason::Breakpoint(0), 2),
+    ];
+    assert!(stops.len() == expected.len());
+    for ((fun, reason, depth), (e_fun, e_reason, e_depth)) in stops.iter().zip(expected) {
+        assert!(fun == e_fun && *reason == e_reason && *depth == e_depth);
+    }
+}
+
 //#[test]
 fn long_strings() {
     let mut ctx = VMState::new();
@@ -182,10 +454,14 @@ fn compile_core_file(path: &str) -> Module {
 fn compiler() {
     let result = std::panic::catch_unwind(|| {
         let mut ctx = VMState::new();
+        ctx.add_trace_sink(
+            ::trace::TraceFilter::all(),
+            Box::new(::trace::ChromeTraceSink::create("trace.json").unwrap()));
 
         ctx.add_native_module(::erl_lib::make_erlang());
         ctx.add_native_module(::erl_lib::make_os());
-        ctx.add_native_module(::erl_lib::make_time());
+        ctx.add_native_module(::erl_lib::make_maps());
+        ctx.add_native_module(::erl_lib::make_file());
 
         ctx.add_erlang_module(compile_core_file(
             "../otp/lib/compiler/ebin/compile.core"));
@@ -206,6 +482,5 @@ fn compiler() {
         let args = vec![Term::new_atom("foo.erl")];
         ctx.call("compile", "file", args);
     });
-    ::trace::dump_trace("trace.json".to_string());
     assert!(result.is_ok());
 }
@@ -0,0 +1,347 @@
+//! Filesystem used by the `file` module.
+//!
+//! Erlang code never touches the host filesystem directly, all access
+//! goes through the `Filesystem` set on the `VMState`. By default this
+//! is an empty `MemoryFilesystem`, which keeps runs deterministic.
+
+use std::collections::{ BTreeMap, BTreeSet };
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    IsDirectory,
+    NotDirectory,
+    PermissionDenied,
+    Exists,
+    Io(String),
+}
+
+impl FsError {
+
+    /// The posix error name used in `{error, Reason}` returns.
+    pub fn reason(&self) -> &'static str {
+        match *self {
+            FsError::NotFound => "enoent",
+            FsError::IsDirectory => "eisdir",
+            FsError::NotDirectory => "enotdir",
+            FsError::PermissionDenied => "eacces",
+            FsError::Exists => "eexist",
+            FsError::Io(_) => "eio",
+        }
+    }
+
+}
+
+impl From<io::Error> for FsError {
+    fn from(err: io::Error) -> FsError {
+        match err.kind() {
+            io::ErrorKind::NotFound => FsError::NotFound,
+            io::ErrorKind::PermissionDenied => FsError::PermissionDenied,
+            io::ErrorKind::AlreadyExists => FsError::Exists,
+            _ => FsError::Io(err.to_string()),
+        }
+    }
+}
+
+pub type FsResult<T> = Result<T, FsError>;
+
+pub trait Filesystem: Send {
+    fn read(&self, path: &str) -> FsResult<Vec<u8>>;
+    /// Creates or truncates the file at `path`.
+    fn write(&mut self, path: &str, data: &[u8]) -> FsResult<()>;
+    fn delete(&mut self, path: &str) -> FsResult<()>;
+    fn make_dir(&mut self, path: &str) -> FsResult<()>;
+    /// Names of the entries in the directory, in no particular order.
+    fn list_dir(&self, path: &str) -> FsResult<Vec<String>>;
+}
+
+/// Resolves `.` and `..` and returns the components of `path`.
+/// Relative paths are taken relative to the root, and `..` never
+/// leaves it.
+fn normalize(path: &str) -> Vec<&str> {
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => { components.pop(); }
+            _ => components.push(component),
+        }
+    }
+    components
+}
+
+/// Filesystem kept entirely in memory.
+#[derive(Debug, Clone)]
+pub struct MemoryFilesystem {
+    files: BTreeMap<String, Vec<u8>>,
+    dirs: BTreeSet<String>,
+}
+
+impl MemoryFilesystem {
+
+    pub fn new() -> Self {
+        let mut dirs = BTreeSet::new();
+        dirs.insert("/".to_string());
+        MemoryFilesystem {
+            files: BTreeMap::new(),
+            dirs: dirs,
+        }
+    }
+
+    fn key(path: &str) -> String {
+        format!("/{}", normalize(path).join("/"))
+    }
+
+    fn parent(key: &str) -> String {
+        match key.rfind('/') {
+            Some(0) | None => "/".to_string(),
+            Some(idx) => key[..idx].to_string(),
+        }
+    }
+
+    fn check_parent(&self, key: &str) -> FsResult<()> {
+        let parent = MemoryFilesystem::parent(key);
+        if self.dirs.contains(&parent) {
+            Ok(())
+        } else if self.files.contains_key(&parent) {
+            Err(FsError::NotDirectory)
+        } else {
+            Err(FsError::NotFound)
+        }
+    }
+
+}
+
+impl Default for MemoryFilesystem {
+    fn default() -> Self {
+        MemoryFilesystem::new()
+    }
+}
+
+impl Filesystem for MemoryFilesystem {
+
+    fn read(&self, path: &str) -> FsResult<Vec<u8>> {
+        let key = MemoryFilesystem::key(path);
+        if let Some(data) = self.files.get(&key) {
+            Ok(data.clone())
+        } else if self.dirs.contains(&key) {
+            Err(FsError::IsDirectory)
+        } else {
+            Err(FsError::NotFound)
+        }
+    }
+
+    fn write(&mut self, path: &str, data: &[u8]) -> FsResult<()> {
+        let key = MemoryFilesystem::key(path);
+        if self.dirs.contains(&key) {
+            return Err(FsError::IsDirectory);
+        }
+        self.check_parent(&key)?;
+        self.files.insert(key, data.to_vec());
+        Ok(())
+    }
+
+    fn delete(&mut self, path: &str) -> FsResult<()> {
+        let key = MemoryFilesystem::key(path);
+        if self.files.remove(&key).is_some() {
+            Ok(())
+        } else if self.dirs.contains(&key) {
+            Err(FsError::PermissionDenied)
+        } else {
+            Err(FsError::NotFound)
+        }
+    }
+
+    fn make_dir(&mut self, path: &str) -> FsResult<()> {
+        let key = MemoryFilesystem::key(path);
+        if self.dirs.contains(&key) || self.files.contains_key(&key) {
+            return Err(FsError::Exists);
+        }
+        self.check_parent(&key)?;
+        self.dirs.insert(key);
+        Ok(())
+    }
+
+    fn list_dir(&self, path: &str) -> FsResult<Vec<String>> {
+        let key = MemoryFilesystem::key(path);
+        if !self.dirs.contains(&key) {
+            return if self.files.contains_key(&key) {
+                Err(FsError::NotDirectory)
+            } else {
+                Err(FsError::NotFound)
+            };
+        }
+        let entries = self.files.keys()
+            .chain(self.dirs.iter())
+            .filter(|k| k.as_str() != "/" && MemoryFilesystem::parent(k) == key)
+            .map(|k| k.rsplit('/').next().unwrap().to_string())
+            .collect();
+        Ok(entries)
+    }
+
+}
+
+/// A file opened with `file:open/2`. The whole file is kept in
+/// memory, and written back to the filesystem on every write.
+#[derive(Debug, Clone)]
+pub struct OpenFile {
+    pub path: String,
+    pub data: Vec<u8>,
+    pub position: usize,
+    pub read: bool,
+    pub write: bool,
+    pub append: bool,
+    pub binary: bool,
+}
+
+impl OpenFile {
+
+    pub fn new(path: String) -> Self {
+        OpenFile {
+            path: path,
+            data: Vec::new(),
+            position: 0,
+            read: false,
+            write: false,
+            append: false,
+            binary: false,
+        }
+    }
+
+    pub fn write_at_position(&mut self, data: &[u8]) {
+        if self.append {
+            self.position = self.data.len();
+        }
+        let overlap = ::std::cmp::min(data.len(), self.data.len() - self.position);
+        self.data[self.position..self.position + overlap]
+            .copy_from_slice(&data[..overlap]);
+        self.data.extend_from_slice(&data[overlap..]);
+        self.position += data.len();
+    }
+
+}
+
+/// Filesystem backed by the real disk. When created with `rooted`,
+/// every path is resolved inside the given directory.
+#[derive(Debug, Clone)]
+pub struct DiskFilesystem {
+    root: Option<PathBuf>,
+}
+
+impl DiskFilesystem {
+
+    pub fn new() -> Self {
+        DiskFilesystem {
+            root: None,
+        }
+    }
+
+    pub fn rooted<P>(root: P) -> Self where P: Into<PathBuf> {
+        DiskFilesystem {
+            root: Some(root.into()),
+        }
+    }
+
+    fn resolve(&self, path: &str) -> PathBuf {
+        match self.root {
+            Some(ref root) => {
+                let mut full = root.clone();
+                for component in normalize(path) {
+                    full.push(component);
+                }
+                full
+            }
+            None => PathBuf::from(path),
+        }
+    }
+
+}
+
+impl Filesystem for DiskFilesystem {
+
+    fn read(&self, path: &str) -> FsResult<Vec<u8>> {
+        let path = self.resolve(path);
+        if path.is_dir() {
+            return Err(FsError::IsDirectory);
+        }
+        Ok(::std::fs::read(path)?)
+    }
+
+    fn write(&mut self, path: &str, data: &[u8]) -> FsResult<()> {
+        let path = self.resolve(path);
+        if path.is_dir() {
+            return Err(FsError::IsDirectory);
+        }
+        Ok(::std::fs::write(path, data)?)
+    }
+
+    fn delete(&mut self, path: &str) -> FsResult<()> {
+        Ok(::std::fs::remove_file(self.resolve(path))?)
+    }
+
+    fn make_dir(&mut self, path: &str) -> FsResult<()> {
+        Ok(::std::fs::create_dir(self.resolve(path))?)
+    }
+
+    fn list_dir(&self, path: &str) -> FsResult<Vec<String>> {
+        let path = self.resolve(path);
+        if path.is_file() {
+            return Err(FsError::NotDirectory);
+        }
+        let mut entries = Vec::new();
+        for entry in ::std::fs::read_dir(path)? {
+            entries.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        Ok(entries)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ Filesystem, MemoryFilesystem, FsError, OpenFile };
+
+    #[test]
+    fn memory_read_write() {
+        let mut fs = MemoryFilesystem::new();
+        assert!(fs.read("a.txt") == Err(FsError::NotFound));
+        fs.write("a.txt", b"hello").unwrap();
+        assert!(fs.read("/a.txt").unwrap() == b"hello".to_vec());
+        assert!(fs.read("./dir/../a.txt").unwrap() == b"hello".to_vec());
+        assert!(fs.write("dir/b.txt", b"").unwrap_err() == FsError::NotFound);
+        fs.delete("a.txt").unwrap();
+        assert!(fs.delete("a.txt") == Err(FsError::NotFound));
+    }
+
+    #[test]
+    fn memory_dirs() {
+        let mut fs = MemoryFilesystem::new();
+        fs.make_dir("dir").unwrap();
+        fs.write("dir/b.txt", b"b").unwrap();
+        fs.write("c.txt", b"c").unwrap();
+        assert!(fs.read("dir") == Err(FsError::IsDirectory));
+        assert!(fs.list_dir("dir").unwrap() == vec!["b.txt".to_string()]);
+
+        let mut root = fs.list_dir("/").unwrap();
+        root.sort();
+        assert!(root == vec!["c.txt".to_string(), "dir".to_string()]);
+        assert!(fs.list_dir("c.txt") == Err(FsError::NotDirectory));
+    }
+
+    #[test]
+    fn open_file_overwrite() {
+        let mut file = OpenFile::new("a".to_string());
+        file.data = b"hello".to_vec();
+        file.position = 3;
+        file.write_at_position(b"p!!");
+        assert!(file.data == b"help!!".to_vec());
+
+        file.append = true;
+        file.position = 0;
+        file.write_at_position(b"?");
+        assert!(file.data == b"help!!?".to_vec());
+    }
+
+}
@@ -0,0 +1,105 @@
+//! Accounting of the memory held by processes.
+//!
+//! Sizes are approximations in machine words, see `Term::size_words`.
+//! Each process reports the size of its heap to the VM as it runs, and
+//! is killed when it grows past its `max_heap_size`. When the total of
+//! all processes goes past the limit of the VM, the largest process is
+//! killed.
+
+use std::collections::HashMap;
+
+use ::term::Pid;
+
+/// Size of a machine word in bytes.
+pub const WORD_SIZE: usize = ::std::mem::size_of::<usize>();
+
+#[derive(Debug, Default)]
+pub struct HeapAccounting {
+    /// Last reported heap size of every live process.
+    sizes: HashMap<Pid, usize>,
+    total: usize,
+    /// Limit on the total, if any.
+    limit: Option<usize>,
+}
+
+impl HeapAccounting {
+
+    pub fn new() -> Self {
+        HeapAccounting::default()
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    /// Records the heap size of the process. Returns false if the total
+    /// is now over the limit.
+    pub fn update(&mut self, pid: Pid, words: usize) -> bool {
+        let old = self.sizes.insert(pid, words).unwrap_or(0);
+        self.total = self.total - old + words;
+        self.limit.map(|limit| self.total <= limit).unwrap_or(true)
+    }
+
+    pub fn remove(&mut self, pid: Pid) {
+        if let Some(old) = self.sizes.remove(&pid) {
+            self.total -= old;
+        }
+    }
+
+    /// Stops accounting for the process with the largest heap, which is
+    /// about to be killed. Ties go to the newest process.
+    pub fn take_largest(&mut self) -> Option<Pid> {
+        let pid = self.sizes.iter()
+            .max_by_key(|(pid, words)| (**words, pid.0))
+            .map(|(pid, _)| *pid)?;
+        self.remove(pid);
+        Some(pid)
+    }
+
+    /// Last reported heap size of the process.
+    pub fn size(&self, pid: Pid) -> Option<usize> {
+        self.sizes.get(&pid).cloned()
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::HeapAccounting;
+    use ::term::Pid;
+
+    #[test]
+    fn total_follows_updates() {
+        let mut heap = HeapAccounting::new();
+        heap.set_limit(Some(100));
+        assert!(heap.update(Pid(0), 40));
+        assert!(heap.update(Pid(1), 50));
+        assert!(!heap.update(Pid(0), 60));
+        assert!(heap.total() == 110);
+
+        heap.remove(Pid(1));
+        assert!(heap.total() == 60);
+        assert!(heap.size(Pid(1)).is_none());
+        assert!(heap.update(Pid(0), 100));
+    }
+
+    #[test]
+    fn take_largest() {
+        let mut heap = HeapAccounting::new();
+        assert!(heap.update(Pid(0), 40));
+        assert!(heap.update(Pid(1), 50));
+        assert!(heap.update(Pid(2), 50));
+        assert!(heap.take_largest() == Some(Pid(2)));
+        assert!(heap.take_largest() == Some(Pid(1)));
+        assert!(heap.total() == 40);
+    }
+
+}
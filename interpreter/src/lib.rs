@@ -24,20 +24,52 @@ extern crate pretty;
 
 mod term;
 pub use term::{ TermType, Term, BoundLambdaEnv, Pid, Reference };
+mod map;
+pub use map::Map;
 mod pattern;
 
 pub mod erl_lib;
 #[cfg(test)] pub mod erl_tests;
 
 mod vm;
-pub use vm::{ VMState, WatchType };
+pub use vm::{ VMState, WatchType, OutputBuffer, SchedulerMode };
+
+mod scheduler;
 
 mod process;
+pub use process::{ CallReturn, ProcessContext, ProcessInfo, ProcessStatus };
+
+mod convert;
+pub use convert::{ IntoTerm, FromTerm, ConvertError, ConvertResult, Binary };
+#[cfg(feature = "serde_term")]
+pub use convert::{ to_term, from_term, TermSerdeError };
+
+mod debugger;
+pub use debugger::{ Debugger, DebugFrontend, DebugContext, Breakpoint, Breakpoints,
+                    StepMode, StopReason, FrameInfo };
 
 mod module;
+pub use module::{ NativeModule, ModuleType };
+
+mod bif;
+pub use bif::{ TypedBif, BifResult, BifError, Badarg };
+
+mod code;
+pub use code::{ CodeTable, ModuleVersion, LoadError };
+
+mod heap;
+
+mod timer;
+pub use timer::{ Timers, Timer };
+
+mod filesystem;
+pub use filesystem::{ Filesystem, FsError, FsResult, MemoryFilesystem, DiskFilesystem };
 
 mod receive;
 
-mod trace;
+pub mod trace;
+
+pub mod dist;
+pub use dist::Cluster;
 
 mod mailbox;
@@ -1,9 +1,13 @@
-use ::term::{ Term, Pid };
+use ::term::Term;
 
 #[derive(Debug)]
 pub struct Mailbox {
     trap_exits: bool,
-    messages: Vec<(Pid, Term)>,
+    /// Sender and message. The sender is a `Term::Pid` or a
+    /// `Term::RemotePid`, or what was monitored for `'DOWN'` messages.
+    messages: Vec<(Term, Term)>,
+    /// Size of the messages in words, see `Term::size_words`.
+    words: usize,
 }
 
 impl Mailbox {
@@ -11,6 +15,7 @@ impl Mailbox {
         Mailbox {
             trap_exits: false,
             messages: vec![],
+            words: 0,
         }
     }
     pub fn get_trap_exits(&self) -> bool {
@@ -19,4 +24,20 @@ impl Mailbox {
     pub fn set_trap_exits(&mut self, val: bool) {
         self.trap_exits = val;
     }
+    pub fn push(&mut self, from: Term, message: Term) {
+        self.words += message.size_words();
+        self.messages.push((from, message));
+    }
+    pub fn messages(&self) -> &[(Term, Term)] {
+        &self.messages
+    }
+    /// Takes a message out of the mailbox.
+    pub fn remove(&mut self, index: usize) -> (Term, Term) {
+        let (from, message) = self.messages.remove(index);
+        self.words -= message.size_words();
+        (from, message)
+    }
+    pub fn heap_words(&self) -> usize {
+        self.words
+    }
 }
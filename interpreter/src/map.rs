@@ -0,0 +1,612 @@
+//! Persistent map used for `Term::Map`.
+//!
+//! Follows the approach of the BEAM. Small maps are stored as a flatmap, a
+//! sorted array of entries. Once a map grows past `FLATMAP_LIMIT` entries it
+//! is promoted to a hash array mapped trie. Both representations are
+//! immutable and share structure between versions, so updating a map never
+//! copies more than a single path through the trie.
+//!
+//! Keys are compared with `=:=`, so `1` and `1.0` are distinct keys.
+//! Iteration is always in map key order (term order, except that all
+//! integers come before all floats), which is what printing and term
+//! comparison rely on. As the trie is ordered by hash, a trie keeps a
+//! sorted view of its entries, built the first time it is iterated.
+
+use std::slice;
+use std::sync::{ Arc, OnceLock };
+use std::cmp::Ordering;
+use std::hash::{ Hash, Hasher };
+use std::collections::hash_map::DefaultHasher;
+
+use ::term::{ Term, ErlExactOrd };
+
+/// Maps with at most this many entries are stored as a flatmap.
+const FLATMAP_LIMIT: usize = 32;
+
+/// Number of hash bits consumed by each level of the trie.
+const HAMT_BITS: u32 = 5;
+const HAMT_MASK: u64 = (1 << HAMT_BITS) - 1;
+
+#[derive(Debug, Clone)]
+pub struct Map {
+    len: usize,
+    repr: MapRepr,
+}
+
+#[derive(Debug, Clone)]
+enum MapRepr {
+    /// Entries sorted by key in map key order.
+    Flat(Arc<Vec<(Term, Term)>>),
+    /// The trie, and its entries in map key order once they were needed.
+    Hamt(Arc<HamtNode>, Arc<OnceLock<Vec<Arc<(Term, Term)>>>>),
+}
+
+impl MapRepr {
+
+    fn hamt(root: HamtNode) -> Self {
+        MapRepr::Hamt(Arc::new(root), Arc::new(OnceLock::new()))
+    }
+
+}
+
+#[derive(Debug, Clone)]
+enum HamtEntry {
+    Leaf(u64, Arc<(Term, Term)>),
+    /// Several keys with the exact same hash.
+    Collision(u64, Arc<Vec<(Term, Term)>>),
+    Node(Arc<HamtNode>),
+}
+
+#[derive(Debug, Clone)]
+struct HamtNode {
+    bitmap: u32,
+    entries: Vec<HamtEntry>,
+}
+
+fn hash_into<H: Hasher>(term: &Term, state: &mut H) {
+    match term {
+        Term::Nil => 0u8.hash(state),
+        Term::Integer(int) => {
+            1u8.hash(state);
+            int.hash(state);
+        }
+        Term::Float(float) => {
+            2u8.hash(state);
+            // 0.0 and -0.0 compare equal
+            let float = if *float == 0.0 { 0.0f64 } else { *float };
+            float.to_bits().hash(state);
+        }
+        Term::Atom(atom) => {
+            3u8.hash(state);
+            atom.as_str().hash(state);
+        }
+        Term::Tuple(items) => {
+            4u8.hash(state);
+            items.len().hash(state);
+            for item in items {
+                hash_into(item, state);
+            }
+        }
+        Term::List(_, _) => {
+            // Lists can be split into several chunks, hash the flattened
+            // form so that equal lists always hash the same.
+            let (head, tail) = term.as_inproper_list();
+            if head.len() == 0 {
+                hash_into(&tail, state);
+                return;
+            }
+            5u8.hash(state);
+            head.len().hash(state);
+            for item in head.iter() {
+                hash_into(item, state);
+            }
+            hash_into(&tail, state);
+        }
+        Term::Map(map) => {
+            6u8.hash(state);
+            map.len().hash(state);
+            for (key, value) in map.iter() {
+                hash_into(key, state);
+                hash_into(value, state);
+            }
+        }
+        Term::Pid(pid) => {
+            7u8.hash(state);
+            pid.0.hash(state);
+        }
+        Term::Reference(reference) => {
+            8u8.hash(state);
+            reference.0.hash(state);
+        }
+        Term::RemotePid(node, pid) => {
+            12u8.hash(state);
+            node.as_str().hash(state);
+            pid.0.hash(state);
+        }
+        Term::RemoteReference(node, reference) => {
+            13u8.hash(state);
+            node.as_str().hash(state);
+            reference.0.hash(state);
+        }
+        Term::Binary(bin) => {
+            9u8.hash(state);
+            bin.hash(state);
+        }
+        Term::CapturedFunction { module, fun_name, arity, .. } => {
+            10u8.hash(state);
+            module.as_str().hash(state);
+            fun_name.as_str().hash(state);
+            arity.hash(state);
+        }
+        Term::BoundLambda { module, fun_name, arity, lambda, bound_env, .. } => {
+            11u8.hash(state);
+            module.as_str().hash(state);
+            fun_name.as_str().hash(state);
+            arity.hash(state);
+            lambda.1.hash(state);
+            for var in bound_env.vars.iter() {
+                hash_into(var, state);
+            }
+        }
+        _ => panic!("internal term can not be a map key: {:?}", term),
+    }
+}
+
+/// Map keys are equal when they compare equal in map key order.
+fn key_eq(lhs: &Term, rhs: &Term) -> bool {
+    lhs.erl_exact_ord(rhs) == Ordering::Equal
+}
+
+fn hash_term(term: &Term) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash_into(term, &mut hasher);
+    hasher.finish()
+}
+
+impl HamtNode {
+
+    fn empty() -> Self {
+        HamtNode {
+            bitmap: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    fn bit(hash: u64, shift: u32) -> u32 {
+        1 << ((hash >> shift) & HAMT_MASK)
+    }
+
+    fn index(&self, bit: u32) -> usize {
+        (self.bitmap & (bit - 1)).count_ones() as usize
+    }
+
+    fn with_entry(hash: u64, shift: u32, entry: HamtEntry) -> Self {
+        HamtNode {
+            bitmap: HamtNode::bit(hash, shift),
+            entries: vec![entry],
+        }
+    }
+
+    fn get<'a>(&'a self, hash: u64, shift: u32, key: &Term) -> Option<&'a Term> {
+        let bit = HamtNode::bit(hash, shift);
+        if self.bitmap & bit == 0 {
+            return None;
+        }
+        match &self.entries[self.index(bit)] {
+            HamtEntry::Leaf(leaf_hash, leaf) => {
+                if *leaf_hash == hash && key_eq(&leaf.0, key) {
+                    Some(&leaf.1)
+                } else {
+                    None
+                }
+            }
+            HamtEntry::Collision(coll_hash, entries) => {
+                if *coll_hash != hash {
+                    return None;
+                }
+                entries.iter()
+                    .find(|(k, _)| key_eq(k, key))
+                    .map(|(_, v)| v)
+            }
+            HamtEntry::Node(node) => node.get(hash, shift + HAMT_BITS, key),
+        }
+    }
+
+    /// Returns the updated node, and whether the key was newly added.
+    fn insert(&self, hash: u64, shift: u32, key: Term, value: Term) -> (HamtNode, bool) {
+        let bit = HamtNode::bit(hash, shift);
+        let idx = self.index(bit);
+        let mut new = self.clone();
+
+        if self.bitmap & bit == 0 {
+            new.bitmap |= bit;
+            new.entries.insert(idx, HamtEntry::Leaf(hash, Arc::new((key, value))));
+            return (new, true);
+        }
+
+        let added;
+        let replacement = match &self.entries[idx] {
+            HamtEntry::Leaf(leaf_hash, leaf) => {
+                if *leaf_hash == hash && key_eq(&leaf.0, &key) {
+                    added = false;
+                    HamtEntry::Leaf(hash, Arc::new((key, value)))
+                } else if *leaf_hash == hash {
+                    added = true;
+                    let entries = vec![(leaf.0.clone(), leaf.1.clone()), (key, value)];
+                    HamtEntry::Collision(hash, Arc::new(entries))
+                } else {
+                    // Hashes differ, they will be split at some level
+                    // below this one.
+                    added = true;
+                    let child = HamtNode::with_entry(
+                        *leaf_hash, shift + HAMT_BITS,
+                        HamtEntry::Leaf(*leaf_hash, leaf.clone()));
+                    let (child, _) = child.insert(hash, shift + HAMT_BITS, key, value);
+                    HamtEntry::Node(Arc::new(child))
+                }
+            }
+            HamtEntry::Collision(coll_hash, entries) => {
+                if *coll_hash == hash {
+                    let mut entries = (**entries).clone();
+                    if let Some(pos) = entries.iter().position(|(k, _)| key_eq(k, &key)) {
+                        added = false;
+                        entries[pos] = (key, value);
+                    } else {
+                        added = true;
+                        entries.push((key, value));
+                    }
+                    HamtEntry::Collision(hash, Arc::new(entries))
+                } else {
+                    added = true;
+                    let child = HamtNode::with_entry(
+                        *coll_hash, shift + HAMT_BITS,
+                        HamtEntry::Collision(*coll_hash, entries.clone()));
+                    let (child, _) = child.insert(hash, shift + HAMT_BITS, key, value);
+                    HamtEntry::Node(Arc::new(child))
+                }
+            }
+            HamtEntry::Node(child) => {
+                let (child, child_added) = child.insert(hash, shift + HAMT_BITS, key, value);
+                added = child_added;
+                HamtEntry::Node(Arc::new(child))
+            }
+        };
+
+        new.entries[idx] = replacement;
+        (new, added)
+    }
+
+    /// Returns `None` if the key is not in the trie.
+    fn remove(&self, hash: u64, shift: u32, key: &Term) -> Option<HamtNode> {
+        let bit = HamtNode::bit(hash, shift);
+        if self.bitmap & bit == 0 {
+            return None;
+        }
+        let idx = self.index(bit);
+
+        let replacement = match &self.entries[idx] {
+            HamtEntry::Leaf(leaf_hash, leaf) => {
+                if *leaf_hash == hash && key_eq(&leaf.0, key) {
+                    None
+                } else {
+                    return None;
+                }
+            }
+            HamtEntry::Collision(coll_hash, entries) => {
+                if *coll_hash != hash {
+                    return None;
+                }
+                let pos = entries.iter().position(|(k, _)| key_eq(k, key))?;
+                let mut entries = (**entries).clone();
+                entries.remove(pos);
+                if entries.len() == 1 {
+                    Some(HamtEntry::Leaf(hash, Arc::new(entries.pop().unwrap())))
+                } else {
+                    Some(HamtEntry::Collision(hash, Arc::new(entries)))
+                }
+            }
+            HamtEntry::Node(child) => {
+                let child = child.remove(hash, shift + HAMT_BITS, key)?;
+                match child.entries.len() {
+                    0 => None,
+                    // Leaves and collisions are addressed by their full
+                    // hash, they can be pulled up a level.
+                    1 if !matches!(child.entries[0], HamtEntry::Node(_)) =>
+                        Some(child.entries[0].clone()),
+                    _ => Some(HamtEntry::Node(Arc::new(child))),
+                }
+            }
+        };
+
+        let mut new = self.clone();
+        match replacement {
+            Some(entry) => new.entries[idx] = entry,
+            None => {
+                new.bitmap &= !bit;
+                new.entries.remove(idx);
+            }
+        }
+        Some(new)
+    }
+
+    fn collect(&self, out: &mut Vec<Arc<(Term, Term)>>) {
+        for entry in self.entries.iter() {
+            match entry {
+                HamtEntry::Leaf(_, leaf) => out.push(leaf.clone()),
+                HamtEntry::Collision(_, entries) =>
+                    out.extend(entries.iter().map(|entry| Arc::new(entry.clone()))),
+                HamtEntry::Node(child) => child.collect(out),
+            }
+        }
+    }
+
+}
+
+impl Map {
+
+    pub fn new() -> Self {
+        Map {
+            len: 0,
+            repr: MapRepr::Flat(Arc::new(Vec::new())),
+        }
+    }
+
+    pub fn from_entries<I>(entries: I) -> Self where I: IntoIterator<Item = (Term, Term)> {
+        let mut map = Map::new();
+        for (key, value) in entries {
+            map = map.insert(key, value);
+        }
+        map
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Exact key lookup, the same semantics as `:=` in a pattern.
+    pub fn get(&self, key: &Term) -> Option<&Term> {
+        match &self.repr {
+            MapRepr::Flat(entries) => {
+                entries.binary_search_by(|(k, _)| k.erl_exact_ord(key))
+                    .ok()
+                    .map(|idx| &entries[idx].1)
+            }
+            MapRepr::Hamt(root, _) => root.get(hash_term(key), 0, key),
+        }
+    }
+
+    pub fn contains_key(&self, key: &Term) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Inserts or updates a key, the same semantics as `=>`.
+    pub fn insert(&self, key: Term, value: Term) -> Map {
+        match &self.repr {
+            MapRepr::Flat(entries) => {
+                match entries.binary_search_by(|(k, _)| k.erl_exact_ord(&key)) {
+                    Ok(idx) => {
+                        let mut new = (**entries).clone();
+                        new[idx].1 = value;
+                        Map {
+                            len: self.len,
+                            repr: MapRepr::Flat(Arc::new(new)),
+                        }
+                    }
+                    Err(idx) if self.len < FLATMAP_LIMIT => {
+                        let mut new = (**entries).clone();
+                        new.insert(idx, (key, value));
+                        Map {
+                            len: self.len + 1,
+                            repr: MapRepr::Flat(Arc::new(new)),
+                        }
+                    }
+                    Err(_) => {
+                        // Flatmap is full, promote to a trie.
+                        let mut root = HamtNode::empty();
+                        for (k, v) in entries.iter() {
+                            root = root.insert(hash_term(k), 0, k.clone(), v.clone()).0;
+                        }
+                        let hash = hash_term(&key);
+                        root = root.insert(hash, 0, key, value).0;
+                        Map {
+                            len: self.len + 1,
+                            repr: MapRepr::hamt(root),
+                        }
+                    }
+                }
+            }
+            MapRepr::Hamt(root, _) => {
+                let hash = hash_term(&key);
+                let (root, added) = root.insert(hash, 0, key, value);
+                Map {
+                    len: if added { self.len + 1 } else { self.len },
+                    repr: MapRepr::hamt(root),
+                }
+            }
+        }
+    }
+
+    /// Updates an existing key, the same semantics as `:=` in a map
+    /// update. Returns `None` if the key is not present.
+    pub fn update(&self, key: Term, value: Term) -> Option<Map> {
+        if self.contains_key(&key) {
+            Some(self.insert(key, value))
+        } else {
+            None
+        }
+    }
+
+    pub fn remove(&self, key: &Term) -> Map {
+        match &self.repr {
+            MapRepr::Flat(entries) => {
+                match entries.binary_search_by(|(k, _)| k.erl_exact_ord(key)) {
+                    Ok(idx) => {
+                        let mut new = (**entries).clone();
+                        new.remove(idx);
+                        Map {
+                            len: self.len - 1,
+                            repr: MapRepr::Flat(Arc::new(new)),
+                        }
+                    }
+                    Err(_) => self.clone(),
+                }
+            }
+            MapRepr::Hamt(root, _) => {
+                match root.remove(hash_term(key), 0, key) {
+                    Some(root) => Map {
+                        len: self.len - 1,
+                        repr: MapRepr::hamt(root),
+                    },
+                    None => self.clone(),
+                }
+            }
+        }
+    }
+
+    /// Iterates the entries of the map in map key order.
+    pub fn iter<'a>(&'a self) -> Iter<'a> {
+        match &self.repr {
+            MapRepr::Flat(entries) => Iter::Flat(entries.iter()),
+            MapRepr::Hamt(root, sorted) => {
+                let sorted = sorted.get_or_init(|| {
+                    let mut out = Vec::with_capacity(self.len);
+                    root.collect(&mut out);
+                    out.sort_by(|e1, e2| e1.0.erl_exact_ord(&e2.0));
+                    out
+                });
+                Iter::Hamt(sorted.iter())
+            }
+        }
+    }
+
+    pub fn keys<'a>(&'a self) -> impl Iterator<Item = &'a Term> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values<'a>(&'a self) -> impl Iterator<Item = &'a Term> {
+        self.iter().map(|(_, v)| v)
+    }
+
+}
+
+/// Iterator over the entries of a map, in map key order.
+pub enum Iter<'a> {
+    Flat(slice::Iter<'a, (Term, Term)>),
+    Hamt(slice::Iter<'a, Arc<(Term, Term)>>),
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Term, &'a Term);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Iter::Flat(inner) => inner.next().map(|(k, v)| (k, v)),
+            Iter::Hamt(inner) => inner.next().map(|entry| (&entry.0, &entry.1)),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Iter::Flat(inner) => inner.size_hint(),
+            Iter::Hamt(inner) => inner.size_hint(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Map;
+    use ::term::{ Term, ErlExactEq };
+
+    #[test]
+    fn flat_insert_get() {
+        let map = Map::new()
+            .insert(Term::new_atom("b"), Term::new_i64(2))
+            .insert(Term::new_atom("a"), Term::new_i64(1));
+        assert!(map.len() == 2);
+        assert!(map.get(&Term::new_atom("a")).unwrap().erl_exact_eq(&Term::new_i64(1)));
+        assert!(map.get(&Term::new_atom("c")).is_none());
+
+        let keys: Vec<_> = map.keys().map(|k| k.atom_str().to_string()).collect();
+        assert!(keys == vec!["a", "b"]);
+    }
+
+    #[test]
+    fn exact_keys() {
+        let map = Map::new()
+            .insert(Term::Float(1.0), Term::new_atom("float"))
+            .insert(Term::new_i64(1), Term::new_atom("int"));
+        assert!(map.len() == 2);
+        assert!(map.get(&Term::new_i64(1)).unwrap().atom_str() == "int");
+        assert!(map.get(&Term::Float(1.0)).unwrap().atom_str() == "float");
+
+        // Integers are ordered before floats
+        let first = map.keys().next().unwrap();
+        assert!(first.erl_exact_eq(&Term::new_i64(1)));
+    }
+
+    #[test]
+    fn update_requires_key() {
+        let map = Map::new().insert(Term::new_i64(1), Term::Nil);
+        assert!(map.update(Term::new_i64(2), Term::Nil).is_none());
+        let updated = map.update(Term::new_i64(1), Term::new_i64(5)).unwrap();
+        assert!(updated.get(&Term::new_i64(1)).unwrap().erl_exact_eq(&Term::new_i64(5)));
+        // Old version is unchanged
+        assert!(map.get(&Term::new_i64(1)).unwrap().erl_exact_eq(&Term::Nil));
+    }
+
+    #[test]
+    fn hamt_promotion() {
+        let mut map = Map::new();
+        for i in 0..1000 {
+            map = map.insert(Term::new_i64(i), Term::new_i64(i * 2));
+        }
+        assert!(map.len() == 1000);
+        for i in 0..1000 {
+            assert!(map.get(&Term::new_i64(i)).unwrap()
+                    .erl_exact_eq(&Term::new_i64(i * 2)));
+        }
+
+        // Iteration is in term order
+        for (idx, (key, _)) in map.iter().enumerate() {
+            assert!(key.erl_exact_eq(&Term::new_i64(idx as i64)));
+        }
+
+        let mut removed = map.clone();
+        for i in 0..500 {
+            removed = removed.remove(&Term::new_i64(i * 2));
+        }
+        assert!(removed.len() == 500);
+        assert!(removed.get(&Term::new_i64(2)).is_none());
+        assert!(removed.get(&Term::new_i64(3)).is_some());
+        assert!(map.len() == 1000);
+
+        // Each version iterates its own entries
+        let keys: Vec<_> = removed.keys().take(3).cloned().collect();
+        assert!(keys.len() == 3);
+        assert!(keys[0].erl_exact_eq(&Term::new_i64(1)));
+        assert!(keys[2].erl_exact_eq(&Term::new_i64(5)));
+        assert!(map.iter().count() == 1000);
+    }
+
+    #[test]
+    fn split_lists_are_same_key() {
+        let split = Term::List(
+            vec![Term::new_i64(1)],
+            Box::new(Term::List(vec![Term::new_i64(2)], Box::new(Term::Nil))));
+        let joined = Term::List(
+            vec![Term::new_i64(1), Term::new_i64(2)], Box::new(Term::Nil));
+
+        let mut map = Map::new();
+        for i in 0..100 {
+            map = map.insert(Term::new_i64(i), Term::Nil);
+        }
+        map = map.insert(split, Term::new_atom("found"));
+        assert!(map.get(&joined).unwrap().atom_str() == "found");
+    }
+
+}
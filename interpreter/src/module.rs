@@ -2,11 +2,12 @@ use std::collections::HashMap;
 use ::{ VMState, Term, FunctionIdent };
 use ::process::{ ProcessContext, CallReturn };
 use ::eir::Module;
+use ::bif::TypedBif;
 
 pub struct NativeModule {
     pub name: String,
     pub functions: HashMap<(String, usize), Box<Fn(&VMState, &mut ProcessContext,
-                                                   &[Term]) -> CallReturn>>,
+                                                   &[Term]) -> CallReturn + Send + Sync>>,
 }
 impl NativeModule {
 
@@ -18,10 +19,17 @@ impl NativeModule {
     }
 
     pub fn add_fun(&mut self, name: String, arity: usize,
-               fun: Box<Fn(&VMState, &mut ProcessContext, &[Term]) -> CallReturn>) {
+               fun: Box<Fn(&VMState, &mut ProcessContext, &[Term]) -> CallReturn + Send + Sync>) {
         self.functions.insert((name, arity), fun);
     }
 
+    /// Registers a function with a typed signature, see `bif`.
+    pub fn add_bif<M, F>(&mut self, name: &str, fun: F) where F: TypedBif<M> {
+        let arity = fun.arity();
+        self.add_fun(name.to_string(), arity,
+                     Box::new(move |vm, proc, args| fun.call(vm, proc, args)));
+    }
+
     pub fn has_fun(&self, ident: &FunctionIdent) -> bool {
         if ident.lambda.is_some() {
             false
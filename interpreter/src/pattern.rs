@@ -56,10 +56,13 @@ pub struct CaseContext {
     pub vars: Vec<Term>,
     pub clauses: Vec<Clause>,
     pub last_binds: Option<HashMap<SSAVariable, Term>>,
+    /// Set when a value was matched against a pattern that is not
+    /// supported yet.
+    pub incomplete: bool,
 }
 
 fn match_node(term: &Term, node: &PatternNode,
-              binds: &mut HashMap<SSAVariable, Term>) -> bool {
+              binds: &mut HashMap<SSAVariable, Term>, incomplete: &mut bool) -> bool {
     //println!("    MATCH_NODE: {:?} {:?}", term, node);
     match (term, node) {
         // Wildcard and purely recursive
@@ -69,7 +72,7 @@ fn match_node(term: &Term, node: &PatternNode,
                 *ssa, //binds_ref.iter().find(|(k, _)| k == var_name).unwrap().1,
                 term.clone()
             );
-            match_node(term, i_node, binds)
+            match_node(term, i_node, binds, incomplete)
         },
 
         // Lists
@@ -77,38 +80,38 @@ fn match_node(term: &Term, node: &PatternNode,
          PatternNode::List(ref p_head, ref p_tail)) => {
             if t_head.len() < p_head.len() {
                 for (pat, term) in p_head.iter().zip(t_head.iter()) {
-                    if !match_node(term, pat, binds) {
+                    if !match_node(term, pat, binds, incomplete) {
                         return false;
                     }
                 }
                 let n_p_head: Vec<_> = p_head.iter().skip(t_head.len())
                     .cloned().collect();
                 let n_pat = PatternNode::List(n_p_head, p_tail.clone());
-                return match_node(t_tail, &n_pat, binds);
+                return match_node(t_tail, &n_pat, binds, incomplete);
             } else if t_head.len() == p_head.len() {
                 for (pat, term) in p_head.iter().zip(t_head.iter()) {
-                    if !match_node(term, pat, binds) {
+                    if !match_node(term, pat, binds, incomplete) {
                         return false;
                     }
                 }
-                return match_node(t_tail, p_tail, binds);
+                return match_node(t_tail, p_tail, binds, incomplete);
             } else { // >
                 assert!(t_head.len() > p_head.len());
                 for (pat, term) in p_head.iter().zip(t_head.iter()) {
-                    if !match_node(term, pat, binds) {
+                    if !match_node(term, pat, binds, incomplete) {
                         return false;
                     }
                 }
                 let head_rest: Vec<_> = t_head.iter().skip(p_head.len())
                     .cloned().collect();
                 let rest_term = Term::List(head_rest, t_tail.clone());
-                let a = match_node(&rest_term, p_tail, binds);
+                let a = match_node(&rest_term, p_tail, binds, incomplete);
                 return a;
             }
         }
         // List with empty head
         (_, PatternNode::List(ref list, ref tail)) if list.len() == 0 =>
-            match_node(term, tail, binds),
+            match_node(term, tail, binds, incomplete),
         // Nil ([])
         (Term::Nil, PatternNode::Atomic(AtomicTerm::Nil)) => true,
         (Term::Nil, _) => false,
@@ -120,7 +123,7 @@ fn match_node(term: &Term, node: &PatternNode,
                 return false;
             }
             for (term, pat) in t_entries.iter().zip(p_entries) {
-                if !match_node(term, pat, binds) {
+                if !match_node(term, pat, binds, incomplete) {
                     return false;
                 }
             }
@@ -139,7 +142,7 @@ fn match_node(term: &Term, node: &PatternNode,
             int == pat_int
         }
         _ => {
-            ::trace::warning("WARNING: Pattern matching incomplete".to_string());
+            *incomplete = true;
             println!("    Warning: Pattern matching incomplete");
             false
         },
@@ -154,6 +157,7 @@ impl CaseContext {
             vars: vars,
             clauses: clauses,
             last_binds: None,
+            incomplete: false,
         }
     }
 
@@ -166,12 +170,13 @@ impl CaseContext {
             //println!("  {:?}", self.vars);
 
             let mut values: HashMap<SSAVariable, Term> = HashMap::new();
+            let incomplete = &mut self.incomplete;
             let matched = self.vars.iter()
                 .zip(&clause.patterns)
                 .enumerate()
                 .all(|(idx, (term, pattern))| {
                     let r = match_node(term, &pattern.node,
-                                       &mut values);
+                                       &mut values, incomplete);
                     println!("  Pattern num: {} {}", idx, r);
                     r
                 });
@@ -1,9 +1,9 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::{ Arc, Mutex };
 
 use ::{ SSAVariable, LabelN, Atom, LambdaEnvIdx, FunctionIdent, Source,
         AtomicTerm, OpKind, BoundLambdaEnv, BasicBlock, Module };
 use ::term::{ Term, TermType, Pid };
+use ::map::Map;
 use ::vm::VMState;
 use ::module::ModuleType;
 use ::pattern::CaseContext;
@@ -24,7 +24,10 @@ fn unpack_value_list(val_list: Term) -> Vec<Term> {
 
 impl StackFrame {
 
-    pub fn exec_block(&mut self, module: &Module, block: &BasicBlock) -> BlockResult {
+    pub fn exec_block(&mut self, vm: &VMState, pid: Pid,
+                      module: &Module, block: &BasicBlock,
+                      before_op: &mut dyn FnMut(&StackFrame, usize, &OpKind))
+                      -> BlockResult {
 
         // Apply phi nodes
         for phi in &block.phi_nodes {
@@ -36,8 +39,10 @@ impl StackFrame {
         }
 
         let mut block_ret: Option<BlockResult> = None;
-        for op in &block.ops {
+        for (op_num, op) in block.ops.iter().enumerate() {
             assert!(block_ret.is_none());
+            before_op(self, op_num, &op.kind);
+            self.executed_ops = op_num + 1;
             match op.kind {
                 OpKind::Arguments => {
                     assert!(op.reads.len() == 0);
@@ -97,6 +102,7 @@ impl StackFrame {
                             module: module_atom,
                             fun: fun_atom,
                             lambda: None,
+                            version: None,
                             args: args,
                         });
                     } else {
@@ -113,6 +119,7 @@ impl StackFrame {
                             module: module_atom,
                             fun: fun_atom,
                             lambda: None,
+                            version: None,
                             args: args,
                             outcomes: outcomes,
                         });
@@ -133,6 +140,7 @@ impl StackFrame {
                         module: module.name.clone(),
                         fun_name: ident.name.clone(),
                         arity: ident.arity,
+                        version: Some(self.version),
                     };
                     self.write(op.writes[0], res);
                 }
@@ -163,7 +171,7 @@ impl StackFrame {
 
                     match fun_var {
                         Term::CapturedFunction { module: ref module_a,
-                                                 ref fun_name, ref arity } => {
+                                                 ref fun_name, ref arity, version } => {
                             assert!(args.len() == *arity as usize);
 
                             if tail_call {
@@ -171,6 +179,7 @@ impl StackFrame {
                                     module: module_a.clone(),
                                     fun: fun_name.clone(),
                                     lambda: None,
+                                    version: version,
                                     args: args,
                                 });
                             } else {
@@ -178,6 +187,7 @@ impl StackFrame {
                                     module: module_a.clone(),
                                     fun: fun_name.clone(),
                                     lambda: None,
+                                    version: version,
                                     args: args,
                                     outcomes: outcomes.unwrap(),
                                 });
@@ -185,7 +195,7 @@ impl StackFrame {
                         }
                         Term::BoundLambda {
                             module: ref module_a, ref fun_name, arity,
-                            lambda, ref bound_env } => {
+                            lambda, version, ref bound_env } => {
 
                             let mut rargs = vec![Term::LambdaEnv(bound_env.clone())];
                             rargs.extend(args.iter().cloned());
@@ -197,6 +207,7 @@ impl StackFrame {
                                     module: module_a.clone(),
                                     fun: fun_name.clone(),
                                     lambda: Some(lambda),
+                                    version: Some(version),
                                     args: rargs,
                                 });
                             } else {
@@ -204,6 +215,7 @@ impl StackFrame {
                                     module: module_a.clone(),
                                     fun: fun_name.clone(),
                                     lambda: Some(lambda),
+                                    version: Some(version),
                                     args: rargs,
                                     outcomes: outcomes.unwrap(),
                                 });
@@ -240,6 +252,7 @@ impl StackFrame {
                         fun_name: ident.name.clone(),
                         arity: ident.arity,
                         lambda: ident.lambda.unwrap(),
+                        version: self.version,
                         bound_env: lenv.clone(),
                     });
                 }
@@ -249,25 +262,34 @@ impl StackFrame {
                     let case_ctx = CaseContext::new(vals, clauses.clone());
 
                     self.write(op.writes[0], Term::CaseContext(
-                        Rc::new(RefCell::new(case_ctx))));
+                        Arc::new(Mutex::new(case_ctx))));
                 }
                 OpKind::Case(_) => {
                     let to_leaf = {
                         let curr = self.read(&op.reads[0]);
                         let mut ctx = if let Term::CaseContext(ref ctx) = curr {
-                            ctx.borrow_mut()
+                            ctx.lock().unwrap()
                         } else {
                             panic!("Case read not case context");
                         };
 
-                        ctx.do_body()
+                        let to_leaf = ctx.do_body();
+                        if ctx.incomplete {
+                            ctx.incomplete = false;
+                            self.warnings.push(
+                                "WARNING: Pattern matching incomplete".to_string());
+                        }
+                        to_leaf
                     };
+                    if to_leaf > 0 {
+                        self.matched_clause = Some(to_leaf - 1);
+                    }
                     block_ret = Some(BlockResult::Branch { slot: to_leaf });
                 }
                 OpKind::CaseGuardFail { clause_num } => {
                     let curr = self.read(&op.reads[0]);
                     let mut ctx = if let Term::CaseContext(ref ctx) = curr {
-                        ctx.borrow_mut()
+                        ctx.lock().unwrap()
                     } else {
                         panic!("Case read not case context");
                     };
@@ -278,7 +300,7 @@ impl StackFrame {
                     let mut vals = {
                         let curr = self.read(&op.reads[0]);
                         let mut ctx = if let Term::CaseContext(ref ctx) = curr {
-                            ctx.borrow_mut()
+                            ctx.lock().unwrap()
                         } else {
                             panic!("case read not case context");
                         };
@@ -292,7 +314,7 @@ impl StackFrame {
                 OpKind::CaseGuardOk => {
                     let curr = self.read(&op.reads[0]);
                     let mut ctx = if let Term::CaseContext(ref ctx) = curr {
-                        ctx.borrow_mut()
+                        ctx.lock().unwrap()
                     } else {
                         panic!("case read not case context");
                     };
@@ -301,20 +323,63 @@ impl StackFrame {
                 OpKind::ReceiveStart => {
                     assert!(op.reads.len() == 1);
                     let timeout_term = self.read(&op.reads[0]);
-                    let receive_ctx = ReceiveContext::new(timeout_term);
+                    let now = vm.timers.lock().unwrap().now();
+                    let receive_ctx = ReceiveContext::new(timeout_term, now);
 
                     self.write(op.writes[0], Term::ReceiveContext(
-                        Rc::new(RefCell::new(receive_ctx))));
+                        Arc::new(Mutex::new(receive_ctx))));
                 }
                 OpKind::ReceiveWait => {
                     let curr = self.read(&op.reads[0]);
                     let mut ctx = if let Term::ReceiveContext(ref ctx) = curr {
-                        ctx.borrow_mut()
+                        ctx.lock().unwrap()
                     } else {
                         panic!("Receive read not receive context");
                     };
 
-                    block_ret = Some(BlockResult::Suspend);
+                    let message = vm.mailbox(pid).and_then(|mailbox| {
+                        mailbox.lock().unwrap().messages().get(ctx.next()).cloned()
+                    });
+                    block_ret = Some(match message {
+                        Some((_, message)) => {
+                            ctx.peek(message);
+                            BlockResult::Branch { slot: 0 }
+                        }
+                        None if ctx.timed_out(vm.timers.lock().unwrap().now()) =>
+                            BlockResult::Branch { slot: 1 },
+                        // Runs the block again once the process is
+                        // scheduled. The clock is moved to the deadline
+                        // when nothing else can run.
+                        None => {
+                            self.receive_deadline = ctx.deadline();
+                            BlockResult::Suspend
+                        }
+                    });
+                }
+                OpKind::ReceiveGetMessage => {
+                    let curr = self.read(&op.reads[0]);
+                    let message = if let Term::ReceiveContext(ref ctx) = curr {
+                        ctx.lock().unwrap().take_message()
+                    } else {
+                        panic!("Receive read not receive context");
+                    };
+                    self.write(op.writes[0], message);
+                }
+                OpKind::ReceiveFinish => {
+                    let curr = self.read(&op.reads[0]);
+                    let index = if let Term::ReceiveContext(ref ctx) = curr {
+                        ctx.lock().unwrap().matched()
+                    } else {
+                        panic!("Receive read not receive context");
+                    };
+
+                    let received = vm.mailbox(pid)
+                        .map(|mailbox| mailbox.lock().unwrap().remove(index));
+                    if let Some((Term::Pid(from), message)) = received {
+                        if let Some(mut tracer) = vm.active_tracer() {
+                            tracer.message_receive(pid, from, &message);
+                        }
+                    }
                 }
                 OpKind::Jump => {
                     block_ret = Some(BlockResult::Branch { slot: 0 });
@@ -360,10 +425,51 @@ impl StackFrame {
                 }
                 OpKind::MakeMap => {
                     assert!(op.writes.len() == 1);
-                    if op.reads.len() == 0 {
-                        self.write(op.writes[0], Term::Map(vec![]))
+                    // With an odd number of reads, the first one is the
+                    // map that is being updated.
+                    let (mut map, kv) = if op.reads.len() % 2 == 1 {
+                        match self.read(&op.reads[0]) {
+                            Term::Map(map) => (map, &op.reads[1..]),
+                            other => {
+                                self.raised = Some(Term::Tuple(vec![
+                                    Term::new_atom("badmap"), other]));
+                                block_ret = Some(BlockResult::Return {
+                                    ret: CallReturn::Throw,
+                                });
+                                break;
+                            }
+                        }
+                    } else {
+                        (Map::new(), &op.reads[..])
+                    };
+                    for pair in kv.chunks(2) {
+                        map = map.insert(self.read(&pair[0]), self.read(&pair[1]));
+                    }
+                    self.write(op.writes[0], Term::Map(map));
+                }
+                OpKind::IsMap => {
+                    assert!(op.reads.len() == 1);
+                    if let Term::Map(_) = self.read(&op.reads[0]) {
+                        block_ret = Some(BlockResult::Branch { slot: 0 });
                     } else {
-                        unimplemented!()
+                        block_ret = Some(BlockResult::Branch { slot: 1 });
+                    }
+                }
+                OpKind::MapGet => {
+                    assert!(op.reads.len() == 2);
+                    assert!(op.writes.len() == 1);
+                    let map_term = self.read(&op.reads[0]);
+                    let key_term = self.read(&op.reads[1]);
+                    let value = if let Term::Map(ref map) = map_term {
+                        map.get(&key_term).cloned()
+                    } else {
+                        None
+                    };
+                    if let Some(value) = value {
+                        self.write(op.writes[0], value);
+                        block_ret = Some(BlockResult::Branch { slot: 0 });
+                    } else {
+                        block_ret = Some(BlockResult::Branch { slot: 1 });
                     }
                 }
                 _ => {
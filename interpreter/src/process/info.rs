@@ -0,0 +1,119 @@
+//! Introspection of processes, as done by `erlang:process_info/1,2`
+//! and `VMState::process_info`.
+
+use ::{ Atom, FunctionIdent };
+use ::term::{ Term, Pid };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessStatus {
+    /// Executing right now, on this or another scheduler thread.
+    Running,
+    /// Ready to run in the next scheduler round.
+    Runnable,
+    /// Sleeping or waiting for a message.
+    Waiting,
+}
+
+impl ProcessStatus {
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProcessStatus::Running => "running",
+            ProcessStatus::Runnable => "runnable",
+            ProcessStatus::Waiting => "waiting",
+        }
+    }
+
+}
+
+/// Snapshot of the state of a live process.
+///
+/// The current function, reductions and dictionary can only be read
+/// while the process is not running. For a process running on another
+/// scheduler thread they are left empty.
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: Pid,
+    pub status: ProcessStatus,
+    /// Messages in the mailbox, oldest first.
+    pub messages: Vec<Term>,
+    /// Linked processes, as local or remote pids.
+    pub links: Vec<Term>,
+    /// Processes monitored by the process, as pids, or `{Name, Node}`
+    /// for remote monitors by name.
+    pub monitors: Vec<Term>,
+    pub current_function: Option<FunctionIdent>,
+    pub registered_name: Option<Atom>,
+    pub reductions: u64,
+    pub dictionary: Vec<(Term, Term)>,
+    pub trap_exit: bool,
+    /// Approximate memory used, in bytes.
+    pub memory: usize,
+}
+
+/// Items of `process_info/1`, the registered name comes first when the
+/// process has one.
+pub const DEFAULT_ITEMS: &[&str] = &[
+    "current_function",
+    "status",
+    "message_queue_len",
+    "links",
+    "dictionary",
+    "trap_exit",
+    "reductions",
+];
+
+fn list(items: Vec<Term>) -> Term {
+    Term::List(items, Box::new(Term::Nil))
+}
+
+impl ProcessInfo {
+
+    pub fn message_queue_len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Value of a `process_info/2` item, `None` if the item is not
+    /// known.
+    pub fn item(&self, item: &str) -> Option<Term> {
+        let value = match item {
+            "status" => Term::new_atom(self.status.as_str()),
+            "message_queue_len" => Term::new_i64(self.messages.len() as i64),
+            "messages" => list(self.messages.clone()),
+            "links" => list(self.links.clone()),
+            "monitors" => list(self.monitors.iter()
+                .map(|m| Term::Tuple(vec![Term::new_atom("process"), m.clone()]))
+                .collect()),
+            "current_function" => match self.current_function {
+                Some(ref ident) => Term::Tuple(vec![
+                    Term::Atom(ident.module.clone()),
+                    Term::Atom(ident.name.clone()),
+                    Term::new_i64(ident.arity as i64),
+                ]),
+                None => Term::new_atom("undefined"),
+            },
+            "registered_name" => match self.registered_name {
+                Some(ref name) => Term::Atom(name.clone()),
+                None => Term::Nil,
+            },
+            "reductions" => Term::new_i64(self.reductions as i64),
+            "dictionary" => list(self.dictionary.iter()
+                .map(|(k, v)| Term::Tuple(vec![k.clone(), v.clone()]))
+                .collect()),
+            "trap_exit" => Term::new_bool(self.trap_exit),
+            "memory" => Term::new_i64(self.memory as i64),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// The `{Item, Value}` tuples returned by `process_info/1`.
+    pub fn default_items(&self) -> Vec<Term> {
+        let registered = self.registered_name.as_ref().map(|_| "registered_name");
+        registered.into_iter()
+            .chain(DEFAULT_ITEMS.iter().cloned())
+            .map(|item| Term::Tuple(vec![Term::new_atom(item), self.item(item).unwrap()]))
+            .collect()
+    }
+
+}
@@ -1,16 +1,18 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::{ Arc, Mutex };
 use std::collections::HashMap;
 
 use num_bigint::BigInt;
 
-use ::{ SSAVariable, LabelN, Atom, LambdaEnvIdx, FunctionIdent, Source };
-use ::term::{ Term, Pid };
+use ::{ SSAVariable, LabelN, Atom, LambdaEnvIdx, FunctionIdent, Source, OpKind };
+use ::term::{ Term, Pid, ErlExactEq };
 use ::vm::VMState;
 use ::module::ModuleType;
+use ::debugger::FrameInfo;
 use eir::{ ConstantTerm , AtomicTerm };
 
 mod exec;
+mod info;
+pub use self::info::{ ProcessInfo, ProcessStatus };
 
 #[derive(Debug, Clone)]
 pub enum CallReturn {
@@ -54,6 +56,8 @@ pub enum BlockResult {
         fun: Atom,
         args: Vec<Term>,
         lambda: Option<(LambdaEnvIdx, usize)>,
+        /// Module version to call, `None` for the current one.
+        version: Option<u64>,
         outcomes: CallOutcomes,
     },
     TailCall {
@@ -61,49 +65,223 @@ pub enum BlockResult {
         fun: Atom,
         args: Vec<Term>,
         lambda: Option<(LambdaEnvIdx, usize)>,
+        version: Option<u64>,
     },
     Suspend,
 }
 
 pub struct ProcessContext {
-    pub stack: Rc<RefCell<Vec<StackFrameType>>>,
+    pub stack: Arc<Mutex<Vec<StackFrameType>>>,
     pub return_val: Option<CallReturn>,
     pub pid: Pid,
+    /// Virtual time the process sleeps until, set by `timer:sleep/1`.
+    pub sleep_until: Option<u64>,
+    /// Virtual time the receive the process waits in times out at.
+    pub receive_deadline: Option<u64>,
+    /// Set when the process was killed, for instance by `code:purge/1`.
+    pub killed: bool,
+    /// The process dictionary, in insertion order.
+    dictionary: Vec<(Term, Term)>,
+    /// Size of the dictionary in words.
+    dictionary_words: usize,
+    /// Set by `process_flag(max_heap_size, Words)`.
+    pub max_heap_size: Option<usize>,
+    /// Number of reductions executed so far.
+    pub reductions: u64,
+    /// Set when the process blocked in its last reduction.
+    suspended: bool,
+    /// Reason of the exception being thrown, if it was raised by an op.
+    exception: Option<Term>,
 }
 
 impl ProcessContext {
 
     pub fn new(pid: Pid) -> Self {
         ProcessContext {
-            stack: Rc::new(RefCell::new(Vec::new())),
+            stack: Arc::new(Mutex::new(Vec::new())),
             return_val: None,
             pid: pid,
+            sleep_until: None,
+            receive_deadline: None,
+            killed: false,
+            dictionary: Vec::new(),
+            dictionary_words: 0,
+            max_heap_size: None,
+            reductions: 0,
+            suspended: false,
+            exception: None,
         }
     }
 
+    /// Throws an error with `reason` from a native function, which
+    /// returns the result.
+    pub fn raise(&mut self, reason: Term) -> CallReturn {
+        self.exception = Some(reason);
+        CallReturn::Throw
+    }
+
+    /// Reason of the exception being thrown, if it has one.
+    pub fn exception(&self) -> Option<&Term> {
+        self.exception.as_ref()
+    }
+
+    /// The function on top of the stack.
+    pub fn current_function(&self) -> Option<FunctionIdent> {
+        self.stack.lock().unwrap().last().map(|frame| frame.frame_info().function)
+    }
+
+    /// Status of the process while it is not running.
+    pub fn status(&self) -> ProcessStatus {
+        if self.sleep_until.is_some() || self.suspended {
+            ProcessStatus::Waiting
+        } else {
+            ProcessStatus::Runnable
+        }
+    }
+
+    /// The process dictionary, in insertion order.
+    pub fn dictionary(&self) -> &[(Term, Term)] {
+        &self.dictionary
+    }
+
+    /// Sets a key in the process dictionary. Returns the old value.
+    pub fn put_dictionary(&mut self, key: Term, value: Term) -> Option<Term> {
+        self.dictionary_words += value.size_words();
+        let pos = self.dictionary.iter().position(|(k, _)| k.erl_exact_eq(&key));
+        match pos {
+            Some(pos) => {
+                let old = ::std::mem::replace(&mut self.dictionary[pos].1, value);
+                self.dictionary_words -= old.size_words();
+                Some(old)
+            }
+            None => {
+                self.dictionary_words += 2 + key.size_words();
+                self.dictionary.push((key, value));
+                None
+            }
+        }
+    }
+
+    /// Removes a key from the process dictionary. Returns its value.
+    pub fn erase_dictionary(&mut self, key: &Term) -> Option<Term> {
+        let pos = self.dictionary.iter().position(|(k, _)| k.erl_exact_eq(key))?;
+        let (key, value) = self.dictionary.remove(pos);
+        self.dictionary_words -= 2 + key.size_words() + value.size_words();
+        Some(value)
+    }
+
+    /// Approximate size in words of the terms held by the process, in
+    /// its stack frames, mailbox and dictionary. The sizes are kept up
+    /// to date as terms are bound, so no terms are walked here.
+    pub fn heap_words(&self, vm: &VMState) -> usize {
+        let stack: usize = self.stack.lock().unwrap().iter()
+            .map(|frame| frame.heap_words())
+            .sum();
+        let mailbox = vm.mailbox(self.pid)
+            .map(|mailbox| mailbox.lock().unwrap().heap_words())
+            .unwrap_or(0);
+        stack + mailbox + self.dictionary_words
+    }
+
+    /// Reports the heap size of the process to the VM. The process is
+    /// killed if it is over its own limit. When the total of the VM is
+    /// over its limit, the process with the largest heap is killed,
+    /// which is not necessarily this one.
+    fn account_heap(&mut self, vm: &VMState) {
+        if self.stack.lock().unwrap().len() == 0 {
+            return;
+        }
+        let words = self.heap_words(vm);
+        if self.max_heap_size.map(|max| words > max).unwrap_or(false) {
+            self.kill(vm, Term::new_atom("killed"));
+            return;
+        }
+        let victim = {
+            let mut heap = vm.heap.lock().unwrap();
+            if heap.update(self.pid, words) {
+                None
+            } else {
+                heap.take_largest()
+            }
+        };
+        match victim {
+            Some(pid) if pid == self.pid => self.kill(vm, Term::new_atom("killed")),
+            Some(pid) => vm.kill_later(pid, Term::new_atom("killed")),
+            None => (),
+        }
+    }
+
+    /// Whether any frame on the stack runs the given module version.
+    pub fn runs_code(&self, module: &str, version: u64) -> bool {
+        self.stack.lock().unwrap().iter().any(|frame| {
+            let (frame_module, frame_version) = frame.code();
+            frame_module.as_str() == module && frame_version == version
+        })
+    }
+
+    /// Terminates the process with the given exit reason. Its stack is
+    /// dropped and the process exits with a throw. Does nothing if the
+    /// process already exited.
+    pub fn kill(&mut self, vm: &VMState, reason: Term) {
+        if self.stack.lock().unwrap().len() == 0 {
+            return;
+        }
+        self.stack.lock().unwrap().clear();
+        self.dictionary.clear();
+        self.dictionary_words = 0;
+        self.killed = true;
+        self.sleep_until = None;
+        self.receive_deadline = None;
+        let ret = CallReturn::Throw;
+        if let Some(mut tracer) = vm.active_tracer() {
+            tracer.exit(self.pid, &ret);
+        }
+        self.return_val = Some(ret);
+        vm.process_exited(self.pid, reason);
+    }
+
+    /// Whether the process has work to do at virtual time `now`.
+    pub fn is_runnable(&mut self, now: u64) -> bool {
+        if let Some(wake) = self.sleep_until {
+            if wake > now {
+                return false;
+            }
+            self.sleep_until = None;
+        }
+        self.stack.lock().unwrap().len() > 0
+    }
+
+    /// Creates the frame for a call into the given version of the
+    /// module. `None` calls the current version, as does a version
+    /// that has since been purged.
     pub fn make_call_stackframe(&self, vm: &VMState,
                             module: Atom, fun_ident: FunctionIdent,
+                            version: Option<u64>,
                             args: Vec<Term>) -> StackFrameType {
 
         println!("-> {}:{}", module, fun_ident);
-        ::trace::enter_function(&module, &fun_ident, &args);
+        if let Some(mut tracer) = vm.active_tracer() {
+            tracer.enter_function(self.pid, &module, &fun_ident, &args);
+        }
 
-        assert!(vm.modules.contains_key(module.as_str()));
-        let module_t = vm.modules.get(module.as_str()).unwrap();
+        let loaded = {
+            let code = vm.modules.read().unwrap();
+            code.get(module.as_str(), version)
+                .or_else(|| code.current(module.as_str()))
+                .cloned()
+        };
+        let loaded = loaded.unwrap_or_else(|| panic!("module not loaded: {}", module));
+        let version = loaded.version;
         assert!(fun_ident.arity == args.len());
 
-        match module_t {
-            ModuleType::Erlang(c_module, native_overlay_opt) => {
+        match *loaded.module {
+            ModuleType::Erlang(ref c_module, ref native_overlay_opt) => {
                 if let Some(native_overlay) = native_overlay_opt {
                     if native_overlay.functions.contains_key(&(
                         fun_ident.name.as_str().to_string(),
                         fun_ident.arity
                     )) {
-                        let native_frame = NativeStackFrame {
-                            module,
-                            fun_ident,
-                            args,
-                        };
+                        let native_frame = NativeStackFrame::new(module, fun_ident, version, args);
                         return StackFrameType::Native(native_frame);
                     }
                 }
@@ -116,6 +294,7 @@ impl ProcessContext {
                 let mut call_frame = StackFrame::new(
                     module.clone(),
                     fun_ident.clone(),
+                    version,
                     c_lir.entry()
                 );
 
@@ -127,16 +306,12 @@ impl ProcessContext {
 
                 StackFrameType::Erlang(call_frame)
             }
-            ModuleType::Native(native) => {
+            ModuleType::Native(ref native) => {
                 if native.functions.contains_key(&(
                     fun_ident.name.as_str().to_string(),
                     fun_ident.arity
                 )) {
-                    let native_frame = NativeStackFrame {
-                        module,
-                        fun_ident,
-                        args,
-                    };
+                    let native_frame = NativeStackFrame::new(module, fun_ident, version, args);
                     StackFrameType::Native(native_frame)
                 } else {
                     panic!("Function not found in native module: {}", fun_ident);
@@ -149,17 +324,42 @@ impl ProcessContext {
     // This should be a decent way to do things for a reference
     // implementation.
     pub fn do_reduction(&mut self, vm: &VMState) -> bool {
-        let mut push_frame_parts: Option<(Atom, FunctionIdent, Vec<Term>)> = None;
+        let mut push_frame_parts: Option<(Atom, FunctionIdent, Option<u64>, Vec<Term>)> = None;
+        let mut native_call: Option<(Arc<ModuleType>, NativeStackFrame)> = None;
         let mut pop_frame = false;
         let mut suspend = false;
 
-        if self.stack.borrow().len() == 0 {
+        if self.stack.lock().unwrap().len() == 0 {
             return true;
         }
 
+        // A purge running on another scheduler thread can not inspect
+        // this process, so it finds out here that its code is gone.
+        let loaded = {
+            let stack = self.stack.lock().unwrap();
+            let (module, version) = stack.last().unwrap().code();
+            vm.modules.read().unwrap().get(module.as_str(), Some(version)).cloned()
+        };
+        let loaded = match loaded {
+            Some(loaded) => loaded,
+            None => {
+                self.kill(vm, Term::new_atom("killed"));
+                return true;
+            }
+        };
+
         {
             let stack_i = self.stack.clone();
-            let mut stack = stack_i.borrow_mut();
+            let mut stack = stack_i.lock().unwrap();
+
+            // Only collected when a debugger is attached.
+            let callers: Option<Vec<FrameInfo>> = if vm.debugger.lock().unwrap().is_some() {
+                let len = stack.len();
+                Some(stack[..len-1].iter().map(|f| f.frame_info()).collect())
+            } else {
+                None
+            };
+
             let frame = stack.last_mut().unwrap();
             match frame {
                 StackFrameType::Erlang(frame) => {
@@ -178,10 +378,15 @@ impl ProcessContext {
                                 CallReturn::Throw => {
                                     frame.prev_basic_block = Some(frame.basic_block);
                                     frame.basic_block = outcomes.ret_throw_label.unwrap();
-                                    // TODO TODO
-                                    let replacement_term = Term::ValueList(
-                                        vec![Term::Nil, Term::Nil]
-                                    );
+                                    // TODO: BIFs do not give a reason yet
+                                    let replacement_term = match self.exception.take() {
+                                        Some(reason) => Term::ValueList(
+                                            vec![Term::new_atom("error"), reason]
+                                        ),
+                                        None => Term::ValueList(
+                                            vec![Term::Nil, Term::Nil]
+                                        ),
+                                    };
                                     frame.write(outcomes.ret_throw_ssa, replacement_term);
                                     println!("Branching to slot {}", outcomes.ret_throw_slot);
                                 }
@@ -196,10 +401,7 @@ impl ProcessContext {
 
                     let curr_block_id = frame.basic_block;
 
-                    assert!(vm.modules.contains_key(frame.module.as_str()));
-                    let module_t = vm.modules.get(frame.module.as_str()).unwrap();
-
-                    if let ModuleType::Erlang(ref module, _native_overlay_opt) = module_t {
+                    if let ModuleType::Erlang(ref module, ref _native_overlay_opt) = *loaded.module {
 
                         let fun = module.functions.iter()
                             .find(|(ident, _fun)| **ident == frame.function)
@@ -208,10 +410,39 @@ impl ProcessContext {
                         let block_container = &lir.graph[frame.basic_block];
                         let block = block_container.inner.borrow();
 
-                        ::trace::start_basic_block(
-                            &frame.module, &frame.function, curr_block_id);
-                        let exec_res = frame.exec_block(module, &*block);
-                        ::trace::end_basic_block();
+                        if let Some(mut tracer) = vm.active_tracer() {
+                            tracer.start_basic_block(
+                                self.pid, &frame.module, &frame.function, curr_block_id);
+                        }
+                        let pid = self.pid;
+                        let mut before_op = |frame: &StackFrame, op_num: usize, op: &OpKind| {
+                            if let Some(ref callers) = callers {
+                                if let Some(ref mut debugger) = *vm.debugger.lock().unwrap() {
+                                    debugger.before_op(vm, pid, callers, frame, op_num, op);
+                                }
+                            }
+                        };
+                        let exec_res = frame.exec_block(
+                            vm, self.pid, module, &*block, &mut before_op);
+                        self.receive_deadline = frame.receive_deadline.take();
+                        for warning in frame.warnings.drain(..) {
+                            if let Some(mut tracer) = vm.active_tracer() {
+                                tracer.warning(self.pid, warning);
+                            }
+                        }
+                        if let Some(reason) = frame.raised.take() {
+                            self.exception = Some(reason);
+                        }
+                        if let Some(clause) = frame.matched_clause.take() {
+                            if let Some(mut tracer) = vm.active_tracer() {
+                                tracer.case_clause(
+                                    self.pid, &frame.module, &frame.function,
+                                    curr_block_id, clause);
+                            }
+                        }
+                        if let Some(mut tracer) = vm.active_tracer() {
+                            tracer.end_basic_block(self.pid, frame.executed_ops);
+                        }
 
                         match exec_res {
                             BlockResult::Branch { slot } => {
@@ -220,7 +451,7 @@ impl ProcessContext {
                                 frame.basic_block = slots[slot];
                                 println!("Branching to slot {}", slot);
                             }
-                            BlockResult::Call { module, fun, args, lambda, mut outcomes } => {
+                            BlockResult::Call { module, fun, args, lambda, version, mut outcomes } => {
                                 let slots = lir.branch_slots(curr_block_id);
                                 outcomes.ret_ok_label = Some(slots[outcomes.ret_ok_slot]);
                                 outcomes.ret_throw_label = Some(slots[outcomes.ret_throw_slot]);
@@ -231,25 +462,29 @@ impl ProcessContext {
                                     arity: args.len(),
                                     lambda: lambda,
                                 };
-                                push_frame_parts = Some((module, ident, args));
+                                push_frame_parts = Some((module, ident, version, args));
                                 frame.state = StackFrameState::InCall(outcomes);
                             }
-                            BlockResult::TailCall { module: m, fun, args, lambda } => {
+                            BlockResult::TailCall { module: m, fun, args, lambda, version } => {
                                 let ident = FunctionIdent {
                                     module: m.clone(),
                                     name: fun,
                                     arity: args.len(),
                                     lambda: lambda,
                                 };
-                                ::trace::exit_function(&module.name, &frame.function,
-                                                       None);
+                                if let Some(mut tracer) = vm.active_tracer() {
+                                    tracer.exit_function(
+                                        self.pid, &module.name, &frame.function, None);
+                                }
                                 pop_frame = true;
-                                push_frame_parts = Some((m, ident, args));
+                                push_frame_parts = Some((m, ident, version, args));
                             }
                             BlockResult::Return { ret } => {
                                 println!("<- {}:{}", module.name, frame.function);
-                                ::trace::exit_function(&module.name, &frame.function,
-                                                       Some(&ret));
+                                if let Some(mut tracer) = vm.active_tracer() {
+                                    tracer.exit_function(
+                                        self.pid, &module.name, &frame.function, Some(&ret));
+                                }
                                 self.return_val = Some(ret);
                                 pop_frame = true;
                             }
@@ -265,101 +500,238 @@ impl ProcessContext {
 
                 }
                 StackFrameType::Native(frame) => {
-                    assert!(vm.modules.contains_key(frame.module.as_str()));
-                    let module_t = vm.modules.get(frame.module.as_str()).unwrap();
-
-                    match module_t {
-                        ModuleType::Native(module) => {
-                            let fun = &module.functions[&(
-                                frame.fun_ident.name.as_str().to_string(),
-                                frame.fun_ident.arity
-                            )];
-                            let ret = (fun)(vm, self, &frame.args);
-                            println!("<- {}:{}", module.name, frame.fun_ident);
-                            ::trace::exit_function(&frame.module, &frame.fun_ident,
-                                                   Some(&ret));
-                            self.return_val = Some(ret);
-                            pop_frame = true;
-                        }
-                        ModuleType::Erlang(_mod, Some(module)) => {
-                            let fun = &module.functions[&(
-                                frame.fun_ident.name.as_str().to_string(),
-                                frame.fun_ident.arity
-                            )];
-                            let ret = (fun)(vm, self, &frame.args);
-                            println!("<- {}:{}", module.name, frame.fun_ident);
-                            ::trace::exit_function(&frame.module, &frame.fun_ident,
-                                                   Some(&ret));
-                            self.return_val = Some(ret);
-                            pop_frame = true;
-                        }
+                    native_call = Some((loaded.module.clone(), frame.clone()));
+                }
+            }
+        }
+
+        // Native functions are called without the stack borrowed, so
+        // that they are free to inspect or kill the calling process.
+        if let Some((module_t, frame)) = native_call {
+            let ret = match self.return_val.take() {
+                // Woke up from a sleep the function started.
+                Some(ret) => ret,
+                None => {
+                    let module = match *module_t {
+                        ModuleType::Native(ref module) => module,
+                        ModuleType::Erlang(_, Some(ref module)) => module,
                         _ => unreachable!(),
+                    };
+                    let fun = &module.functions[&(
+                        frame.fun_ident.name.as_str().to_string(),
+                        frame.fun_ident.arity
+                    )];
+                    let ret = (fun)(vm, self, &frame.args);
+                    if self.killed {
+                        return true;
+                    }
+                    // The function put the process to sleep. Its frame
+                    // stays on the stack and returns once the process
+                    // wakes up, so that a process sleeping in its entry
+                    // function stays alive.
+                    if self.sleep_until.is_some() {
+                        self.return_val = Some(ret);
+                        return true;
                     }
+                    ret
                 }
+            };
+            println!("<- {}:{}", frame.module, frame.fun_ident);
+            if let Some(mut tracer) = vm.active_tracer() {
+                tracer.exit_function(
+                    self.pid, &frame.module, &frame.fun_ident, Some(&ret));
             }
+            self.return_val = Some(ret);
+            pop_frame = true;
         }
 
         if pop_frame {
-            let mut stack = self.stack.borrow_mut();
+            let mut stack = self.stack.lock().unwrap();
             stack.pop();
         }
         if push_frame_parts.is_some() {
-            let (module, ident, args) = push_frame_parts.take().unwrap();
-            let frame = self.make_call_stackframe(vm, module, ident, args);
-            let mut stack = self.stack.borrow_mut();
+            let (module, ident, version, args) = push_frame_parts.take().unwrap();
+            let frame = self.make_call_stackframe(vm, module, ident, version, args);
+            let mut stack = self.stack.lock().unwrap();
             stack.push(frame);
         }
 
+        if self.stack.lock().unwrap().len() == 0 {
+            let reason = {
+                let ret = self.return_val.as_ref().unwrap();
+                if let Some(mut tracer) = vm.active_tracer() {
+                    tracer.exit(self.pid, ret);
+                }
+                match ret {
+                    CallReturn::Return { .. } => Term::new_atom("normal"),
+                    CallReturn::Throw => self.exception.take()
+                        .unwrap_or(Term::new_atom("error")),
+                }
+            };
+            vm.process_exited(self.pid, reason);
+        }
+        if self.sleep_until.is_some() {
+            suspend = true;
+        }
+
         suspend
     }
 
-    pub fn run_reductions(&mut self, vm: &mut VMState, reductions: u64) {
-        let reduction_counter = 0;
-        let stack_len = self.stack.borrow().len();
-        while reduction_counter < reductions && stack_len > 0 {
-            if self.do_reduction(vm) {
-                break;
+    /// Returns true if the process blocked before using up its
+    /// reductions.
+    pub fn run_reductions(&mut self, vm: &VMState, reductions: u64) -> bool {
+        // Reporting the heap takes the lock of the VM wide accounting,
+        // so it is only done after every reduction when there is a
+        // limit to enforce.
+        let limited = self.max_heap_size.is_some()
+            || vm.heap.lock().unwrap().limit().is_some();
+
+        let mut reduction_counter = 0;
+        let mut blocked = false;
+        while !blocked && reduction_counter < reductions
+            && self.stack.lock().unwrap().len() > 0 {
+            reduction_counter += 1;
+            self.reductions += 1;
+            blocked = self.do_reduction(vm);
+            self.report_compare_warnings(vm);
+            if limited {
+                self.account_heap(vm);
+            }
+        }
+        if !limited {
+            self.account_heap(vm);
+        }
+        self.suspended = blocked && self.stack.lock().unwrap().len() > 0;
+        self.suspended
+    }
+
+    /// Comparisons between terms have no access to the process, so
+    /// their warnings are collected on the thread and reported here.
+    fn report_compare_warnings(&self, vm: &VMState) {
+        for (text, lhs, rhs) in ::term::take_compare_warnings() {
+            if let Some(mut tracer) = vm.active_tracer() {
+                tracer.warning_args(self.pid, text.to_string(), || {
+                    let mut args = HashMap::new();
+                    args.insert("lhs".to_string(), ::serde_json::Value::String(lhs));
+                    args.insert("rhs".to_string(), ::serde_json::Value::String(rhs));
+                    args
+                });
             }
         }
     }
 
 }
 
+#[derive(Clone)]
 pub struct NativeStackFrame {
     module: Atom,
     fun_ident: FunctionIdent,
+    version: u64,
     args: Vec<Term>,
+    /// Size of the arguments in words.
+    heap_words: usize,
+}
+impl NativeStackFrame {
+
+    fn new(module: Atom, fun_ident: FunctionIdent, version: u64, args: Vec<Term>) -> Self {
+        NativeStackFrame {
+            heap_words: args.iter().map(|t| t.size_words()).sum(),
+            module: module,
+            fun_ident: fun_ident,
+            version: version,
+            args: args,
+        }
+    }
+
 }
 
 pub enum StackFrameType {
     Erlang(StackFrame),
     Native(NativeStackFrame),
 }
+impl StackFrameType {
+
+    fn heap_words(&self) -> usize {
+        match self {
+            StackFrameType::Erlang(frame) => frame.heap_words,
+            StackFrameType::Native(frame) => frame.heap_words,
+        }
+    }
+
+    /// The module and module version the frame is running.
+    pub fn code(&self) -> (&Atom, u64) {
+        match self {
+            StackFrameType::Erlang(frame) => (&frame.module, frame.version),
+            StackFrameType::Native(frame) => (&frame.module, frame.version),
+        }
+    }
+
+    pub fn frame_info(&self) -> FrameInfo {
+        match self {
+            StackFrameType::Erlang(frame) => FrameInfo {
+                module: frame.module.clone(),
+                function: frame.function.clone(),
+                block: Some(frame.basic_block),
+            },
+            StackFrameType::Native(frame) => FrameInfo {
+                module: frame.module.clone(),
+                function: frame.fun_ident.clone(),
+                block: None,
+            },
+        }
+    }
+
+}
 
 pub struct StackFrame {
-    variables: HashMap<SSAVariable, Term>,
+    pub(crate) variables: HashMap<SSAVariable, Term>,
+    /// Size of the variables in words.
+    heap_words: usize,
     state: StackFrameState,
-    module: Atom,
-    function: FunctionIdent,
-    basic_block: LabelN,
-    prev_basic_block: Option<LabelN>,
+    pub(crate) module: Atom,
+    pub(crate) function: FunctionIdent,
+    /// Version of the module the frame runs, see `code`.
+    pub(crate) version: u64,
+    pub(crate) basic_block: LabelN,
+    pub(crate) prev_basic_block: Option<LabelN>,
+    /// Set when a `Case` op in the current block matched a clause.
+    matched_clause: Option<usize>,
+    /// Reason of an exception raised by an op in the current block.
+    raised: Option<Term>,
+    /// Deadline of the receive the current block suspended in.
+    receive_deadline: Option<u64>,
+    /// Warnings raised by ops in the current block, for the tracer.
+    warnings: Vec<String>,
+    /// Number of ops run in the current block. Less than the length
+    /// of the block when an op raised.
+    executed_ops: usize,
 }
 impl StackFrame {
 
-    fn new(module: Atom, function: FunctionIdent, label: LabelN) -> Self {
+    fn new(module: Atom, function: FunctionIdent, version: u64, label: LabelN) -> Self {
         StackFrame {
             variables: HashMap::new(),
+            heap_words: 0,
             state: StackFrameState::Normal,
             function: function,
+            version: version,
             basic_block: label,
             module: module,
             prev_basic_block: None,
+            matched_clause: None,
+            raised: None,
+            receive_deadline: None,
+            warnings: Vec::new(),
+            executed_ops: 0,
         }
     }
 
     fn write(&mut self, ssa: SSAVariable, term: Term) {
         //self.tombstones.remove(&ssa);
-        self.variables.insert(ssa, term);
+        self.heap_words += term.size_words();
+        if let Some(old) = self.variables.insert(ssa, term) {
+            self.heap_words -= old.size_words();
+        }
     }
 
     fn read(&self, src: &Source) -> Term {
@@ -387,7 +759,9 @@ impl StackFrame {
 
     fn tombstone(&mut self, ssa: SSAVariable) {
         //self.tombstones.insert(ssa);
-        self.variables.remove(&ssa);
+        if let Some(old) = self.variables.remove(&ssa) {
+            self.heap_words -= old.size_words();
+        }
     }
 
 
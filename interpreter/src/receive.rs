@@ -1,15 +1,91 @@
 use ::term::Term;
 
+/// State of a receive structure, from `ReceiveStart` until a message
+/// was accepted by `ReceiveFinish`.
 #[derive(Debug)]
 pub struct ReceiveContext {
+    /// Virtual time the receive gives up at, `None` for `infinity`.
+    deadline: Option<u64>,
+    /// Mailbox index of the next message to match.
+    next: usize,
+    /// Message peeked by the last `ReceiveWait`.
+    message: Option<Term>,
 }
 
 impl ReceiveContext {
 
-    pub fn new(timeout: Term) -> Self {
-        // TODO: Timeout
+    /// `now` is the virtual time the receive starts at.
+    pub fn new(timeout: Term, now: u64) -> Self {
+        let deadline = timeout.as_i64()
+            .filter(|time| *time >= 0)
+            .map(|time| now + time as u64);
         ReceiveContext {
+            deadline: deadline,
+            next: 0,
+            message: None,
         }
     }
 
+    pub fn next(&self) -> usize {
+        self.next
+    }
+
+    pub fn deadline(&self) -> Option<u64> {
+        self.deadline
+    }
+
+    /// Whether the receive gives up at virtual time `now` when the
+    /// mailbox has no message left to match.
+    pub fn timed_out(&self, now: u64) -> bool {
+        self.deadline.map(|deadline| now >= deadline).unwrap_or(false)
+    }
+
+    pub fn peek(&mut self, message: Term) {
+        self.message = Some(message);
+    }
+
+    /// Takes the peeked message. If it does not match, the next
+    /// `ReceiveWait` looks at the message after it.
+    pub fn take_message(&mut self) -> Term {
+        self.next += 1;
+        self.message.take().unwrap()
+    }
+
+    /// Mailbox index of the message that was matched.
+    pub fn matched(&self) -> usize {
+        self.next - 1
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::ReceiveContext;
+    use ::term::{ Term, ErlEq };
+
+    #[test]
+    fn skip_unmatched_messages() {
+        let mut ctx = ReceiveContext::new(Term::new_atom("infinity"), 0);
+        assert!(!ctx.timed_out(::std::u64::MAX));
+
+        ctx.peek(Term::new_atom("a"));
+        assert!(ctx.take_message().erl_eq(&Term::new_atom("a")));
+        assert!(ctx.next() == 1);
+
+        ctx.peek(Term::new_atom("b"));
+        ctx.take_message();
+        assert!(ctx.matched() == 1);
+    }
+
+    #[test]
+    fn timeout_from_start() {
+        let ctx = ReceiveContext::new(Term::new_i64(0), 10);
+        assert!(ctx.timed_out(10));
+
+        let ctx = ReceiveContext::new(Term::new_i64(50), 10);
+        assert!(ctx.deadline() == Some(60));
+        assert!(!ctx.timed_out(59));
+        assert!(ctx.timed_out(60));
+    }
+
 }
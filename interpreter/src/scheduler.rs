@@ -0,0 +1,158 @@
+//! Worker threads of the `SchedulerMode::Threads` scheduler.
+//!
+//! Each worker owns a run queue, which keeps its processes from round
+//! to round. A worker takes from the front of its own queue, and steals
+//! from the back of the others once it is empty. A stolen process stays
+//! with the worker that stole it.
+//!
+//! The worker threads borrow the VM, so they only live for a call to
+//! `Workers::run_rounds`, which runs as many rounds on them as the
+//! caller asks for.
+
+use std::collections::VecDeque;
+use std::panic::{ self, AssertUnwindSafe };
+use std::sync::Mutex;
+use std::sync::mpsc::channel;
+use std::thread;
+
+use ::term::Pid;
+use ::vm::VMState;
+
+#[derive(Debug, Default)]
+struct RunQueue {
+    /// Processes still to run in the current round.
+    ready: VecDeque<Pid>,
+    /// Processes to run in the next round.
+    next: Vec<Pid>,
+}
+
+pub(crate) struct Workers {
+    queues: Vec<Mutex<RunQueue>>,
+}
+
+impl Workers {
+
+    pub fn new(threads: usize) -> Self {
+        assert!(threads > 0);
+        Workers {
+            queues: (0..threads)
+                .map(|_| Mutex::new(RunQueue::default()))
+                .collect(),
+        }
+    }
+
+    /// Hands a new process to a worker. It first runs in the next
+    /// round.
+    pub fn add(&self, pid: Pid) {
+        self.queues[pid.0 % self.queues.len()].lock().unwrap().next.push(pid);
+    }
+
+    /// Runs rounds on every worker until `between_rounds` returns false.
+    /// A round ends once all workers ran out of processes, and
+    /// `between_rounds` is told whether any process used up its slice
+    /// or was killed in it. A panic in a worker is resumed on the
+    /// caller after the round.
+    pub fn run_rounds<F>(&self, vm: &VMState, mut between_rounds: F)
+    where F: FnMut(bool) -> bool {
+        let queues = &self.queues;
+        thread::scope(|scope| {
+            // The worker loops end when `rounds` is dropped, as this
+            // closure returns or unwinds, so the scope can join them.
+            let (done_send, done) = channel();
+            let mut rounds = Vec::new();
+            for worker in 0..queues.len() {
+                let (round_send, round_recv) = channel::<()>();
+                let done = done_send.clone();
+                scope.spawn(move || {
+                    for () in round_recv.iter() {
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            run_worker(vm, queues, worker)
+                        }));
+                        done.send(result).unwrap();
+                    }
+                });
+                rounds.push(round_send);
+            }
+
+            loop {
+                for queue in queues.iter() {
+                    let mut queue = queue.lock().unwrap();
+                    let next = ::std::mem::replace(&mut queue.next, Vec::new());
+                    queue.ready.extend(next);
+                }
+
+                for round in rounds.iter() {
+                    round.send(()).unwrap();
+                }
+                let mut progress = false;
+                let mut panicked = None;
+                for _ in 0..rounds.len() {
+                    match done.recv().unwrap() {
+                        Ok(worker_progress) => progress |= worker_progress,
+                        Err(payload) => panicked = Some(payload),
+                    }
+                }
+
+                if let Some(payload) = panicked {
+                    panic::resume_unwind(payload);
+                }
+                if !between_rounds(progress) {
+                    break;
+                }
+            }
+        });
+    }
+
+}
+
+fn run_worker(vm: &VMState, queues: &[Mutex<RunQueue>], worker: usize) -> bool {
+    let mut progress = false;
+    while let Some(pid) = next_job(queues, worker) {
+        if vm.run_process(pid) {
+            progress = true;
+        }
+        if vm.handle_kills() {
+            progress = true;
+        }
+        if vm.is_alive(pid) {
+            queues[worker].lock().unwrap().next.push(pid);
+        }
+    }
+    progress
+}
+
+fn next_job(queues: &[Mutex<RunQueue>], worker: usize) -> Option<Pid> {
+    if let Some(pid) = queues[worker].lock().unwrap().ready.pop_front() {
+        return Some(pid);
+    }
+    (1..queues.len())
+        .map(|offset| (worker + offset) % queues.len())
+        .filter_map(|victim| queues[victim].lock().unwrap().ready.pop_back())
+        .next()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ RunQueue, next_job };
+    use std::sync::Mutex;
+    use ::term::Pid;
+
+    fn queues(ready: &[&[usize]]) -> Vec<Mutex<RunQueue>> {
+        ready.iter()
+            .map(|pids| Mutex::new(RunQueue {
+                ready: pids.iter().map(|p| Pid(*p)).collect(),
+                next: Vec::new(),
+            }))
+            .collect()
+    }
+
+    #[test]
+    fn own_queue_then_steal() {
+        let queues = queues(&[&[0], &[], &[1, 2]]);
+        assert!(next_job(&queues, 0) == Some(Pid(0)));
+        assert!(next_job(&queues, 0) == Some(Pid(2)));
+        assert!(next_job(&queues, 1) == Some(Pid(1)));
+        assert!(next_job(&queues, 1) == None);
+    }
+
+}
@@ -1,11 +1,12 @@
-use ::std::cell::RefCell;
-use ::std::rc::Rc;
+use ::std::sync::{ Arc, Mutex };
+use std::cell::RefCell;
 use std::cmp::Ord;
 
 use eir::Atom;
 use eir::LambdaEnvIdx;
 use ::pattern::CaseContext;
 use ::receive::ReceiveContext;
+use ::map::Map;
 
 use ::num_bigint::BigInt;
 use ::num_traits::cast::ToPrimitive;
@@ -54,27 +55,36 @@ pub enum Term {
     Atom(Atom),
     Tuple(Vec<Term>),
     List(Vec<Term>, Box<Term>),
-    Map(Vec<(Term, Term)>),
+    Map(Map),
     Pid(Pid),
     Reference(Reference),
+    /// Pid of a process on another node.
+    RemotePid(Atom, Pid),
+    /// Reference created on another node.
+    RemoteReference(Atom, Reference),
     Binary(Vec<u8>),
     BoundLambda {
         module: Atom,
         fun_name: Atom,
         arity: usize,
         lambda: (LambdaEnvIdx, usize),
+        /// Version of the module the lambda was created in.
+        version: u64,
         bound_env: BoundLambdaEnv,
     },
     CapturedFunction {
         module: Atom,
         fun_name: Atom,
         arity: usize,
+        /// Module version for local funs, `None` for funs that always
+        /// call the current version.
+        version: Option<u64>,
     },
 
     // Internal
     LambdaEnv(BoundLambdaEnv),
-    CaseContext(Rc<RefCell<CaseContext>>),
-    ReceiveContext(Rc<RefCell<ReceiveContext>>),
+    CaseContext(Arc<Mutex<CaseContext>>),
+    ReceiveContext(Arc<Mutex<ReceiveContext>>),
     ValueList(Vec<Term>),
 }
 impl Term {
@@ -135,8 +145,8 @@ impl Term {
         match self {
             Term::Binary(_) => TermType::Binary,
             Term::Nil => TermType::Nil,
-            Term::Pid(_) => TermType::Pid,
-            Term::Reference(_) => TermType::Reference,
+            Term::Pid(_) | Term::RemotePid(_, _) => TermType::Pid,
+            Term::Reference(_) | Term::RemoteReference(_, _) => TermType::Reference,
             Term::Integer(_) => TermType::Integer,
             Term::Float(_) => TermType::Float,
             Term::Atom(_) => TermType::Atom,
@@ -152,6 +162,41 @@ impl Term {
         }
     }
 
+    /// Approximate number of machine words the term takes up on a
+    /// process heap, including the word referring to it. Follows the
+    /// layout of terms in BEAM, sharing between subterms is not taken
+    /// into account.
+    pub fn size_words(&self) -> usize {
+        1 + self.heap_words()
+    }
+
+    fn heap_words(&self) -> usize {
+        match self {
+            Term::Nil | Term::Atom(_) | Term::Pid(_) => 0,
+            Term::Integer(int) => {
+                // Small integers are immediates of 60 bits.
+                let bits = int.bits();
+                if bits < 60 { 0 } else { 1 + (bits + 63) / 64 }
+            }
+            Term::Float(_) => 2,
+            Term::Reference(_) => 3,
+            Term::RemotePid(_, _) | Term::RemoteReference(_, _) => 4,
+            Term::Tuple(items) | Term::ValueList(items) =>
+                1 + items.iter().map(|t| t.size_words()).sum::<usize>(),
+            Term::List(head, tail) =>
+                head.iter().map(|t| 1 + t.size_words()).sum::<usize>() + tail.heap_words(),
+            Term::Map(map) =>
+                2 + map.iter().map(|(k, v)| k.size_words() + v.size_words()).sum::<usize>(),
+            Term::Binary(bin) => 2 + (bin.len() + 7) / 8,
+            Term::BoundLambda { bound_env, .. } =>
+                5 + bound_env.vars.iter().map(|t| t.size_words()).sum::<usize>(),
+            Term::LambdaEnv(env) =>
+                1 + env.vars.iter().map(|t| t.size_words()).sum::<usize>(),
+            Term::CapturedFunction { .. } => 2,
+            Term::CaseContext(_) | Term::ReceiveContext(_) => 1,
+        }
+    }
+
     pub fn as_boolean(&self) -> Option<bool> {
         if let Term::Atom(ref val) = self {
             let is_truthy = *val == Atom::from("true");
@@ -238,8 +283,8 @@ impl Term {
                     .append(tail_doc)
                     .append(Doc::text("]"))
             },
-            Term::Map(entries) => {
-                let entries_doc: Vec<_> = entries.iter()
+            Term::Map(map) => {
+                let entries_doc: Vec<_> = map.iter()
                     .map(|(k, v)| {
                         Doc::group(
                             k.to_doc()
@@ -253,6 +298,9 @@ impl Term {
             },
             Term::Pid(pid) => Doc::text(format!("Pid<{}>", pid.0)),
             Term::Reference(refe) => Doc::text(format!("Reference<{}>", refe.0)),
+            Term::RemotePid(node, pid) => Doc::text(format!("Pid<{}@{}>", node, pid.0)),
+            Term::RemoteReference(node, refe) =>
+                Doc::text(format!("Reference<{}@{}>", node, refe.0)),
             Term::Binary(bin) => {
                 if let Ok(utf) = std::str::from_utf8(&bin) {
                     Doc::text("\"")
@@ -270,7 +318,7 @@ impl Term {
                 Doc::text(format!("Bound<{}:{}@{}.{}/{}>", module, fun_name,
                                   lambda.0, lambda.1, arity))
             },
-            Term::CapturedFunction { module, fun_name, arity } => {
+            Term::CapturedFunction { module, fun_name, arity, .. } => {
                 Doc::text(format!("Captured<{}:{}/{}>", module, fun_name, arity))
             },
             Term::LambdaEnv(_env) =>
@@ -303,12 +351,36 @@ pub trait ErlOrd<Rhs = Self> {
     fn erl_ord(&self, other: &Rhs) -> ::std::cmp::Ordering;
 }
 
+/// Total order consistent with `ErlExactEq`, used for map keys.
+pub trait ErlExactOrd<Rhs = Self> {
+    fn erl_exact_ord(&self, other: &Rhs) -> ::std::cmp::Ordering;
+}
+
 impl ErlEq for f64 {
     fn erl_eq(&self, other: &f64) -> bool {
         (*self) == (*other)
     }
 }
 
+std::thread_local! {
+    /// Comparisons that fell through to the unimplemented case on this
+    /// thread, as warning text and the two operands.
+    static COMPARE_WARNINGS: RefCell<Vec<(&'static str, String, String)>> =
+        RefCell::new(Vec::new());
+}
+
+fn compare_warning(text: &'static str, lhs: &Term, rhs: &Term) {
+    COMPARE_WARNINGS.with(|w| {
+        w.borrow_mut().push((text, format!("{:?}", lhs), format!("{:?}", rhs)))
+    });
+}
+
+/// Takes the comparison warnings raised on this thread since the last
+/// call. The process that ran the comparisons hands them to the tracer.
+pub(crate) fn take_compare_warnings() -> Vec<(&'static str, String, String)> {
+    COMPARE_WARNINGS.with(|w| ::std::mem::replace(&mut *w.borrow_mut(), Vec::new()))
+}
+
 impl ErlEq for Term {
     fn erl_eq(&self, other: &Term) -> bool {
         match (self, other) {
@@ -353,29 +425,24 @@ impl ErlEq for Term {
             (Term::Atom(ref a1), Term::Atom(ref a2)) => a1 == a2,
             (Term::Tuple(ref v1), Term::Tuple(ref v2)) =>
                 v1.iter().zip(v2).all(|(e1, e2)| e1.erl_eq(e2)),
+            (Term::Map(ref m1), Term::Map(ref m2)) =>
+                m1.len() == m2.len() && m1.iter().zip(m2.iter())
+                .all(|((k1, v1), (k2, v2))| {
+                    k1.erl_exact_ord(k2) == ::std::cmp::Ordering::Equal
+                        && v1.erl_eq(v2)
+                }),
+            (Term::Pid(_), _) | (Term::RemotePid(_, _), _)
+                | (Term::Reference(_), _) | (Term::RemoteReference(_, _), _) =>
+                term_cmp(self, other, true) == ::std::cmp::Ordering::Equal,
             (Term::CapturedFunction {
                 module: ref mod1, fun_name: ref fun_name1,
-                arity: ref arity1 },
+                arity: ref arity1, .. },
              Term::CapturedFunction {
                  module: ref mod2, fun_name: ref fun_name2,
-                 arity: ref arity2 }) =>
+                 arity: ref arity2, .. }) =>
                 mod1 == mod2 && fun_name1 == fun_name2 && arity1 == arity2,
             _ => {
-                ::trace::warning_args(
-                    "WARNING: ErlEq might be unimplemented".to_string(),
-                    || {
-                        let mut event_args = std::collections::HashMap::new();
-                        event_args.insert(
-                            "lhs".to_string(),
-                            ::serde_json::Value::String(format!("{:?}", self))
-                        );
-                        event_args.insert(
-                            "rhs".to_string(),
-                            ::serde_json::Value::String(format!("{:?}", other))
-                        );
-                        event_args
-                    }
-                );
+                compare_warning("WARNING: ErlEq might be unimplemented", self, other);
                 println!("WARNING: ErlEq might be unimplemented");
                 println!("  {:?} == {:?}", self, other);
                 false
@@ -428,22 +495,19 @@ impl ErlExactEq for Term {
             (Term::Nil, _) => false,
             (_, Term::Nil) => false,
 
+            (Term::Map(ref m1), Term::Map(ref m2)) =>
+                m1.len() == m2.len() && m1.iter().zip(m2.iter())
+                .all(|((k1, v1), (k2, v2))| {
+                    k1.erl_exact_ord(k2) == ::std::cmp::Ordering::Equal
+                        && v1.erl_exact_eq(v2)
+                }),
+
+            (Term::Pid(_), _) | (Term::RemotePid(_, _), _)
+                | (Term::Reference(_), _) | (Term::RemoteReference(_, _), _) =>
+                term_cmp(self, other, true) == ::std::cmp::Ordering::Equal,
+
             _ => {
-                ::trace::warning_args(
-                    "WARNING: ErlExactEq might be unimplemented".to_string(),
-                    || {
-                        let mut event_args = std::collections::HashMap::new();
-                        event_args.insert(
-                            "lhs".to_string(),
-                            ::serde_json::Value::String(format!("{:?}", self))
-                        );
-                        event_args.insert(
-                            "rhs".to_string(),
-                            ::serde_json::Value::String(format!("{:?}", other))
-                        );
-                        event_args
-                    }
-                );
+                compare_warning("WARNING: ErlExactEq might be unimplemented", self, other);
                 println!("WARNING: ErlExactEq might be unimplemented");
                 println!("  {:?} =:= {:?}", self, other);
                 false
@@ -452,12 +516,153 @@ impl ErlExactEq for Term {
     }
 }
 
+/// Position of the type of a term in the Erlang term order:
+/// number < atom < reference < fun < port < pid < tuple < map < nil
+/// < list < bitstring
+fn term_type_order(term: &Term) -> usize {
+    match term {
+        Term::Integer(_) | Term::Float(_) => 0,
+        Term::Atom(_) => 1,
+        Term::Reference(_) | Term::RemoteReference(_, _) => 2,
+        Term::BoundLambda { .. } | Term::CapturedFunction { .. } => 3,
+        // 4 is reserved for ports, which we don't have
+        Term::Pid(_) | Term::RemotePid(_, _) => 5,
+        Term::Tuple(_) => 6,
+        Term::Map(_) => 7,
+        Term::Nil => 8,
+        Term::List(_, _) => 9,
+        Term::Binary(_) => 10,
+        _ => unreachable!(), // Internal terms are never compared
+    }
+}
+
+/// A list with an empty head is the same term as its tail.
+fn skip_empty_list(term: &Term) -> &Term {
+    match term {
+        Term::List(head, tail) if head.len() == 0 => skip_empty_list(tail),
+        _ => term,
+    }
+}
+
+fn cmp_int_float(int: &BigInt, float: f64) -> ::std::cmp::Ordering {
+    int.to_f64().unwrap().partial_cmp(&float)
+        .unwrap_or(::std::cmp::Ordering::Equal)
+}
+
+fn cmp_seq(lhs: &[Term], rhs: &[Term], exact: bool) -> ::std::cmp::Ordering {
+    for (l, r) in lhs.iter().zip(rhs.iter()) {
+        let ord = term_cmp(l, r, exact);
+        if ord != ::std::cmp::Ordering::Equal {
+            return ord;
+        }
+    }
+    lhs.len().cmp(&rhs.len())
+}
+
+/// Compares two terms in Erlang term order.
+///
+/// When `exact` is set, integers and floats are never considered equal.
+/// All integers are then ordered before all floats, which is the order
+/// the BEAM uses for map keys.
+fn term_cmp(lhs: &Term, rhs: &Term, exact: bool) -> ::std::cmp::Ordering {
+    use ::std::cmp::Ordering;
+
+    let lhs = skip_empty_list(lhs);
+    let rhs = skip_empty_list(rhs);
+
+    let lhs_type = term_type_order(lhs);
+    let rhs_type = term_type_order(rhs);
+    if lhs_type != rhs_type {
+        return lhs_type.cmp(&rhs_type);
+    }
+
+    match (lhs, rhs) {
+        (Term::Integer(i1), Term::Integer(i2)) => i1.cmp(i2),
+        (Term::Float(f1), Term::Float(f2)) =>
+            f1.partial_cmp(f2).unwrap_or(Ordering::Equal),
+        (Term::Integer(_), Term::Float(_)) if exact => Ordering::Less,
+        (Term::Float(_), Term::Integer(_)) if exact => Ordering::Greater,
+        (Term::Integer(i1), Term::Float(f2)) => cmp_int_float(i1, *f2),
+        (Term::Float(f1), Term::Integer(i2)) => cmp_int_float(i2, *f1).reverse(),
+        (Term::Atom(a1), Term::Atom(a2)) => a1.as_str().cmp(a2.as_str()),
+        (Term::Reference(r1), Term::Reference(r2)) => r1.0.cmp(&r2.0),
+        (Term::Pid(p1), Term::Pid(p2)) => p1.0.cmp(&p2.0),
+        // Local pids and references sort before remote ones, which are
+        // ordered by node first.
+        (Term::Reference(_), Term::RemoteReference(_, _)) => Ordering::Less,
+        (Term::RemoteReference(_, _), Term::Reference(_)) => Ordering::Greater,
+        (Term::RemoteReference(n1, r1), Term::RemoteReference(n2, r2)) =>
+            (n1.as_str(), r1.0).cmp(&(n2.as_str(), r2.0)),
+        (Term::Pid(_), Term::RemotePid(_, _)) => Ordering::Less,
+        (Term::RemotePid(_, _), Term::Pid(_)) => Ordering::Greater,
+        (Term::RemotePid(n1, p1), Term::RemotePid(n2, p2)) =>
+            (n1.as_str(), p1.0).cmp(&(n2.as_str(), p2.0)),
+        (Term::Tuple(t1), Term::Tuple(t2)) =>
+            t1.len().cmp(&t2.len()).then_with(|| cmp_seq(t1, t2, exact)),
+        (Term::Map(m1), Term::Map(m2)) => {
+            // Maps are ordered by size, then by keys, then by values
+            // in key order.
+            m1.len().cmp(&m2.len())
+                .then_with(|| {
+                    for ((k1, _), (k2, _)) in m1.iter().zip(m2.iter()) {
+                        let ord = term_cmp(k1, k2, true);
+                        if ord != Ordering::Equal { return ord; }
+                    }
+                    Ordering::Equal
+                })
+                .then_with(|| {
+                    for ((_, v1), (_, v2)) in m1.iter().zip(m2.iter()) {
+                        let ord = term_cmp(v1, v2, exact);
+                        if ord != Ordering::Equal { return ord; }
+                    }
+                    Ordering::Equal
+                })
+        }
+        (Term::Nil, Term::Nil) => Ordering::Equal,
+        (Term::List(_, _), Term::List(_, _)) => {
+            let (h1, t1) = lhs.as_inproper_list();
+            let (h2, t2) = rhs.as_inproper_list();
+            for (l, r) in h1.iter().zip(h2.iter()) {
+                let ord = term_cmp(l, r, exact);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            if h1.len() == h2.len() {
+                term_cmp(&t1, &t2, exact)
+            } else if h1.len() > h2.len() {
+                let rest = Term::List(h1[h2.len()..].to_vec(), Box::new(t1));
+                term_cmp(&rest, &t2, exact)
+            } else {
+                let rest = Term::List(h2[h1.len()..].to_vec(), Box::new(t2));
+                term_cmp(&t1, &rest, exact)
+            }
+        }
+        (Term::Binary(b1), Term::Binary(b2)) => b1.cmp(b2),
+        (Term::CapturedFunction { module: m1, fun_name: f1, arity: a1, .. },
+         Term::CapturedFunction { module: m2, fun_name: f2, arity: a2, .. }) =>
+            (m1.as_str(), f1.as_str(), a1).cmp(&(m2.as_str(), f2.as_str(), a2)),
+        (Term::CapturedFunction { .. }, Term::BoundLambda { .. }) => Ordering::Less,
+        (Term::BoundLambda { .. }, Term::CapturedFunction { .. }) => Ordering::Greater,
+        (Term::BoundLambda { module: m1, fun_name: f1, arity: a1, lambda: l1,
+                             bound_env: e1, .. },
+         Term::BoundLambda { module: m2, fun_name: f2, arity: a2, lambda: l2,
+                             bound_env: e2, .. }) =>
+            (m1.as_str(), f1.as_str(), a1, l1.1)
+            .cmp(&(m2.as_str(), f2.as_str(), a2, l2.1))
+            .then_with(|| cmp_seq(&e1.vars, &e2.vars, exact)),
+        _ => unreachable!(),
+    }
+}
+
 impl ErlOrd for Term {
     fn erl_ord(&self, other: &Term) -> ::std::cmp::Ordering {
-        match (self, other) {
-            (Term::Integer(val1), Term::Integer(val2)) =>
-                val1.cmp(val2),
-            (_, _) => unimplemented!(),
-        }
+        term_cmp(self, other, false)
+    }
+}
+
+impl ErlExactOrd for Term {
+    fn erl_exact_ord(&self, other: &Term) -> ::std::cmp::Ordering {
+        term_cmp(self, other, true)
     }
 }
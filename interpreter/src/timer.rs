@@ -0,0 +1,125 @@
+//! Timers on a virtual clock.
+//!
+//! Time only moves when the scheduler finds every process blocked, at
+//! which point it jumps straight to the next timer or sleeping process.
+//! Timers that fire at the same time are delivered in the order they
+//! were started.
+
+use std::collections::{ BTreeMap, HashMap };
+
+use ::term::{ Term, Pid, Reference };
+
+#[derive(Debug, Clone)]
+pub struct Timer {
+    pub reference: Reference,
+    /// Process that started the timer.
+    pub owner: Pid,
+    pub dest: Pid,
+    /// The message as it will be delivered.
+    pub message: Term,
+}
+
+#[derive(Debug, Default)]
+pub struct Timers {
+    /// Current virtual time in milliseconds.
+    now: u64,
+    next_seq: u64,
+    pending: BTreeMap<(u64, u64), Timer>,
+    by_ref: HashMap<Reference, (u64, u64)>,
+}
+
+impl Timers {
+
+    pub fn new() -> Self {
+        Timers::default()
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn start(&mut self, fire_at: u64, timer: Timer) {
+        let key = (fire_at, self.next_seq);
+        self.next_seq += 1;
+        self.by_ref.insert(timer.reference, key);
+        self.pending.insert(key, timer);
+    }
+
+    /// Milliseconds left until the timer fires, if it is still pending.
+    pub fn read(&self, reference: Reference) -> Option<u64> {
+        self.by_ref.get(&reference)
+            .map(|(fire_at, _)| fire_at.saturating_sub(self.now))
+    }
+
+    /// Cancels the timer, returning the milliseconds it had left.
+    pub fn cancel(&mut self, reference: Reference) -> Option<u64> {
+        let remaining = self.read(reference)?;
+        let key = self.by_ref.remove(&reference).unwrap();
+        self.pending.remove(&key);
+        Some(remaining)
+    }
+
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.pending.keys().next().map(|(fire_at, _)| *fire_at)
+    }
+
+    /// Moves the clock forward to `time` and returns the timers that
+    /// fired, in delivery order.
+    pub fn advance_to(&mut self, time: u64) -> Vec<Timer> {
+        assert!(time >= self.now);
+        self.now = time;
+
+        let mut fired = Vec::new();
+        while let Some(key) = self.pending.keys().next().cloned() {
+            if key.0 > time {
+                break;
+            }
+            let timer = self.pending.remove(&key).unwrap();
+            self.by_ref.remove(&timer.reference);
+            fired.push(timer);
+        }
+        fired
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ Timers, Timer };
+    use ::term::{ Term, Pid, Reference };
+
+    fn timer(num: usize) -> Timer {
+        Timer {
+            reference: Reference(num),
+            owner: Pid(0),
+            dest: Pid(0),
+            message: Term::new_i64(num as i64),
+        }
+    }
+
+    #[test]
+    fn same_time_fires_in_start_order() {
+        let mut timers = Timers::new();
+        timers.start(10, timer(0));
+        timers.start(5, timer(1));
+        timers.start(10, timer(2));
+        assert!(timers.next_deadline() == Some(5));
+
+        let fired: Vec<_> = timers.advance_to(10).iter()
+            .map(|t| t.reference.0).collect();
+        assert!(fired == vec![1, 0, 2]);
+        assert!(timers.next_deadline() == None);
+    }
+
+    #[test]
+    fn cancel_and_read() {
+        let mut timers = Timers::new();
+        timers.start(100, timer(0));
+        timers.advance_to(30);
+        assert!(timers.read(Reference(0)) == Some(70));
+        assert!(timers.cancel(Reference(0)) == Some(70));
+        assert!(timers.cancel(Reference(0)) == None);
+        assert!(timers.advance_to(200).len() == 0);
+    }
+
+}
@@ -0,0 +1,192 @@
+use std::io::{ self, Read, Write };
+
+use ::process::CallReturn;
+use super::{ TraceSink, TraceEvent, TraceEventKind };
+
+/// Writes events as a compact binary log.
+///
+/// Every record is laid out as
+/// `tag: u8, pid: u32, num_fields: u16, (len: u32, utf8 bytes)*`,
+/// all integers little endian. Terms are stored pretty printed.
+/// `read_binary_trace` reads the log back. If writing fails the error
+/// is printed once, and the rest of the trace is dropped.
+pub struct BinaryTraceSink<W: Write> {
+    out: W,
+    failed: bool,
+}
+
+impl<W: Write> BinaryTraceSink<W> {
+
+    pub fn new(out: W) -> Self {
+        BinaryTraceSink {
+            out: out,
+            failed: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn write_record(&mut self, tag: u8, pid: u32, fields: &[String]) -> io::Result<()> {
+        self.out.write_all(&[tag])?;
+        self.out.write_all(&pid.to_le_bytes())?;
+        self.out.write_all(&(fields.len() as u16).to_le_bytes())?;
+        for field in fields {
+            self.out.write_all(&(field.len() as u32).to_le_bytes())?;
+            self.out.write_all(field.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn fail(&mut self, err: io::Error) {
+        println!("WARNING: Writing binary trace failed: {}", err);
+        self.failed = true;
+    }
+
+}
+
+fn term_string(term: &::Term) -> String {
+    format!("{}", term.to_doc().pretty(80))
+}
+
+fn ret_string(ret: &CallReturn) -> String {
+    match ret {
+        CallReturn::Return { term } => term_string(term),
+        CallReturn::Throw => "throw".to_string(),
+    }
+}
+
+impl<W: Write + Send> TraceSink for BinaryTraceSink<W> {
+
+    fn event(&mut self, event: &TraceEvent) {
+        if self.failed { return; }
+        let module = event.module.as_ref()
+            .map(|m| m.to_string())
+            .unwrap_or_else(String::new);
+        let (tag, mut fields) = match event.kind {
+            TraceEventKind::FunctionEnter { ref ident, ref args } =>
+                (0, vec![format!("{}", ident)].into_iter()
+                 .chain(args.iter().map(term_string)).collect()),
+            TraceEventKind::FunctionExit { ref ident, ref ret } =>
+                (1, vec![format!("{}", ident),
+                         ret.as_ref().map(ret_string)
+                         .unwrap_or_else(|| "tail_call".to_string())]),
+            TraceEventKind::BasicBlockStart { ref ident, block } =>
+                (2, vec![format!("{}", ident), format!("{}", block)]),
+            TraceEventKind::BasicBlockEnd { ops } =>
+                (3, vec![ops.to_string()]),
+            TraceEventKind::CaseClause { ref ident, block, clause } =>
+                (9, vec![format!("{}", ident), format!("{}", block), clause.to_string()]),
+            TraceEventKind::MessageSend { to, ref message } =>
+                (4, vec![to.0.to_string(), term_string(message)]),
+            TraceEventKind::MessageReceive { from, ref message } =>
+                (5, vec![from.0.to_string(), term_string(message)]),
+            TraceEventKind::Spawn { child, ref ident } =>
+                (6, vec![child.0.to_string(), format!("{}", ident)]),
+            TraceEventKind::Exit { ref ret } =>
+                (7, vec![ret_string(ret)]),
+            TraceEventKind::Warning { ref text, ref args } =>
+                (8, vec![text.clone()].into_iter()
+                 .chain(args.iter().map(|(k, v)| format!("{}={}", k, v)))
+                 .collect()),
+        };
+        fields.insert(0, module);
+        if let Err(err) = self.write_record(tag, event.pid.0 as u32, &fields) {
+            self.fail(err);
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.failed { return; }
+        if let Err(err) = self.out.flush() {
+            self.fail(err);
+        }
+    }
+
+}
+
+/// A record read back from a binary trace. The first field is always
+/// the module the event happened in, or empty if unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinaryRecord {
+    pub tag: u8,
+    pub pid: u32,
+    pub fields: Vec<String>,
+}
+
+fn read_bytes<R: Read>(input: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    input.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+pub fn read_binary_trace<R: Read>(mut input: R) -> io::Result<Vec<BinaryRecord>> {
+    let mut records = Vec::new();
+    loop {
+        let mut tag = [0u8];
+        if input.read(&mut tag)? == 0 {
+            break;
+        }
+
+        let mut pid = [0u8; 4];
+        input.read_exact(&mut pid)?;
+        let mut num_fields = [0u8; 2];
+        input.read_exact(&mut num_fields)?;
+
+        let mut fields = Vec::new();
+        for _ in 0..u16::from_le_bytes(num_fields) {
+            let mut len = [0u8; 4];
+            input.read_exact(&mut len)?;
+            let bytes = read_bytes(&mut input, u32::from_le_bytes(len) as usize)?;
+            let field = String::from_utf8(bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            fields.push(field);
+        }
+
+        records.push(BinaryRecord {
+            tag: tag[0],
+            pid: u32::from_le_bytes(pid),
+            fields: fields,
+        });
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ BinaryTraceSink, read_binary_trace };
+    use ::trace::{ TraceSink, TraceEvent, TraceEventKind };
+    use ::{ Atom, Term, Pid };
+    use ::process::CallReturn;
+
+    #[test]
+    fn roundtrip() {
+        let mut sink = BinaryTraceSink::new(Vec::new());
+        sink.event(&TraceEvent {
+            pid: Pid(3),
+            module: Some(Atom::from_str("foo")),
+            kind: TraceEventKind::MessageSend {
+                to: Pid(1),
+                message: Term::new_atom("hello"),
+            },
+        });
+        sink.event(&TraceEvent {
+            pid: Pid(3),
+            module: None,
+            kind: TraceEventKind::Exit {
+                ret: CallReturn::Throw,
+            },
+        });
+
+        let data = sink.into_inner();
+        let records = read_binary_trace(&data[..]).unwrap();
+        assert!(records.len() == 2);
+        assert!(records[0].tag == 4);
+        assert!(records[0].pid == 3);
+        assert!(records[0].fields == vec!["foo".to_string(), "1".to_string(),
+                                          "hello".to_string()]);
+        assert!(records[1].fields == vec!["".to_string(), "throw".to_string()]);
+    }
+
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::io::{ self, Write };
+
+use ::serde::{ Serialize };
+
+use ::process::CallReturn;
+use super::{ TraceSink, TraceEvent, TraceEventKind };
+
+#[derive(Serialize)]
+#[serde(tag = "ph")]
+enum TraceEntry {
+    #[serde(rename = "B")]
+    DurationStart {
+        name: String,
+        #[serde(rename = "cat")]
+        categories: String,
+        #[serde(rename = "ts")]
+        timestamp: u64,
+        pid: u64,
+        tid: u64,
+        cname: Option<String>,
+        args: HashMap<String, ::serde_json::Value>,
+    },
+    #[serde(rename = "E")]
+    DurationEnd {
+        #[serde(rename = "ts")]
+        timestamp: u64,
+        pid: u64,
+        tid: u64,
+        args: HashMap<String, ::serde_json::Value>,
+    },
+    #[serde(rename = "i")]
+    Instant {
+        name: String,
+        #[serde(rename = "ts")]
+        timestamp: u64,
+        pid: u64,
+        tid: u64,
+        #[serde(rename = "s")]
+        scope: &'static str,
+        args: HashMap<String, ::serde_json::Value>,
+    }
+}
+
+/// Writes events in the Chrome trace-event JSON format, viewable in
+/// `chrome://tracing`. Timestamps are logical, one tick per executed
+/// basic block or returned function.
+///
+/// Entries are written out on every `flush`, and the JSON array is
+/// closed when the sink is dropped. Chrome also reads the array before
+/// it is closed. If writing fails the error is printed once, and the
+/// rest of the trace is dropped.
+pub struct ChromeTraceSink {
+    out: Box<dyn Write + Send>,
+    /// Entries not written out yet.
+    entries: Vec<TraceEntry>,
+    idx: u64,
+    /// Whether the opening bracket of the array was written.
+    started: bool,
+    failed: bool,
+}
+
+impl ChromeTraceSink {
+
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        ChromeTraceSink {
+            out: out,
+            entries: Vec::new(),
+            idx: 0,
+            started: false,
+            failed: false,
+        }
+    }
+
+    pub fn create(filename: &str) -> ::std::io::Result<Self> {
+        let file = ::std::fs::File::create(filename)?;
+        Ok(ChromeTraceSink::new(Box::new(file)))
+    }
+
+    fn instant(&self, pid: u64, name: String,
+               args: HashMap<String, ::serde_json::Value>) -> TraceEntry {
+        TraceEntry::Instant {
+            name: name,
+            timestamp: self.idx,
+            pid: pid,
+            tid: 0,
+            scope: "p",
+            args: args,
+        }
+    }
+
+    fn write_entries(&mut self) -> io::Result<()> {
+        for entry in self.entries.drain(..) {
+            let separator = if self.started { ",\n" } else { "[\n" };
+            self.started = true;
+            self.out.write_all(separator.as_bytes())?;
+            ::serde_json::to_writer(&mut self.out, &entry)?;
+        }
+        self.out.flush()
+    }
+
+    fn write_end(&mut self) -> io::Result<()> {
+        let end = if self.started { "\n]\n" } else { "[]\n" };
+        self.out.write_all(end.as_bytes())?;
+        self.out.flush()
+    }
+
+    fn fail(&mut self, err: io::Error) {
+        println!("WARNING: Writing chrome trace failed: {}", err);
+        self.failed = true;
+        self.entries.clear();
+    }
+
+}
+
+fn term_value(term: &::Term) -> ::serde_json::Value {
+    ::serde_json::Value::String(format!("{}", term.to_doc().pretty(40)))
+}
+
+impl TraceSink for ChromeTraceSink {
+
+    fn event(&mut self, event: &TraceEvent) {
+        let pid = event.pid.0 as u64;
+        let entry = match event.kind {
+            TraceEventKind::FunctionEnter { ref ident, ref args } => {
+                let mut event_args = HashMap::new();
+                let fun_args: Vec<_> = args.iter().map(term_value).collect();
+                event_args.insert(
+                    "Call Arguments".to_string(),
+                    ::serde_json::Value::Array(fun_args)
+                );
+                TraceEntry::DurationStart {
+                    timestamp: self.idx,
+                    pid: pid,
+                    tid: 0,
+                    cname: None,
+                    args: event_args,
+                    name: format!("{}", ident),
+                    categories: "".to_string(),
+                }
+            }
+            TraceEventKind::FunctionExit { ref ret, .. } => {
+                if ret.is_some() { self.idx += 1; }
+                let mut event_args = HashMap::new();
+                event_args.insert(
+                    "Call Return".to_string(),
+                    match ret {
+                        None => ::serde_json::Value::String(
+                            "TailCall".to_string()),
+                        Some(CallReturn::Return { term }) => term_value(term),
+                        Some(CallReturn::Throw) =>
+                            ::serde_json::Value::String("Throw".to_string()),
+                    }
+                );
+                TraceEntry::DurationEnd {
+                    timestamp: self.idx,
+                    pid: pid,
+                    tid: 0,
+                    args: event_args,
+                }
+            }
+            TraceEventKind::BasicBlockStart { ref ident, block } => {
+                let module = event.module.as_ref().unwrap();
+                TraceEntry::DurationStart {
+                    timestamp: self.idx,
+                    pid: pid,
+                    tid: 1,
+                    cname: None,
+                    args: HashMap::new(),
+                    name: format!("{} ({}:{})", block, module, ident),
+                    categories: "".to_string(),
+                }
+            }
+            TraceEventKind::BasicBlockEnd { .. } => {
+                self.idx += 1;
+                TraceEntry::DurationEnd {
+                    timestamp: self.idx,
+                    pid: pid,
+                    tid: 1,
+                    args: HashMap::new(),
+                }
+            }
+            TraceEventKind::CaseClause { block, clause, .. } => {
+                self.instant(pid, format!("Case clause {} ({})", clause, block),
+                             HashMap::new())
+            }
+            TraceEventKind::MessageSend { to, ref message } => {
+                let mut args = HashMap::new();
+                args.insert("Message".to_string(), term_value(message));
+                self.instant(pid, format!("Send to Pid<{}>", to.0), args)
+            }
+            TraceEventKind::MessageReceive { from, ref message } => {
+                let mut args = HashMap::new();
+                args.insert("Message".to_string(), term_value(message));
+                self.instant(pid, format!("Receive from Pid<{}>", from.0), args)
+            }
+            TraceEventKind::Spawn { child, ref ident } => {
+                self.instant(pid, format!("Spawn Pid<{}> {}", child.0, ident),
+                             HashMap::new())
+            }
+            TraceEventKind::Exit { ref ret } => {
+                let mut args = HashMap::new();
+                let value = match ret {
+                    CallReturn::Return { term } => term_value(term),
+                    CallReturn::Throw =>
+                        ::serde_json::Value::String("Throw".to_string()),
+                };
+                args.insert("Return".to_string(), value);
+                self.instant(pid, "Exit".to_string(), args)
+            }
+            TraceEventKind::Warning { ref text, ref args } => {
+                self.instant(pid, text.clone(), args.clone())
+            }
+        };
+        if !self.failed {
+            self.entries.push(entry);
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.failed { return; }
+        if let Err(err) = self.write_entries() {
+            self.fail(err);
+        }
+    }
+
+}
+
+impl Drop for ChromeTraceSink {
+    fn drop(&mut self) {
+        self.flush();
+        if self.failed { return; }
+        if let Err(err) = self.write_end() {
+            self.fail(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{ self, Write };
+    use std::sync::{ Arc, Mutex };
+
+    use super::ChromeTraceSink;
+    use ::trace::{ TraceSink, TraceEvent, TraceEventKind };
+    use ::{ Atom, FunctionIdent, Pid };
+
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct FailingOutput;
+
+    impl Write for FailingOutput {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::Other, "disk full"))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn enter() -> TraceEvent {
+        TraceEvent {
+            pid: Pid(0),
+            module: Some(Atom::from_str("test")),
+            kind: TraceEventKind::FunctionEnter {
+                ident: FunctionIdent {
+                    module: Atom::from_str("test"),
+                    name: Atom::from_str("fun"),
+                    arity: 0,
+                    lambda: None,
+                },
+                args: vec![],
+            },
+        }
+    }
+
+    #[test]
+    fn flushes_append_to_one_array() {
+        let output = SharedOutput::default();
+        {
+            let mut sink = ChromeTraceSink::new(Box::new(output.clone()));
+            sink.event(&enter());
+            sink.flush();
+            sink.event(&enter());
+            sink.flush();
+            sink.flush();
+        }
+
+        let json = output.0.lock().unwrap().clone();
+        let entries: ::serde_json::Value = ::serde_json::from_slice(&json).unwrap();
+        assert!(entries.as_array().unwrap().len() == 2);
+    }
+
+    #[test]
+    fn write_errors_do_not_panic() {
+        let mut sink = ChromeTraceSink::new(Box::new(FailingOutput));
+        sink.event(&enter());
+        sink.flush();
+        sink.event(&enter());
+        sink.flush();
+    }
+
+}
@@ -0,0 +1,382 @@
+use std::sync::{ Arc, Mutex, MutexGuard };
+use std::collections::HashMap;
+use std::io::{ self, Write };
+
+use ::{ Atom, FunctionIdent, Module, OpKind, Pid };
+use ::eir::{ Function, Ebb, Op };
+use ::eir::text::{ DotOverlay, function_to_dot_with_overlay };
+use super::{ TraceSink, TraceEvent, TraceEventKind };
+
+#[derive(Debug, Clone, Default)]
+pub struct FunctionCoverage {
+    pub calls: u64,
+    pub blocks: HashMap<Ebb, u64>,
+    /// Hits of the ops of each block, in block order. An op raising an
+    /// exception ends its block early, so later ops can have fewer hits
+    /// than the block itself.
+    pub ops: HashMap<Ebb, Vec<u64>>,
+    /// Keyed on the block containing the `Case` op and the clause number.
+    pub clauses: HashMap<(Ebb, usize), u64>,
+}
+
+impl FunctionCoverage {
+
+    pub fn block_hits(&self, block: Ebb) -> u64 {
+        self.blocks.get(&block).cloned().unwrap_or(0)
+    }
+
+    pub fn op_hits(&self, block: Ebb, op: usize) -> u64 {
+        self.ops.get(&block)
+            .and_then(|ops| ops.get(op))
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    pub fn clause_hits(&self, block: Ebb, clause: usize) -> u64 {
+        self.clauses.get(&(block, clause)).cloned().unwrap_or(0)
+    }
+
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    pub functions: HashMap<FunctionIdent, FunctionCoverage>,
+}
+
+/// Color on a red to green scale, relative to the hottest block.
+fn hit_color(hits: u64, max: u64) -> String {
+    if hits == 0 {
+        return "#f4cccc".to_string();
+    }
+    let ratio = (hits as f64).ln_1p() / (max as f64).ln_1p();
+    let green = 0xd9 - (ratio * 0x60 as f64) as u8;
+    format!("#b6{:02x}a8", green)
+}
+
+/// Draws hit counts on top of the control flow graph printed by
+/// `eir::text::dot_printer`.
+struct CoverageOverlay<'a> {
+    fun: &'a Function,
+    cov: &'a FunctionCoverage,
+    max: u64,
+}
+
+impl<'a> DotOverlay for CoverageOverlay<'a> {
+
+    fn ebb_attributes(&self, ebb: Ebb) -> Option<String> {
+        Some(format!("style=filled, fillcolor=\"{}\"",
+                     hit_color(self.cov.block_hits(ebb), self.max)))
+    }
+
+    fn ebb_note(&self, ebb: Ebb) -> Option<String> {
+        if ebb == self.fun.ebb_entry() {
+            Some(format!("{} calls, {} hits", self.cov.calls, self.cov.block_hits(ebb)))
+        } else {
+            Some(format!("{} hits", self.cov.block_hits(ebb)))
+        }
+    }
+
+    fn op_note(&self, op: Op) -> Option<String> {
+        let ebb = self.fun.op_ebb(op);
+        let num = self.fun.iter_op(ebb).position(|o| o == op).unwrap();
+        let hits = self.cov.op_hits(ebb, num);
+        match self.fun.op_kind(op) {
+            OpKind::Case(clauses) => {
+                let clause_hits: Vec<_> = (0..*clauses)
+                    .map(|c| self.cov.clause_hits(ebb, c).to_string())
+                    .collect();
+                Some(format!("{:>6} (clauses {})", hits, clause_hits.join(" ")))
+            }
+            _ => Some(format!("{:>6}", hits)),
+        }
+    }
+
+}
+
+impl Coverage {
+
+    pub fn function(&self, ident: &FunctionIdent) -> Option<&FunctionCoverage> {
+        self.functions.get(ident)
+    }
+
+    /// Writes an lcov tracefile for `module`. Lines come from the Core
+    /// Erlang annotations the compiler keeps on each op. A line has the
+    /// hits of its most executed op.
+    pub fn write_lcov(&self, module: &Module, source_file: &str,
+                      w: &mut dyn Write) -> io::Result<()> {
+        let empty = FunctionCoverage::default();
+
+        write!(w, "TN:\n")?;
+        write!(w, "SF:{}\n", source_file)?;
+
+        let mut functions: Vec<_> = module.functions.iter().collect();
+        functions.sort_by_key(|(ident, _)| ident.to_string());
+
+        let mut line_hits: HashMap<u32, u64> = HashMap::new();
+        let mut functions_hit = 0;
+        for (ident, fun) in functions.iter() {
+            let cov = self.functions.get(ident).unwrap_or(&empty);
+
+            let mut fun_line = None;
+            for ebb in fun.iter_ebb() {
+                for (num, op) in fun.iter_op(ebb).enumerate() {
+                    if let Some(line) = fun.op_line(op) {
+                        let entry = line_hits.entry(line).or_insert(0);
+                        *entry = ::std::cmp::max(*entry, cov.op_hits(ebb, num));
+                        fun_line = Some(fun_line.map_or(line, |l| ::std::cmp::min(l, line)));
+                    }
+                }
+            }
+
+            write!(w, "FN:{},{}\n", fun_line.unwrap_or(0), ident)?;
+            write!(w, "FNDA:{},{}\n", cov.calls, ident)?;
+            if cov.calls > 0 {
+                functions_hit += 1;
+            }
+        }
+        write!(w, "FNF:{}\n", module.functions.len())?;
+        write!(w, "FNH:{}\n", functions_hit)?;
+
+        let mut sorted_lines: Vec<_> = line_hits.iter().collect();
+        sorted_lines.sort();
+        for (line, hits) in sorted_lines.iter() {
+            write!(w, "DA:{},{}\n", line, hits)?;
+        }
+        write!(w, "LF:{}\n", sorted_lines.len())?;
+        write!(w, "LH:{}\n", sorted_lines.iter().filter(|(_, h)| **h > 0).count())?;
+        write!(w, "end_of_record\n")?;
+        Ok(())
+    }
+
+    /// Writes the control flow graph of `ident` in dot format, with
+    /// blocks colored by hit count and each op and clause annotated
+    /// with its hits.
+    pub fn write_dot(&self, module: &Module, ident: &FunctionIdent,
+                     w: &mut dyn Write) -> io::Result<()> {
+        let empty = FunctionCoverage::default();
+        let cov = self.functions.get(ident).unwrap_or(&empty);
+        let fun = &module.functions[ident];
+        let max = cov.blocks.values().cloned().max().unwrap_or(0);
+
+        let overlay = CoverageOverlay {
+            fun: fun,
+            cov: cov,
+            max: max,
+        };
+        function_to_dot_with_overlay(fun, &overlay, w)
+    }
+
+}
+
+/// Records coverage from trace events. Clones share the same data, so
+/// a clone can be kept to read the results after the sink has been
+/// handed to the VM.
+#[derive(Clone, Default)]
+pub struct CoverageSink {
+    data: Arc<Mutex<Coverage>>,
+    /// Block each process is executing, for `BasicBlockEnd`.
+    current: HashMap<Pid, (FunctionIdent, Ebb)>,
+}
+
+impl CoverageSink {
+
+    pub fn new() -> Self {
+        CoverageSink::default()
+    }
+
+    pub fn coverage(&self) -> MutexGuard<Coverage> {
+        self.data.lock().unwrap()
+    }
+
+    /// Coverage of the functions in `module` only.
+    pub fn module_coverage(&self, module: &Atom) -> Coverage {
+        let data = self.data.lock().unwrap();
+        Coverage {
+            functions: data.functions.iter()
+                .filter(|(ident, _)| &ident.module == module)
+                .map(|(ident, cov)| (ident.clone(), cov.clone()))
+                .collect(),
+        }
+    }
+
+}
+
+impl TraceSink for CoverageSink {
+
+    fn event(&mut self, event: &TraceEvent) {
+        let mut data = self.data.lock().unwrap();
+        match event.kind {
+            TraceEventKind::FunctionEnter { ref ident, .. } => {
+                data.functions.entry(ident.clone()).or_default().calls += 1;
+            }
+            TraceEventKind::BasicBlockStart { ref ident, block } => {
+                let fun = data.functions.entry(ident.clone()).or_default();
+                *fun.blocks.entry(block).or_insert(0) += 1;
+                self.current.insert(event.pid, (ident.clone(), block));
+            }
+            TraceEventKind::BasicBlockEnd { ops } => {
+                if let Some((ident, block)) = self.current.remove(&event.pid) {
+                    let fun = data.functions.entry(ident).or_default();
+                    let hits = fun.ops.entry(block).or_default();
+                    if hits.len() < ops {
+                        hits.resize(ops, 0);
+                    }
+                    for hit in hits[..ops].iter_mut() {
+                        *hit += 1;
+                    }
+                }
+            }
+            TraceEventKind::CaseClause { ref ident, block, clause } => {
+                let fun = data.functions.entry(ident.clone()).or_default();
+                *fun.clauses.entry((block, clause)).or_insert(0) += 1;
+            }
+            _ => (),
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use super::{ CoverageSink, hit_color };
+    use ::trace::{ TraceSink, TraceEvent, TraceEventKind };
+    use ::{ Atom, FunctionIdent, Module, Pid };
+    use ::eir::{ Function, FunctionBuilder, ModuleEnvs, Ebb };
+
+    fn ident() -> FunctionIdent {
+        FunctionIdent {
+            module: Atom::from_str("foo"),
+            name: Atom::from_str("bar"),
+            arity: 1,
+            lambda: None,
+        }
+    }
+
+    /// `bar(A) -> {A}` with the tuple on line 3 and the return on
+    /// line 4, all in the entry block.
+    fn module() -> (Module, Ebb) {
+        let mut fun = Function::new(ident());
+        let entry = {
+            let mut b = FunctionBuilder::new(&mut fun);
+            let entry = b.insert_ebb_entry();
+            let arg = b.add_ebb_argument(entry);
+            b.position_at_end(entry);
+            b.set_source_line(Some(3));
+            let tuple = b.op_make_tuple(&[arg]);
+            b.set_source_line(Some(4));
+            b.op_return_ok(tuple);
+            entry
+        };
+        let mut functions = HashMap::new();
+        functions.insert(ident(), fun);
+        let module = Module {
+            name: Atom::from_str("foo"),
+            envs: ModuleEnvs::new(),
+            functions: functions,
+        };
+        (module, entry)
+    }
+
+    /// Enters the function `times` times, the last time raising in
+    /// the first op.
+    fn run(sink: &mut CoverageSink, entry: Ebb, times: usize) {
+        for num in 0..times {
+            let events = vec![
+                TraceEventKind::FunctionEnter { ident: ident(), args: vec![] },
+                TraceEventKind::BasicBlockStart { ident: ident(), block: entry },
+                TraceEventKind::BasicBlockEnd { ops: if num + 1 == times { 1 } else { 2 } },
+            ];
+            for kind in events {
+                sink.event(&TraceEvent {
+                    pid: Pid(0),
+                    module: Some(Atom::from_str("foo")),
+                    kind: kind,
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn counts_calls() {
+        let ident = FunctionIdent {
+            module: Atom::from_str("foo"),
+            name: Atom::from_str("bar"),
+            arity: 0,
+            lambda: None,
+        };
+        let mut sink = CoverageSink::new();
+        for _ in 0..3 {
+            sink.event(&TraceEvent {
+                pid: Pid(0),
+                module: Some(ident.module.clone()),
+                kind: TraceEventKind::FunctionEnter {
+                    ident: ident.clone(),
+                    args: vec![],
+                },
+            });
+        }
+        assert!(sink.coverage().function(&ident).unwrap().calls == 3);
+        assert!(sink.module_coverage(&Atom::from_str("baz")).functions.len() == 0);
+    }
+
+    #[test]
+    fn hottest_block_is_greenest() {
+        assert!(hit_color(0, 10) == "#f4cccc");
+        assert!(hit_color(10, 10) == "#b679a8");
+        assert!(hit_color(1, 10) != hit_color(10, 10));
+    }
+
+    #[test]
+    fn ops_after_a_raise_are_not_hit() {
+        let (_module, entry) = module();
+        let mut sink = CoverageSink::new();
+        run(&mut sink, entry, 3);
+
+        let coverage = sink.coverage();
+        let cov = coverage.function(&ident()).unwrap();
+        assert!(cov.block_hits(entry) == 3);
+        assert!(cov.op_hits(entry, 0) == 3);
+        assert!(cov.op_hits(entry, 1) == 2);
+    }
+
+    #[test]
+    fn lcov_lines_from_annotations() {
+        let (module, entry) = module();
+        let mut sink = CoverageSink::new();
+        run(&mut sink, entry, 3);
+
+        let mut out = Vec::new();
+        sink.coverage().write_lcov(&module, "foo.erl", &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out == "TN:
+SF:foo.erl
+FN:3,foo:bar/1
+FNDA:3,foo:bar/1
+FNF:1
+FNH:1
+DA:3,3
+DA:4,2
+LF:2
+LH:2
+end_of_record
+");
+    }
+
+    #[test]
+    fn dot_overlays_hits() {
+        let (module, entry) = module();
+        let mut sink = CoverageSink::new();
+        run(&mut sink, entry, 3);
+
+        let mut out = Vec::new();
+        sink.coverage().write_dot(&module, &ident(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("digraph g {"));
+        assert!(out.contains("3 calls, 3 hits"));
+        assert!(out.contains(&format!("fillcolor=\"{}\"", hit_color(3, 3))));
+        assert!(out.contains("     3 \\| "));
+        assert!(out.contains("     2 \\| "));
+    }
+
+}
@@ -1,2 +0,0 @@
-fn enter_function(module: &Atom, ident: &FunctionIdent, args: &[Term]) {}
-fn exit_function(module: &Atom, ident: &FunctionIdent) {}
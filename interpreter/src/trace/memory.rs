@@ -0,0 +1,33 @@
+use std::sync::{ Arc, Mutex, MutexGuard };
+
+use super::{ TraceSink, TraceEvent };
+
+/// Collects events in memory. Clones share the same buffer, so a clone
+/// can be kept around to inspect the events after the sink has been
+/// handed to the VM.
+#[derive(Clone, Default)]
+pub struct MemoryTraceSink {
+    events: Arc<Mutex<Vec<TraceEvent>>>,
+}
+
+impl MemoryTraceSink {
+
+    pub fn new() -> Self {
+        MemoryTraceSink::default()
+    }
+
+    pub fn events(&self) -> MutexGuard<Vec<TraceEvent>> {
+        self.events.lock().unwrap()
+    }
+
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+
+}
+
+impl TraceSink for MemoryTraceSink {
+    fn event(&mut self, event: &TraceEvent) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
@@ -1,12 +1,319 @@
+//! Tracing of interpreter execution.
+//!
+//! Every `VMState` owns a `Tracer`. Sinks implementing `TraceSink` are
+//! registered on it together with a `TraceFilter`, and receive every
+//! event the filter lets through. When no sinks are registered, tracing
+//! costs close to nothing.
 
-#[cfg(not(feature = "trace"))]
-#[macro_use]
-mod dummy;
-#[cfg(not(feature = "trace"))]
-use self::dummy as trace;
+use std::collections::{ HashMap, HashSet };
 
-#[cfg(feature = "trace")]
-#[macro_use]
-mod trace;
+use ::{ Atom, FunctionIdent, Term, Pid };
+use ::eir::Ebb;
+use ::process::CallReturn;
 
-pub use self::trace::*;
+mod chrome;
+pub use self::chrome::ChromeTraceSink;
+
+mod binary;
+pub use self::binary::{ BinaryTraceSink, BinaryRecord, read_binary_trace };
+
+mod memory;
+pub use self::memory::MemoryTraceSink;
+
+mod profile;
+pub use self::profile::{ ProfileSink, Profile, FunctionProfile, ProfileWeight, StackId };
+
+mod coverage;
+pub use self::coverage::{ Coverage, CoverageSink, FunctionCoverage };
+
+#[derive(Debug, Clone)]
+pub struct TraceEvent {
+    pub pid: Pid,
+    /// Module of the function the process was executing when the
+    /// event happened, if any.
+    pub module: Option<Atom>,
+    pub kind: TraceEventKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum TraceEventKind {
+    FunctionEnter {
+        ident: FunctionIdent,
+        args: Vec<Term>,
+    },
+    /// `ret` is `None` when the function left through a tail call.
+    FunctionExit {
+        ident: FunctionIdent,
+        ret: Option<CallReturn>,
+    },
+    BasicBlockStart {
+        ident: FunctionIdent,
+        block: Ebb,
+    },
+    /// `ops` is the number of ops executed in the block.
+    BasicBlockEnd {
+        ops: usize,
+    },
+    /// A `Case` op in `block` matched the patterns of `clause`.
+    CaseClause {
+        ident: FunctionIdent,
+        block: Ebb,
+        clause: usize,
+    },
+    MessageSend {
+        to: Pid,
+        message: Term,
+    },
+    MessageReceive {
+        from: Pid,
+        message: Term,
+    },
+    Spawn {
+        child: Pid,
+        ident: FunctionIdent,
+    },
+    Exit {
+        ret: CallReturn,
+    },
+    Warning {
+        text: String,
+        args: HashMap<String, ::serde_json::Value>,
+    },
+}
+
+pub trait TraceSink: Send {
+    fn event(&mut self, event: &TraceEvent);
+
+    /// Called when the VM is done running. Sinks that buffer should
+    /// write out their contents here.
+    fn flush(&mut self) {}
+}
+
+/// Selects which events are passed to a sink. An empty filter lets
+/// everything through.
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    pids: Option<HashSet<Pid>>,
+    modules: Option<HashSet<Atom>>,
+}
+
+impl TraceFilter {
+
+    pub fn all() -> Self {
+        TraceFilter::default()
+    }
+
+    pub fn pid(mut self, pid: Pid) -> Self {
+        self.pids.get_or_insert_with(HashSet::new).insert(pid);
+        self
+    }
+
+    pub fn module(mut self, module: Atom) -> Self {
+        self.modules.get_or_insert_with(HashSet::new).insert(module);
+        self
+    }
+
+    pub fn matches(&self, event: &TraceEvent) -> bool {
+        if let Some(ref pids) = self.pids {
+            if !pids.contains(&event.pid) {
+                return false;
+            }
+        }
+        if let Some(ref modules) = self.modules {
+            match event.module {
+                Some(ref module) if modules.contains(module) => (),
+                _ => return false,
+            }
+        }
+        true
+    }
+
+}
+
+pub struct Tracer {
+    /// Module of each function on the stack of each process.
+    stacks: HashMap<Pid, Vec<Atom>>,
+    sinks: Vec<(TraceFilter, Box<dyn TraceSink>)>,
+}
+
+impl Tracer {
+
+    pub fn new() -> Self {
+        Tracer {
+            stacks: HashMap::new(),
+            sinks: Vec::new(),
+        }
+    }
+
+    pub fn add_sink(&mut self, filter: TraceFilter, sink: Box<dyn TraceSink>) {
+        self.sinks.push((filter, sink));
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sinks.len() > 0
+    }
+
+    pub fn flush(&mut self) {
+        for (_, sink) in self.sinks.iter_mut() {
+            sink.flush();
+        }
+    }
+
+    fn emit(&mut self, pid: Pid, module: Option<Atom>, kind: TraceEventKind) {
+        let event = TraceEvent {
+            pid: pid,
+            module: module,
+            kind: kind,
+        };
+        for (filter, sink) in self.sinks.iter_mut() {
+            if filter.matches(&event) {
+                sink.event(&event);
+            }
+        }
+    }
+
+    fn current_module(&self, pid: Pid) -> Option<Atom> {
+        self.stacks.get(&pid).and_then(|s| s.last().cloned())
+    }
+
+    pub fn enter_function(&mut self, pid: Pid, module: &Atom,
+                          ident: &FunctionIdent, args: &[Term]) {
+        if !self.is_enabled() { return; }
+        self.stacks.entry(pid).or_insert_with(Vec::new).push(module.clone());
+        self.emit(pid, Some(module.clone()), TraceEventKind::FunctionEnter {
+            ident: ident.clone(),
+            args: args.to_vec(),
+        });
+    }
+
+    pub fn exit_function(&mut self, pid: Pid, module: &Atom,
+                         ident: &FunctionIdent, ret: Option<&CallReturn>) {
+        if !self.is_enabled() { return; }
+        if let Some(stack) = self.stacks.get_mut(&pid) {
+            let removed = stack.pop();
+            assert!(removed.as_ref() == Some(module));
+        }
+        self.emit(pid, Some(module.clone()), TraceEventKind::FunctionExit {
+            ident: ident.clone(),
+            ret: ret.cloned(),
+        });
+    }
+
+    pub fn start_basic_block(&mut self, pid: Pid, module: &Atom,
+                             ident: &FunctionIdent, block: Ebb) {
+        if !self.is_enabled() { return; }
+        self.emit(pid, Some(module.clone()), TraceEventKind::BasicBlockStart {
+            ident: ident.clone(),
+            block: block,
+        });
+    }
+
+    pub fn end_basic_block(&mut self, pid: Pid, ops: usize) {
+        if !self.is_enabled() { return; }
+        let module = self.current_module(pid);
+        self.emit(pid, module, TraceEventKind::BasicBlockEnd {
+            ops: ops,
+        });
+    }
+
+    pub fn case_clause(&mut self, pid: Pid, module: &Atom, ident: &FunctionIdent,
+                       block: Ebb, clause: usize) {
+        if !self.is_enabled() { return; }
+        self.emit(pid, Some(module.clone()), TraceEventKind::CaseClause {
+            ident: ident.clone(),
+            block: block,
+            clause: clause,
+        });
+    }
+
+    pub fn message_send(&mut self, from: Pid, to: Pid, message: &Term) {
+        if !self.is_enabled() { return; }
+        let module = self.current_module(from);
+        self.emit(from, module, TraceEventKind::MessageSend {
+            to: to,
+            message: message.clone(),
+        });
+    }
+
+    pub fn message_receive(&mut self, pid: Pid, from: Pid, message: &Term) {
+        if !self.is_enabled() { return; }
+        let module = self.current_module(pid);
+        self.emit(pid, module, TraceEventKind::MessageReceive {
+            from: from,
+            message: message.clone(),
+        });
+    }
+
+    pub fn spawn(&mut self, parent: Pid, child: Pid, ident: &FunctionIdent) {
+        if !self.is_enabled() { return; }
+        let module = self.current_module(parent);
+        self.emit(parent, module, TraceEventKind::Spawn {
+            child: child,
+            ident: ident.clone(),
+        });
+    }
+
+    pub fn exit(&mut self, pid: Pid, ret: &CallReturn) {
+        if !self.is_enabled() { return; }
+        self.stacks.remove(&pid);
+        self.emit(pid, None, TraceEventKind::Exit {
+            ret: ret.clone(),
+        });
+    }
+
+    pub fn warning(&mut self, pid: Pid, text: String) {
+        self.warning_args(pid, text, HashMap::new)
+    }
+
+    pub fn warning_args<F>(&mut self, pid: Pid, text: String, make_args: F)
+    where F: FnOnce() -> HashMap<String, ::serde_json::Value> {
+        if !self.is_enabled() { return; }
+        let module = self.current_module(pid);
+        self.emit(pid, module, TraceEventKind::Warning {
+            text: text,
+            args: (make_args)(),
+        });
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ Tracer, TraceFilter, TraceEventKind, MemoryTraceSink };
+    use ::{ Atom, FunctionIdent, Pid };
+
+    fn ident(module: &str, name: &str) -> FunctionIdent {
+        FunctionIdent {
+            module: Atom::from_str(module),
+            name: Atom::from_str(name),
+            arity: 0,
+            lambda: None,
+        }
+    }
+
+    #[test]
+    fn filter_by_pid_and_module() {
+        let mut tracer = Tracer::new();
+        let all = MemoryTraceSink::new();
+        let filtered = MemoryTraceSink::new();
+        tracer.add_sink(TraceFilter::all(), Box::new(all.clone()));
+        tracer.add_sink(TraceFilter::all().pid(Pid(1)).module(Atom::from_str("a")),
+                        Box::new(filtered.clone()));
+
+        let a = ident("a", "f");
+        let b = ident("b", "g");
+        tracer.enter_function(Pid(0), &a.module, &a, &[]);
+        tracer.enter_function(Pid(1), &a.module, &a, &[]);
+        tracer.enter_function(Pid(1), &b.module, &b, &[]);
+        tracer.spawn(Pid(1), Pid(2), &a);
+
+        assert!(all.events().len() == 4);
+        let events = filtered.events();
+        assert!(events.len() == 1);
+        match events[0].kind {
+            TraceEventKind::FunctionEnter { ref ident, .. } => assert!(*ident == a),
+            _ => panic!(),
+        }
+    }
+
+}
@@ -0,0 +1,368 @@
+use std::sync::{ Arc, Mutex, MutexGuard };
+use std::collections::{ HashMap, HashSet };
+use std::io::{ self, Write };
+
+use ::{ FunctionIdent, Pid };
+use super::{ TraceSink, TraceEvent, TraceEventKind };
+
+#[derive(Debug, Clone, Default)]
+pub struct FunctionProfile {
+    pub calls: u64,
+    /// Reductions spent in the function itself.
+    pub own_reductions: u64,
+    /// Reductions spent in the function and everything it called.
+    pub inclusive_reductions: u64,
+    pub own_ops: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileWeight {
+    Reductions,
+    Ops,
+}
+
+/// Index of an interned call stack in a `Profile`.
+pub type StackId = usize;
+
+#[derive(Debug, Clone)]
+struct StackNode {
+    parent: Option<StackId>,
+    function: FunctionIdent,
+    reductions: u64,
+    ops: u64,
+}
+
+/// Call stacks are interned as a tree, each stack being its innermost
+/// function on top of its parent stack. Reductions and ops are charged
+/// to the stack they ran under, the per function numbers are derived
+/// from those when asked for.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    calls: HashMap<FunctionIdent, u64>,
+    nodes: Vec<StackNode>,
+    ids: HashMap<(Option<StackId>, FunctionIdent), StackId>,
+}
+
+impl Profile {
+
+    fn push(&mut self, parent: Option<StackId>, function: &FunctionIdent) -> StackId {
+        let key = (parent, function.clone());
+        if let Some(id) = self.ids.get(&key) {
+            return *id;
+        }
+        let id = self.nodes.len();
+        self.nodes.push(StackNode {
+            parent: parent,
+            function: function.clone(),
+            reductions: 0,
+            ops: 0,
+        });
+        self.ids.insert(key, id);
+        id
+    }
+
+    /// Functions of the stack, innermost first.
+    fn walk<'a>(&'a self, id: StackId) -> impl Iterator<Item = &'a StackNode> + 'a {
+        let mut next = Some(id);
+        ::std::iter::from_fn(move || {
+            let node = &self.nodes[next?];
+            next = node.parent;
+            Some(node)
+        })
+    }
+
+    /// The functions of an interned stack, outermost first.
+    pub fn stack(&self, id: StackId) -> Vec<FunctionIdent> {
+        let mut stack: Vec<_> = self.walk(id).map(|n| n.function.clone()).collect();
+        stack.reverse();
+        stack
+    }
+
+    /// Every stack that was charged anything, with its reductions and
+    /// ops.
+    pub fn stacks(&self) -> Vec<(Vec<FunctionIdent>, u64, u64)> {
+        (0..self.nodes.len())
+            .filter(|id| self.nodes[*id].reductions > 0 || self.nodes[*id].ops > 0)
+            .map(|id| (self.stack(id), self.nodes[id].reductions, self.nodes[id].ops))
+            .collect()
+    }
+
+    pub fn functions(&self) -> HashMap<FunctionIdent, FunctionProfile> {
+        let mut functions: HashMap<FunctionIdent, FunctionProfile> = self.calls.iter()
+            .map(|(ident, calls)| (ident.clone(), FunctionProfile {
+                calls: *calls,
+                ..Default::default()
+            }))
+            .collect();
+
+        for (id, node) in self.nodes.iter().enumerate() {
+            if node.reductions == 0 && node.ops == 0 {
+                continue;
+            }
+            {
+                let own = functions.entry(node.function.clone()).or_default();
+                own.own_reductions += node.reductions;
+                own.own_ops += node.ops;
+            }
+
+            // Recursive functions are only charged once per reduction.
+            let mut seen = HashSet::new();
+            for parent in self.walk(id) {
+                if seen.insert(&parent.function) {
+                    functions.entry(parent.function.clone()).or_default()
+                        .inclusive_reductions += node.reductions;
+                }
+            }
+        }
+
+        functions
+    }
+
+    /// Writes the profile in the folded stack format understood by
+    /// `flamegraph.pl` and compatible tools, one stack per line.
+    pub fn write_folded(&self, weight: ProfileWeight, w: &mut dyn Write) -> io::Result<()> {
+        let mut lines: Vec<_> = self.stacks().iter()
+            .map(|(stack, reductions, ops)| {
+                let names: Vec<_> = stack.iter().map(|i| i.to_string()).collect();
+                let count = match weight {
+                    ProfileWeight::Reductions => *reductions,
+                    ProfileWeight::Ops => *ops,
+                };
+                (names.join(";"), count)
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        lines.sort();
+        for (stack, count) in lines {
+            write!(w, "{} {}\n", stack, count)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a table of all functions, hottest by inclusive
+    /// reductions first.
+    pub fn write_summary(&self, w: &mut dyn Write) -> io::Result<()> {
+        let functions = self.functions();
+        let mut rows: Vec<_> = functions.iter().collect();
+        rows.sort_by(|(i1, p1), (i2, p2)| {
+            p2.inclusive_reductions.cmp(&p1.inclusive_reductions)
+                .then_with(|| i1.to_string().cmp(&i2.to_string()))
+        });
+
+        write!(w, "{:>10} {:>10} {:>10} {:>10}  {}\n",
+               "calls", "own", "inclusive", "ops", "function")?;
+        for (ident, prof) in rows {
+            write!(w, "{:>10} {:>10} {:>10} {:>10}  {}\n",
+                   prof.calls, prof.own_reductions, prof.inclusive_reductions,
+                   prof.own_ops, ident)?;
+        }
+        Ok(())
+    }
+
+}
+
+/// Charges reductions and ops to the call stack they were executed
+/// under. A reduction is a single basic block, same as in the
+/// scheduler.
+///
+/// Clones share the same data, so a clone can be kept to read the
+/// profile after the sink has been handed to the VM.
+#[derive(Clone, Default)]
+pub struct ProfileSink {
+    data: Arc<Mutex<Profile>>,
+    /// Stack each process is currently running under.
+    current: Arc<Mutex<HashMap<Pid, StackId>>>,
+}
+
+impl ProfileSink {
+
+    pub fn new() -> Self {
+        ProfileSink::default()
+    }
+
+    pub fn profile(&self) -> MutexGuard<Profile> {
+        self.data.lock().unwrap()
+    }
+
+}
+
+impl TraceSink for ProfileSink {
+
+    fn event(&mut self, event: &TraceEvent) {
+        let mut current = self.current.lock().unwrap();
+        let mut data = self.data.lock().unwrap();
+        match event.kind {
+            TraceEventKind::FunctionEnter { ref ident, .. } => {
+                let parent = current.get(&event.pid).cloned();
+                let id = data.push(parent, ident);
+                current.insert(event.pid, id);
+                *data.calls.entry(ident.clone()).or_insert(0) += 1;
+            }
+            TraceEventKind::FunctionExit { ref ident, .. } => {
+                // Unwind to the exited function, so that a frame left
+                // without an exit of its own can not keep the stack
+                // growing.
+                let top = match current.get(&event.pid) {
+                    Some(top) => *top,
+                    None => return,
+                };
+                let exited = data.walk(top)
+                    .find(|node| node.function == *ident)
+                    .map(|node| node.parent);
+                match exited {
+                    Some(Some(parent)) => { current.insert(event.pid, parent); }
+                    Some(None) => { current.remove(&event.pid); }
+                    None => (),
+                }
+            }
+            TraceEventKind::Exit { .. } => {
+                current.remove(&event.pid);
+            }
+            TraceEventKind::BasicBlockStart { .. } => {
+                if let Some(id) = current.get(&event.pid) {
+                    data.nodes[*id].reductions += 1;
+                }
+            }
+            TraceEventKind::BasicBlockEnd { ops } => {
+                if let Some(id) = current.get(&event.pid) {
+                    data.nodes[*id].ops += ops as u64;
+                }
+            }
+            _ => (),
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ ProfileSink, ProfileWeight };
+    use ::trace::{ TraceSink, TraceEvent, TraceEventKind };
+    use ::{ Atom, FunctionIdent, Pid };
+    use ::eir::{ Function, FunctionBuilder, Ebb };
+
+    fn ident(name: &str) -> FunctionIdent {
+        FunctionIdent {
+            module: Atom::from_str("m"),
+            name: Atom::from_str(name),
+            arity: 0,
+            lambda: None,
+        }
+    }
+
+    fn event(kind: TraceEventKind) -> TraceEvent {
+        TraceEvent {
+            pid: Pid(0),
+            module: Some(Atom::from_str("m")),
+            kind: kind,
+        }
+    }
+
+    fn enter(sink: &mut ProfileSink, name: &str) {
+        sink.event(&event(TraceEventKind::FunctionEnter {
+            ident: ident(name),
+            args: vec![],
+        }));
+    }
+
+    fn exit(sink: &mut ProfileSink, name: &str) {
+        sink.event(&event(TraceEventKind::FunctionExit {
+            ident: ident(name),
+            ret: None,
+        }));
+    }
+
+    fn label() -> Ebb {
+        let mut fun = Function::new(ident("label"));
+        let mut b = FunctionBuilder::new(&mut fun);
+        b.insert_ebb_entry()
+    }
+
+    /// A reduction running `ops` ops.
+    fn block(sink: &mut ProfileSink, name: &str, ops: usize) {
+        sink.event(&event(TraceEventKind::BasicBlockStart {
+            ident: ident(name),
+            block: label(),
+        }));
+        sink.event(&event(TraceEventKind::BasicBlockEnd { ops: ops }));
+    }
+
+    #[test]
+    fn ops_are_charged_to_stack() {
+        let mut sink = ProfileSink::new();
+        enter(&mut sink, "a");
+        block(&mut sink, "a", 2);
+        enter(&mut sink, "b");
+        block(&mut sink, "b", 3);
+        exit(&mut sink, "b");
+        block(&mut sink, "a", 1);
+        exit(&mut sink, "a");
+
+        let profile = sink.profile();
+        let functions = profile.functions();
+        assert!(functions[&ident("a")].own_ops == 3);
+        assert!(functions[&ident("b")].own_ops == 3);
+        assert!(functions[&ident("b")].calls == 1);
+
+        let mut folded = Vec::new();
+        profile.write_folded(ProfileWeight::Ops, &mut folded).unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        assert!(folded == "m:a/0 3\nm:a/0;m:b/0 3\n");
+    }
+
+    #[test]
+    fn recursion_is_charged_once() {
+        let mut sink = ProfileSink::new();
+        enter(&mut sink, "a");
+        block(&mut sink, "a", 1);
+        enter(&mut sink, "a");
+        block(&mut sink, "a", 1);
+        enter(&mut sink, "b");
+        block(&mut sink, "b", 1);
+        block(&mut sink, "b", 1);
+        exit(&mut sink, "b");
+        exit(&mut sink, "a");
+        block(&mut sink, "a", 1);
+        exit(&mut sink, "a");
+
+        let profile = sink.profile();
+        let functions = profile.functions();
+        let a = &functions[&ident("a")];
+        assert!(a.calls == 2);
+        assert!(a.own_reductions == 3);
+        assert!(a.inclusive_reductions == 5);
+        let b = &functions[&ident("b")];
+        assert!(b.own_reductions == 2);
+        assert!(b.inclusive_reductions == 2);
+
+        let mut folded = Vec::new();
+        profile.write_folded(ProfileWeight::Reductions, &mut folded).unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        assert!(folded == "m:a/0 2\nm:a/0;m:a/0 1\nm:a/0;m:a/0;m:b/0 2\n");
+
+        let mut summary = Vec::new();
+        profile.write_summary(&mut summary).unwrap();
+        let summary = String::from_utf8(summary).unwrap();
+        assert!(summary == "     calls        own  inclusive        ops  function
+         2          3          5          3  m:a/0
+         1          2          2          2  m:b/0
+");
+    }
+
+    #[test]
+    fn exit_unwinds_frames_without_exit() {
+        let mut sink = ProfileSink::new();
+        enter(&mut sink, "a");
+        enter(&mut sink, "b");
+        // `b` never exits, leaving `a` has to drop it as well
+        exit(&mut sink, "a");
+        enter(&mut sink, "c");
+        block(&mut sink, "c", 1);
+        exit(&mut sink, "c");
+
+        let mut folded = Vec::new();
+        sink.profile().write_folded(ProfileWeight::Reductions, &mut folded).unwrap();
+        assert!(String::from_utf8(folded).unwrap() == "m:c/0 1\n");
+    }
+
+}
@@ -1,11 +1,21 @@
 use std::collections::HashMap;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::{ Arc, Mutex, MutexGuard, RwLock };
+use std::sync::atomic::{ AtomicBool, Ordering };
 
 use ::{ Atom, Module, FunctionIdent };
 use ::module::{ NativeModule, ModuleType };
-use ::process::{ ProcessContext, CallReturn };
+use ::code::{ CodeTable, LoadError };
+use ::process::{ ProcessContext, CallReturn, ProcessInfo, ProcessStatus };
 use ::term::{ Term, Pid, Reference };
+use ::debugger::Debugger;
+use ::timer::Timers;
+use ::filesystem::{ Filesystem, MemoryFilesystem, OpenFile };
+use ::trace::{ Tracer, TraceSink, TraceFilter };
+use ::term::{ ErlEq, ErlExactEq };
+use ::dist::NodeState;
+use ::heap::{ HeapAccounting, WORD_SIZE };
+use ::scheduler::Workers;
+use ::mailbox::Mailbox;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum WatchType {
@@ -29,40 +39,210 @@ impl ReferenceGenerator {
 
 }
 
+/// Destination of everything written through the `io` module. Output
+/// goes to stdout unless capturing is enabled.
+#[derive(Debug, Default)]
+pub struct OutputBuffer {
+    capture: bool,
+    captured: String,
+}
+impl OutputBuffer {
+
+    pub fn set_capture(&mut self, capture: bool) {
+        self.capture = capture;
+    }
+
+    pub fn write(&mut self, text: &str) {
+        if self.capture {
+            self.captured.push_str(text);
+        } else {
+            print!("{}", text);
+        }
+    }
+
+    /// Returns and clears everything captured so far.
+    pub fn take(&mut self) -> String {
+        ::std::mem::replace(&mut self.captured, String::new())
+    }
+
+}
+
+/// How `run_round` runs the processes of the VM.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SchedulerMode {
+    /// Processes run one after the other on the calling thread, always
+    /// in the same order. Runs are reproducible, which is what tests
+    /// want.
+    Deterministic,
+    /// Processes are spread over the given number of worker threads,
+    /// which are kept for all the rounds of a `run_rounds` call. A
+    /// process stays with its worker from round to round, unless a
+    /// worker that ran out of processes steals it.
+    Threads(usize),
+}
+
 pub struct VMState {
-    pub modules: HashMap<String, ModuleType>,
-    pub processes: RefCell<Vec<Rc<RefCell<ProcessContext>>>>,
+    pub modules: RwLock<CodeTable>,
+    pub processes: RwLock<Vec<Arc<Mutex<ProcessContext>>>>,
+
+    pub scheduler: SchedulerMode,
+    workers: Option<Workers>,
+
+    pub ref_gen: Mutex<ReferenceGenerator>,
+
+    /// Hashmap of all watches placed on a process. The watcher is a
+    /// `Term::Pid`, or a `Term::RemotePid` for watches from other nodes.
+    pub watches: Mutex<HashMap<Pid, Vec<(Term, WatchType)>>>,
+
+    pub registered: Mutex<HashMap<Atom, Pid>>,
+
+    /// Processes to be killed by an exit signal, along with the reason.
+    /// They are killed by the scheduler between process runs.
+    pending_kills: Mutex<Vec<(Pid, Term)>>,
 
-    pub ref_gen: RefCell<ReferenceGenerator>,
+    /// Mailbox of every process, indexed by pid like `processes`. Each
+    /// one has its own lock, so sends to different processes don't
+    /// contend.
+    mailboxes: RwLock<Vec<Arc<Mutex<Mailbox>>>>,
 
-    /// Hashmap of all watches a process has placed on it.
-    pub watches: RefCell<HashMap<Pid, Vec<(Pid, WatchType)>>>,
+    pub debugger: Mutex<Option<Debugger>>,
 
-    pub mailboxes: RefCell<HashMap<Pid, ::mailbox::Mailbox>>,
+    pub output: Mutex<OutputBuffer>,
+
+    pub filesystem: Mutex<Box<dyn Filesystem>>,
+    pub open_files: Mutex<HashMap<Reference, OpenFile>>,
+
+    pub timers: Mutex<Timers>,
+
+    pub heap: Mutex<HeapAccounting>,
+
+    pub tracer: Arc<Mutex<Tracer>>,
+    /// Set once a trace sink is added. Checked before locking the
+    /// tracer, so processes don't contend on it when nothing traces.
+    tracing: AtomicBool,
+
+    /// Set when the VM is a node in a `Cluster`.
+    pub(crate) dist: Mutex<Option<NodeState>>,
 }
 
 impl VMState {
 
     pub fn new() -> Self {
         VMState {
-            modules: HashMap::new(),
-            processes: RefCell::new(Vec::new()),
-            ref_gen: RefCell::new(ReferenceGenerator::new()),
-            watches: RefCell::new(HashMap::new()),
-            mailboxes: RefCell::new(HashMap::new()),
+            modules: RwLock::new(CodeTable::new()),
+            processes: RwLock::new(Vec::new()),
+            scheduler: SchedulerMode::Deterministic,
+            workers: None,
+            ref_gen: Mutex::new(ReferenceGenerator::new()),
+            watches: Mutex::new(HashMap::new()),
+            registered: Mutex::new(HashMap::new()),
+            pending_kills: Mutex::new(Vec::new()),
+            mailboxes: RwLock::new(Vec::new()),
+            debugger: Mutex::new(None),
+            output: Mutex::new(OutputBuffer::default()),
+            filesystem: Mutex::new(Box::new(MemoryFilesystem::new())),
+            open_files: Mutex::new(HashMap::new()),
+            timers: Mutex::new(Timers::new()),
+            heap: Mutex::new(HeapAccounting::new()),
+            tracer: Arc::new(Mutex::new(Tracer::new())),
+            tracing: AtomicBool::new(false),
+            dist: Mutex::new(None),
         }
     }
 
+    pub fn add_trace_sink(&mut self, filter: TraceFilter, sink: Box<dyn TraceSink>) {
+        self.tracer.lock().unwrap().add_sink(filter, sink);
+        self.tracing.store(true, Ordering::Relaxed);
+    }
+
+    /// The tracer, if any trace sink is registered.
+    pub(crate) fn active_tracer(&self) -> Option<MutexGuard<Tracer>> {
+        if self.tracing.load(Ordering::Relaxed) {
+            Some(self.tracer.lock().unwrap())
+        } else {
+            None
+        }
+    }
+
+    pub fn flush_trace(&self) {
+        self.tracer.lock().unwrap().flush();
+    }
+
+    pub fn set_scheduler(&mut self, mode: SchedulerMode) {
+        self.workers = match mode {
+            SchedulerMode::Deterministic => None,
+            SchedulerMode::Threads(threads) => {
+                let workers = Workers::new(threads);
+                for pid in self.live_processes() {
+                    workers.add(pid);
+                }
+                Some(workers)
+            }
+        };
+        self.scheduler = mode;
+    }
+
+    /// Limits the total size of the heaps of all processes, in words.
+    /// When the total grows past the limit, the process with the
+    /// largest heap is killed, whichever process caused the growth.
+    pub fn set_memory_limit(&mut self, words: Option<usize>) {
+        self.heap.lock().unwrap().set_limit(words);
+    }
+
+    /// Approximate memory used by all processes, in bytes.
+    pub fn memory_used(&self) -> usize {
+        self.heap.lock().unwrap().total() * WORD_SIZE
+    }
+
+    /// Approximate memory used by the process, in bytes. `current` is
+    /// the calling process when called from a native function. A process
+    /// running on another scheduler thread reports the size measured at
+    /// the end of its last reduction.
+    pub fn process_memory(&self, current: Option<&ProcessContext>, pid: Pid) -> Option<usize> {
+        if !self.is_alive(pid) {
+            return None;
+        }
+        let words = match current {
+            Some(current) if current.pid == pid => Some(current.heap_words(self)),
+            _ => match self.process(pid)?.try_lock() {
+                Ok(process) => Some(process.heap_words(self)),
+                Err(_) => self.heap.lock().unwrap().size(pid),
+            },
+        };
+        Some(words.unwrap_or(0) * WORD_SIZE)
+    }
+
+    pub fn set_filesystem(&mut self, filesystem: Box<dyn Filesystem>) {
+        *self.filesystem.lock().unwrap() = filesystem;
+    }
+
+    pub fn attach_debugger(&mut self, debugger: Debugger) {
+        *self.debugger.lock().unwrap() = Some(debugger);
+    }
+
+    pub fn detach_debugger(&mut self) -> Option<Debugger> {
+        self.debugger.lock().unwrap().take()
+    }
+
+    /// Loads the module as its new current version. Any old code for
+    /// the module is purged first, killing the processes running it.
     pub fn add_erlang_module(&mut self, module: Module) {
-        self.modules.insert(module.name.to_string(), ModuleType::Erlang(module, None));
+        let name = module.name.to_string();
+        self.purge_module(None, &name);
+        self.load_module(&name, ModuleType::Erlang(module, None)).unwrap();
     }
 
     pub fn add_native_module(&mut self, module: NativeModule) {
-        self.modules.insert(module.name.clone(), ModuleType::Native(module));
+        let name = module.name.clone();
+        self.purge_module(None, &name);
+        self.load_module(&name, ModuleType::Native(module)).unwrap();
     }
 
     pub fn add_nif_overlay(&mut self, module: NativeModule) {
-        let existing = self.modules.get_mut(&module.name).unwrap();
+        let mut code = self.modules.write().unwrap();
+        let existing = code.current_mut(&module.name).unwrap();
+        let existing = Arc::get_mut(&mut existing.module)
+            .expect("nif overlay added to module in use");
         if let ModuleType::Erlang(_, ref mut overlay) = existing {
             assert!(overlay.is_none());
             *overlay = Some(module);
@@ -71,66 +251,621 @@ impl VMState {
         }
     }
 
-    pub fn call(&mut self, module_name: &str, fun_name: &str, args: Vec<Term>)
-                -> CallReturn {
-        let fun_ident = FunctionIdent {
-            module: Atom::from_str(module_name),
-            name: Atom::from_str(fun_name),
-            arity: args.len(),
-            lambda: None,
+    /// Loads a new version of the module, which fully qualified calls
+    /// will go to from now on. Fails if old code for the module has not
+    /// been purged yet.
+    pub fn load_module(&self, name: &str, module: ModuleType) -> Result<u64, LoadError> {
+        self.modules.write().unwrap().load(name, module)
+    }
+
+    pub(crate) fn process(&self, pid: Pid) -> Option<Arc<Mutex<ProcessContext>>> {
+        self.processes.read().unwrap().get(pid.0).cloned()
+    }
+
+    pub(crate) fn mailbox(&self, pid: Pid) -> Option<Arc<Mutex<Mailbox>>> {
+        self.mailboxes.read().unwrap().get(pid.0).cloned()
+    }
+
+    /// Pids of the processes with the given module version on their
+    /// stack. `current` is the calling process when called from a
+    /// native function, as it is locked by the scheduler. Processes
+    /// running on other scheduler threads are skipped, they notice
+    /// the purge themselves.
+    fn processes_running(&self, current: Option<&ProcessContext>,
+                         module: &str, version: u64) -> Vec<Pid> {
+        let mut pids = Vec::new();
+        if let Some(current) = current {
+            if current.runs_code(module, version) {
+                pids.push(current.pid);
+            }
+        }
+        for process in self.processes.read().unwrap().iter() {
+            if let Ok(process) = process.try_lock() {
+                if process.runs_code(module, version) {
+                    pids.push(process.pid);
+                }
+            }
+        }
+        pids
+    }
+
+    /// Removes the old code for the module, killing every process that
+    /// is still running it. Returns `None` if there was no old code,
+    /// otherwise whether any process was killed.
+    pub fn purge_module(&self, mut current: Option<&mut ProcessContext>,
+                        module: &str) -> Option<bool> {
+        let version = self.modules.read().unwrap().old(module)?.version;
+        let pids = self.processes_running(current.as_ref().map(|c| &**c), module, version);
+        for pid in pids.iter() {
+            let is_current = current.as_ref().map(|c| c.pid == *pid).unwrap_or(false);
+            if is_current {
+                current.as_mut().unwrap().kill(self, Term::new_atom("killed"));
+            } else {
+                let process = self.process(*pid).unwrap();
+                // Started running on another thread since it was
+                // inspected, it is killed once its slice is over.
+                match process.try_lock() {
+                    Ok(mut process) => process.kill(self, Term::new_atom("killed")),
+                    Err(_) => self.pending_kills.lock().unwrap()
+                        .push((*pid, Term::new_atom("killed"))),
+                }
+            }
+        }
+        self.modules.write().unwrap().purge(module);
+        Some(pids.len() > 0)
+    }
+
+    /// Removes the old code for the module unless a process is still
+    /// running it. Returns false if the code is in use.
+    pub fn soft_purge_module(&self, current: Option<&ProcessContext>,
+                             module: &str) -> bool {
+        let version = match self.modules.read().unwrap().old(module) {
+            Some(old) => old.version,
+            None => return true,
         };
+        if self.processes_running(current, module, version).len() > 0 {
+            return false;
+        }
+        self.modules.write().unwrap().purge(module);
+        true
+    }
 
-        let self_pid = {
-            let processes = self.processes.borrow();
-            Pid(processes.len())
+    /// Name of the node, `nonode@nohost` unless the VM is part of a
+    /// `Cluster`.
+    pub fn node_name(&self) -> Atom {
+        match *self.dist.lock().unwrap() {
+            Some(ref dist) => dist.name.clone(),
+            None => Atom::from_str("nonode@nohost"),
+        }
+    }
+
+    /// Whether the process exists and has not exited. The process that
+    /// is currently running counts as alive.
+    pub fn is_alive(&self, pid: Pid) -> bool {
+        match self.process(pid) {
+            Some(process) => match process.try_lock() {
+                Ok(process) => process.stack.lock().unwrap().len() > 0,
+                Err(_) => true,
+            },
+            None => false,
+        }
+    }
+
+    /// Pids of all live processes.
+    pub fn live_processes(&self) -> Vec<Pid> {
+        let len = self.processes.read().unwrap().len();
+        (0..len).map(Pid).filter(|pid| self.is_alive(*pid)).collect()
+    }
+
+    /// Information about a live process, see `ProcessInfo`.
+    pub fn process_info(&self, pid: Pid) -> Option<ProcessInfo> {
+        self.collect_process_info(None, pid)
+    }
+
+    /// `current` is the calling process when called from a native
+    /// function.
+    pub(crate) fn collect_process_info(&self, current: Option<&ProcessContext>,
+                                       pid: Pid) -> Option<ProcessInfo> {
+        if !self.is_alive(pid) {
+            return None;
+        }
+
+        let (messages, trap_exit) = {
+            let mailbox = self.mailbox(pid)?;
+            let mailbox = mailbox.lock().unwrap();
+            let messages = mailbox.messages().iter().map(|(_, m)| m.clone()).collect();
+            (messages, mailbox.get_trap_exits())
+        };
+
+        let (links, mut monitors) = {
+            let watches = self.watches.lock().unwrap();
+            let links: Vec<Term> = watches.get(&pid).into_iter()
+                .flat_map(|w| w.iter())
+                .filter(|(_, kind)| *kind == WatchType::Link)
+                .map(|(watcher, _)| watcher.clone())
+                .collect();
+            let monitors: Vec<Term> = watches.iter()
+                .flat_map(|(target, w)| w.iter().map(move |entry| (*target, entry)))
+                .filter(|(_, (watcher, kind))| {
+                    *kind != WatchType::Link && watcher.erl_exact_eq(&Term::Pid(pid))
+                })
+                .map(|(target, _)| Term::Pid(target))
+                .collect();
+            (links, monitors)
         };
-        ::trace::set_pid(self_pid);
+        monitors.extend(::dist::remote_monitors(self, pid));
 
-        let process = ProcessContext::new(self_pid);
+        let registered_name = self.registered.lock().unwrap().iter()
+            .find(|(_, p)| **p == pid)
+            .map(|(name, _)| name.clone());
+        let memory = self.process_memory(current, pid).unwrap_or(0);
+
+        let local = |process: &ProcessContext, status: ProcessStatus| {
+            (status, process.current_function(), process.reductions,
+             process.dictionary().to_vec())
+        };
+        let (status, current_function, reductions, dictionary) = match current {
+            Some(current) if current.pid == pid => local(current, ProcessStatus::Running),
+            _ => match self.process(pid)?.try_lock() {
+                Ok(process) => local(&*process, process.status()),
+                Err(_) => (ProcessStatus::Running, None, 0, Vec::new()),
+            },
+        };
+
+        Some(ProcessInfo {
+            pid: pid,
+            status: status,
+            messages: messages,
+            links: links,
+            monitors: monitors,
+            current_function: current_function,
+            registered_name: registered_name,
+            reductions: reductions,
+            dictionary: dictionary,
+            trap_exit: trap_exit,
+            memory: memory,
+        })
+    }
+
+    pub fn whereis(&self, name: &Atom) -> Option<Pid> {
+        self.registered.lock().unwrap().get(name).cloned()
+    }
+
+    /// Places a message in the mailbox of a local process. Messages to
+    /// processes that do not exist are dropped.
+    pub fn deliver_message(&self, from: Term, to: Pid, message: Term) {
+        if let Term::Pid(from_pid) = from {
+            if let Some(mut tracer) = self.active_tracer() {
+                tracer.message_send(from_pid, to, &message);
+            }
+        }
+        if let Some(mailbox) = self.mailbox(to) {
+            mailbox.lock().unwrap().push(from, message);
+        }
+    }
 
+    /// Sends an exit signal over a link. A process trapping exits gets
+    /// it as an `{'EXIT', From, Reason}` message, any other process is
+    /// killed unless the reason is `normal`.
+    pub fn signal_exit(&self, to: Pid, from: Term, reason: Term) {
+        let trap_exits = match self.mailbox(to) {
+            Some(mailbox) => mailbox.lock().unwrap().get_trap_exits(),
+            None => return,
+        };
+        if trap_exits {
+            let message = Term::Tuple(vec![Term::new_atom("EXIT"), from.clone(), reason]);
+            self.deliver_message(from, to, message);
+        } else if !reason.erl_eq(&Term::new_atom("normal")) {
+            self.pending_kills.lock().unwrap().push((to, reason));
+        }
+    }
+
+    /// Sends the `{'DOWN', Ref, process, From, Reason}` message of a
+    /// monitor.
+    pub fn signal_down(&self, to: Pid, reference: Term, from: Term, reason: Term) {
+        let message = Term::Tuple(vec![
+            Term::new_atom("DOWN"),
+            reference,
+            Term::new_atom("process"),
+            from.clone(),
+            reason,
+        ]);
+        self.deliver_message(from, to, message);
+    }
+
+    /// Notifies the links and monitors of a process that exited, and
+    /// drops its registered name.
+    pub(crate) fn process_exited(&self, pid: Pid, reason: Term) {
+        self.registered.lock().unwrap().retain(|_, p| *p != pid);
+        self.heap.lock().unwrap().remove(pid);
+
+        let watchers = self.watches.lock().unwrap().remove(&pid).unwrap_or_default();
+        for (watcher, kind) in watchers {
+            match (watcher, kind) {
+                (Term::Pid(watcher), WatchType::Link) => {
+                    if let Some(links) = self.watches.lock().unwrap().get_mut(&watcher) {
+                        links.retain(|(p, k)| {
+                            *k != WatchType::Link || !p.erl_exact_eq(&Term::Pid(pid))
+                        });
+                    }
+                    self.signal_exit(watcher, Term::Pid(pid), reason.clone());
+                }
+                (Term::Pid(watcher), WatchType::Monitor(reference)) => {
+                    self.signal_down(watcher, Term::Reference(reference),
+                                     Term::Pid(pid), reason.clone());
+                }
+                (remote, kind) => ::dist::send_exit(self, pid, &remote, kind, &reason),
+            }
+        }
+
+        ::dist::forget_process(self, pid);
+    }
+
+    /// Kills the process with the given reason between process runs.
+    pub(crate) fn kill_later(&self, pid: Pid, reason: Term) {
+        self.pending_kills.lock().unwrap().push((pid, reason));
+    }
+
+    /// Kills the processes that were sent an exit signal.
+    pub(crate) fn handle_kills(&self) -> bool {
+        let mut any = false;
+        loop {
+            let next = self.pending_kills.lock().unwrap().pop();
+            match next {
+                Some((pid, reason)) => {
+                    let process = self.process(pid).unwrap();
+                    process.lock().unwrap().kill(self, reason);
+                    any = true;
+                }
+                None => return any,
+            }
+        }
+    }
+
+    /// Creates a process calling the given function, and returns its
+    /// pid. `parent` is the spawning process, if any.
+    pub(crate) fn spawn_process(&self, parent: Option<Pid>, ident: &FunctionIdent,
+                                version: Option<u64>, args: Vec<Term>) -> Pid {
+        let mut processes = self.processes.write().unwrap();
+        let pid = Pid(processes.len());
+        if let Some(parent) = parent {
+            if let Some(mut tracer) = self.active_tracer() {
+                tracer.spawn(parent, pid, ident);
+            }
+        }
+
+        let process = ProcessContext::new(pid);
         let frame = process.make_call_stackframe(
             self,
-            Atom::from_str(module_name),
-            fun_ident,
+            ident.module.clone(),
+            ident.clone(),
+            version,
             args
         );
+        process.stack.lock().unwrap().push(frame);
 
-        {
-            let mut processes = self.processes.borrow_mut();
-            {
-                let mut stack = process.stack.borrow_mut();
-                stack.push(frame);
+        self.mailboxes.write().unwrap().push(Arc::new(Mutex::new(Mailbox::new())));
+        processes.push(Arc::new(Mutex::new(process)));
+        if let Some(ref workers) = self.workers {
+            workers.add(pid);
+        }
+        pid
+    }
+
+    /// Starts a process calling `module_name:fun_name(args)`. It runs
+    /// as part of `run_round`, and its result can be taken with
+    /// `take_result` once it finished.
+    pub fn start(&self, module_name: &str, fun_name: &str, args: Vec<Term>) -> Pid {
+        let fun_ident = FunctionIdent {
+            module: Atom::from_str(module_name),
+            name: Atom::from_str(fun_name),
+            arity: args.len(),
+            lambda: None,
+        };
+
+        self.spawn_process(None, &fun_ident, None, args)
+    }
+
+    pub fn is_finished(&self, pid: Pid) -> bool {
+        self.process(pid).unwrap().lock().unwrap().stack.lock().unwrap().len() == 0
+    }
+
+    /// Takes the result of a finished process.
+    pub fn take_result(&self, pid: Pid) -> Option<CallReturn> {
+        self.process(pid).unwrap().lock().unwrap().return_val.take()
+    }
+
+    /// Gives the process a slice of reductions if it is runnable.
+    /// Returns true if it used up the slice without blocking.
+    pub(crate) fn run_process(&self, pid: Pid) -> bool {
+        let process = self.process(pid).unwrap();
+        let mut process = process.lock().unwrap();
+        let now = self.timers.lock().unwrap().now();
+        if !process.is_runnable(now) {
+            return false;
+        }
+
+        println!("=====================================");
+        println!("======== SWITCH TO PROCESS {} ========", pid.0);
+        println!("=====================================");
+
+        !process.run_reductions(self, 4000)
+    }
+
+    /// Gives every runnable process a slice of reductions. Returns true
+    /// if all of them blocked. Processes spawned during the round first
+    /// run in the next one.
+    pub fn run_round(&self) -> bool {
+        let mut all_blocked = true;
+        self.run_rounds(|blocked| {
+            all_blocked = blocked;
+            false
+        });
+        all_blocked
+    }
+
+    /// Runs rounds until `between_rounds` returns false. It is told
+    /// after every round whether all processes blocked. The threaded
+    /// scheduler keeps the same worker threads for all of the rounds.
+    pub fn run_rounds<F>(&self, mut between_rounds: F) where F: FnMut(bool) -> bool {
+        match self.scheduler {
+            SchedulerMode::Deterministic => {
+                loop {
+                    if !between_rounds(self.run_round_deterministic()) {
+                        break;
+                    }
+                }
+            }
+            SchedulerMode::Threads(_) => {
+                let mut killed = self.handle_kills();
+                self.workers.as_ref().unwrap().run_rounds(self, |progress| {
+                    if !between_rounds(!killed && !progress) {
+                        return false;
+                    }
+                    killed = self.handle_kills();
+                    true
+                });
             }
-            processes.push(Rc::new(RefCell::new(process)));
         }
-        {
-            let mut mailboxes = self.mailboxes.borrow_mut();
-            mailboxes.insert(self_pid, ::mailbox::Mailbox::new());
+    }
+
+    fn run_round_deterministic(&self) -> bool {
+        let mut all_blocked = !self.handle_kills();
+
+        let processes_len = self.processes.read().unwrap().len();
+        for process_num in 0..processes_len {
+            if self.run_process(Pid(process_num)) {
+                all_blocked = false;
+            }
+            if self.handle_kills() {
+                all_blocked = false;
+            }
         }
 
-        loop {
-            let processes_len = self.processes.borrow().len();
-            for process_num in 0..processes_len {
-                println!("=====================================");
-                println!("======== SWITCH TO PROCESS {} ========", process_num);
-                println!("=====================================");
-                ::trace::set_pid(Pid(process_num));
+        all_blocked
+    }
+
+    /// Virtual time of the next timer, sleeping process or receive
+    /// timeout.
+    pub fn next_wakeup(&self) -> Option<u64> {
+        // A process sleeps in a native function, so it never waits in
+        // a receive at the same time.
+        let next_wake = self.processes.read().unwrap().iter()
+            .filter_map(|p| {
+                let p = p.lock().unwrap();
+                p.sleep_until.or(p.receive_deadline)
+            })
+            .filter(|t| *t != ::std::u64::MAX)
+            .min();
+        let next_timer = self.timers.lock().unwrap().next_deadline();
+        match (next_wake, next_timer) {
+            (Some(wake), Some(timer)) => Some(::std::cmp::min(wake, timer)),
+            (Some(time), None) | (None, Some(time)) => Some(time),
+            (None, None) => None,
+        }
+    }
 
-                let process_rc = self.processes.borrow()[process_num].clone();
-                let mut process = process_rc.borrow_mut();
-                process.run_reductions(self, 4000);
+    /// Moves the virtual clock forward to `time`, and delivers every
+    /// timer that fired.
+    pub fn advance_clock_to(&self, time: u64) {
+        let now = self.timers.lock().unwrap().now();
+        let fired = self.timers.lock().unwrap().advance_to(::std::cmp::max(time, now));
+        for timer in fired {
+            self.deliver_message(Term::Pid(timer.owner), timer.dest, timer.message);
+        }
+    }
+
+    /// Moves the virtual clock to the next timer or sleeping process.
+    /// Returns false if there was nothing to wait for.
+    fn advance_clock(&self) -> bool {
+        match self.next_wakeup() {
+            Some(time) => {
+                self.advance_clock_to(time);
+                true
             }
+            None => false,
+        }
+    }
+
+    pub fn call(&mut self, module_name: &str, fun_name: &str, args: Vec<Term>)
+                -> CallReturn {
+        let pid = self.start(module_name, fun_name, args);
+
+        self.run_rounds(|all_blocked| {
+            if self.is_finished(pid) {
+                return false;
+            }
+            if all_blocked {
+                self.advance_clock();
+            }
+            true
+        });
+
+        self.flush_trace();
+        self.take_result(pid).unwrap()
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+
+    use super::{ VMState, SchedulerMode, WatchType };
+    use ::heap::WORD_SIZE;
+    use ::module::NativeModule;
+    use ::process::{ ProcessContext, CallReturn, ProcessStatus };
+    use ::term::{ Term, Pid, ErlExactEq };
+    use ::Atom;
+
+    /// Puts a list of the given length in the dictionary, and sleeps.
+    fn grow(_vm: &VMState, proc: &mut ProcessContext, args: &[Term]) -> CallReturn {
+        let len = args[0].as_usize().unwrap();
+        let list = Term::List(vec![Term::new_i64(1); len], Box::new(Term::Nil));
+        proc.put_dictionary(Term::new_atom("data"), list);
+        proc.sleep_until = Some(::std::u64::MAX);
+        CallReturn::Return { term: Term::new_atom("ok") }
+    }
+
+    fn make_vm(mode: SchedulerMode, counter: Arc<AtomicUsize>) -> VMState {
+        let mut vm = VMState::new();
+        vm.set_scheduler(mode);
+        vm.add_native_module(::erl_lib::make_erlang());
+        vm.add_native_module(::erl_lib::make_timer());
+
+        let mut module = NativeModule::new("work".to_string());
+        module.add_fun("bump".to_string(), 0, Box::new(
+            move |_vm: &VMState, _proc: &mut ProcessContext, _args: &[Term]| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                CallReturn::Return { term: Term::new_atom("ok") }
+            }));
+        module.add_fun("grow".to_string(), 1, Box::new(grow));
+        vm.add_native_module(module);
+        vm
+    }
 
-            let processes_borrow = self.processes.borrow();
-            let process_borrow = processes_borrow[self_pid.0].borrow();
-            if process_borrow.stack.borrow().len() == 0 {
-                break;
+    #[test]
+    fn modes_agree() {
+        for mode in [SchedulerMode::Deterministic, SchedulerMode::Threads(4)].iter() {
+            let counter = Arc::new(AtomicUsize::new(0));
+            let mut vm = make_vm(*mode, counter.clone());
+
+            let workers: Vec<_> = (0..32)
+                .map(|_| vm.start("work", "bump", vec![]))
+                .collect();
+            let sleepers: Vec<_> = (0..8)
+                .map(|n| vm.start("timer", "sleep", vec![Term::new_i64(n * 20)]))
+                .collect();
+            vm.call("timer", "sleep", vec![Term::new_i64(100)]);
+
+            assert!(counter.load(Ordering::SeqCst) == 32);
+            assert!(workers.iter().all(|pid| vm.is_finished(*pid)));
+            for (n, pid) in sleepers.iter().enumerate() {
+                assert!(vm.is_finished(*pid) == (n * 20 <= 100));
             }
+            assert!(vm.timers.lock().unwrap().now() == 100);
         }
+    }
+
+    #[test]
+    fn max_heap_size() {
+        let vm = make_vm(SchedulerMode::Deterministic, Arc::new(AtomicUsize::new(0)));
+        let small = vm.start("work", "grow", vec![Term::new_i64(10)]);
+        let big = vm.start("work", "grow", vec![Term::new_i64(1000)]);
+        vm.process(big).unwrap().lock().unwrap().max_heap_size = Some(500);
+        vm.run_round();
+
+        assert!(vm.is_alive(small));
+        assert!(!vm.is_alive(big));
+        assert!(vm.process_memory(None, small).unwrap() >= 20 * WORD_SIZE);
+        assert!(vm.process_memory(None, big).is_none());
+    }
+
+    #[test]
+    fn memory_limit() {
+        let mut vm = make_vm(SchedulerMode::Deterministic, Arc::new(AtomicUsize::new(0)));
+        vm.set_memory_limit(Some(1000));
+        let first = vm.start("work", "grow", vec![Term::new_i64(300)]);
+        let second = vm.start("work", "grow", vec![Term::new_i64(300)]);
+        vm.run_round();
+
+        assert!(vm.is_alive(first));
+        assert!(!vm.is_alive(second));
+        assert!(vm.memory_used() == vm.process_memory(None, first).unwrap());
+    }
+
+    #[test]
+    fn memory_limit_kills_largest() {
+        let mut vm = make_vm(SchedulerMode::Deterministic, Arc::new(AtomicUsize::new(0)));
+        vm.set_memory_limit(Some(1000));
+        let big = vm.start("work", "grow", vec![Term::new_i64(400)]);
+        let small = vm.start("work", "grow", vec![Term::new_i64(150)]);
+        vm.run_round();
+
+        // The small process pushed the total over the limit
+        assert!(!vm.is_alive(big));
+        assert!(vm.is_alive(small));
+        assert!(vm.memory_used() == vm.process_memory(None, small).unwrap());
+    }
+
+    #[test]
+    fn process_info() {
+        let vm = make_vm(SchedulerMode::Deterministic, Arc::new(AtomicUsize::new(0)));
+        let pid = vm.start("work", "grow", vec![Term::new_i64(2)]);
+        let other = vm.start("work", "grow", vec![Term::new_i64(2)]);
+        vm.run_round();
+
+        vm.registered.lock().unwrap().insert(Atom::from_str("grower"), pid);
+        vm.deliver_message(Term::Pid(other), pid, Term::new_atom("hello"));
+        {
+            let mut watches = vm.watches.lock().unwrap();
+            watches.entry(other).or_insert_with(Vec::new)
+                .push((Term::Pid(pid), WatchType::Link));
+            watches.entry(pid).or_insert_with(Vec::new)
+                .push((Term::Pid(other), WatchType::Link));
+        }
+
+        let info = vm.process_info(pid).unwrap();
+        assert!(info.status == ProcessStatus::Waiting);
+        assert!(info.message_queue_len() == 1);
+        assert!(info.links.len() == 1 && info.links[0].erl_exact_eq(&Term::Pid(other)));
+        assert!(info.monitors.len() == 0);
+        assert!(info.registered_name == Some(Atom::from_str("grower")));
+        assert!(info.current_function.unwrap().name.as_str() == "grow");
+        assert!(info.reductions == 1);
+        assert!(info.dictionary.len() == 1);
+        assert!(!info.trap_exit);
+    }
+
+    #[test]
+    fn process_info_bif() {
+        let mut vm = make_vm(SchedulerMode::Deterministic, Arc::new(AtomicUsize::new(0)));
+        let pid = vm.start("work", "grow", vec![Term::new_i64(2)]);
+        vm.run_round();
+        vm.deliver_message(Term::Pid(pid), pid, Term::new_atom("hello"));
+
+        let items = Term::List(vec![Term::new_atom("status"), Term::new_atom("messages")],
+                               Box::new(Term::Nil));
+        let ret = vm.call("erlang", "process_info", vec![Term::Pid(pid), items]);
+        let expected = Term::List(vec![
+            Term::Tuple(vec![Term::new_atom("status"), Term::new_atom("waiting")]),
+            Term::Tuple(vec![
+                Term::new_atom("messages"),
+                Term::List(vec![Term::new_atom("hello")], Box::new(Term::Nil)),
+            ]),
+        ], Box::new(Term::Nil));
+        assert!(ret.unwrap_return().erl_exact_eq(&expected));
+
+        let ret = vm.call("erlang", "process_info",
+                          vec![Term::Pid(pid), Term::new_atom("registered_name")]);
+        assert!(ret.unwrap_return().erl_exact_eq(&Term::Nil));
 
-        let processes_borrow = self.processes.borrow();
-        let mut process = processes_borrow[self_pid.0].borrow_mut();
-        process.return_val.take().unwrap()
+        // The earlier calls have exited, the last one is the caller.
+        let ret = vm.call("erlang", "processes", vec![]);
+        let live = Term::List(vec![Term::Pid(pid), Term::Pid(Pid(3))], Box::new(Term::Nil));
+        assert!(ret.unwrap_return().erl_exact_eq(&live));
+        assert!(vm.process_info(Pid(1)).is_none());
     }
 
 }
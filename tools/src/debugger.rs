@@ -0,0 +1,253 @@
+extern crate core_erlang_compiler;
+extern crate core_erlang_interpreter;
+extern crate eir;
+
+use core_erlang_interpreter::{ VMState, Term, Debugger, DebugFrontend, DebugContext,
+                               Breakpoint, Breakpoints, StepMode, StopReason };
+use core_erlang_interpreter::erl_lib;
+use eir::{ Atom, FunctionIdent };
+
+use std::io::{ Read, Write, BufRead, BufReader };
+
+const HELP: &str = "\
+s, step          run until the next op
+n, next          run until the next op in this frame, stepping over calls
+f, finish        run until the current frame returns
+c, continue      run until a breakpoint is hit
+b [fun/arity]    break on entry of a function in the current module,
+                 or on the current op if no function is given
+bb               break on the current block
+bl               list breakpoints
+d <id>           delete breakpoint
+v, vars          print values bound in the current frame
+bt               print the call stack
+mb               print the mailbox of the process
+q, quit          exit the debugger";
+
+/// Reads commands line by line from `input`, and writes to `output`.
+/// Exits the program when the input is closed.
+struct LineFrontend<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> LineFrontend<R, W> {
+
+    fn print_location(&mut self, ctx: &DebugContext) {
+        match ctx.reason {
+            StopReason::Breakpoint(id) =>
+                writeln!(self.output, "breakpoint {} hit", id).unwrap(),
+            StopReason::Step => (),
+        }
+        writeln!(self.output, "{} {:?} #{}: {:?}",
+                 ctx.function(), ctx.block, ctx.op_num, ctx.op).unwrap();
+    }
+
+    fn parse_function(&self, ctx: &DebugContext, spec: &str) -> Option<FunctionIdent> {
+        let mut split = spec.splitn(2, '/');
+        let name = split.next()?;
+        let arity = split.next()?.parse().ok()?;
+        Some(FunctionIdent {
+            module: ctx.module().clone(),
+            name: Atom::from_str(name),
+            arity: arity,
+            lambda: None,
+        })
+    }
+
+}
+
+impl<R, W> DebugFrontend for LineFrontend<R, W> where R: BufRead + Send, W: Write + Send {
+
+    fn stopped(&mut self, ctx: &DebugContext, breakpoints: &mut Breakpoints) -> StepMode {
+        self.print_location(ctx);
+
+        loop {
+            write!(self.output, "(dbg) ").unwrap();
+            self.output.flush().unwrap();
+
+            let mut line = String::new();
+            if self.input.read_line(&mut line).unwrap() == 0 {
+                std::process::exit(0);
+            }
+            let mut words = line.split_whitespace();
+
+            match (words.next(), words.next()) {
+                (Some("s"), _) | (Some("step"), _) =>
+                    return StepMode::Op,
+                (Some("n"), _) | (Some("next"), _) =>
+                    return StepMode::Over { depth: ctx.depth() },
+                (Some("f"), _) | (Some("finish"), _) =>
+                    return StepMode::Out { depth: ctx.depth() },
+                (Some("c"), _) | (Some("continue"), _) =>
+                    return StepMode::Continue,
+                (Some("b"), None) => {
+                    let id = breakpoints.add(Breakpoint::Op(
+                        ctx.function().clone(), ctx.block, ctx.op_num));
+                    writeln!(self.output, "breakpoint {} added", id).unwrap();
+                }
+                (Some("b"), Some(spec)) => {
+                    if let Some(ident) = self.parse_function(ctx, spec) {
+                        let id = breakpoints.add(Breakpoint::Function(ident));
+                        writeln!(self.output, "breakpoint {} added", id).unwrap();
+                    } else {
+                        writeln!(self.output, "expected fun/arity").unwrap();
+                    }
+                }
+                (Some("bb"), _) => {
+                    let id = breakpoints.add(Breakpoint::Block(
+                        ctx.function().clone(), ctx.block));
+                    writeln!(self.output, "breakpoint {} added", id).unwrap();
+                }
+                (Some("bl"), _) => {
+                    for (id, breakpoint) in breakpoints.iter() {
+                        writeln!(self.output, "{}: {:?}", id, breakpoint).unwrap();
+                    }
+                }
+                (Some("d"), Some(id)) => {
+                    match id.parse() {
+                        Ok(id) if breakpoints.remove(id) =>
+                            writeln!(self.output, "deleted").unwrap(),
+                        _ => writeln!(self.output, "no such breakpoint").unwrap(),
+                    }
+                }
+                (Some("v"), _) | (Some("vars"), _) => {
+                    for (var, term) in ctx.variables() {
+                        writeln!(self.output, "{:?} = {:?}", var, term).unwrap();
+                    }
+                }
+                (Some("bt"), _) => {
+                    for (num, frame) in ctx.stack.iter().rev().enumerate() {
+                        match frame.block {
+                            Some(block) => writeln!(self.output, "#{} {} {:?}",
+                                                    num, frame.function, block).unwrap(),
+                            None => writeln!(self.output, "#{} {} (native)",
+                                             num, frame.function).unwrap(),
+                        }
+                    }
+                }
+                (Some("mb"), _) => {
+                    for (from, msg) in ctx.mailbox() {
+                        writeln!(self.output, "{:?}: {:?}", from, msg).unwrap();
+                    }
+                }
+                (Some("q"), _) | (Some("quit"), _) =>
+                    std::process::exit(0),
+                (None, _) => (),
+                _ => writeln!(self.output, "{}", HELP).unwrap(),
+            }
+        }
+    }
+
+}
+
+fn parse_arg(arg: &str) -> Term {
+    match arg.parse::<i64>() {
+        Ok(num) => Term::new_i64(num),
+        Err(_) => Term::new_atom(arg),
+    }
+}
+
+/// Creates a VM with the Core Erlang module loaded. Returns the name of
+/// the module along with it.
+fn make_vm(text: &str) -> (VMState, String) {
+    let res = core_erlang_compiler::parser::parse(text).unwrap();
+    let module = core_erlang_compiler::ir::from_parsed(&res.0);
+    let module_name = module.name.to_string();
+
+    let mut vm = VMState::new();
+    vm.add_native_module(erl_lib::make_erlang());
+    vm.add_native_module(erl_lib::make_os());
+    vm.add_native_module(erl_lib::make_maps());
+    vm.add_erlang_module(module);
+
+    (vm, module_name)
+}
+
+fn main() {
+    let mut args = std::env::args();
+    args.next().unwrap();
+    let infile = args.next().expect("usage: debugger <file.core> <fun> [args..]");
+    let fun_name = args.next().expect("no function name provided");
+    let fun_args: Vec<Term> = args.map(|a| parse_arg(&a)).collect();
+
+    let mut text = String::new();
+    std::fs::File::open(&infile).unwrap()
+        .read_to_string(&mut text).unwrap();
+
+    let (mut vm, module_name) = make_vm(&text);
+
+    let frontend = LineFrontend {
+        input: BufReader::new(std::io::stdin()),
+        output: std::io::stdout(),
+    };
+    vm.attach_debugger(Debugger::new(Box::new(frontend)));
+
+    let ret = vm.call(&module_name, &fun_name, fun_args);
+    println!("{:?}", ret);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ LineFrontend, make_vm };
+    use core_erlang_interpreter::{ Debugger, Term };
+    use std::io::{ Cursor, Write };
+    use std::sync::{ Arc, Mutex };
+
+    const ADD_CORE: &str = "
+module 'test' ['add'/2, 'add_two'/3]
+    attributes []
+'add'/2 =
+    fun (_0, _1) ->
+        call 'erlang':'+'
+            (_0, _1)
+'add_two'/3 =
+    fun (_0, _1, _2) ->
+        let <_3> =
+            apply 'add'/2
+                (_0, _1)
+        in  apply 'add'/2
+                (_3, _2)
+end
+";
+
+    /// Output that can still be read after the debugger took the
+    /// frontend.
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn break_finish_continue() {
+        let (mut vm, module_name) = make_vm(ADD_CORE);
+
+        let output = SharedOutput::default();
+        let frontend = LineFrontend {
+            // Stops at the entry of add_two, at the first call to add,
+            // and after returning from it.
+            input: Cursor::new("b add/2\nc\nf\nd 0\nc\n"),
+            output: output.clone(),
+        };
+        vm.attach_debugger(Debugger::new(Box::new(frontend)));
+
+        let args = vec![Term::new_i64(1), Term::new_i64(2), Term::new_i64(3)];
+        let ret = vm.call(&module_name, "add_two", args);
+        assert!(ret.unwrap_return().as_i64() == Some(6));
+
+        let out = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(out.matches("(dbg) ").count() == 5);
+        assert!(out.contains("breakpoint 0 added"));
+        // The breakpoint was deleted before the second call
+        assert!(out.matches("breakpoint 0 hit").count() == 1);
+        assert!(out.contains("deleted"));
+    }
+
+}